@@ -0,0 +1,31 @@
+#! /bin/bash
+# Script used to create golden db input files.
+# Change these lines when copying to make a similar script.
+prefix=added_columns
+purpose="Test rows written before ALTER TABLE ADD COLUMN, whose records have fewer values than the table has columns"
+
+
+if test -f "${prefix}.db"; then
+    echo "Remove existing db if regeneration is needed."
+    exit -1
+fi
+
+(
+echo "Purpose: ${purpose}"
+echo "Tool version:"
+sqlite3 --version
+(
+cat << EOF
+.open $prefix.db
+create table t (a int);
+insert into t values (1), (2);
+alter table t add column b text;
+insert into t values (3, 'three');
+alter table t add column c int;
+alter table t add column d real;
+insert into t values (4, 'four', 44, 4.5);
+select * from t;
+select sql from sqlite_schema;
+EOF
+) | sqlite3
+) > $prefix.log
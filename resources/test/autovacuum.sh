@@ -0,0 +1,31 @@
+#! /bin/bash
+# Script used to create golden db input files.
+# Change these lines when copying to make a similar script.
+prefix=autovacuum
+purpose="Test auto-vacuum mode, which has pointer map pages, in its incremental variant"
+
+
+if test -f "${prefix}.db"; then
+    echo "Remove existing db if regeneration is needed."
+    exit -1
+fi
+
+(
+echo "Purpose: ${purpose}"
+echo "Tool version:"
+sqlite3 --version
+(
+cat << EOF
+.open $prefix.db
+pragma page_size = 512;
+pragma auto_vacuum = incremental;
+create table t (a int, b text);
+create table u (c int);
+with recursive n(i) as (select 1 union all select i + 1 from n where i < 300)
+insert into t select i, 'row ' || i from n;
+insert into u values (1), (2);
+delete from t where a > 250;
+select count(*) from t;
+EOF
+) | sqlite3
+) > $prefix.log
@@ -0,0 +1,32 @@
+#! /bin/bash
+# Script used to create golden db input files.
+# Change these lines when copying to make a similar script.
+prefix=checksums
+purpose="Test pages with an 8 byte checksum in their reserved bytes, as written by the checksum VFS (ext/misc/cksumvfs.c)"
+
+
+if test -f "${prefix}.db"; then
+    echo "Remove existing db if regeneration is needed."
+    exit -1
+fi
+
+(
+echo "Purpose: ${purpose}"
+echo "Tool version:"
+sqlite3 --version
+(
+cat << EOF
+.load ./cksumvfs
+.open $prefix.db
+pragma page_size = 512;
+vacuum;
+.filectrl reserve_bytes 8
+vacuum;
+create table t (a int, b text);
+with recursive n(i) as (select 1 union all select i + 1 from n where i < 100)
+insert into t select i, 'row ' || i from n;
+vacuum;
+select count(*), sum(length(b)) from t;
+EOF
+) | sqlite3
+) > $prefix.log
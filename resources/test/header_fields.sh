@@ -0,0 +1,31 @@
+#! /bin/bash
+# Script used to create golden db input files.
+# Change these lines when copying to make a similar script.
+prefix=header_fields
+purpose="Test header fields that SQLite sets: a freelist, user_version, application_id and default_cache_size"
+
+
+if test -f "${prefix}.db"; then
+    echo "Remove existing db if regeneration is needed."
+    exit -1
+fi
+
+(
+echo "Purpose: ${purpose}"
+echo "Tool version:"
+sqlite3 --version
+(
+cat << EOF
+.open $prefix.db
+pragma page_size = 512;
+pragma user_version = 7;
+pragma application_id = 1684628578;
+pragma default_cache_size = 100;
+create table t (a int, b text);
+with recursive n(i) as (select 1 union all select i + 1 from n where i < 200)
+insert into t select i, 'row ' || i from n;
+delete from t where a > 20;
+select count(*) from t;
+EOF
+) | sqlite3
+) > $prefix.log
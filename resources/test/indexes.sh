@@ -0,0 +1,28 @@
+#! /bin/bash
+# Script used to create golden db input files.
+# Change these lines when copying to make a similar script.
+prefix=indexes
+purpose="Test indexes with several btree levels"
+
+
+if test -f "${prefix}.db"; then
+    echo "Remove existing db if regeneration is needed."
+    exit -1
+fi
+
+(
+echo "Purpose: ${purpose}"
+echo "Tool version:"
+sqlite3 --version
+(
+cat << EOF
+.open $prefix.db
+pragma page_size = 512;
+create table people (name text, age int, city text);
+with recursive n(i) as (select 1 union all select i + 1 from n where i < 1000)
+insert into people select 'p' || i, case when i % 50 = 0 then null else (i * 37) % 90 end, case i % 5 when 0 then 'Austin' when 1 then 'Boston' when 2 then 'Chicago' when 3 then 'Denver' else 'Eugene' end from n;
+create index people_age on people (age);
+create index people_city_age on people (city, age);
+EOF
+) | sqlite3
+) > $prefix.log
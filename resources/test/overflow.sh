@@ -0,0 +1,30 @@
+#! /bin/bash
+# Script used to create golden db input files.
+# Change these lines when copying to make a similar script.
+prefix=overflow
+purpose="Test rows and index entries too big for one page, whose payloads spill to overflow pages"
+
+
+if test -f "${prefix}.db"; then
+    echo "Remove existing db if regeneration is needed."
+    exit -1
+fi
+
+(
+echo "Purpose: ${purpose}"
+echo "Tool version:"
+sqlite3 --version
+(
+cat << EOF
+.open $prefix.db
+pragma page_size = 512;
+create table t (a int, b text, c blob);
+create index t_b on t (b);
+insert into t values (1, 'short', x'01');
+insert into t values (2, replace(hex(zeroblob(500)), '00', 'ab'), null);
+insert into t values (3, replace(hex(zeroblob(240)), '00', 'cd'), null);
+insert into t values (4, 'x', zeroblob(5000));
+select a, length(b), length(c) from t;
+EOF
+) | sqlite3
+) > $prefix.log
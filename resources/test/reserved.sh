@@ -0,0 +1,33 @@
+#! /bin/bash
+# Script used to create golden db input files.
+# Change these lines when copying to make a similar script.
+prefix=reserved
+purpose="Test pages with 32 reserved bytes at their ends, so that 512 byte pages have 480 usable bytes, with an index and rows that spill to overflow pages"
+
+
+if test -f "${prefix}.db"; then
+    echo "Remove existing db if regeneration is needed."
+    exit -1
+fi
+
+(
+echo "Purpose: ${purpose}"
+echo "Tool version:"
+sqlite3 --version
+(
+cat << EOF
+.open $prefix.db
+pragma page_size = 512;
+vacuum;
+.filectrl reserve_bytes 32
+vacuum;
+create table t (a int, b text);
+create index t_b on t (b);
+with recursive n(i) as (select 1 union all select i + 1 from n where i < 300)
+insert into t select i, printf('%04d', i) || substr(hex(zeroblob(600)), 1, (i % 7) * 100) from n;
+delete from t where a % 5 = 0;
+vacuum;
+select count(*), sum(length(b)) from t;
+EOF
+) | sqlite3
+) > $prefix.log
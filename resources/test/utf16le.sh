@@ -0,0 +1,27 @@
+#! /bin/bash
+# Script used to create golden db input files.
+# Change these lines when copying to make a similar script.
+prefix=utf16le
+purpose="Test a database whose text is stored as UTF-16le, with text that is not ASCII"
+
+
+if test -f "${prefix}.db"; then
+    echo "Remove existing db if regeneration is needed."
+    exit -1
+fi
+
+(
+echo "Purpose: ${purpose}"
+echo "Tool version:"
+sqlite3 --version
+(
+cat << EOF
+.open $prefix.db
+pragma encoding = 'UTF-16le';
+create table t (a int, b text);
+create index t_b on t (b);
+insert into t values (1, 'hello'), (2, 'héllo wörld'), (3, '日本語'), (4, '😀 smile'), (5, NULL);
+select a, b from t order by b;
+EOF
+) | sqlite3
+) > $prefix.log
@@ -0,0 +1,33 @@
+#! /bin/bash
+# Script used to create golden db input files.
+# Change these lines when copying to make a similar script.
+prefix=wal
+purpose="Test WAL mode, with committed changes that are in the WAL file and not yet checkpointed"
+
+
+if test -f "${prefix}.db"; then
+    echo "Remove existing db if regeneration is needed."
+    exit -1
+fi
+
+(
+echo "Purpose: ${purpose}"
+echo "Tool version:"
+sqlite3 --version
+(
+cat << EOF
+.open $prefix.db
+.dbconfig no_ckpt_on_close on
+pragma page_size = 512;
+pragma journal_mode = wal;
+pragma wal_autocheckpoint = 0;
+create table t (a int, b text);
+insert into t values (1, 'one'), (2, 'two'), (3, 'three');
+pragma wal_checkpoint;
+update t set b = 'TWO' where a = 2;
+delete from t where a = 3;
+with recursive n(i) as (select 4 union all select i + 1 from n where i < 100)
+insert into t select i, 'row ' || i from n;
+EOF
+) | sqlite3
+) > $prefix.log
@@ -0,0 +1,313 @@
+//! provides helper functions for the aggregate block of a query.
+//!
+//! Aggregation is done by hashing: each input row is looked up by the values of its group by expressions in a hash table of groups,
+//! and then added to the accumulators of that group.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use streaming_iterator::StreamingIterator;
+
+use crate::ast::AggregateFunc;
+use crate::eval::{self, BoundExpr};
+use crate::ir;
+use crate::project;
+use crate::sql_type::SqlType;
+use crate::sql_value::{self, SqlValue};
+use crate::Row;
+
+/// holds an aggregate function call that is ready to be evaluated on input rows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundAggregate {
+    func: AggregateFunc,
+    args: Vec<BoundExpr>,
+}
+
+/// builds the information needed to aggregate the rows of a table at runtime.
+///
+/// Returns the bound group by expressions, the bound aggregates, and the types of the output columns.
+pub fn build_aggregate(
+    in_tablenames: &[String],
+    in_colnames: &[String],
+    in_coltypes: &[SqlType],
+    agg: &ir::Aggregate,
+) -> Result<(Vec<BoundExpr>, Vec<BoundAggregate>, Vec<SqlType>)> {
+    let mut out_coltypes = vec![];
+    let mut group_by = vec![];
+    for e in &agg.group_by {
+        let b = eval::bind(e, in_tablenames, in_colnames)?;
+        out_coltypes.push(project::expr_type(&b, in_coltypes));
+        group_by.push(b);
+    }
+    let mut aggregates = vec![];
+    for a in &agg.aggregates {
+        let num_args_ok = match a.func {
+            AggregateFunc::CountStar => a.args.is_empty(),
+            AggregateFunc::GroupConcat => a.args.len() == 1 || a.args.len() == 2,
+            _ => a.args.len() == 1,
+        };
+        if !num_args_ok {
+            bail!("Wrong number of arguments to function {}()", a.func);
+        }
+        let args = a
+            .args
+            .iter()
+            .map(|e| eval::bind(e, in_tablenames, in_colnames))
+            .collect::<Result<Vec<_>>>()?;
+        out_coltypes.push(match a.func {
+            AggregateFunc::CountStar | AggregateFunc::Count => SqlType::Int,
+            AggregateFunc::Sum => match project::expr_type(&args[0], in_coltypes) {
+                SqlType::Int => SqlType::Int,
+                _ => SqlType::Real,
+            },
+            AggregateFunc::Avg => SqlType::Real,
+            AggregateFunc::Min | AggregateFunc::Max => project::expr_type(&args[0], in_coltypes),
+            AggregateFunc::GroupConcat => SqlType::Text,
+        });
+        aggregates.push(BoundAggregate { func: a.func, args });
+    }
+    Ok((group_by, aggregates, out_coltypes))
+}
+
+/// holds the running state of one aggregate function for one group.
+enum Accumulator {
+    Count(i64),
+    Sum(SqlValue),
+    Avg { sum: f64, count: i64 },
+    Min(SqlValue),
+    Max(SqlValue),
+    GroupConcat(Option<String>),
+}
+
+impl Accumulator {
+    fn new(func: AggregateFunc) -> Accumulator {
+        match func {
+            AggregateFunc::CountStar | AggregateFunc::Count => Accumulator::Count(0),
+            AggregateFunc::Sum => Accumulator::Sum(SqlValue::Null()),
+            AggregateFunc::Avg => Accumulator::Avg { sum: 0.0, count: 0 },
+            AggregateFunc::Min => Accumulator::Min(SqlValue::Null()),
+            AggregateFunc::Max => Accumulator::Max(SqlValue::Null()),
+            AggregateFunc::GroupConcat => Accumulator::GroupConcat(None),
+        }
+    }
+
+    /// adds one input row to the accumulator.
+    fn add(&mut self, agg: &BoundAggregate, row: &Row) -> Result<()> {
+        if agg.func == AggregateFunc::CountStar {
+            if let Accumulator::Count(n) = self {
+                *n += 1;
+            }
+            return Ok(());
+        }
+        let v = agg.args[0].eval(row)?;
+        // All the aggregate functions, other than `count(*)`, ignore NULLs.
+        if let SqlValue::Null() = v {
+            return Ok(());
+        }
+        match self {
+            Accumulator::Count(n) => *n += 1,
+            Accumulator::Sum(total) => {
+                *total = match (&*total, &v) {
+                    (_, SqlValue::Text(_) | SqlValue::Blob(_)) => bail!("Invalid type in sum(): {}", v),
+                    (SqlValue::Null(), _) => v,
+                    (SqlValue::Int(i), SqlValue::Int(j)) => match i.checked_add(*j) {
+                        Some(k) => SqlValue::Int(k),
+                        None => bail!("Integer overflow in sum()"),
+                    },
+                    (t, _) => SqlValue::Real(to_f64(t) + to_f64(&v)),
+                }
+            }
+            Accumulator::Avg { sum, count } => {
+                if let SqlValue::Text(_) | SqlValue::Blob(_) = v {
+                    bail!("Invalid type in avg(): {}", v);
+                }
+                *sum += to_f64(&v);
+                *count += 1;
+            }
+            Accumulator::Min(m) => {
+                if matches!(m, SqlValue::Null()) || sql_value::collate(&v, m) == std::cmp::Ordering::Less {
+                    *m = v;
+                }
+            }
+            Accumulator::Max(m) => {
+                if matches!(m, SqlValue::Null()) || sql_value::collate(&v, m) == std::cmp::Ordering::Greater {
+                    *m = v;
+                }
+            }
+            Accumulator::GroupConcat(s) => {
+                let text = eval::to_text(&v);
+                *s = Some(match s.take() {
+                    None => text,
+                    Some(prev) => {
+                        let separator = match agg.args.get(1) {
+                            None => String::from(","),
+                            Some(e) => match e.eval(row)? {
+                                SqlValue::Null() => String::new(),
+                                sep => eval::to_text(&sep),
+                            },
+                        };
+                        prev + &separator + &text
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// returns the value of the aggregate function over the rows added.
+    fn finish(self) -> SqlValue {
+        match self {
+            Accumulator::Count(n) => SqlValue::Int(n),
+            Accumulator::Sum(v) | Accumulator::Min(v) | Accumulator::Max(v) => v,
+            Accumulator::Avg { count: 0, .. } => SqlValue::Null(),
+            Accumulator::Avg { sum, count } => SqlValue::Real(sum / count as f64),
+            Accumulator::GroupConcat(s) => s.map_or(SqlValue::Null(), SqlValue::Text),
+        }
+    }
+}
+
+fn to_f64(v: &SqlValue) -> f64 {
+    match v {
+        SqlValue::Int(i) => *i as f64,
+        SqlValue::Real(f) => *f,
+        SqlValue::Bool(b) => *b as i64 as f64,
+        _ => 0.0,
+    }
+}
+
+/// is a group by value, or a hash join key value, in a form that can be hashed.
+///
+/// Values which compare equal in SQL, like `1` and `1.0`, have the same `KeyValue`, so they are put in the same group.
+#[derive(Debug, Hash, PartialEq, Eq)]
+pub enum KeyValue {
+    Null,
+    Int(i64),
+    Real(u64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+pub fn key_value(v: &SqlValue) -> KeyValue {
+    match v {
+        SqlValue::Null() => KeyValue::Null,
+        SqlValue::Int(i) => KeyValue::Int(*i),
+        SqlValue::Bool(b) => KeyValue::Int(*b as i64),
+        SqlValue::Real(f) if f.fract() == 0.0 && f.abs() < 9.0e18 => KeyValue::Int(*f as i64),
+        SqlValue::Real(f) => KeyValue::Real(f.to_bits()),
+        SqlValue::Text(s) => KeyValue::Text(s.clone()),
+        SqlValue::Blob(b) => KeyValue::Blob(b.clone()),
+    }
+}
+
+/// groups the rows of `input` and computes `aggregates` over each group, returning one row per group.
+///
+/// The rows are returned in order of their group by values, as SQLite happens to do.
+pub fn hash_aggregate<I>(input: &mut I, group_by: &[BoundExpr], aggregates: &[BoundAggregate]) -> Result<Vec<Row>>
+where
+    I: StreamingIterator<Item = Row> + ?Sized,
+{
+    let mut group_indexes: HashMap<Vec<KeyValue>, usize> = HashMap::new();
+    let mut groups: Vec<(Vec<SqlValue>, Vec<Accumulator>)> = vec![];
+    if group_by.is_empty() {
+        // Without a GROUP BY, there is one group, even if there are no input rows.
+        group_indexes.insert(vec![], 0);
+        groups.push((vec![], aggregates.iter().map(|a| Accumulator::new(a.func)).collect()));
+    }
+    while let Some(row) = input.next() {
+        let key = group_by.iter().map(|e| e.eval(row)).collect::<Result<Vec<_>>>()?;
+        let idx = *group_indexes.entry(key.iter().map(key_value).collect()).or_insert_with(|| {
+            groups.push((key, aggregates.iter().map(|a| Accumulator::new(a.func)).collect()));
+            groups.len() - 1
+        });
+        for (acc, agg) in groups[idx].1.iter_mut().zip(aggregates) {
+            acc.add(agg, row)?;
+        }
+    }
+    groups.sort_by(|a, b| {
+        a.0.iter()
+            .zip(b.0.iter())
+            .map(|(x, y)| sql_value::collate(x, y))
+            .find(|o| o.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    Ok(groups
+        .into_iter()
+        .map(|(key, accs)| Row {
+            items: key.into_iter().chain(accs.into_iter().map(Accumulator::finish)).collect(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+fn aggregate_rows(group_by: &[&str], aggregates: &[(AggregateFunc, Vec<&str>)], rows: Vec<Row>) -> Result<Vec<Row>> {
+    use crate::ast;
+    let colnames = vec![String::from("a"), String::from("b")];
+    let tablenames = vec![String::from("t"); colnames.len()];
+    let coltypes = vec![SqlType::Int, SqlType::Int];
+    let colref = |c: &&str| ast::Expr::ColRef { tablename: None, colname: String::from(*c) };
+    let agg = ir::Aggregate {
+        group_by: group_by.iter().map(colref).collect(),
+        aggregates: aggregates
+            .iter()
+            .map(|(func, args)| ir::AggregateCall { func: *func, args: args.iter().map(colref).collect() })
+            .collect(),
+        colnames: vec![],
+        input: Box::new(ir::Block::ConstantRow(ir::ConstantRow { row: vec![], colnames: vec![] })),
+    };
+    let (group_by, aggregates, _) = build_aggregate(&tablenames, &colnames, &coltypes, &agg)?;
+    let mut input = streaming_iterator::convert(rows);
+    hash_aggregate(&mut input, &group_by, &aggregates)
+}
+
+#[test]
+fn test_hash_aggregate() {
+    use AggregateFunc::*;
+    use SqlValue::*;
+    let rows = vec![
+        Row { items: vec![Int(1), Int(10)] },
+        Row { items: vec![Int(2), Int(20)] },
+        Row { items: vec![Int(1), Null()] },
+        Row { items: vec![Null(), Int(5)] },
+        Row { items: vec![Real(1.0), Int(30)] },
+    ];
+    let aggs = vec![
+        (CountStar, vec![]),
+        (Count, vec!["b"]),
+        (Sum, vec!["b"]),
+        (Avg, vec!["b"]),
+        (Min, vec!["b"]),
+        (Max, vec!["b"]),
+        (GroupConcat, vec!["b"]),
+    ];
+    let actual = aggregate_rows(&["a"], &aggs, rows.clone()).unwrap();
+    let text = |s: &str| Text(String::from(s));
+    assert_eq!(
+        actual,
+        vec![
+            Row { items: vec![Null(), Int(1), Int(1), Int(5), Real(5.0), Int(5), Int(5), text("5")] },
+            Row { items: vec![Int(1), Int(3), Int(2), Int(40), Real(20.0), Int(10), Int(30), text("10,30")] },
+            Row { items: vec![Int(2), Int(1), Int(1), Int(20), Real(20.0), Int(20), Int(20), text("20")] },
+        ]
+    );
+    let actual = aggregate_rows(&[], &aggs, rows).unwrap();
+    assert_eq!(
+        actual,
+        vec![Row { items: vec![Int(5), Int(4), Int(65), Real(16.25), Int(5), Int(30), text("10,20,5,30")] }]
+    );
+}
+
+#[test]
+fn test_hash_aggregate_empty_input() {
+    use AggregateFunc::*;
+    use SqlValue::*;
+    let aggs = vec![(CountStar, vec![]), (Sum, vec!["b"]), (Max, vec!["b"])];
+    assert_eq!(aggregate_rows(&[], &aggs, vec![]).unwrap(), vec![Row { items: vec![Int(0), Null(), Null()] }]);
+    assert_eq!(aggregate_rows(&["a"], &aggs, vec![]).unwrap(), vec![]);
+}
+
+#[test]
+fn test_build_aggregate_checks_arguments() {
+    assert!(aggregate_rows(&[], &[(AggregateFunc::Sum, vec![])], vec![]).is_err());
+    assert!(aggregate_rows(&[], &[(AggregateFunc::Min, vec!["a", "b"])], vec![]).is_err());
+    assert!(aggregate_rows(&[], &[(AggregateFunc::Count, vec!["c"])], vec![]).is_err());
+}
@@ -21,6 +21,11 @@ pub enum SelItem {
     Expr(Expr),
     ColName(ColName),
     Star,
+    /// an expression (which may be just a column name) that is given a name for the output with `AS`.
+    Aliased {
+        expr: Expr,
+        alias: String,
+    },
 }
 
 impl std::fmt::Display for SelItem {
@@ -29,40 +34,87 @@ impl std::fmt::Display for SelItem {
             SelItem::Expr(x) => x.fmt(f),
             SelItem::ColName(x) => x.fmt(f),
             SelItem::Star => "*".fmt(f),
+            SelItem::Aliased { expr, alias } => write!(f, "{} AS {}", expr, alias),
         }
     }
 }
 
+/// a table named in a FROM clause.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct FromClause {
+pub struct TableRef {
     pub databasename: String,
     pub tablename: String,
+    /// the name that the rest of the statement uses for the table, if it was given one with `AS`.
+    pub alias: Option<String>,
 }
 
-// #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-// pub struct WhereClause {}
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromClause {
+    pub table: TableRef,
+    /// the tables joined to `table`, in order.  The first is joined to `table`, the next to the result of that join, and so on.
+    pub joins: Vec<Join>,
+}
 
-// #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-// pub struct GroupByClause {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    /// an inner join, which includes `JOIN`, `INNER JOIN`, `CROSS JOIN` and joining with a comma.
+    Inner,
+    /// a left outer join, which keeps rows of the left side that match no rows of the right side, padding them with NULLs.
+    Left,
+}
 
-// #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-// pub struct OrderByClause {}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: TableRef,
+    /// the `ON` condition.  Without one, every pair of rows matches.
+    pub on: Option<Expr>,
+}
 
-// #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-// pub struct HavingClause {}
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhereClause {
+    pub expr: Expr,
+}
 
-// #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-// pub struct LimitClause {}
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupByClause {
+    pub exprs: Vec<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderByClause {
+    pub terms: Vec<OrderingTerm>,
+}
+
+/// one of the keys in an `ORDER BY` clause.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+    /// `Some(true)` for `NULLS FIRST`, `Some(false)` for `NULLS LAST`, and `None` if not specified.
+    pub nulls_first: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HavingClause {
+    pub expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitClause {
+    pub limit: Expr,
+    pub offset: Option<Expr>,
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct SelectStatement {
     pub select: SelectClause,
     pub from: Option<FromClause>,
-    // pub r#where: Option<WhereClause>,
-    // pub group_by: Option<GroupByClause>,
-    // pub order_by: Option<OrderByClause>,
-    // pub having: Option<HavingClause>,
-    // pub limit: Option<LimitClause>,
+    pub r#where: Option<WhereClause>,
+    pub group_by: Option<GroupByClause>,
+    pub order_by: Option<OrderByClause>,
+    pub having: Option<HavingClause>,
+    pub limit: Option<LimitClause>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -79,12 +131,79 @@ pub struct CreateStatement {
     pub strict: bool,
 }
 
+/// formats the statement the way SQLite stores it in the schema table, which is without the database name.
+impl std::fmt::Display for CreateStatement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use itertools::Itertools;
+        write!(
+            f,
+            "CREATE TABLE {} ({}){}",
+            self.tablename,
+            self.coldefs.iter().map(|c| format!("{} {}", c.colname, c.coltype)).join(", "),
+            match self.strict {
+                true => " STRICT",
+                false => "",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedColumn {
+    pub colname: String,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateIndexStatement {
+    pub databasename: String, // The database of the index, which is also the database of the table.
+    pub indexname: String,
+    pub tablename: String,
+    pub unique: bool,
+    pub columns: Vec<IndexedColumn>,
+}
+
 pub struct InsertStatement {
     pub databasename: String, // "temp" or "main" currently supported values.
     pub tablename: String,
     pub values: Vec<Vec<Constant>>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeleteStatement {
+    pub databasename: String, // "temp" or "main" currently supported values.
+    pub tablename: String,
+    /// the condition on the rows to delete.  Without one, every row is deleted.
+    pub r#where: Option<WhereClause>,
+}
+
+/// one `column = expr` of the `SET` clause of an `UPDATE` statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub colname: ColName,
+    pub expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateStatement {
+    pub databasename: String, // "temp" or "main" currently supported values.
+    pub tablename: String,
+    pub assignments: Vec<Assignment>,
+    /// the condition on the rows to update.  Without one, every row is updated.
+    pub r#where: Option<WhereClause>,
+}
+
+/// a statement that starts or ends a transaction, or a savepoint within one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransactionStatement {
+    Begin,
+    Commit,
+    /// rolls back the whole transaction, or, with a savepoint name, the changes made since that savepoint.
+    Rollback(Option<String>),
+    Savepoint(String),
+    Release(String),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Constant {
     Int(i64),
@@ -113,18 +232,79 @@ impl std::fmt::Display for Constant {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Constant(Constant),
+    /// a reference to a column of the input, optionally qualified with the table name, like `t.a`.
+    ColRef {
+        tablename: Option<String>,
+        colname: String,
+    },
     BinOp {
         lhs: Box<Expr>,
         op: Op,
         rhs: Box<Expr>,
     },
+    UnaryOp {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    /// a call to an aggregate function, like `count(*)` or `sum(a)`.  `COUNT(*)` has no args.
+    Aggregate {
+        func: AggregateFunc,
+        args: Vec<Expr>,
+    },
+    /// `expr IS NULL`, or `expr IS NOT NULL` when negated.
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    /// `expr BETWEEN low AND high`, or `expr NOT BETWEEN low AND high` when negated.
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    /// `expr IN (list...)`, or `expr NOT IN (list...)` when negated.
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+}
+
+impl Expr {
+    /// returns the terms of a conjunction, like `a`, `b` and `c` for `a AND b AND c`.  Any other expression is one term.
+    pub fn conjuncts(&self) -> Vec<&Expr> {
+        match self {
+            Expr::BinOp { lhs, op: Op::And, rhs } => {
+                let mut terms = lhs.conjuncts();
+                terms.extend(rhs.conjuncts());
+                terms
+            }
+            e => vec![e],
+        }
+    }
 }
 
 impl std::fmt::Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let not = |negated: &bool| if *negated { "NOT " } else { "" };
         match self {
             Expr::Constant(x) => x.fmt(f),
+            Expr::ColRef{ tablename: Some(t), colname } => write!(f, "{}.{}", t, colname),
+            Expr::ColRef{ tablename: None, colname } => colname.fmt(f),
             Expr::BinOp{ lhs: l, op: o, rhs: r} => l.fmt(f).and_then(|_| o.fmt(f)).and_then(|_| r.fmt(f)),
+            Expr::UnaryOp{ op, expr } => write!(f, "{}{}", op, expr),
+            Expr::Aggregate{ func: AggregateFunc::CountStar, .. } => "count(*)".fmt(f),
+            Expr::Aggregate{ func, args } => {
+                use itertools::Itertools;
+                write!(f, "{}({})", func, args.iter().join(", "))
+            }
+            Expr::IsNull{ expr, negated } => write!(f, "{} IS {}NULL", expr, not(negated)),
+            Expr::Between{ expr, low, high, negated } => write!(f, "{} {}BETWEEN {} AND {}", expr, not(negated), low, high),
+            Expr::InList{ expr, list, negated } => {
+                use itertools::Itertools;
+                write!(f, "{} {}IN ({})", expr, not(negated), list.iter().join(", "))
+            }
         }
     }
 }
@@ -136,6 +316,16 @@ pub enum Op {
     Subtract,
     Multiply,
     Divide,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    Like,
 }
 
 impl std::fmt::Display for Op {
@@ -146,6 +336,54 @@ impl std::fmt::Display for Op {
             Subtract => "-".fmt(f),
             Multiply => "*".fmt(f),
             Divide => "/".fmt(f),
+            Concat => "||".fmt(f),
+            Eq => "=".fmt(f),
+            NotEq => "<>".fmt(f),
+            Lt => "<".fmt(f),
+            LtEq => "<=".fmt(f),
+            Gt => ">".fmt(f),
+            GtEq => ">=".fmt(f),
+            And => " AND ".fmt(f),
+            Or => " OR ".fmt(f),
+            Like => " LIKE ".fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnaryOp {
+    Not,
+}
+
+impl std::fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnaryOp::Not => "NOT ".fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunc {
+    CountStar,
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    GroupConcat,
+}
+
+impl std::fmt::Display for AggregateFunc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use AggregateFunc::*;
+        match self {
+            CountStar | Count => "count".fmt(f),
+            Sum => "sum".fmt(f),
+            Avg => "avg".fmt(f),
+            Min => "min".fmt(f),
+            Max => "max".fmt(f),
+            GroupConcat => "group_concat".fmt(f),
         }
     }
-}
\ No newline at end of file
+}
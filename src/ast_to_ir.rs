@@ -9,22 +9,30 @@ use anyhow::{bail, Result};
 use std::boxed::Box;
 
 pub fn ast_select_statement_to_ir(ss: &ast::SelectStatement) -> Result<ir::Block, anyhow::Error> {
+    if is_aggregate_query(ss) {
+        return aggregate_query_to_ir(ss);
+    }
     // If the select only has a select clause,then we just need to return a constant
     // single row one time (or maybe multiple rows if we support UNION in the future and simplify it).
     if ss.from.is_none() {
         let mut row: Vec<ast::Constant> = vec![];
-        for item in &ss.select.items {
+        let mut colnames: Vec<String> = vec![];
+        for (i, item) in ss.select.items.iter().enumerate() {
             match item {
-                ast::SelItem::Expr(e) => {
+                ast::SelItem::Expr(e) | ast::SelItem::Aliased { expr: e, .. } => {
                     match e {
                         ast::Expr::Constant(c) => {
-                            row.push(c.clone())
+                            row.push(c.clone());
+                            colnames.push(match item {
+                                ast::SelItem::Aliased { alias, .. } => alias.clone(),
+                                _ => format!("_f{i}"),
+                            });
                         }
-                        ast::Expr::BinOp{..} => {
+                        _ => {
                             // We have done a constant propagation pass over the AST.
-                            // So, if there is a BinOp expression, it must contain a ColName. 
+                            // So, if there is a non-constant expression, it must contain a ColName.
                             // You can't use a ColName when there is no FROM clause.
-                            bail!("Unexpected BinOp in a query without a FROM clause");
+                            bail!("Unexpected non-constant expression in a query without a FROM clause");
                         }
                     }
                 }
@@ -32,33 +40,31 @@ pub fn ast_select_statement_to_ir(ss: &ast::SelectStatement) -> Result<ir::Block
                 ast::SelItem::Star => bail!("Cannot select * without a FROM clause"),
             }
         }
-        return Ok(ir::Block::ConstantRow(ir::ConstantRow { row }));
+        return with_limit(ss, with_sort(ss, with_filter(ss, ir::Block::ConstantRow(ir::ConstantRow { row, colnames })))?);
     }
     // At this point, the select has a "from" clause.  In a degenerate case, it might not
     // be referenced by the select or where or other clauses, but we still have to "scan" to return
     // one result row for every input row.
     let from = ss.from.as_ref().unwrap();
-    let scan = ir::Scan {
-        databasename: from.databasename.clone(),
-        tablename: from.tablename.clone(),
-    };
+    let scan = with_sort(ss, from_clause_to_ir(ss, from))?;
     let mut outcols: Vec<ast::SelItem> = vec![];
     for item in &ss.select.items[..] {
         match item {
             ast::SelItem::Expr(_) => outcols.push(item.clone()),
             ast::SelItem::ColName(_) => outcols.push(item.clone()),
             ast::SelItem::Star => outcols.push(item.clone()),
+            ast::SelItem::Aliased { .. } => outcols.push(item.clone()),
         }
     }
     if outcols.len() == 1 && outcols[0].is_star()
     {
         // No project block needed if all columns selected.
-        return Ok(ir::Block::Scan(scan));
+        return with_limit(ss, scan);
         // Ponder: This could be moved to an opimization pass?
         // Call it Project Elimination (?): remove unneeded Project() from Project(Scan), if
         // the Project is not adding or eliminating any rows (minor efficiency boost maybe?)
     }
-    Ok(ir::Block::Project(ir::Project {
+    with_limit(ss, ir::Block::Project(ir::Project {
         // TODO: Consider whether to lookup the table's column names and types at this point.
         // Table information like sizes would be needed prior to execution to do cost-based optimization.
         // This lookup can be done as a pass after building the initial IR but before interpreting it.
@@ -73,10 +79,311 @@ pub fn ast_select_statement_to_ir(ss: &ast::SelectStatement) -> Result<ir::Block
         // outwards so that we can propagate up output names to input names.  That is currently handled during interpretation.
         // Would need to be handled earlier for code generation, and maybe for other optimizations.
         outcols,
-        input: Box::new(ir::Block::Scan(scan)),
+        input: Box::new(scan),
+    }))
+}
+
+/// converts the from clause of the statement, and its where clause if any, to IR.
+///
+/// Tables are joined from left to right.  When the last join is an inner join, the where clause is merged into its
+/// ON condition.  That gives the same rows, but lets equalities in the where clause, such as those of a comma join,
+/// be used for a hash join.
+fn from_clause_to_ir(ss: &ast::SelectStatement, from: &ast::FromClause) -> ir::Block {
+    let scan = |t: &ast::TableRef| {
+        ir::Block::Scan(ir::Scan {
+            databasename: t.databasename.clone(),
+            tablename: t.tablename.clone(),
+            alias: t.alias.clone(),
+        })
+    };
+    if from.joins.is_empty() {
+        return with_filter(ss, scan(&from.table));
+    }
+    let mut block = scan(&from.table);
+    let mut where_merged = false;
+    for (i, join) in from.joins.iter().enumerate() {
+        let mut on = join.on.clone();
+        if let (true, ast::JoinKind::Inner, Some(w)) = (i + 1 == from.joins.len(), join.kind, &ss.r#where) {
+            on = Some(match on {
+                None => w.expr.clone(),
+                Some(e) => ast::Expr::BinOp { lhs: Box::new(e), op: ast::Op::And, rhs: Box::new(w.expr.clone()) },
+            });
+            where_merged = true;
+        }
+        block = ir::Block::Join(ir::Join {
+            kind: join.kind,
+            on,
+            left: Box::new(block),
+            right: Box::new(scan(&join.table)),
+        });
+    }
+    match where_merged {
+        true => block,
+        false => with_filter(ss, block),
+    }
+}
+
+/// wraps `input` in a Filter block if the statement has a where clause.
+fn with_filter(ss: &ast::SelectStatement, input: ir::Block) -> ir::Block {
+    match &ss.r#where {
+        None => input,
+        Some(w) => ir::Block::Filter(ir::Filter {
+            predicate: w.expr.clone(),
+            input: Box::new(input),
+        }),
+    }
+}
+
+/// wraps `input` in a Limit block if the statement has a limit clause.
+fn with_limit(ss: &ast::SelectStatement, input: ir::Block) -> Result<ir::Block> {
+    let l = match &ss.limit {
+        None => return Ok(input),
+        Some(l) => l,
+    };
+    // The limit and offset were simplified to constants, if possible, in the AST optimization pass.
+    let to_int = |e: &ast::Expr, what: &str| match e {
+        ast::Expr::Constant(ast::Constant::Int(i)) => Ok(*i),
+        _ => Err(anyhow::anyhow!("{} must be an integer constant: {}", what, e)),
+    };
+    let limit = to_int(&l.limit, "LIMIT")?;
+    let offset = match &l.offset {
+        None => 0,
+        Some(o) => to_int(o, "OFFSET")?,
+    };
+    Ok(ir::Block::Limit(ir::Limit {
+        // Like SQLite, a negative limit means no limit, and a negative offset is the same as zero.
+        limit: usize::try_from(limit).ok(),
+        offset: usize::try_from(offset).unwrap_or(0),
+        input: Box::new(input),
     }))
 }
 
+/// wraps `input` in a Sort block if the statement has an order by clause.
+///
+/// The Sort is done before the Project, so that rows can be ordered by columns which are not selected.
+fn with_sort(ss: &ast::SelectStatement, input: ir::Block) -> Result<ir::Block> {
+    if ss.order_by.is_none() {
+        return Ok(input);
+    }
+    Ok(ir::Block::Sort(ir::Sort {
+        keys: sort_keys(ss)?,
+        input: Box::new(input),
+    }))
+}
+
+/// returns the keys of the statement's order by clause.
+fn sort_keys(ss: &ast::SelectStatement) -> Result<Vec<ir::SortKey>> {
+    let mut keys = vec![];
+    for (i, term) in ss.order_by.iter().flat_map(|o| o.terms.iter()).enumerate() {
+        keys.push(ir::SortKey {
+            expr: resolve_select_list_ref(ss, &term.expr, "ORDER BY", i)?,
+            descending: term.descending,
+            // NULLs are smaller than any other value, so by default they come first in ascending order.
+            nulls_first: term.nulls_first.unwrap_or(!term.descending),
+        });
+    }
+    Ok(keys)
+}
+
+/// replaces a term of an order by or group by clause which refers to the select list, by position (`ORDER BY 2`) or by alias,
+/// with the expression from the select list.
+fn resolve_select_list_ref(ss: &ast::SelectStatement, expr: &ast::Expr, clause: &str, i: usize) -> Result<ast::Expr> {
+    Ok(match expr {
+        ast::Expr::Constant(ast::Constant::Int(pos)) => {
+            if *pos < 1 || *pos as usize > ss.select.items.len() {
+                bail!("{} term {} is out of range - should be between 1 and {}", clause, i + 1, ss.select.items.len());
+            }
+            match &ss.select.items[*pos as usize - 1] {
+                ast::SelItem::Expr(e) | ast::SelItem::Aliased { expr: e, .. } => e.clone(),
+                ast::SelItem::ColName(c) => ast::Expr::ColRef { tablename: None, colname: c.name.clone() },
+                ast::SelItem::Star => bail!("{} term {} refers to *, which is not supported", clause, i + 1),
+            }
+        }
+        ast::Expr::ColRef { tablename: None, colname } => {
+            let aliased = ss.select.items.iter().find_map(|item| match item {
+                ast::SelItem::Aliased { expr, alias } if alias == colname => Some(expr.clone()),
+                _ => None,
+            });
+            aliased.unwrap_or_else(|| expr.clone())
+        }
+        e => e.clone(),
+    })
+}
+
+/// returns true if the expression contains an aggregate function call.
+fn contains_aggregate(expr: &ast::Expr) -> bool {
+    use ast::Expr;
+    match expr {
+        Expr::Aggregate { .. } => true,
+        Expr::Constant(_) | Expr::ColRef { .. } => false,
+        Expr::BinOp { lhs, rhs, .. } => contains_aggregate(lhs) || contains_aggregate(rhs),
+        Expr::UnaryOp { expr, .. } | Expr::IsNull { expr, .. } => contains_aggregate(expr),
+        Expr::Between { expr, low, high, .. } => contains_aggregate(expr) || contains_aggregate(low) || contains_aggregate(high),
+        Expr::InList { expr, list, .. } => contains_aggregate(expr) || list.iter().any(contains_aggregate),
+    }
+}
+
+fn is_aggregate_query(ss: &ast::SelectStatement) -> bool {
+    ss.group_by.is_some()
+        || ss.having.is_some()
+        || ss.select.items.iter().any(|item| match item {
+            ast::SelItem::Expr(e) | ast::SelItem::Aliased { expr: e, .. } => contains_aggregate(e),
+            _ => false,
+        })
+        || ss.order_by.iter().flat_map(|o| o.terms.iter()).any(|t| contains_aggregate(&t.expr))
+}
+
+/// holds the outputs of an Aggregate block, so that expressions evaluated after it can be rewritten to refer to them.
+struct AggregateOutputs {
+    group_by: Vec<ast::Expr>,
+    aggregates: Vec<ir::AggregateCall>,
+    colnames: Vec<String>,
+}
+
+impl AggregateOutputs {
+    fn column(&self, idx: usize) -> ast::Expr {
+        ast::Expr::ColRef { tablename: None, colname: self.colnames[idx].clone() }
+    }
+
+    /// rewrites an expression that is evaluated on the input rows of the Aggregate, to one that is evaluated on its output rows.
+    ///
+    /// Group by expressions and aggregates become references to the Aggregate's output columns.  Any other references to
+    /// input columns are an error, since they don't have a single value per group.
+    fn rewrite(&mut self, expr: &ast::Expr) -> Result<ast::Expr> {
+        use ast::Expr;
+        if let Some(idx) = self.group_by.iter().position(|g| same_expr(g, expr)) {
+            return Ok(self.column(idx));
+        }
+        Ok(match expr {
+            Expr::Aggregate { func, args } => {
+                if args.iter().any(contains_aggregate) {
+                    bail!("Aggregate functions cannot be nested: {}", expr);
+                }
+                let call = ir::AggregateCall { func: *func, args: args.clone() };
+                let idx = match self.aggregates.iter().position(|a| *a == call) {
+                    Some(idx) => idx,
+                    None => {
+                        self.aggregates.push(call);
+                        self.colnames.push(format!("_agg{}", self.aggregates.len() - 1));
+                        self.aggregates.len() - 1
+                    }
+                };
+                self.column(self.group_by.len() + idx)
+            }
+            Expr::Constant(_) => expr.clone(),
+            Expr::ColRef { .. } => bail!("Column {} must appear in the GROUP BY clause or be used in an aggregate function", expr),
+            Expr::BinOp { lhs, op, rhs } => Expr::BinOp {
+                lhs: Box::new(self.rewrite(lhs)?),
+                op: op.clone(),
+                rhs: Box::new(self.rewrite(rhs)?),
+            },
+            Expr::UnaryOp { op, expr } => Expr::UnaryOp { op: op.clone(), expr: Box::new(self.rewrite(expr)?) },
+            Expr::IsNull { expr, negated } => Expr::IsNull { expr: Box::new(self.rewrite(expr)?), negated: *negated },
+            Expr::Between { expr, low, high, negated } => Expr::Between {
+                expr: Box::new(self.rewrite(expr)?),
+                low: Box::new(self.rewrite(low)?),
+                high: Box::new(self.rewrite(high)?),
+                negated: *negated,
+            },
+            Expr::InList { expr, list, negated } => Expr::InList {
+                expr: Box::new(self.rewrite(expr)?),
+                list: list.iter().map(|e| self.rewrite(e)).collect::<Result<Vec<_>>>()?,
+                negated: *negated,
+            },
+        })
+    }
+}
+
+/// compares expressions, treating `a` and `t.a` as the same column.
+fn same_expr(a: &ast::Expr, b: &ast::Expr) -> bool {
+    match (a, b) {
+        (ast::Expr::ColRef { tablename: t1, colname: c1 }, ast::Expr::ColRef { tablename: t2, colname: c2 }) => {
+            c1 == c2 && (t1.is_none() || t2.is_none() || t1 == t2)
+        }
+        _ => a == b,
+    }
+}
+
+/// converts a select statement that has aggregate functions or a group by clause to IR.
+///
+/// The IR is `Limit(Project(Sort(Filter(Aggregate(Filter(Scan))))))`, where the upper Filter is for the having clause.
+/// Expressions in the blocks above the Aggregate are rewritten to refer to the Aggregate's output columns.
+fn aggregate_query_to_ir(ss: &ast::SelectStatement) -> Result<ir::Block> {
+    if let Some(w) = &ss.r#where {
+        if contains_aggregate(&w.expr) {
+            bail!("Aggregate functions are not allowed in the WHERE clause");
+        }
+    }
+    let input = match &ss.from {
+        // Without a FROM, aggregate over a single row with no columns, like SQLite.
+        None => with_filter(ss, ir::Block::ConstantRow(ir::ConstantRow { row: vec![], colnames: vec![] })),
+        Some(from) => from_clause_to_ir(ss, from),
+    };
+
+    let mut group_by = vec![];
+    for (i, e) in ss.group_by.iter().flat_map(|g| g.exprs.iter()).enumerate() {
+        let e = resolve_select_list_ref(ss, e, "GROUP BY", i)?;
+        if contains_aggregate(&e) {
+            bail!("Aggregate functions are not allowed in the GROUP BY clause");
+        }
+        group_by.push(e);
+    }
+    let mut outputs = AggregateOutputs {
+        // A group by column keeps its name, so that it can be selected by name.
+        colnames: group_by
+            .iter()
+            .enumerate()
+            .map(|(i, e)| match e {
+                ast::Expr::ColRef { colname, .. } => colname.clone(),
+                _ => format!("_group{}", i),
+            })
+            .collect(),
+        group_by,
+        aggregates: vec![],
+    };
+
+    let mut outcols = vec![];
+    for item in &ss.select.items {
+        outcols.push(match item {
+            // Like SQLite, an aggregate is named after its call, like "count(*)", rather than "?column?".
+            ast::SelItem::Expr(e @ ast::Expr::Aggregate { .. }) => ast::SelItem::Aliased { expr: outputs.rewrite(e)?, alias: e.to_string() },
+            ast::SelItem::Expr(e) => ast::SelItem::Expr(outputs.rewrite(e)?),
+            ast::SelItem::Aliased { expr, alias } => ast::SelItem::Aliased { expr: outputs.rewrite(expr)?, alias: alias.clone() },
+            ast::SelItem::ColName(c) => {
+                let e = ast::Expr::ColRef { tablename: None, colname: c.name.clone() };
+                match outputs.rewrite(&e)? {
+                    ast::Expr::ColRef { colname, .. } if colname == c.name => item.clone(),
+                    // An unnamed group by expression, which happens to be this column.
+                    e => ast::SelItem::Aliased { expr: e, alias: c.name.clone() },
+                }
+            }
+            ast::SelItem::Star => bail!("Cannot select * in a query with aggregate functions or GROUP BY"),
+        });
+    }
+    let having = match &ss.having {
+        None => None,
+        Some(h) => Some(outputs.rewrite(&h.expr)?),
+    };
+    let mut keys = sort_keys(ss)?;
+    for k in keys.iter_mut() {
+        k.expr = outputs.rewrite(&k.expr)?;
+    }
+
+    let mut block = ir::Block::Aggregate(ir::Aggregate {
+        group_by: outputs.group_by,
+        aggregates: outputs.aggregates,
+        colnames: outputs.colnames,
+        input: Box::new(input),
+    });
+    if let Some(predicate) = having {
+        block = ir::Block::Filter(ir::Filter { predicate, input: Box::new(block) });
+    }
+    if !keys.is_empty() {
+        block = ir::Block::Sort(ir::Sort { keys, input: Box::new(block) });
+    }
+    with_limit(ss, ir::Block::Project(ir::Project { outcols, input: Box::new(block) }))
+}
+
 #[test]
 fn test_ast_select_statement_to_ir() {
     struct Case {
@@ -92,9 +399,15 @@ fn test_ast_select_statement_to_ir() {
                     items: vec![ast::SelItem::Expr(ast::Expr::Constant(ast::Constant::Int(1)))],
                 },
                 from: None,
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
             expected: Ok(ir::Block::ConstantRow(ir::ConstantRow {
                 row: vec![ast::Constant::Int(1)],
+                colnames: vec![String::from("_f0")],
             })),
         },
         Case {
@@ -106,9 +419,18 @@ fn test_ast_select_statement_to_ir() {
                     })],
                 },
                 from: Some(ast::FromClause {
-                    databasename: String::from("db"),
-                    tablename: String::from("t"),
+                    table: ast::TableRef {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    },
+                    joins: vec![],
                 }),
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
             expected: Ok(ir::Block::Project(ir::Project {
                 outcols: vec![ast::SelItem::ColName(ast::ColName {
@@ -117,6 +439,7 @@ fn test_ast_select_statement_to_ir() {
                 input: std::boxed::Box::new(ir::Block::Scan(ir::Scan {
                     databasename: String::from("db"),
                     tablename: String::from("t"),
+                    alias: None,
                 })),
             })),
         },
@@ -127,13 +450,23 @@ fn test_ast_select_statement_to_ir() {
                     items: vec![ast::SelItem::Star],
                 },
                 from: Some(ast::FromClause {
-                    databasename: String::from("db"),
-                    tablename: String::from("t"),
+                    table: ast::TableRef {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    },
+                    joins: vec![],
                 }),
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
             expected: Ok(ir::Block::Scan(ir::Scan {
                 databasename: String::from("db"),
                 tablename: String::from("t"),
+                alias: None,
             })),
         },
         Case {
@@ -143,15 +476,25 @@ fn test_ast_select_statement_to_ir() {
                     items: vec![ast::SelItem::Expr(ast::Expr::Constant(ast::Constant::Int(1)))],
                 },
                 from: Some(ast::FromClause {
-                    databasename: String::from("db"),
-                    tablename: String::from("t"),
+                    table: ast::TableRef {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    },
+                    joins: vec![],
                 }),
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
             expected: Ok(ir::Block::Project(ir::Project {
                 outcols: vec![ast::SelItem::Expr(ast::Expr::Constant(ast::Constant::Int(1)))],
                 input: std::boxed::Box::new(ir::Block::Scan(ir::Scan {
                     databasename: String::from("db"),
                     tablename: String::from("t"),
+                    alias: None,
                 })),
             })),
         },
@@ -168,9 +511,18 @@ fn test_ast_select_statement_to_ir() {
                     ],
                 },
                 from: Some(ast::FromClause {
-                    tablename: String::from("t"),
-                    databasename: String::from("db"),
+                    table: ast::TableRef {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    },
+                    joins: vec![],
                 }),
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
             expected: Ok(ir::Block::Project(ir::Project {
                 outcols: vec![
@@ -183,6 +535,7 @@ fn test_ast_select_statement_to_ir() {
                 input: std::boxed::Box::new(ir::Block::Scan(ir::Scan {
                     databasename: String::from("db"),
                     tablename: String::from("t"),
+                    alias: None,
                 })),
             })),
         },
@@ -195,6 +548,11 @@ fn test_ast_select_statement_to_ir() {
                     })],
                 },
                 from: None,
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
             expected: Err(()),
         },
@@ -205,9 +563,83 @@ fn test_ast_select_statement_to_ir() {
                     items: vec![ast::SelItem::Star],
                 },
                 from: None,
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
             expected: Err(()),
         },
+        Case {
+            desc: "Select * from t where 1;".to_string(),
+            input: ast::SelectStatement {
+                select: ast::SelectClause {
+                    items: vec![ast::SelItem::Star],
+                },
+                from: Some(ast::FromClause {
+                    table: ast::TableRef {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    },
+                    joins: vec![],
+                }),
+                r#where: Some(ast::WhereClause {
+                    expr: ast::Expr::Constant(ast::Constant::Int(1)),
+                }),
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
+            },
+            expected: Ok(ir::Block::Filter(ir::Filter {
+                predicate: ast::Expr::Constant(ast::Constant::Int(1)),
+                input: std::boxed::Box::new(ir::Block::Scan(ir::Scan {
+                    databasename: String::from("db"),
+                    tablename: String::from("t"),
+                    alias: None,
+                })),
+            })),
+        },
+        Case {
+            desc: "Select a from t where 1;".to_string(),
+            input: ast::SelectStatement {
+                select: ast::SelectClause {
+                    items: vec![ast::SelItem::ColName(ast::ColName {
+                        name: String::from("a"),
+                    })],
+                },
+                from: Some(ast::FromClause {
+                    table: ast::TableRef {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    },
+                    joins: vec![],
+                }),
+                r#where: Some(ast::WhereClause {
+                    expr: ast::Expr::Constant(ast::Constant::Int(1)),
+                }),
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
+            },
+            expected: Ok(ir::Block::Project(ir::Project {
+                outcols: vec![ast::SelItem::ColName(ast::ColName {
+                    name: String::from("a"),
+                })],
+                input: std::boxed::Box::new(ir::Block::Filter(ir::Filter {
+                    predicate: ast::Expr::Constant(ast::Constant::Int(1)),
+                    input: std::boxed::Box::new(ir::Block::Scan(ir::Scan {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    })),
+                })),
+            })),
+        },
     ];
     for case in cases {
         println!("Running case: {}", case.desc);
@@ -223,3 +655,182 @@ fn test_ast_select_statement_to_ir() {
         assert_eq!(actual_ok, expected_ok);
     }
 }
+
+#[test]
+fn test_order_by_to_ir() {
+    let colref = |c: &str| ast::Expr::ColRef { tablename: None, colname: String::from(c) };
+    let term = |expr: ast::Expr, descending: bool| ast::OrderingTerm { expr, descending, nulls_first: None };
+    let ss = ast::SelectStatement {
+        select: ast::SelectClause {
+            items: vec![
+                ast::SelItem::ColName(ast::ColName { name: String::from("a") }),
+                ast::SelItem::Aliased { expr: colref("b"), alias: String::from("x") },
+            ],
+        },
+        from: Some(ast::FromClause {
+            table: ast::TableRef {
+                databasename: String::from("db"),
+                tablename: String::from("t"),
+                alias: None,
+            },
+            joins: vec![],
+        }),
+        r#where: None,
+        order_by: Some(ast::OrderByClause {
+            terms: vec![
+                term(colref("x"), true),
+                term(ast::Expr::Constant(ast::Constant::Int(1)), false),
+                term(colref("c"), false),
+            ],
+        }),
+        group_by: None,
+        having: None,
+        limit: None,
+    };
+    let ir = ast_select_statement_to_ir(&ss).unwrap();
+    let sort = ir.as_project().unwrap().input.as_sort().unwrap();
+    assert_eq!(
+        sort.keys,
+        vec![
+            ir::SortKey { expr: colref("b"), descending: true, nulls_first: false },
+            ir::SortKey { expr: colref("a"), descending: false, nulls_first: true },
+            ir::SortKey { expr: colref("c"), descending: false, nulls_first: true },
+        ]
+    );
+
+    let mut out_of_range = ss.clone();
+    out_of_range.order_by = Some(ast::OrderByClause { terms: vec![term(ast::Expr::Constant(ast::Constant::Int(3)), false)] });
+    assert!(ast_select_statement_to_ir(&out_of_range).is_err());
+}
+
+#[test]
+fn test_limit_to_ir() {
+    let ss = |limit: ast::Expr, offset: Option<ast::Expr>| ast::SelectStatement {
+        select: ast::SelectClause {
+            items: vec![ast::SelItem::Star],
+        },
+        from: Some(ast::FromClause {
+            table: ast::TableRef {
+                databasename: String::from("db"),
+                tablename: String::from("t"),
+                alias: None,
+            },
+            joins: vec![],
+        }),
+        r#where: None,
+        order_by: None,
+        group_by: None,
+        having: None,
+        limit: Some(ast::LimitClause { limit, offset }),
+    };
+    let int = |i: i64| ast::Expr::Constant(ast::Constant::Int(i));
+    let scan = Box::new(ir::Block::Scan(ir::Scan {
+        databasename: String::from("db"),
+        tablename: String::from("t"),
+        alias: None,
+    }));
+    assert_eq!(
+        ast_select_statement_to_ir(&ss(int(10), Some(int(5)))).unwrap(),
+        ir::Block::Limit(ir::Limit { limit: Some(10), offset: 5, input: scan.clone() })
+    );
+    assert_eq!(
+        ast_select_statement_to_ir(&ss(int(-1), Some(int(-5)))).unwrap(),
+        ir::Block::Limit(ir::Limit { limit: None, offset: 0, input: scan })
+    );
+    assert!(ast_select_statement_to_ir(&ss(ast::Expr::Constant(ast::Constant::String(String::from("x"))), None)).is_err());
+}
+
+#[test]
+fn test_aggregate_to_ir() {
+    let ss = crate::pt_to_ast::pt_select_statement_to_ast(
+        "select a, count(*) from db.t group by a having sum(b) > 1 order by count(*) desc",
+    )
+    .unwrap();
+    let col = |name: &str| ast::Expr::ColRef { tablename: None, colname: String::from(name) };
+    let aggregate = ir::Block::Aggregate(ir::Aggregate {
+        group_by: vec![col("a")],
+        aggregates: vec![
+            ir::AggregateCall { func: ast::AggregateFunc::CountStar, args: vec![] },
+            ir::AggregateCall { func: ast::AggregateFunc::Sum, args: vec![col("b")] },
+        ],
+        colnames: vec![String::from("a"), String::from("_agg0"), String::from("_agg1")],
+        input: Box::new(ir::Block::Scan(ir::Scan {
+            databasename: String::from("db"),
+            tablename: String::from("t"),
+            alias: None,
+        })),
+    });
+    let having = ir::Block::Filter(ir::Filter {
+        predicate: ast::Expr::BinOp {
+            lhs: Box::new(col("_agg1")),
+            op: ast::Op::Gt,
+            rhs: Box::new(ast::Expr::Constant(ast::Constant::Int(1))),
+        },
+        input: Box::new(aggregate),
+    });
+    let sort = ir::Block::Sort(ir::Sort {
+        keys: vec![ir::SortKey { expr: col("_agg0"), descending: true, nulls_first: false }],
+        input: Box::new(having),
+    });
+    assert_eq!(
+        ast_select_statement_to_ir(&ss).unwrap(),
+        ir::Block::Project(ir::Project {
+            outcols: vec![
+                ast::SelItem::ColName(ast::ColName { name: String::from("a") }),
+                ast::SelItem::Aliased { expr: col("_agg0"), alias: String::from("count(*)") },
+            ],
+            input: Box::new(sort),
+        })
+    );
+
+    for q in ["select b from db.t group by a", "select sum(count(*)) from db.t", "select a from db.t group by sum(a)"] {
+        let ss = crate::pt_to_ast::pt_select_statement_to_ast(q).unwrap();
+        assert!(ast_select_statement_to_ir(&ss).is_err(), "{} should fail", q);
+    }
+}
+
+#[test]
+fn test_join_to_ir() {
+    let scan = |t: &str, alias: Option<&str>| {
+        Box::new(ir::Block::Scan(ir::Scan {
+            databasename: String::from("main"),
+            tablename: String::from(t),
+            alias: alias.map(String::from),
+        }))
+    };
+    let parse = |q: &str| crate::pt_to_ast::pt_select_statement_to_ast(q).unwrap();
+    let expr = |e: &str| {
+        use pest::Parser;
+        let pairs = crate::parser::SQLParser::parse(crate::parser::Rule::expr, e).unwrap().next().unwrap().into_inner();
+        crate::parser::parse_expr(pairs)
+    };
+
+    // The where clause is merged into the condition of the last join, when that is an inner join.
+    assert_eq!(
+        ast_select_statement_to_ir(&parse("select * from t x left join u on x.a = u.a, v where v.b = u.b")).unwrap(),
+        ir::Block::Join(ir::Join {
+            kind: ast::JoinKind::Inner,
+            on: Some(expr("v.b = u.b")),
+            left: Box::new(ir::Block::Join(ir::Join {
+                kind: ast::JoinKind::Left,
+                on: Some(expr("x.a = u.a")),
+                left: scan("t", Some("x")),
+                right: scan("u", None),
+            })),
+            right: scan("v", None),
+        })
+    );
+    // Otherwise it filters the rows of the join, so that it does not remove unmatched rows of a left join.
+    assert_eq!(
+        ast_select_statement_to_ir(&parse("select * from t left join u on t.a = u.a where u.a is null")).unwrap(),
+        ir::Block::Filter(ir::Filter {
+            predicate: expr("u.a is null"),
+            input: Box::new(ir::Block::Join(ir::Join {
+                kind: ast::JoinKind::Left,
+                on: Some(expr("t.a = u.a")),
+                left: scan("t", None),
+                right: scan("u", None),
+            })),
+        })
+    );
+}
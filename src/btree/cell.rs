@@ -1,98 +1,95 @@
 //! cell::Iterator iterates over the cells in a btree page.
 
-use byteorder::{BigEndian, ReadBytesExt};
-use std::io::{Cursor, Seek, SeekFrom};
+use byteorder::{BigEndian, ByteOrder};
+use std::ops::Range;
 
 use super::PageType;
+use crate::stored_db::{Error, PageNum};
 
 /// Iterator over cells within a page, without interpreting the cell contents.
 pub struct Iterator<'a> {
     page: &'a Vec<u8>,
-    cell_idx: usize,
-    cell_offsets: Vec<usize>,
-    cell_lengths: Vec<usize>,
+    ranges: std::vec::IntoIter<Range<usize>>,
 }
 
 impl<'a> Iterator<'a> {
-    /// Creates an iterator over the cells of a single page of a btree.
+    /// Creates an iterator over the cells of a single page of a btree, page `pgnum`, or returns an error if the page
+    /// header or cell pointers are not valid.
     ///
-    /// Iterator produces cells which are slices of bytes, which contain a record.
+    /// Iterator produces the offset of each cell in the page, and the cell, which is a slice of bytes that contains a
+    /// record.
     ///
     /// # Arguments
     ///
     /// * `s` - A byte slice.  Borrowed for the lifetime of the iterator.  Slice begins with the record header length (a varint).
     ///         slives ends with the last byte of the record body.
-    pub fn new(p: &Vec<u8>, non_btree_header_bytes: usize, page_size: u32) -> Iterator {
-        let mut c = Cursor::new(p);
-        c.seek(SeekFrom::Start(non_btree_header_bytes as u64))
-            .expect("Should have seeked.");
-        let btree_page_type = match c.read_u8().expect("Should have read btree header") {
-            0x02 => PageType::IndexInterior,
-            0x05 => PageType::TableInterior,
-            0x0a => PageType::IndexLeaf,
-            0x0d => PageType::TableLeaf,
-            b => panic!("Invalid Btree Page Type: {}", b ),
-        };
-        c.seek(SeekFrom::Start(3 + non_btree_header_bytes as u64))
-            .expect("Should have seeked.");
-        let num_cells: u32 = c
-            .read_u16::<BigEndian>()
-            .expect("Should have read btree header") as u32;
-
-        let btree_header_bytes = match btree_page_type {
-            PageType::IndexInterior | PageType::TableInterior => 12,
-            PageType::IndexLeaf | PageType::TableLeaf => 8,
-        };
-        c.seek(SeekFrom::Start(
-            btree_header_bytes + non_btree_header_bytes as u64,
-        ))
-        .expect("Should have seeked to cell offset.");
-
-        let mut it = Iterator {
+    pub fn new(p: &Vec<u8>, pgnum: PageNum, non_btree_header_bytes: usize, usable_size: u32) -> Result<Iterator<'_>, Error> {
+        Ok(Iterator {
             page: p,
-            cell_idx: 0,
-            cell_offsets: Vec::new(),
-            cell_lengths: Vec::new(),
-        };
+            ranges: ranges(p, pgnum, non_btree_header_bytes, usable_size)?.into_iter(),
+        })
+    }
+}
 
-        // Read the cell pointer array:
-        // """
-        // The cell pointer array of a b-tree page immediately follows the b-tree page header.
-        // Let K be the number of cells on the btree. The cell pointer array consists of K 2-byte
-        // integer offsets to the cell contents. The cell pointers are arranged in key order with
-        // left-most cell (the cell with the smallest key) first and the right-most cell (the cell
-        // with the largest key) last.
-        // """()
-        let mut last_offset: usize = page_size as usize; // First cell in pointer list is the last cell on the page, so it ends on byte PAGESIZE, I think (?).
-        for _ in 0..num_cells {
-            let off = c
-                .read_u16::<BigEndian>()
-                .expect("Should have read cell pointer") as usize;
-            it.cell_offsets.push(off);
-            it.cell_lengths.push(last_offset - off);
-            last_offset = off;
-        }
-        it
+/// returns the byte ranges of the cells of btree page `pgnum`, in key order.
+///
+/// Every range is at least 4 bytes long, which is the smallest a cell can be, and is within the usable part of the
+/// page.  An error is returned if the cell pointers do not fit on the page, or point outside of it.
+///
+/// This is for callers that hold the page in a way that the cells cannot borrow from, such as a `PageRef`.
+pub fn ranges(p: &[u8], pgnum: PageNum, non_btree_header_bytes: usize, usable_size: u32) -> Result<Vec<Range<usize>>, Error> {
+    let hdr = super::header::check_header(p, pgnum, non_btree_header_bytes)?;
+    let btree_header_bytes = match hdr.btree_page_type {
+        PageType::IndexInterior | PageType::TableInterior => 12,
+        PageType::IndexLeaf | PageType::TableLeaf => 8,
+    };
+    let usable_size = (usable_size as usize).min(p.len());
+    let pointers_start = btree_header_bytes + non_btree_header_bytes;
+    let pointers_end = pointers_start + 2 * hdr.num_cells as usize;
+    if pointers_end > usable_size {
+        let detail = format!("{} cell pointers do not fit on the page", hdr.num_cells);
+        return Err(super::corrupt(pgnum, non_btree_header_bytes + 3, detail));
     }
+
+    // Read the cell pointer array:
+    // """
+    // The cell pointer array of a b-tree page immediately follows the b-tree page header.
+    // Let K be the number of cells on the btree. The cell pointer array consists of K 2-byte
+    // integer offsets to the cell contents. The cell pointers are arranged in key order with
+    // left-most cell (the cell with the smallest key) first and the right-most cell (the cell
+    // with the largest key) last.
+    // """()
+    let cell_offsets: Vec<usize> =
+        p[pointers_start..pointers_end].chunks_exact(2).map(|c| BigEndian::read_u16(c) as usize).collect();
+    // Cells are not always stored in the reverse order of their pointers, as when cells were inserted into the
+    // middle of a page, so each cell is taken to end where the next cell in the content area begins, or at the end
+    // of the page.  A cell may be followed by free space, so its slice may be longer than the cell.
+    let mut starts = cell_offsets.clone();
+    starts.sort_unstable();
+    cell_offsets
+        .iter()
+        .enumerate()
+        .map(|(i, off)| {
+            let end = starts.get(starts.partition_point(|s| s <= off)).copied().unwrap_or(usable_size);
+            if *off < pointers_end || end < off + 4 {
+                let detail = format!("cell pointer {} is outside the cell content area", off);
+                return Err(super::corrupt(pgnum, pointers_start + 2 * i, detail));
+            }
+            Ok(*off..end)
+        })
+        .collect()
 }
 
 impl<'a> core::iter::Iterator for Iterator<'a> {
-    // The iterator returns a reference to a cell (&[u8]).  The format of the data in the cell
+    // The iterator returns the offset of a cell and a reference to it (&[u8]).  The format of the data in the cell
     // is dependent on the type of the btree page.
-    type Item = &'a [u8];
+    type Item = (usize, &'a [u8]);
 
-    /// Returns the next item, which is a &[u8], the slice of bytes containing the contents of the cell.
+    /// Returns the next item, which is the offset of the cell, and a &[u8], the slice of bytes containing the contents
+    /// of the cell.
     fn next(&mut self) -> Option<Self::Item> {
-        if self.cell_idx >= self.cell_offsets.len() {
-            return None;
-        }
-        let mut c = Cursor::new(self.page);
-        c.seek(SeekFrom::Start(self.cell_offsets[self.cell_idx] as u64))
-            .expect("Should have seeked to cell offset.");
-        let b = self.cell_offsets[self.cell_idx];
-        let e = b + self.cell_lengths[self.cell_idx];
-        self.cell_idx += 1;
-        Some(&self.page[b..e])
+        let page = self.page;
+        self.ranges.next().map(|r| (r.start, &page[r]))
     }
 }
 
@@ -138,18 +135,29 @@ fn test_cell_iterator() {
         Vec::from_hex(TEST_PAGE.replace(&[' ', '\n'][..], "")).expect("Invalid Hex String");
     println!("{:?}", p);
     assert_eq!(p.len(), 512);
-    let mut ci = Iterator::new(&p, 0, 512);
-    assert_eq!(ci.next().unwrap(), Vec::from_hex("0301020f41").unwrap());
-    assert_eq!(ci.next().unwrap(), Vec::from_hex("0302020f42").unwrap());
-    assert_eq!(ci.next().unwrap(), Vec::from_hex("0303020f43").unwrap());
-    assert_eq!(ci.next().unwrap(), Vec::from_hex("0304020f44").unwrap());
-    assert_eq!(ci.next().unwrap(), Vec::from_hex("0305020f45").unwrap());
-    assert_eq!(ci.next().unwrap(), Vec::from_hex("0306020f46").unwrap());
-    assert_eq!(ci.next().unwrap(), Vec::from_hex("0307020f47").unwrap());
-    assert_eq!(ci.next().unwrap(), Vec::from_hex("0308020f48").unwrap());
-    assert_eq!(ci.next().unwrap(), Vec::from_hex("0309020f49").unwrap());
-    assert_eq!(ci.next().unwrap(), Vec::from_hex("030a020f4a").unwrap());
+    let mut ci = Iterator::new(&p, 2, 0, 512).unwrap();
+    assert_eq!(ci.next().unwrap(), (0x1fb, &Vec::from_hex("0301020f41").unwrap()[..]));
+    assert_eq!(ci.next().unwrap().1, Vec::from_hex("0302020f42").unwrap());
+    assert_eq!(ci.next().unwrap().1, Vec::from_hex("0303020f43").unwrap());
+    assert_eq!(ci.next().unwrap().1, Vec::from_hex("0304020f44").unwrap());
+    assert_eq!(ci.next().unwrap().1, Vec::from_hex("0305020f45").unwrap());
+    assert_eq!(ci.next().unwrap().1, Vec::from_hex("0306020f46").unwrap());
+    assert_eq!(ci.next().unwrap().1, Vec::from_hex("0307020f47").unwrap());
+    assert_eq!(ci.next().unwrap().1, Vec::from_hex("0308020f48").unwrap());
+    assert_eq!(ci.next().unwrap().1, Vec::from_hex("0309020f49").unwrap());
+    assert_eq!(ci.next().unwrap().1, Vec::from_hex("030a020f4a").unwrap());
     assert_eq!(ci.next(), None);
+
+    // A cell pointer past the end of the page.
+    let mut bad = p.clone();
+    bad[8..10].copy_from_slice(&[0x02, 0x00]);
+    assert!(matches!(ranges(&bad, 2, 0, 512), Err(Error::Corrupt { page: 2, offset: 8, .. })));
+    // More cell pointers than fit on the page.
+    bad[3..5].copy_from_slice(&[0x01, 0x00]);
+    assert!(matches!(ranges(&bad, 2, 0, 512), Err(Error::Corrupt { page: 2, offset: 3, .. })));
+    // A page type that is not a btree page type.
+    bad[0] = 0x07;
+    assert!(matches!(Iterator::new(&bad, 2, 0, 512), Err(Error::Corrupt { page: 2, offset: 0, .. })));
 }
 
 // Cell Formats from https://www.sqlite.org/fileformat2.html#b_tree_pages
@@ -0,0 +1,554 @@
+//! check verifies the structure of a database's btrees and freelist, as SQLite's `PRAGMA integrity_check` does.
+//!
+//! A `Checker` walks each btree from its root, and checks every page that it reaches:
+//! - The btree page header, and that the page is of the right kind for its btree.
+//! - That the cell pointers and the cells are within the usable part of the page, and that no two cells overlap.
+//! - That keys are in order, both within a page and across the pages of the btree, and that all leaves are at the
+//!   same depth.
+//! - That each overflow chain has as many pages as its payload needs.
+//! - That each record is well formed, and that the records of tables decode as rows of the table.
+//!
+//! It also walks the freelist.  Each page is counted as it is reached, so that pages that are used twice, or not at
+//! all, are found.
+
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use super::node::{self, btree_start_offset};
+use super::{header, overflow, PageType, RowId, MAX_DEPTH};
+use crate::sql_type::SqlType;
+use crate::sql_value::SqlValue;
+use crate::stored_db::{PageNum, StoredDb};
+
+/// the most problems that are reported.  A badly damaged file could have one for every page.
+const MAX_PROBLEMS: usize = 100;
+
+/// a key of a btree: the rowid of a table row, or the values of an index entry.
+#[derive(Debug, Clone)]
+enum Key {
+    Rowid(RowId),
+    Entry(Vec<SqlValue>),
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Key::Rowid(rowid) => write!(f, "rowid {}", rowid),
+            Key::Entry(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "entry ({})", values.join(", "))
+            }
+        }
+    }
+}
+
+/// describes the btree that is being checked.
+struct Tree<'t> {
+    /// true for table btrees, and false for index btrees.
+    table: bool,
+    /// the types of the columns of a table, which its records must decode as.
+    column_types: &'t Vec<SqlType>,
+}
+
+/// checks the btrees, overflow chains and freelist of a database, collecting a description of each problem found.
+pub struct Checker<'a> {
+    db: &'a StoredDb,
+    /// whether each page, by page number, has been reached yet.
+    reached: Vec<bool>,
+    problems: Vec<String>,
+}
+
+impl<'a> Checker<'a> {
+    pub fn new(db: &'a StoredDb) -> Checker<'a> {
+        Checker { db, reached: vec![false; db.get_numpages() + 1], problems: vec![] }
+    }
+
+    /// returns the problems found so far.
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
+
+    /// adds a description of a problem.
+    pub fn problem(&mut self, description: String) {
+        if self.problems.len() < MAX_PROBLEMS {
+            self.problems.push(description);
+        }
+    }
+
+    /// checks the table btree rooted at `root`, whose records must decode as rows with `column_types`, and returns
+    /// the number of rows found.
+    pub fn check_table(&mut self, name: &str, root: PageNum, column_types: &Vec<SqlType>) -> usize {
+        self.check_tree(name, root, &Tree { table: true, column_types })
+    }
+
+    /// checks the index btree rooted at `root`, and returns the number of entries found.
+    pub fn check_index(&mut self, name: &str, root: PageNum) -> usize {
+        self.check_tree(name, root, &Tree { table: false, column_types: &vec![] })
+    }
+
+    fn check_tree(&mut self, name: &str, root: PageNum, tree: &Tree) -> usize {
+        let mut count = 0;
+        if self.reach(root, &format!("Root of {}", name)) {
+            self.check_page(root, tree, None, None, 1, &mut count);
+        }
+        count
+    }
+
+    /// checks the freelist, and that the number of pages on it matches the database header.
+    pub fn check_freelist(&mut self) {
+        let (first_trunk, freelist_count) = match self.db.get_page_ro(1) {
+            Ok(page1) => crate::dbheader::freelist(&page1),
+            Err(e) => return self.problem(format!("Page 1: {}", e)),
+        };
+        // SQLite reads trunk pages with more leaves than this as corrupt.
+        let max_leaves = self.db.get_usable_size() as usize / 4 - 2;
+        let (mut trunk, mut count, mut from) = (first_trunk as PageNum, 0, String::from("Freelist"));
+        while trunk != 0 && self.reach(trunk, &from) {
+            count += 1;
+            let page = match self.db.get_page_ro(trunk) {
+                Ok(p) => p,
+                Err(e) => return self.problem(format!("Freelist trunk page {}: {}", trunk, e)),
+            };
+            let num_leaves = BigEndian::read_u32(&page[4..]) as usize;
+            if num_leaves > max_leaves {
+                return self.problem(format!("Freelist trunk page {}: {} leaves is too many", trunk, num_leaves));
+            }
+            from = format!("Freelist trunk page {}", trunk);
+            for i in 0..num_leaves {
+                if self.reach(BigEndian::read_u32(&page[8 + 4 * i..]) as PageNum, &from) {
+                    count += 1;
+                }
+            }
+            trunk = BigEndian::read_u32(&page[0..]) as PageNum;
+        }
+        if count != freelist_count as usize {
+            self.problem(format!("Freelist has {} pages, but the header says it has {}", count, freelist_count));
+        }
+    }
+
+    /// returns the problems found, after checking that every page was reached, except for pages that are never used,
+    /// which are pointer map pages and the lock-byte page.
+    pub fn finish(mut self) -> Vec<String> {
+        for pn in 1..self.reached.len() {
+            if !self.reached[pn] && !self.db.is_ptrmap_page(pn) && !self.db.is_lock_byte_page(pn) {
+                self.problem(format!("Page {} is never used", pn));
+            }
+        }
+        self.problems
+    }
+
+    /// counts page `pn` as reached from `from`, returning false if it cannot be, because it is not a page that can be
+    /// used, or was already reached.
+    fn reach(&mut self, pn: PageNum, from: &str) -> bool {
+        let problem = match pn {
+            0 => "is not a page",
+            pn if pn >= self.reached.len() => "is beyond the end of the database",
+            pn if self.db.is_ptrmap_page(pn) => "is a pointer map page",
+            pn if self.db.is_lock_byte_page(pn) => "is the lock-byte page",
+            pn if self.reached[pn] => "is already used elsewhere",
+            _ => {
+                self.reached[pn] = true;
+                return true;
+            }
+        };
+        self.problem(format!("{}: page {} {}", from, pn, problem));
+        false
+    }
+
+    /// checks the subtree rooted at page `pn`, at `level` of the btree, whose keys must be greater than `lower`, and
+    /// less than `upper`, or, in table btrees, equal to it.  Returns the depth of the subtree's leaves, if it could be
+    /// found.
+    fn check_page(
+        &mut self,
+        pn: PageNum,
+        tree: &Tree,
+        lower: Option<Key>,
+        upper: Option<Key>,
+        level: usize,
+        count: &mut usize,
+    ) -> Option<usize> {
+        let db = self.db;
+        let page = match db.get_page_ro(pn) {
+            Ok(p) => p,
+            Err(e) => {
+                self.problem(format!("Page {}: {}", pn, e));
+                return None;
+            }
+        };
+        let hdr = match header::check_header(&page, pn, btree_start_offset(pn)) {
+            Ok(hdr) => hdr,
+            Err(e) => {
+                self.problem(e.to_string());
+                return None;
+            }
+        };
+        let page_type = hdr.btree_page_type.clone();
+        if matches!(page_type, PageType::TableInterior | PageType::TableLeaf) != tree.table {
+            self.problem(format!("Page {}: {:?} page in a {} btree", pn, page_type, tree_kind(tree)));
+            return None;
+        }
+        let ranges = self.cell_ranges(pn, &page, &hdr)?;
+
+        let mut prev = lower;
+        let mut depth: Option<usize> = None;
+        for (i, r) in ranges.into_iter().enumerate() {
+            let c = &page[r];
+            let (left_child, rest) = match page_type {
+                PageType::TableInterior | PageType::IndexInterior => (Some(node::left_child(c)), &c[4..]),
+                PageType::TableLeaf | PageType::IndexLeaf => (None, c),
+            };
+            let (first, n) = sqlite_varint::read_varint(rest);
+            // The key is None for an index entry that could not be read, so the keys around it are not compared with it.
+            let key = match page_type {
+                PageType::TableInterior => Some(Key::Rowid(first)),
+                PageType::TableLeaf => {
+                    let (rowid, m) = sqlite_varint::read_varint(&rest[n..]);
+                    if let Some(record) = self.payload(pn, i, &page_type, &rest[n + m..], first as usize) {
+                        self.check_row(pn, i, &record, tree.column_types);
+                    }
+                    *count += 1;
+                    Some(Key::Rowid(rowid))
+                }
+                PageType::IndexInterior | PageType::IndexLeaf => {
+                    *count += 1;
+                    self.payload(pn, i, &page_type, &rest[n..], first as usize)
+                        .and_then(|record| self.decode_entry(pn, i, &record))
+                        .map(Key::Entry)
+                }
+            };
+            if let Some(child) = left_child {
+                let d = self.check_child(pn, child, tree, prev.clone(), key.clone(), level, count);
+                self.check_depth(pn, &mut depth, d);
+            }
+            if let Some(key) = key {
+                if !self.in_order(prev.as_ref(), &key, upper.as_ref(), tree.table) {
+                    self.problem(format!("Page {} cell {}: {} is out of order", pn, i, key));
+                }
+                prev = Some(key);
+            }
+        }
+        match hdr.rightmost_pointer {
+            None => Some(0),
+            Some(right) => {
+                let d = self.check_child(pn, right as PageNum, tree, prev, upper, level, count);
+                self.check_depth(pn, &mut depth, d);
+                depth.map(|d| d + 1)
+            }
+        }
+    }
+
+    /// checks the subtree rooted at `child`, a child of page `pn` at `level` of the btree.
+    ///
+    /// A btree deeper than `MAX_DEPTH` is reported rather than followed, since a corrupt file could have a chain of
+    /// interior pages long enough to overflow the stack.
+    #[allow(clippy::too_many_arguments)]
+    fn check_child(
+        &mut self,
+        pn: PageNum,
+        child: PageNum,
+        tree: &Tree,
+        lower: Option<Key>,
+        upper: Option<Key>,
+        level: usize,
+        count: &mut usize,
+    ) -> Option<usize> {
+        if level >= MAX_DEPTH {
+            self.problem(format!("Page {}: btree has more than {} levels", pn, MAX_DEPTH));
+            return None;
+        }
+        match self.reach(child, &format!("Page {}", pn)) {
+            true => self.check_page(child, tree, lower, upper, level + 1, count),
+            false => None,
+        }
+    }
+
+    /// checks that the leaves under a child of page `pn` are at `child_depth`, like those of its other children.
+    fn check_depth(&mut self, pn: PageNum, depth: &mut Option<usize>, child_depth: Option<usize>) {
+        match (*depth, child_depth) {
+            (Some(d), Some(c)) if d != c => self.problem(format!("Page {}: children have leaves at different depths", pn)),
+            (None, c) => *depth = c,
+            _ => (),
+        }
+    }
+
+    /// returns the byte range of each cell of page `pn`, in key order, if the cell pointers and cells are all within
+    /// the usable part of the page, and no two cells overlap.
+    fn cell_ranges(&mut self, pn: PageNum, page: &[u8], hdr: &header::Header) -> Option<Vec<Range<usize>>> {
+        let usable = self.db.get_usable_size() as usize;
+        let header_len = match hdr.btree_page_type {
+            PageType::IndexInterior | PageType::TableInterior => 12,
+            PageType::IndexLeaf | PageType::TableLeaf => 8,
+        };
+        let pointers_start = btree_start_offset(pn) + header_len;
+        let pointers_end = pointers_start + 2 * hdr.num_cells as usize;
+        let content_start = hdr.cell_content_start as usize;
+        if pointers_end > usable || content_start < pointers_end || content_start > usable {
+            self.problem(format!(
+                "Page {}: {} cell pointers and the cell content area at offset {} do not fit on the page",
+                pn, hdr.num_cells, content_start
+            ));
+            return None;
+        }
+        let mut ranges = vec![];
+        for i in 0..hdr.num_cells as usize {
+            let off = BigEndian::read_u16(&page[pointers_start + 2 * i..]) as usize;
+            // The smallest cell is 4 bytes long.
+            if off < content_start || off + 4 > usable {
+                self.problem(format!("Page {} cell {}: offset {} is outside the cell content area", pn, i, off));
+                return None;
+            }
+            let len = node::cell_len(&hdr.btree_page_type, &page[off..usable], usable as u32).ok()?;
+            if off + len > usable {
+                self.problem(format!("Page {} cell {}: cell at offset {} extends past the end of the page", pn, i, off));
+                return None;
+            }
+            ranges.push(off..off + len);
+        }
+        let mut sorted = ranges.clone();
+        sorted.sort_by_key(|r| r.start);
+        if let Some(w) = sorted.windows(2).find(|w| w[0].end > w[1].start) {
+            self.problem(format!("Page {}: cells at offsets {} and {} overlap", pn, w[0].start, w[1].start));
+            return None;
+        }
+        Some(ranges)
+    }
+
+    /// returns the payload of `payload_len` bytes of cell `i` of page `pn`, where `local` is as for
+    /// `overflow::read_payload`, if its overflow chain, if any, has as many pages as it needs.
+    fn payload(&mut self, pn: PageNum, i: usize, page_type: &PageType, local: &[u8], payload_len: usize) -> Option<Vec<u8>> {
+        let db = self.db;
+        let usable = db.get_usable_size() as usize;
+        let n = overflow::local_len(page_type, payload_len, usable as u32);
+        let mut payload = local[..n].to_vec();
+        if n == payload_len {
+            return Some(payload);
+        }
+        let from = format!("Page {} cell {}", pn, i);
+        let needed = (payload_len - n).div_ceil(usable - 4);
+        let mut next = BigEndian::read_u32(&local[n..]) as PageNum;
+        for k in 0..needed {
+            if next == 0 {
+                self.problem(format!("{}: overflow chain has {} pages, but its payload needs {}", from, k, needed));
+                return None;
+            }
+            if !self.reach(next, &from) {
+                return None;
+            }
+            let page = match db.get_page_ro(next) {
+                Ok(p) => p,
+                Err(e) => {
+                    self.problem(format!("{}: overflow page {}: {}", from, next, e));
+                    return None;
+                }
+            };
+            let take = (payload_len - payload.len()).min(usable - 4);
+            payload.extend_from_slice(&page[4..4 + take]);
+            next = BigEndian::read_u32(&page[0..]) as PageNum;
+        }
+        if next != 0 {
+            self.problem(format!("{}: overflow chain is longer than the {} pages its payload needs", from, needed));
+        }
+        Some(payload)
+    }
+
+    /// checks that the record of cell `i` of page `pn` decodes as a row with `column_types`.
+    fn check_row(&mut self, pn: PageNum, i: usize, record: &[u8], column_types: &Vec<SqlType>) {
+        let result = record_problem(record).map_or_else(
+            || crate::typed_row::from_serialized(column_types, record, self.db.text_encoding()).map(|_| ()).map_err(|e| e.to_string()),
+            Err,
+        );
+        if let Err(e) = result {
+            self.problem(format!("Page {} cell {}: {}", pn, i, e));
+        }
+    }
+
+    /// returns the values of the index entry record of cell `i` of page `pn`, if it decodes.
+    fn decode_entry(&mut self, pn: PageNum, i: usize, record: &[u8]) -> Option<Vec<SqlValue>> {
+        let result = match record_problem(record) {
+            Some(e) => Err(e),
+            None => crate::record::ValueIterator::new(record)
+                .map(|value| {
+                    let (serty, bytes) = value.map_err(|e| e.to_string())?;
+                    crate::serial_type::to_sql_value(&serty, bytes, self.db.text_encoding()).map_err(|e| e.to_string())
+                })
+                .collect::<Result<Vec<SqlValue>, _>>(),
+        };
+        match result {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                self.problem(format!("Page {} cell {}: {}", pn, i, e));
+                None
+            }
+        }
+    }
+
+    /// returns true if `key` is greater than `lower`, and less than `upper`, or, in table btrees, equal to it.
+    fn in_order(&self, lower: Option<&Key>, key: &Key, upper: Option<&Key>, table: bool) -> bool {
+        let above_lower = lower.is_none_or(|l| self.compare(key, l) == Ordering::Greater);
+        let below_upper = upper.is_none_or(|u| match self.compare(key, u) {
+            Ordering::Less => true,
+            Ordering::Equal => table,
+            Ordering::Greater => false,
+        });
+        above_lower && below_upper
+    }
+
+    fn compare(&self, a: &Key, b: &Key) -> Ordering {
+        match (a, b) {
+            (Key::Rowid(a), Key::Rowid(b)) => a.cmp(b),
+            (Key::Entry(a), Key::Entry(b)) => super::index::compare_prefix(a, b, self.db.text_encoding()),
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+fn tree_kind(tree: &Tree) -> &'static str {
+    match tree.table {
+        true => "table",
+        false => "index",
+    }
+}
+
+/// returns a description of what is wrong with the format of `record`, if anything: its header must hold valid
+/// serial types, whose values fill the rest of the record exactly.
+fn record_problem(record: &[u8]) -> Option<String> {
+    let (header_len, mut off) = sqlite_varint::read_varint(record);
+    if header_len < off as i64 || header_len as usize > record.len() {
+        return Some(format!("record header length {} does not fit in a record of {} bytes", header_len, record.len()));
+    }
+    let mut values_len = 0;
+    while off < header_len as usize {
+        let (serial_type, n) = sqlite_varint::read_varint(&record[off..]);
+        values_len += match crate::serial_type::serialized_size(serial_type) {
+            Ok(len) => len,
+            Err(_) => return Some(format!("record has invalid serial type {}", serial_type)),
+        };
+        off += n;
+    }
+    if off != header_len as usize || header_len as usize + values_len != record.len() {
+        return Some(format!("record of {} bytes does not match its header", record.len()));
+    }
+    None
+}
+
+#[cfg(test)]
+fn check_copy(filename: &str, test: &str, corrupt: impl Fn(&mut StoredDb)) -> Vec<String> {
+    let path = crate::stored_db::copy_of_testdata(filename, test);
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    corrupt(&mut db);
+    db.integrity_check()
+}
+
+#[test]
+fn test_check_finds_problems() {
+    use super::node::Node;
+    let db_file = "multipage-512B-page.db";
+    assert_eq!(check_copy(db_file, "check_finds_problems", |_| ()), Vec::<String>::new());
+    let root_of = |db: &StoredDb| db.get_root_pagenum("thousandrows").unwrap();
+
+    // Children in the wrong order have keys out of order.
+    let problems = check_copy(db_file, "check_finds_problems", |db| {
+        let root = root_of(db);
+        let mut n = Node::read(db, root).unwrap();
+        let (a, b) = (n.child(0), n.child(1));
+        n.set_child(0, b);
+        n.set_child(1, a);
+        n.write(db, root).unwrap();
+    });
+    assert!(problems.iter().any(|p| p.contains("out of order")), "{:?}", problems);
+
+    // A page used twice leaves another page unused.
+    let problems = check_copy(db_file, "check_finds_problems", |db| {
+        let root = root_of(db);
+        let mut n = Node::read(db, root).unwrap();
+        let child = n.child(0);
+        n.set_child(1, child);
+        n.write(db, root).unwrap();
+    });
+    assert!(problems.iter().any(|p| p.contains("is already used elsewhere")), "{:?}", problems);
+    assert!(problems.iter().any(|p| p.contains("is never used")), "{:?}", problems);
+
+    // A page that is not a btree page.
+    let problems = check_copy(db_file, "check_finds_problems", |db| {
+        let child = Node::read(db, root_of(db)).unwrap().child(0);
+        db.get_page_rw(child).unwrap()[0] = 0x07;
+    });
+    assert!(problems.iter().any(|p| p.contains("0x07 is not a btree page type")), "{:?}", problems);
+
+    // A cell pointer beyond the end of the page.
+    let problems = check_copy(db_file, "check_finds_problems", |db| {
+        let child = Node::read(db, root_of(db)).unwrap().child(0);
+        BigEndian::write_u16(&mut db.get_page_rw(child).unwrap()[12..], 600);
+    });
+    assert!(problems.iter().any(|p| p.contains("outside the cell content area")), "{:?}", problems);
+
+    // A record with an invalid serial type.
+    let problems = check_copy(db_file, "check_finds_problems", |db| {
+        let (_, pn, mut n) = node::find_table_leaf(db, root_of(db), 1).unwrap();
+        // The cell is the payload length, the rowid, and the record, whose header starts with its length.
+        n.cells[0][3] = 10;
+        n.write(db, pn).unwrap();
+    });
+    assert!(problems.iter().any(|p| p.contains("invalid serial type 10")), "{:?}", problems);
+
+    // A freelist count that does not match the freelist.
+    let problems = check_copy(db_file, "check_finds_problems", |db| {
+        crate::dbheader::set_freelist(db.get_page_rw(1).unwrap(), 0, 3);
+    });
+    assert_eq!(problems, vec!["Freelist has 0 pages, but the header says it has 3".to_string()]);
+
+    // An overflow chain that ends too soon.
+    let problems = check_copy("overflow.db", "check_finds_problems", |db| {
+        let root = db.get_root_pagenum("t").unwrap();
+        let (_, pn, n) = node::find_table_leaf(db, root, 2).unwrap();
+        let c = &n.cells[node::table_cell_position(&n, 2)];
+        let (payload_len, len) = sqlite_varint::read_varint(c);
+        let len = len + sqlite_varint::read_varint(&c[len..]).1;
+        let local = overflow::local_len(&PageType::TableLeaf, payload_len as usize, db.get_usable_size());
+        let first = BigEndian::read_u32(&c[len + local..]) as PageNum;
+        assert_ne!(first, pn);
+        BigEndian::write_u32(&mut db.get_page_rw(first).unwrap()[0..], 0);
+    });
+    assert!(problems.iter().any(|p| p.contains("overflow chain has 1 pages")), "{:?}", problems);
+    assert!(problems.iter().any(|p| p.contains("is never used")), "{:?}", problems);
+}
+
+#[test]
+fn test_check_stops_at_max_depth() {
+    use super::node::Node;
+    // The root's first child is replaced by a chain of 50,000 interior pages, each the only child of the last.
+    let problems = check_copy("multipage-512B-page.db", "check_stops_at_max_depth", |db| {
+        let root = db.get_root_pagenum("thousandrows").unwrap();
+        let mut n = Node::read(db, root).unwrap();
+        let mut next = n.child(0);
+        for _ in 0..50000 {
+            let pn = db.alloc_new_page().unwrap();
+            let link = Node { page_type: PageType::TableInterior, cells: vec![], rightmost_pointer: Some(next) };
+            link.write(db, pn).unwrap();
+            next = pn;
+        }
+        n.set_child(0, next);
+        n.write(db, root).unwrap();
+    });
+    assert!(problems.iter().any(|p| p.contains(&format!("btree has more than {} levels", MAX_DEPTH))), "{:?}", problems);
+}
+
+#[test]
+fn test_check_reads_48_bit_ints() {
+    // SQLite stores integers that need 6 bytes with serial type 5, which this crate does not write itself.
+    let problems = check_copy("added_columns.db", "check_reads_48_bit_ints", |db| {
+        let root = db.get_root_pagenum("t").unwrap();
+        let (_, pn, mut n) = node::find_table_leaf(db, root, 1).unwrap();
+        let mut record = vec![2, 5];
+        record.extend(&1_700_000_000_000_i64.to_be_bytes()[2..]);
+        let pos = node::table_cell_position(&n, 1);
+        n.cells[pos] = node::table_leaf_cell(db, 1, &record).unwrap();
+        n.write(db, pn).unwrap();
+        let (_, _, n) = node::find_table_leaf(db, root, 1).unwrap();
+        let value = crate::record::ValueIterator::new(&n.cells[pos][2..]).next().unwrap().unwrap();
+        assert_eq!(crate::serial_type::to_sql_value(&value.0, value.1, db.text_encoding()).unwrap(), SqlValue::Int(1_700_000_000_000));
+    });
+    assert_eq!(problems, Vec::<String>::new());
+}
@@ -0,0 +1,133 @@
+//! delete removes rows from table btrees, merging pages that become too empty.
+//!
+//! A row's cell is removed from its leaf.  When that leaves a page less than half full, it is merged with a sibling:
+//! - If the two fit on one page, the left page gets the cells of both, and the right page goes on the freelist.  For
+//!   interior pages, the parent's cell between them moves down to join them.  The parent loses a cell, and may in
+//!   turn be merged with one of its siblings.
+//! - Otherwise, the cells of the two pages are shared evenly between them, and the parent's key for the left page is
+//!   changed.
+//!
+//! When the root is left as an interior page with no cells, the contents of its only child are moved up into it, so
+//! that the tree gets shorter while its root page number stays the same.
+
+use anyhow::Result;
+
+use super::insert;
+use super::node::{self, Node};
+use super::{PageType, RowId};
+use crate::stored_db::{PageNum, StoredDb};
+
+/// deletes the row with `rowid` from the table btree rooted at `root`, returning false if there was no such row.
+pub fn delete(db: &mut StoredDb, root: PageNum, rowid: RowId) -> Result<bool> {
+    let (mut path, mut pgnum, mut n) = node::find_table_leaf(db, root, rowid)?;
+    let pos = node::table_cell_position(&n, rowid);
+    if n.cells.get(pos).is_none_or(|c| node::table_cell_rowid(&n.page_type, c) != rowid) {
+        return Ok(false);
+    }
+    node::free_table_leaf_overflow(db, &n.cells.remove(pos))?;
+
+    let usable_size = db.get_usable_size();
+    while let Some(&(parent_pgnum, idx)) = path.last() {
+        if n.used_bytes(node::btree_start_offset(pgnum)) >= usable_size as usize / 2 {
+            break;
+        }
+        let mut parent = Node::read(db, parent_pgnum)?;
+        if parent.cells.is_empty() {
+            // The root can be left with only one child when that child is too big to move up into it.
+            break;
+        }
+        // Merge with the left sibling, or with the right one for the leftmost child.
+        let li = idx.saturating_sub(1);
+        let (lpg, rpg) = (parent.child(li), parent.child(li + 1));
+        let (left, right) = match idx {
+            0 => (n, Node::read(db, rpg)?),
+            _ => (Node::read(db, lpg)?, n),
+        };
+        let key = node::table_cell_rowid(&parent.page_type, &parent.cells[li]);
+        let merged = merge(left, key, right);
+        path.pop();
+        if merged.fits(node::btree_start_offset(lpg), usable_size) {
+            merged.write(db, lpg)?;
+            db.free_page(rpg)?;
+            parent.cells.remove(li);
+            parent.set_child(li, lpg);
+            (pgnum, n) = (parent_pgnum, parent);
+            continue;
+        }
+        let (left, key, right) = insert::split(merged, false, usable_size);
+        left.write(db, lpg)?;
+        right.write(db, rpg)?;
+        // The new key may be longer than the old one, so the parent may need to be split.
+        parent.cells[li] = node::table_interior_cell(lpg, key);
+        insert::write_splitting(db, path, parent_pgnum, parent, false)?;
+        return Ok(true);
+    }
+
+    while pgnum == root && n.cells.is_empty() && matches!(n.page_type, PageType::TableInterior) {
+        let child = n.child(0);
+        let c = Node::read(db, child)?;
+        if !c.fits(node::btree_start_offset(root), usable_size) {
+            break;
+        }
+        db.free_page(child)?;
+        n = c;
+    }
+    n.write(db, pgnum)?;
+    Ok(true)
+}
+
+/// joins two adjacent nodes of a table btree, where `key` is the parent's key for the left one.
+fn merge(left: Node, key: RowId, right: Node) -> Node {
+    let mut cells = left.cells;
+    if let Some(p) = left.rightmost_pointer {
+        cells.push(node::table_interior_cell(p, key));
+    }
+    cells.extend(right.cells);
+    Node { page_type: right.page_type, cells, rightmost_pointer: right.rightmost_pointer }
+}
+
+#[test]
+fn test_delete_merges_pages() {
+    use super::insert::insert;
+    use crate::stored_db::copy_of_testdata;
+    // The table has 1000 rows on 512 byte pages, so the btree has three levels.
+    let path = copy_of_testdata("multipage-512B-page.db", "delete");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    let root = db.get_root_pagenum("thousandrows").expect("Should have found table.");
+    let numpages = db.get_numpages();
+    let rows_of = |db: &StoredDb| -> Vec<(RowId, Vec<u8>)> {
+        super::table::Iterator::new(root, db).collect::<Result<_, _>>().unwrap()
+    };
+    let before = rows_of(&db);
+
+    // Delete most rows, from the middle out and at scattered positions, so that pages merge in both directions.
+    let deleted = |rowid: RowId| !(rowid % 7 == 0 || rowid > 990);
+    for rowid in (1..=500).rev().chain(501..=1000).filter(|rowid| deleted(*rowid)) {
+        assert!(delete(&mut db, root, rowid).unwrap());
+    }
+    assert!(!delete(&mut db, root, 3).unwrap());
+    assert!(!delete(&mut db, root, 5000).unwrap());
+    db.commit().expect("Should have written db.");
+    let expected: Vec<(RowId, Vec<u8>)> = before.iter().filter(|(rowid, _)| !deleted(*rowid)).cloned().collect();
+    assert_eq!(rows_of(&db), expected);
+    let (_, freelist_len) = crate::dbheader::freelist(&db.get_page_ro(1).unwrap());
+    assert!(freelist_len > 10);
+
+    // Freed pages are used again before the file grows.
+    let record = [vec![2, 13 + 2 * 30], vec![b'x'; 30]].concat();
+    for rowid in 2000..2100 {
+        insert(&mut db, root, rowid, &record).unwrap();
+    }
+    db.commit().expect("Should have written db.");
+    assert_eq!(db.get_numpages(), numpages);
+
+    // Deleting every row leaves an empty leaf at the root.
+    let db_rows: Vec<RowId> = rows_of(&db).iter().map(|(rowid, _)| *rowid).collect();
+    for rowid in db_rows {
+        assert!(delete(&mut db, root, rowid).unwrap());
+    }
+    db.commit().expect("Should have written db.");
+    let db = StoredDb::open(&path).expect("Should have reopened db.");
+    assert!(rows_of(&db).is_empty());
+    assert!(matches!(Node::read(&db, root).unwrap().page_type, PageType::TableLeaf));
+}
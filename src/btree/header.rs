@@ -8,8 +8,9 @@
 //! 6. The reserved region.  (hope to assume always 0)
 
 use super::PageType;
+use crate::stored_db::{Error, PageNum};
 use byteorder::{BigEndian, ReadBytesExt};
-use std::io::{Cursor, Seek, SeekFrom};
+use std::io::Cursor;
 
 // The database file header.
 #[derive(Debug, Clone)]
@@ -21,57 +22,57 @@ pub struct Header {
     pub rightmost_pointer: Option<u32>,
 }
 
-pub fn check_header(page: &'_ Vec<u8>, non_btree_header_bytes: usize) -> Header {
+/// returns the header of btree page `pgnum`, whose btree header follows `non_btree_header_bytes` bytes of other
+/// headers.
+pub fn check_header(page: &[u8], pgnum: PageNum, non_btree_header_bytes: usize) -> Result<Header, Error> {
     //The 8 or 12 byte b-tree page (currently just the header).
     let mut c = Cursor::new(page);
     // The first page has a header which is not btree content, but which is included in cell pointers.
-    if non_btree_header_bytes > 0 {
-        c.seek(SeekFrom::Current(non_btree_header_bytes as i64))
-            .expect("Should have seeked past db file header.");
-    }
+    c.set_position(non_btree_header_bytes as u64);
+    let truncated = |_| super::corrupt(pgnum, non_btree_header_bytes, String::from("page is too short for a btree header"));
     // Read btree header.
 
     // Offset	Size	Description
     // 0	1	The one-byte flag at offset 0 indicating the b-tree page type.
-    let btree_page_type = match c.read_u8().expect("Should have read btree header") {
+    let btree_page_type = match c.read_u8().map_err(truncated)? {
         0x02 => PageType::IndexInterior,
         0x05 => PageType::TableInterior,
         0x0a => PageType::IndexLeaf,
         0x0d => PageType::TableLeaf,
-        b => panic!("Invalid Btree Page Type: {}", b),
+        b => return Err(super::corrupt(pgnum, non_btree_header_bytes, format!("{:#04x} is not a btree page type", b))),
     };
 
     // 1	2	The two-byte integer at offset 1 gives the start of the first freeblock on the page, or is zero if there are no freeblocks.
-    let freeblock_start: u32 = c.read_u16::<BigEndian>().expect("Should have btree header") as u32;
+    let freeblock_start: u32 = c.read_u16::<BigEndian>().map_err(truncated)? as u32;
     // 3	2	The two-byte integer at offset 3 gives the number of cells on the page.
     let num_cells: u32 = c
         .read_u16::<BigEndian>()
-        .expect("Should have read btree header") as u32;
+        .map_err(truncated)? as u32;
     // 5	2	The two-byte integer at offset 5 designates the start of the cell content area. A zero value for this integer is interpreted as 65536.
     let cell_content_start: u32 = match c
         .read_u16::<BigEndian>()
-        .expect("Should have read btree header")
+        .map_err(truncated)?
     {
-        0 => 655365,
+        0 => 65536,
         x => x as u32,
     };
     // 7	1	The one-byte integer at offset 7 gives the number of fragmented free bytes within the cell content area.
-    let _: u32 = c.read_u8().expect("Should have read btree header") as u32;
+    let _: u32 = c.read_u8().map_err(truncated)? as u32;
     // 8	4	The four-byte page number at offset 8 is the right-most pointer. This value appears in the header of interior b-tree pages only and is omitted from all other pages.
 
     let rightmost_pointer = match btree_page_type {
         PageType::IndexInterior | PageType::TableInterior => Some(
             c.read_u32::<BigEndian>()
-                .expect("Should have read rightmost pointer"),
+                .map_err(truncated)?,
         ),
         PageType::IndexLeaf | PageType::TableLeaf => None,
     };
 
-    Header {
+    Ok(Header {
         btree_page_type,
         freeblock_start,
         num_cells,
         cell_content_start,
         rightmost_pointer,
-    }
+    })
 }
@@ -0,0 +1,241 @@
+//! index provides iterators over Index type btrees.
+//!
+//! Each entry of an index is a record holding the values of the indexed columns, followed by the rowid of the table
+//! row that they came from.  Entries are kept in order of their values, compared as by `sql_value::collate`.
+//!
+//! Unlike in a table btree, the interior pages of an index btree hold entries too.  The entry in a cell of an interior
+//! page comes after all the entries in the cell's left child, and before all the entries in the next child.
+
+use std::cmp::Ordering;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use super::{cell, overflow, PageType, MAX_DEPTH};
+use crate::sql_value::{collate_in, SqlValue};
+use crate::stored_db::{Error, PageNum, TextEncoding};
+
+/// holds the cells of one page of an index btree, and the position of an in-order traversal of them.
+struct PageCursor {
+    /// the left child page number, for interior pages, and the entry, of each cell.
+    cells: Vec<(Option<PageNum>, Vec<SqlValue>)>,
+    rightmost_pointer: Option<PageNum>,
+    /// the cell whose entry is returned next, or `cells.len()` once only the rightmost child is left.
+    pos: usize,
+    /// whether the child to the left of cell `pos` has been visited.
+    descended: bool,
+}
+
+impl PageCursor {
+    /// reads the cells of page `pgnum`, returning an error if the page is not an index page, or if any of its child
+    /// page numbers or entries are not valid.
+    fn new(pager: &crate::stored_db::StoredDb, pgnum: PageNum) -> Result<PageCursor, Error> {
+        let btree_start_offset = match pgnum {
+            1 => 100,
+            _ => 0,
+        };
+        let page = pager.get_page_ro(pgnum)?;
+        let hdr = super::header::check_header(&page, pgnum, btree_start_offset)?;
+        let interior = match hdr.btree_page_type {
+            PageType::IndexInterior => true,
+            PageType::IndexLeaf => false,
+            PageType::TableInterior | PageType::TableLeaf => {
+                return Err(super::corrupt(pgnum, btree_start_offset, String::from("table page in an index btree")));
+            }
+        };
+        let check_child = |child: u32, offset: usize| match child == 0 || child as PageNum > pager.get_numpages() {
+            true => Err(super::corrupt(pgnum, offset, format!("child page {} is not a page of the database", child))),
+            false => Ok(child as PageNum),
+        };
+        let mut cells = vec![];
+        for r in cell::ranges(&page, pgnum, btree_start_offset, pager.get_usable_size())? {
+            // Cells are at least 4 bytes long.
+            let cell = &page[r.clone()];
+            // Index B-Tree Interior Cell (header 0x02):  A 4-byte big-endian left child page number, then the same
+            // fields as an Index B-Tree Leaf Cell (header 0x0a): a varint which is the number of bytes of payload,
+            // then the payload.
+            let (left_child, rest_offset) = match interior {
+                true => (Some(check_child(BigEndian::read_u32(cell), r.start)?), 4),
+                false => (None, 0),
+            };
+            let rest = &cell[rest_offset..];
+            let (payload_len, bytesread) = sqlite_varint::read_varint(rest);
+            let payload = overflow::read_payload(
+                pager,
+                pgnum,
+                r.start + rest_offset + bytesread,
+                &hdr.btree_page_type,
+                &rest[bytesread..],
+                payload_len as usize,
+            )?;
+            let entry = decode_entry(&payload, pager.text_encoding()).map_err(|e| super::corrupt(pgnum, r.start, e))?;
+            cells.push((left_child, entry));
+        }
+        Ok(PageCursor {
+            cells,
+            rightmost_pointer: hdr.rightmost_pointer.map(|p| check_child(p, btree_start_offset + 8)).transpose()?,
+            pos: 0,
+            descended: false,
+        })
+    }
+
+    /// returns the child to the left of cell `pos`, which is the rightmost child when `pos` is past the last cell.
+    fn child(&self, pos: usize) -> Option<PageNum> {
+        match self.cells.get(pos) {
+            Some((left_child, _)) => *left_child,
+            None => self.rightmost_pointer,
+        }
+    }
+}
+
+/// decodes the values of an index entry record, whose text is in `encoding`, or returns what is wrong with it.  The
+/// last value is the rowid.
+fn decode_entry(record: &[u8], encoding: TextEncoding) -> Result<Vec<SqlValue>, String> {
+    let entry = crate::record::ValueIterator::new(record)
+        .map(|value| {
+            let (serty, bytes) = value.map_err(|e| e.to_string())?;
+            crate::serial_type::to_sql_value(&serty, bytes, encoding).map_err(|e| e.to_string())
+        })
+        .collect::<Result<Vec<SqlValue>, String>>()?;
+    match entry.last() {
+        Some(SqlValue::Int(_)) => Ok(entry),
+        _ => Err(String::from("index entry does not end with a rowid")),
+    }
+}
+
+/// compares the first `key.len()` values of an index entry with `key`, where text is ordered as in a database in
+/// `encoding`.
+pub fn compare_prefix(entry: &[SqlValue], key: &[SqlValue], encoding: TextEncoding) -> Ordering {
+    entry
+        .iter()
+        .zip(key)
+        .map(|(a, b)| collate_in(a, b, encoding))
+        .find(|o| *o != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+/// iterates over the entries of an index btree, in order.
+///
+/// Each item is the values of the indexed columns of one row, followed by its rowid.  If a page of the btree is
+/// corrupt, the iterator produces an error, and then ends.
+pub struct Iterator<'p> {
+    pager: &'p crate::stored_db::StoredDb,
+    stack: Vec<PageCursor>,
+}
+
+impl<'p> Iterator<'p> {
+    /// Creates an iterator over the entries of an Index-typed btree, starting at the first entry which is not less
+    /// than `key`.
+    ///
+    /// `key` may have fewer values than the entries, in which case only the first `key.len()` values of each entry
+    /// are compared with it.  An empty `key` starts at the first entry.  Only the pages on the path to the first entry
+    /// are visited to find it, and an error is returned if any of them is corrupt.
+    ///
+    /// # Arguments
+    ///
+    /// * `root_page` - The root page of the btree.
+    /// * `pager`     - A pager for the file that holds this btree.
+    pub fn new_from(root_page: PageNum, pager: &'p crate::stored_db::StoredDb, key: &[SqlValue]) -> Result<Iterator<'p>, Error> {
+        let mut it = Iterator { pager, stack: vec![] };
+        it.seek(root_page, key)?;
+        Ok(it)
+    }
+
+    /// Descends from `starting_page` to the first entry not less than `key`, pushing a cursor for each page on the way
+    /// onto the stack.
+    fn seek(&mut self, starting_page: PageNum, key: &[SqlValue]) -> Result<(), Error> {
+        let mut next_page = Some(starting_page);
+        while let Some(pgnum) = next_page {
+            if self.stack.len() >= MAX_DEPTH {
+                return Err(super::corrupt(pgnum, 0, format!("btree has more than {} levels", MAX_DEPTH)));
+            }
+            let mut cursor = PageCursor::new(self.pager, pgnum)?;
+            let encoding = self.pager.text_encoding();
+            // The entries before this cell, and those in the children to their left, are all less than `key`.
+            cursor.pos = cursor
+                .cells
+                .iter()
+                .position(|(_, entry)| compare_prefix(entry, key, encoding) != Ordering::Less)
+                .unwrap_or(cursor.cells.len());
+            cursor.descended = true;
+            next_page = cursor.child(cursor.pos);
+            self.stack.push(cursor);
+        }
+        Ok(())
+    }
+}
+
+impl<'p> core::iter::Iterator for Iterator<'p> {
+    type Item = Result<Vec<SqlValue>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let top = self.stack.last_mut()?;
+            if !top.descended {
+                top.descended = true;
+                if let Some(child) = top.child(top.pos) {
+                    if let Err(e) = self.seek(child, &[]) {
+                        // The iteration ends after an error.
+                        self.stack.clear();
+                        return Some(Err(e));
+                    }
+                    continue;
+                }
+            }
+            if top.pos < top.cells.len() {
+                // Each entry is returned once, so it is moved out of the cursor.
+                let entry = std::mem::take(&mut top.cells[top.pos].1);
+                top.pos += 1;
+                top.descended = false;
+                return Some(Ok(entry));
+            }
+            // The page and all its children are done, so go back up to its parent.
+            self.stack.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+fn path_to_testdata(filename: &str) -> String {
+    std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set")
+        + "/resources/test/"
+        + filename
+}
+
+#[test]
+fn test_index_iterator_on_indexes_db() {
+    // The index is on (city, age) of 1000 rows, on 512 byte pages, so it has interior pages.
+    let path = path_to_testdata("indexes.db");
+    let db = crate::stored_db::StoredDb::open(path.as_str()).expect("Should have opened db.");
+    let index = db
+        .get_indexes("people")
+        .unwrap()
+        .into_iter()
+        .find(|i| i.name == "people_city_age")
+        .expect("Should have found index.");
+    let entries: Vec<Vec<SqlValue>> =
+        Iterator::new_from(index.root_pagenum, &db, &[]).unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(entries.len(), 1000);
+    for pair in entries.windows(2) {
+        assert_ne!(compare_prefix(&pair[0], &pair[1], TextEncoding::Utf8), Ordering::Greater);
+    }
+    let mut rowids: Vec<i64> = entries
+        .iter()
+        .map(|e| match e[2] {
+            SqlValue::Int(rowid) => rowid,
+            _ => panic!("Index entry should end with a rowid."),
+        })
+        .collect();
+    rowids.sort();
+    assert_eq!(rowids, (1..=1000).collect::<Vec<i64>>());
+
+    use SqlValue::*;
+    let first = |key: &[SqlValue]| Iterator::new_from(index.root_pagenum, &db, key).unwrap().next().map(Result::unwrap);
+    // NULLs come first.
+    assert_eq!(first(&[Text(String::from("Austin"))]), Some(vec![Text(String::from("Austin")), Null(), Int(50)]));
+    assert_eq!(
+        first(&[Text(String::from("Chicago")), Int(30)]),
+        Some(vec![Text(String::from("Chicago")), Int(34), Int(52)])
+    );
+    assert_eq!(first(&[Text(String::from("Zurich"))]), None);
+    assert_eq!(Iterator::new_from(index.root_pagenum, &db, &[Text(String::from("Eugene")), Int(80)]).unwrap().count(), 22);
+}
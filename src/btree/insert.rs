@@ -0,0 +1,242 @@
+//! insert adds rows to table btrees, splitting pages that become too full.
+//!
+//! A row is put in the leaf that holds the nearest rowids.  When a page has no room for a new cell, it is split:
+//! - The original page keeps the cells with the smaller keys, and a new page gets the rest.
+//! - The parent's pointer to the original page is changed to point to the new page, and a cell pointing to the
+//!   original page, whose key is the largest rowid left on it, is added to the parent before that pointer.
+//! - Adding that cell may in turn split the parent.
+//! - A leaf with a big cell among small ones, which cannot be split in two, is split in three instead, with the big
+//!   cell on a page of its own.
+//!
+//! When the root splits, its two halves are moved to two new pages, and the root becomes an interior page pointing
+//! to them, so that the root page number of the table never changes.
+
+use anyhow::{bail, Result};
+
+use super::node::{self, Node};
+use super::{PageType, RowId, MAX_DEPTH};
+use crate::stored_db::{PageNum, StoredDb};
+
+/// inserts a row with `rowid`, whose contents are the serialized `record`, into the table btree rooted at `root`.
+pub fn insert(db: &mut StoredDb, root: PageNum, rowid: RowId, record: &[u8]) -> Result<()> {
+    let (path, pgnum, mut n) = node::find_table_leaf(db, root, rowid)?;
+    let pos = node::table_cell_position(&n, rowid);
+    if n.cells.get(pos).is_some_and(|c| node::table_cell_rowid(&n.page_type, c) == rowid) {
+        bail!("A row with rowid {} already exists.", rowid);
+    }
+    n.cells.insert(pos, node::table_leaf_cell(db, rowid, record)?);
+    // Appending to the end of a leaf, as when rows get increasing rowids, moves just the new cell to the new page,
+    // which leaves the original page full.
+    let appended = pos + 1 == n.cells.len();
+    write_splitting(db, path, pgnum, n, appended)
+}
+
+/// writes node `n` to page `pgnum`, at the end of `path`, splitting it and then its ancestors as needed.
+pub(super) fn write_splitting(
+    db: &mut StoredDb,
+    mut path: node::Path,
+    mut pgnum: PageNum,
+    mut n: Node,
+    mut appended: bool,
+) -> Result<()> {
+    let usable_size = db.get_usable_size();
+    while !n.fits(node::btree_start_offset(pgnum), usable_size) {
+        let (lefts, right) = split_to_fit(n, appended, usable_size);
+        appended = false;
+        // The parent gets a cell for each node but the last, which takes the place of the original node.
+        let mut cells = vec![];
+        let r = db.alloc_new_page()?;
+        right.write(db, r)?;
+        match path.pop() {
+            None => {
+                // Splitting the root.
+                for (left, key) in lefts {
+                    let l = db.alloc_new_page()?;
+                    left.write(db, l)?;
+                    cells.push(node::table_interior_cell(l, key));
+                }
+                n = Node { page_type: PageType::TableInterior, cells, rightmost_pointer: Some(r) };
+            }
+            Some((parent, pos)) => {
+                // The first node stays on the original page.
+                for (i, (left, key)) in lefts.into_iter().enumerate() {
+                    let l = match i {
+                        0 => pgnum,
+                        _ => db.alloc_new_page()?,
+                    };
+                    left.write(db, l)?;
+                    cells.push(node::table_interior_cell(l, key));
+                }
+                n = Node::read(db, parent)?;
+                n.set_child(pos, r);
+                n.cells.splice(pos..pos, cells);
+                pgnum = parent;
+            }
+        }
+    }
+    n.write(db, pgnum)
+}
+
+/// splits a table btree node that is too big for a page into nodes that each fit on a page, returning the nodes but
+/// the last, each with the largest rowid in it, and the last node.
+///
+/// A node is split in two when it can be.  A leaf where a big cell is among small ones may not have a split point
+/// where both parts fit, and is split in three, with its largest cell alone in the middle.
+fn split_to_fit(n: Node, appended: bool, usable_size: u32) -> (Vec<(Node, RowId)>, Node) {
+    let (left, key, right) = split(n, appended, usable_size);
+    if left.fits(0, usable_size) && right.fits(0, usable_size) {
+        return (vec![(left, key)], right);
+    }
+    // The leaf was on one page before its largest cell was added or grew, and the largest cell is no smaller than
+    // the one that was added or grew, so the cells on either side of the largest cell fit on a page.
+    let mut before = left.cells;
+    before.extend(right.cells);
+    let largest = (0..before.len()).max_by_key(|i| before[*i].len()).unwrap_or(0);
+    let after = before.split_off(largest + 1);
+    let big = before.split_off(largest);
+    let leaf = |cells: Vec<Vec<u8>>| {
+        let key = cells.last().map_or(0, |c| node::table_cell_rowid(&PageType::TableLeaf, c));
+        (Node { page_type: PageType::TableLeaf, cells, rightmost_pointer: None }, key)
+    };
+    let mut lefts = vec![];
+    if !before.is_empty() {
+        lefts.push(leaf(before));
+    }
+    match after.is_empty() {
+        true => (lefts, leaf(big).0),
+        false => {
+            lefts.push(leaf(big));
+            (lefts, leaf(after).0)
+        }
+    }
+}
+
+/// splits a table btree node that is too big for a page into two nodes, returning the left node, the largest rowid
+/// in it, and the right node.
+///
+/// If `appended`, the last cell of a leaf was just added, and it alone goes to the right node.
+pub(super) fn split(n: Node, appended: bool, usable_size: u32) -> (Node, RowId, Node) {
+    let mut cells = n.cells;
+    match n.page_type {
+        PageType::TableLeaf => {
+            let at = match appended {
+                true => cells.len() - 1,
+                false => balanced_split_point(&cells, usable_size),
+            };
+            let right = cells.split_off(at);
+            let key = node::table_cell_rowid(&n.page_type, cells.last().expect("Split leaf should have cells."));
+            (
+                Node { page_type: PageType::TableLeaf, cells, rightmost_pointer: None },
+                key,
+                Node { page_type: PageType::TableLeaf, cells: right, rightmost_pointer: None },
+            )
+        }
+        _ => {
+            // The middle cell moves up to the parent: its left child becomes the rightmost child of the left node.
+            let mut right = cells.split_off(cells.len() / 2);
+            let middle = right.remove(0);
+            let key = node::table_cell_rowid(&n.page_type, &middle);
+            (
+                Node { page_type: n.page_type.clone(), cells, rightmost_pointer: Some(node::left_child(&middle)) },
+                key,
+                Node { page_type: n.page_type, cells: right, rightmost_pointer: n.rightmost_pointer },
+            )
+        }
+    }
+}
+
+/// returns the position to split leaf `cells` at, so that both parts fit on a page and they have about the same
+/// number of bytes.
+fn balanced_split_point(cells: &[Vec<u8>], usable_size: u32) -> usize {
+    let fits = |cells: &[Vec<u8>]| {
+        Node { page_type: PageType::TableLeaf, cells: cells.to_vec(), rightmost_pointer: None }.fits(0, usable_size)
+    };
+    let total: usize = cells.iter().map(|c| c.len()).sum();
+    let mut at = 1;
+    let mut left_bytes = cells[0].len();
+    while at < cells.len() - 1 && left_bytes + cells[at].len() <= total / 2 {
+        left_bytes += cells[at].len();
+        at += 1;
+    }
+    // Rounding to whole cells can leave the right part too big.
+    while !fits(&cells[at..]) && at < cells.len() - 1 {
+        at += 1;
+    }
+    at
+}
+
+/// allocates a page for the root of a new, empty table btree, and returns its page number.
+pub fn create_table_btree(db: &mut StoredDb) -> Result<PageNum> {
+    let root = db.alloc_new_page()?;
+    init_table_btree(db, root)?;
+    Ok(root)
+}
+
+/// makes page `pgnum` the root of an empty table btree.
+pub fn init_table_btree(db: &mut StoredDb, pgnum: PageNum) -> Result<()> {
+    Node { page_type: PageType::TableLeaf, cells: vec![], rightmost_pointer: None }.write(db, pgnum)
+}
+
+/// returns the largest rowid in the table btree rooted at `root`, or None if the table is empty.
+pub fn max_rowid(db: &StoredDb, root: PageNum) -> Result<Option<RowId>> {
+    let (mut pgnum, mut n) = (root, Node::read(db, root)?);
+    let mut depth = 1;
+    while let Some(p) = n.rightmost_pointer {
+        if depth >= MAX_DEPTH {
+            return Err(super::corrupt(pgnum, 0, format!("btree has more than {} levels", MAX_DEPTH)).into());
+        }
+        (pgnum, n, depth) = (p, Node::read(db, p)?, depth + 1);
+    }
+    Ok(n.cells.last().map(|c| node::table_cell_rowid(&n.page_type, c)))
+}
+
+#[test]
+fn test_insert_splits_pages() {
+    use crate::stored_db::copy_of_testdata;
+    // The table has rowids 1 to 10, on 512 byte pages, so inserting a few thousand rows splits interior pages too.
+    let path = copy_of_testdata("multipage-512B-page.db", "insert");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    let root = db.get_root_pagenum("letters").expect("Should have found table.");
+    assert_eq!(max_rowid(&db, root).unwrap(), Some(10));
+    // A record holding one text value, of a length that varies with `i`.
+    let record = |i: i64| {
+        let len = i.unsigned_abs() as usize % 40;
+        [vec![2, 13 + 2 * len as u8], vec![b'x'; len]].concat()
+    };
+    // Rows are appended, and then inserted before the existing rows, so that leaves are split both ways.
+    for i in 11..3000 {
+        insert(&mut db, root, i, &record(i)).unwrap();
+    }
+    for i in (-500..0).rev() {
+        insert(&mut db, root, i, &record(i)).unwrap();
+    }
+    assert!(insert(&mut db, root, 5, &record(5)).is_err());
+    assert_eq!(max_rowid(&db, root).unwrap(), Some(2999));
+    db.commit().expect("Should have written db.");
+
+    let db = StoredDb::open(&path).expect("Should have reopened db.");
+    let rows: Vec<(RowId, Vec<u8>)> =
+        super::table::Iterator::new(root, &db).collect::<Result<_, _>>().unwrap();
+    let expected: Vec<RowId> = (-500..0).chain(1..3000).collect();
+    assert_eq!(rows.iter().map(|(rowid, _)| *rowid).collect::<Vec<RowId>>(), expected);
+    assert!(rows.iter().filter(|(rowid, _)| *rowid > 10 || *rowid < 0).all(|(rowid, r)| *r == record(*rowid)));
+}
+
+#[test]
+fn test_insert_into_a_cycle_is_an_error() {
+    use crate::stored_db::copy_of_testdata;
+    // The rightmost child of the root is the root itself, so following it never reaches a leaf.
+    let path = copy_of_testdata("multipage-512B-page.db", "insert_into_a_cycle");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    let root = db.get_root_pagenum("thousandrows").expect("Should have found table.");
+    let mut n = Node::read(&db, root).unwrap();
+    n.rightmost_pointer = Some(root);
+    n.write(&mut db, root).unwrap();
+    let e = max_rowid(&db, root).unwrap_err();
+    assert!(e.to_string().contains("btree has more than"), "{}", e);
+    let e = insert(&mut db, root, 5000, &[2, 13]).unwrap_err();
+    assert!(e.to_string().contains("btree has more than"), "{}", e);
+    let table = db.get_table_info("thousandrows").unwrap();
+    let e = db.append_row(&table, &vec![crate::sql_value::SqlValue::Null(); 3]).unwrap_err();
+    assert!(e.to_string().contains("btree has more than"), "{}", e);
+}
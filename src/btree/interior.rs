@@ -1,41 +1,22 @@
-use byteorder::{BigEndian, ReadBytesExt};
-use std::io::Cursor;
+use byteorder::{BigEndian, ByteOrder};
+use std::ops::Range;
 
 use super::cell;
 use super::RowId;
-use crate::stored_db::PageNum;
-
-/// Iterator over the values and child pointers of a btree interior page.
-/// Intended for searching for a specific value or range.
-/// Produces a tuple (left_child_pagenum, value, right_child_pagenum).
-pub struct SearchIterator<'a> {
-    ci: cell::Iterator<'a>,
-    // TODO: implement this.
-    // note it is rarely possible for there to not be two child pointers on page 1.  but IIUC, there is always a rightmost, so there is always
-    // a right and a left to return.
-}
+use crate::stored_db::{Error, PageNum, PageRef};
 
 /// Iterator over the child pointers of a btree interior page.
-/// Intended for use in full scans.
-/// Produces child page numbers.
+/// Intended for use in full scans, and in range scans after a call to `seek`.
+/// Produces child page numbers, or an error for a child page number that is not a page of the database.
 pub struct ScanIterator<'a> {
-    ci: cell::Iterator<'a>,
+    pgnum: PageNum,
+    numpages: PageNum,
+    page: PageRef<'a>,
+    cells: std::vec::IntoIter<Range<usize>>,
     returned_rightmost: bool,
     rightmost_pointer: PageNum,
 }
 
-impl<'a> SearchIterator<'a> {
-    /// Creates an iterator over the cells of a single page of a btree, with page of type TableLeaf.
-    ///
-    /// # Arguments
-    ///
-    /// * `ci` - A cell iterator for the page. Borrowed for the lifetime of the iterator.  
-    #[allow(dead_code)] // Use to build lookup by rowid as part of using indexes.
-    pub fn new(ci: cell::Iterator) -> SearchIterator {
-        SearchIterator { ci }
-    }
-}
-
 impl<'a> ScanIterator<'a> {
 
     fn btree_start_offset(pgnum: usize) -> usize {
@@ -47,58 +28,68 @@ impl<'a> ScanIterator<'a> {
 
     /// Creates an iterator over the cells of a single page of a btree, with page of type TableLeaf.
     ///
-    /// # Arguments
-    ///
-    /// * `ci` - A cell iterator for the page. Borrowed for the lifetime of the iterator.
-    /// * `rmp` - The rightmost pointer for this page.
-    pub fn new(pager: &crate::stored_db::StoredDb, pgnum: usize) -> ScanIterator {
-        let page = pager.get_page_ro(pgnum).unwrap();
-        let hdr = super::header::check_header(page, Self::btree_start_offset(pgnum));
-
-        let ci = cell::Iterator::new(
-            page,
+    /// The page is kept in memory until the iterator is dropped.  An error is returned if the page cannot be read, or
+    /// is not an interior page, or its cell pointers are not valid.
+    pub fn new(pager: &crate::stored_db::StoredDb, pgnum: usize) -> Result<ScanIterator<'_>, Error> {
+        let page = pager.get_page_ro(pgnum)?;
+        let hdr = super::header::check_header(&page, pgnum, Self::btree_start_offset(pgnum))?;
+
+        let cells = cell::ranges(
+            &page,
+            pgnum,
             Self::btree_start_offset(pgnum),
-            pager.get_page_size()
-        );
-        ScanIterator {
-            ci,
+            pager.get_usable_size()
+        )?;
+        let rightmost_pointer = hdr.rightmost_pointer.ok_or_else(|| {
+            super::corrupt(pgnum, Self::btree_start_offset(pgnum), String::from("leaf page in place of an interior page"))
+        })?;
+        Ok(ScanIterator {
+            pgnum,
+            numpages: pager.get_numpages(),
+            page,
+            cells: cells.into_iter(),
             returned_rightmost: false,
-            rightmost_pointer: hdr.rightmost_pointer.expect("Interior pages should always have rightmost pointer.") as usize,
+            rightmost_pointer: rightmost_pointer as usize,
+        })
+    }
+
+    /// returns `child`, which was read from `offset` of the page, if it is a page of the database.
+    fn check_child(&self, child: PageNum, offset: usize) -> Result<PageNum, Error> {
+        match child == 0 || child > self.numpages {
+            true => Err(super::corrupt(self.pgnum, offset, format!("child page {} is not a page of the database", child))),
+            false => Ok(child),
         }
     }
-}
 
-impl<'a> core::iter::Iterator for SearchIterator<'a> {
-    type Item = (PageNum, RowId, PageNum);
+    /// returns the rightmost child.
+    fn rightmost(&mut self) -> Result<PageNum, Error> {
+        self.returned_rightmost = true;
+        self.check_child(self.rightmost_pointer, Self::btree_start_offset(self.pgnum) + 8)
+    }
 
-    /// Returns the next item, which is a tuple of (lc, v, rc), where
-    ///   `lc` is the page number of the left child.
-    ///   `v` is the row number (u64).
-    ///   `rc` is the page number of the right child.
-    ///   All values in page lc are less than or equal to v.
-    ///   All values in page rc are greater than v.
-    #[allow(dead_code)] // Use to build lookup by rowid as part of using indexes.
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.ci.next() {
-            None => {
-                unimplemented!();
-            }
-            Some(cell) => {
-                let mut c = Cursor::new(cell);
-                let _ = c
-                    .read_u32::<BigEndian>()
-                    .expect("Should have read left child page number.");
-                let (_, _) = sqlite_varint::read_varint(&cell[4..]);
-                unimplemented!();
+    /// Skips the children whose rows all have rowids less than `rowid`, and returns the page number of the child that
+    /// would hold `rowid`.  After that, `next` returns the children that follow it.
+    ///
+    /// Must be called before any call to `next`.
+    pub fn seek(&mut self, rowid: RowId) -> Result<PageNum, Error> {
+        while let Some(r) = self.cells.next() {
+            // Cells are at least 4 bytes long.
+            let cell = &self.page[r.clone()];
+            // Every rowid in the left child is less than or equal to the key of the cell.
+            let left_child_pagenum = BigEndian::read_u32(cell);
+            let (key, _) = sqlite_varint::read_varint(&cell[4..]);
+            if key as RowId >= rowid {
+                return self.check_child(left_child_pagenum as PageNum, r.start);
             }
         }
+        self.rightmost()
     }
 }
 
 impl<'a> core::iter::Iterator for ScanIterator<'a> {
     // The iterator returns a tuple of (rowid, cell_payload).
     // Overflowing payloads are not supported.
-    type Item = PageNum;
+    type Item = Result<PageNum, Error>;
 
     /// Returns the next item, which is a tuple of (k, v), where
     ///   `k` is a key, the row number (u64)
@@ -108,20 +99,15 @@ impl<'a> core::iter::Iterator for ScanIterator<'a> {
         if self.returned_rightmost {
             return None;
         }
-        match self.ci.next() {
-            None => {
-                self.returned_rightmost = true;
-                Some(self.rightmost_pointer)
-            }
-            Some(cell) => {
+        match self.cells.next() {
+            None => Some(self.rightmost()),
+            Some(r) => {
+                let cell = &self.page[r.clone()];
                 // Table B-Tree Interior Cell (header 0x05):
                 // A 4-byte big-endian page number which is the left child pointer.
                 // A varint which is the integer key.
-                let mut c = Cursor::new(cell);
-                let left_child_pagenum = c
-                    .read_u32::<BigEndian>()
-                    .expect("Should have read left child page number.");
-                Some(left_child_pagenum as crate::stored_db::PageNum)
+                let left_child_pagenum = BigEndian::read_u32(cell);
+                Some(self.check_child(left_child_pagenum as PageNum, r.start))
             }
         }
     }
@@ -164,19 +150,28 @@ fn test_interior_iterator_on_multipage_db() {
         1 => 100,
         _ => 0,
     };
-    let hdr = super::header::check_header(page, btree_start_offset);
+    let hdr = super::header::check_header(&page, pgnum, btree_start_offset).unwrap();
     println!("Examining page {} with header {:?}", pgnum, hdr);
 
     let mut ri: ScanIterator<'_> = match hdr.btree_page_type {
         btree::PageType::TableInterior => btree::interior::ScanIterator::new(
-            &pgr, pgnum),
+            &pgr, pgnum).unwrap(),
         _ => {
             unreachable!();
         }
     };
 
-    assert_eq!(ri.next(), Some(4));
-    assert_eq!(ri.next(), Some(5));
-    assert_eq!(ri.next(), Some(6));
-    assert_eq!(ri.next(), None);
+    assert_eq!(ri.next().map(Result::unwrap), Some(4));
+    assert_eq!(ri.next().map(Result::unwrap), Some(5));
+    assert_eq!(ri.next().map(Result::unwrap), Some(6));
+    assert!(ri.next().is_none());
+
+    // Rows 352-691 are on the second leaf page.
+    let mut si = btree::interior::ScanIterator::new(&pgr, pgnum).unwrap();
+    assert_eq!(si.seek(400).unwrap(), 5);
+    assert_eq!(si.next().map(Result::unwrap), Some(6));
+    assert!(si.next().is_none());
+    let mut si = btree::interior::ScanIterator::new(&pgr, pgnum).unwrap();
+    assert_eq!(si.seek(5000).unwrap(), 6);
+    assert!(si.next().is_none());
 }
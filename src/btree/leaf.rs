@@ -1,8 +1,14 @@
-use super::cell;
+use std::ops::Range;
+
+use super::{cell, overflow, PageType};
 use super::RowId;
+use crate::stored_db::{Error, PageNum, PageRef};
 
 pub struct Iterator<'a> {
-    ci: cell::Iterator<'a>,
+    pager: &'a crate::stored_db::StoredDb,
+    pgnum: PageNum,
+    page: PageRef<'a>,
+    cells: std::vec::IntoIter<Range<usize>>,
 }
 
 impl<'a> Iterator<'a> {
@@ -16,46 +22,49 @@ impl<'a> Iterator<'a> {
 
     /// Creates an iterator over the cells of a single page of a btree, with page of type TableLeaf.
     ///
-    /// Iterator produces cells which are slices of bytes, which contain a record.
-    ///
-    /// # Arguments
+    /// Iterator produces the payloads of the cells, which contain a record.
     ///
-    /// * `s` - A byte slice.  Borrowed for the lifetime of the iterator.  Slice begins with the record header length (a varint).
-    ///         slives ends with the last byte of the record body.
-    pub fn new(pager: &crate::stored_db::StoredDb, pgnum: usize) -> Iterator {
-        let page = pager.get_page_ro(pgnum).unwrap();
-        let ci = cell::Iterator::new(
-            page,
+    /// The page is kept in memory until the iterator is dropped.  An error is returned if the page cannot be read, or
+    /// its cell pointers are not valid.
+    pub fn new(pager: &crate::stored_db::StoredDb, pgnum: usize) -> Result<Iterator<'_>, Error> {
+        let page = pager.get_page_ro(pgnum)?;
+        let cells = cell::ranges(
+            &page,
+            pgnum,
             Self::btree_start_offset(pgnum),
-            pager.get_page_size()
-        );
-        Iterator { ci }
+            pager.get_usable_size()
+        )?;
+        Ok(Iterator { pager, pgnum, page, cells: cells.into_iter() })
     }
 }
 
 impl<'a> core::iter::Iterator for Iterator<'a> {
     // The iterator returns a tuple of (rowid, cell_payload).
-    // Overflowing payloads are not supported.
-    type Item = (RowId, &'a [u8]);
+    // Payloads that spill are read from their overflow pages.
+    type Item = Result<(RowId, Vec<u8>), Error>;
 
     /// Returns the next item, which is a tuple of (k, v), where
     ///   `k` is a key, the row number (u64)
-    ///   `v` is a value, &[u8].
+    ///   `v` is a value, a copy of the payload.
     fn next(&mut self) -> Option<Self::Item> {
-        match self.ci.next() {
+        match self.cells.next() {
             None => None,
-            Some(cell) => {
+            Some(r) => {
+                let cell = &self.page[r.clone()];
                 let mut offset = 0;
                 let (payload_len, bytesread) = sqlite_varint::read_varint(cell);
                 offset += bytesread;
                 let (rowid, bytesread2) = sqlite_varint::read_varint(&cell[offset..]);
                 offset += bytesread2;
-                if cell.len() - offset != (payload_len as usize) {
-                    unimplemented!("Spilled payloads not implemented.");
-                }
-                //let payload = &cell[offset..].to_vec();
-                //println!("payload bytes {:?}", &payload);
-                Some((rowid as RowId, &cell[offset..]))
+                let payload = overflow::read_payload(
+                    self.pager,
+                    self.pgnum,
+                    r.start + offset,
+                    &PageType::TableLeaf,
+                    &cell[offset..],
+                    payload_len as usize,
+                );
+                Some(payload.map(|payload| (rowid as RowId, payload)))
             }
         }
     }
@@ -82,19 +91,19 @@ fn test_leaf_iterator_on_minimal_db() {
             1 => 100,
             _ => 0,
         };
-        let hdr = crate::btree::header::check_header(page, btree_start_offset).btree_page_type;
+        let hdr = crate::btree::header::check_header(&page, pgnum, btree_start_offset).unwrap().btree_page_type;
         println!("Examining page {} with header {:?}", pgnum, hdr);
         hdr
     };
     let mut ri = match pgtype {
-        crate::btree::PageType::TableLeaf => crate::btree::leaf::Iterator::new(&pgr, pgnum),
+        crate::btree::PageType::TableLeaf => crate::btree::leaf::Iterator::new(&pgr, pgnum).unwrap(),
         _ => {
             unreachable!()
         }
     };
 
-    let first_item = ri.next().clone();
+    let first_item = ri.next();
     assert!(first_item.is_some());
-    assert_eq!(first_item.unwrap().0, 1);
+    assert_eq!(first_item.unwrap().unwrap().0, 1);
     assert!(ri.next().is_none());
 }
@@ -1,7 +1,10 @@
 //! Btree provides iterators over tables stored in SQLlite btrees.
-//! SQLlite btrees come in two types: Tables and Indexes.    Indexes are not implemented yet.
+//! SQLlite btrees come in two types: Tables and Indexes.
 //! Btree pages are either leaves or interior pages.
 //! Each of these 4 combinations has a different cell format.
+//!
+//! Reading a page whose contents are not valid returns `stored_db::Error::Corrupt`, with the page and the offset of
+//! the bad bytes, rather than panicking.
 #[derive(Debug, Clone)]
 pub enum PageType {
     IndexInterior,
@@ -11,17 +14,46 @@ pub enum PageType {
 }
 
 // SQLite row ids are 64b integers.
-type RowId = i64;
+pub type RowId = i64;
+
+/// the most levels that a btree is read to.  As in SQLite, a deeper btree is taken to be corrupt, which also stops
+/// loops of child pointers from being followed forever.
+const MAX_DEPTH: usize = 20;
+
+/// returns the error for the bytes at `offset` of btree page `page`, which are not valid.
+fn corrupt(page: crate::stored_db::PageNum, offset: usize, detail: String) -> crate::stored_db::Error {
+    crate::stored_db::Error::Corrupt { page, offset, detail }
+}
 
 /// module `header` defines types and methods for btree page headers.
 pub mod header;
 /// Organization of btree submodules and types:
 /// *  `pub table::Iterator` iterates over all the pages of one btree.
 /// *  `pub table::Iterator` uses either `leaf::Iterator` or `interior::ScanIterator` on a given page.
+/// *  `interior::ScanIterator::seek` finds the child page that holds a rowid, for iterators over a range of rowids.
 /// *  `leaf::Iterator` or `interior::ScanIterator`  use `cell::Iterator` to iterate over the cells on a page.
+/// *  `pub index::Iterator` iterates over the entries of one index btree, from a given key, using `cell::Iterator` on each page.
+/// *  `pub insert::insert` adds a row to a table btree, using `node::Node` to read and rewrite the pages it changes.
+/// *  `pub delete::delete` removes a row from a table btree, and `pub update::update` replaces one, also using `node::Node`.
+/// *  `overflow` reads and writes the parts of payloads that spill from cells onto overflow pages.
+/// *  `pub check::Checker` checks the pages of btrees, and the freelist, for `StoredDb::integrity_check`.
 
 /// module `table` defines iterators over btrees.
 pub mod table;
+/// module `index` defines iterators over index btrees.
+pub mod index;
+/// module `insert` adds rows to table btrees, splitting pages as needed.
+pub mod insert;
+/// module `delete` removes rows from table btrees, merging pages that become too empty.
+pub mod delete;
+/// module `update` replaces the contents of rows of table btrees, splitting pages as needed.
+pub mod update;
+/// module `check` verifies the structure of btrees and the freelist.
+pub mod check;
+// module `overflow` reads, writes and frees chains of overflow pages.
+mod overflow;
+// module `node` reads all the cells of a page, and writes a whole page, for changing btrees.
+mod node;
 // module `leaf` provides an interator over the cells of the leaf pages of a table btree.
 mod leaf;
 // module `interior` provides an interator over the cells of the interior pages of a table btree.
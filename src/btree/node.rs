@@ -0,0 +1,226 @@
+//! node reads all the cells of a btree page into memory, and writes a whole page from cells held in memory.
+//!
+//! Btrees are changed by reading a page's cells into a `Node`, changing the cells, and writing the whole page again.
+//! Since a page is written from scratch, it never has freeblocks or fragmented bytes, so free space on a page
+//! does not need to be tracked.
+
+use anyhow::{bail, Result};
+use byteorder::{BigEndian, ByteOrder};
+
+use super::{cell, overflow, PageType, RowId, MAX_DEPTH};
+use crate::stored_db::{PageNum, StoredDb};
+
+/// returns the offset of the btree page header, which follows the database file header on page 1.
+pub fn btree_start_offset(pgnum: PageNum) -> usize {
+    match pgnum {
+        1 => 100,
+        _ => 0,
+    }
+}
+
+/// holds the contents of one btree page.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub page_type: PageType,
+    /// the cells of the page, in key order.  Each cell is exactly as long as its contents.
+    pub cells: Vec<Vec<u8>>,
+    /// the right-most child, for interior pages.
+    pub rightmost_pointer: Option<PageNum>,
+}
+
+impl Node {
+    /// reads the cells of page `pgnum`.
+    pub fn read(db: &StoredDb, pgnum: PageNum) -> Result<Node> {
+        let page = db.get_page_ro(pgnum)?;
+        let hdr = super::header::check_header(&page, pgnum, btree_start_offset(pgnum))?;
+        let mut cells = vec![];
+        for (offset, c) in cell::Iterator::new(&page, pgnum, btree_start_offset(pgnum), db.get_usable_size())? {
+            let len = cell_len(&hdr.btree_page_type, c, db.get_usable_size())?;
+            if len > c.len() {
+                return Err(super::corrupt(pgnum, offset, String::from("cell overlaps the cell after it")).into());
+            }
+            cells.push(c[..len].to_vec());
+        }
+        Ok(Node {
+            page_type: hdr.btree_page_type,
+            cells,
+            rightmost_pointer: hdr.rightmost_pointer.map(|p| p as PageNum),
+        })
+    }
+
+    /// returns the number of bytes of a page, whose btree header starts at `btree_start_offset`, that the node uses.
+    pub fn used_bytes(&self, btree_start_offset: usize) -> usize {
+        btree_start_offset
+            + header_len(&self.page_type)
+            + 2 * self.cells.len()
+            + self.cells.iter().map(|c| c.len()).sum::<usize>()
+    }
+
+    /// returns true if the node fits on a page whose btree header starts at `btree_start_offset`.
+    pub fn fits(&self, btree_start_offset: usize, usable_size: u32) -> bool {
+        self.used_bytes(btree_start_offset) <= usable_size as usize
+    }
+
+    /// returns the child of an interior node to the left of cell `pos`, which is the rightmost child when `pos` is
+    /// past the last cell.
+    pub fn child(&self, pos: usize) -> PageNum {
+        match self.cells.get(pos) {
+            Some(c) => left_child(c),
+            None => self.rightmost_pointer.expect("Interior pages should have a rightmost pointer."),
+        }
+    }
+
+    /// changes the child of an interior node to the left of cell `pos`, or the rightmost child.
+    pub fn set_child(&mut self, pos: usize, pgnum: PageNum) {
+        match self.cells.get_mut(pos) {
+            Some(c) => set_left_child(c, pgnum),
+            None => self.rightmost_pointer = Some(pgnum),
+        }
+    }
+
+    /// writes the node to page `pgnum`, replacing what was there, except for the database file header on page 1.
+    pub fn write(&self, db: &mut StoredDb, pgnum: PageNum) -> Result<()> {
+        let usable_size = db.get_usable_size();
+        let start = btree_start_offset(pgnum);
+        if !self.fits(start, usable_size) {
+            bail!("Internal error: btree cells do not fit on page {}.", pgnum);
+        }
+        let page = db.get_page_rw(pgnum)?;
+        // The reserved bytes at the end of the page belong to extensions, so they are left as they are.
+        page[start..usable_size as usize].fill(0);
+        page[start] = match self.page_type {
+            PageType::IndexInterior => 0x02,
+            PageType::TableInterior => 0x05,
+            PageType::IndexLeaf => 0x0a,
+            PageType::TableLeaf => 0x0d,
+        };
+        // The first freeblock (offset 1) and the number of fragmented bytes (offset 7) are left zero.
+        BigEndian::write_u16(&mut page[start + 3..], self.cells.len() as u16);
+        if let Some(p) = self.rightmost_pointer {
+            BigEndian::write_u32(&mut page[start + 8..], p as u32);
+        }
+        // Cells are packed at the end of the page, with the first cell last.
+        let mut content_start = usable_size as usize;
+        let mut pointer = start + header_len(&self.page_type);
+        for c in &self.cells {
+            content_start -= c.len();
+            page[content_start..content_start + c.len()].copy_from_slice(c);
+            BigEndian::write_u16(&mut page[pointer..], content_start as u16);
+            pointer += 2;
+        }
+        // A cell content area that starts at 65536 is stored as zero.
+        BigEndian::write_u16(&mut page[start + 5..], content_start as u16);
+        Ok(())
+    }
+}
+
+/// the path from the root of a table btree to a leaf: each interior page on the way, and the position of the child
+/// pointer followed on it.
+pub type Path = Vec<(PageNum, usize)>;
+
+/// finds the leaf of the table btree rooted at `root` that holds, or would hold, `rowid`.
+///
+/// Returns the path to the leaf, the leaf's page number, and the leaf.
+pub fn find_table_leaf(db: &StoredDb, root: PageNum, rowid: RowId) -> Result<(Path, PageNum, Node)> {
+    let mut path: Path = vec![];
+    let mut pgnum = root;
+    let mut n = Node::read(db, pgnum)?;
+    while matches!(n.page_type, PageType::TableInterior) {
+        // A cell's key is the largest rowid in its left child.
+        let pos = table_cell_position(&n, rowid);
+        path.push((pgnum, pos));
+        if path.len() >= MAX_DEPTH {
+            return Err(super::corrupt(pgnum, 0, format!("btree has more than {} levels", MAX_DEPTH)).into());
+        }
+        pgnum = n.child(pos);
+        n = Node::read(db, pgnum)?;
+    }
+    if !matches!(n.page_type, PageType::TableLeaf) {
+        bail!("Page {} of a table btree is not a table page.", pgnum);
+    }
+    Ok((path, pgnum, n))
+}
+
+/// returns the position of the first cell of a table node whose rowid or key is not less than `rowid`.
+pub fn table_cell_position(n: &Node, rowid: RowId) -> usize {
+    n.cells.partition_point(|c| table_cell_rowid(&n.page_type, c) < rowid)
+}
+
+/// returns the length of the btree page header for pages of type `page_type`.
+fn header_len(page_type: &PageType) -> usize {
+    match page_type {
+        PageType::IndexInterior | PageType::TableInterior => 12,
+        PageType::IndexLeaf | PageType::TableLeaf => 8,
+    }
+}
+
+/// returns the length of the cell at the start of `bytes`, which comes from a page of type `page_type`.
+pub fn cell_len(page_type: &PageType, bytes: &[u8], usable_size: u32) -> Result<usize> {
+    // Interior cells start with the 4 byte page number of the left child.
+    let child_len = match page_type {
+        PageType::IndexInterior | PageType::TableInterior => 4,
+        PageType::IndexLeaf | PageType::TableLeaf => 0,
+    };
+    let rest = &bytes[child_len..];
+    let (first, mut n) = sqlite_varint::read_varint(rest);
+    match page_type {
+        // The varint is the key, and there is no payload.
+        PageType::TableInterior => return Ok(child_len + n),
+        // The payload length is followed by the rowid.
+        PageType::TableLeaf => n += sqlite_varint::read_varint(&rest[n..]).1,
+        PageType::IndexLeaf | PageType::IndexInterior => (),
+    }
+    // A payload that spills keeps only its first part in the cell, followed by the first overflow page number.
+    let payload_len = first as usize;
+    let local = overflow::local_len(page_type, payload_len, usable_size);
+    let overflow_pointer_len = match local < payload_len {
+        true => 4,
+        false => 0,
+    };
+    Ok(child_len + n + local + overflow_pointer_len)
+}
+
+/// builds a table leaf cell holding `record` for row `rowid`, writing the part of `record` that does not fit in the
+/// cell to overflow pages.
+pub fn table_leaf_cell(db: &mut StoredDb, rowid: RowId, record: &[u8]) -> Result<Vec<u8>> {
+    let mut c = sqlite_varint::serialize_to_varint(record.len() as i64);
+    c.extend(sqlite_varint::serialize_to_varint(rowid));
+    c.extend(overflow::spill(db, &PageType::TableLeaf, record)?);
+    Ok(c)
+}
+
+/// puts the overflow pages of the record of table leaf cell `c`, if it has any, on the freelist.
+pub fn free_table_leaf_overflow(db: &mut StoredDb, c: &[u8]) -> Result<()> {
+    let (payload_len, n) = sqlite_varint::read_varint(c);
+    let n = n + sqlite_varint::read_varint(&c[n..]).1;
+    overflow::free(db, &PageType::TableLeaf, &c[n..], payload_len as usize)
+}
+
+/// builds a table interior cell pointing to `left_child`, whose rowids are all no larger than `key`.
+pub fn table_interior_cell(left_child: PageNum, key: RowId) -> Vec<u8> {
+    let mut c = vec![0_u8; 4];
+    BigEndian::write_u32(&mut c, left_child as u32);
+    c.extend(sqlite_varint::serialize_to_varint(key));
+    c
+}
+
+/// returns the rowid of a table leaf cell, or the key of a table interior cell.
+pub fn table_cell_rowid(page_type: &PageType, c: &[u8]) -> RowId {
+    match page_type {
+        PageType::TableInterior => sqlite_varint::read_varint(&c[4..]).0,
+        _ => {
+            let (_, n) = sqlite_varint::read_varint(c);
+            sqlite_varint::read_varint(&c[n..]).0
+        }
+    }
+}
+
+/// returns the left child of an interior cell.
+pub fn left_child(c: &[u8]) -> PageNum {
+    BigEndian::read_u32(c) as PageNum
+}
+
+/// changes the left child of an interior cell.
+pub fn set_left_child(c: &mut [u8], pgnum: PageNum) {
+    BigEndian::write_u32(c, pgnum as u32)
+}
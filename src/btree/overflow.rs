@@ -0,0 +1,155 @@
+//! overflow reads and writes the parts of cell payloads that spill onto overflow pages.
+//!
+//! A payload that is too big to be kept whole in a cell keeps its first bytes in the cell, followed by the 4-byte page
+//! number of the first overflow page.  Each overflow page starts with the 4-byte page number of the next overflow page,
+//! or zero for the last one, and the rest of the page holds the next part of the payload.
+//!
+//! The number of bytes kept in the cell follows the rules at https://www.sqlite.org/fileformat.html#b_tree_pages:
+//! - A payload of up to `X` bytes is kept whole, where `X` is `U-35` for table leaves, and `((U-12)*64/255)-23` for
+//!   index pages, and `U` is the usable size of a page.
+//! - Otherwise, with `M = ((U-12)*32/255)-23` and `K = M+((P-M)%(U-4))` for a payload of `P` bytes, the cell keeps
+//!   `K` bytes if that is no more than `X`, and `M` bytes if not.  `K` is chosen so that the last overflow page is
+//!   full.
+
+use anyhow::Result;
+use byteorder::{BigEndian, ByteOrder};
+
+use super::{corrupt, PageType};
+use crate::stored_db::{Error, PageNum, StoredDb};
+
+/// returns the most payload that a cell can hold without spilling to overflow pages.
+pub fn max_local_payload(page_type: &PageType, usable_size: u32) -> usize {
+    let usable = usable_size as usize;
+    match page_type {
+        PageType::TableLeaf => usable - 35,
+        _ => (usable - 12) * 64 / 255 - 23,
+    }
+}
+
+/// returns the number of bytes of a payload of `payload_len` bytes that are kept in the cell.
+pub fn local_len(page_type: &PageType, payload_len: usize, usable_size: u32) -> usize {
+    let usable = usable_size as usize;
+    let max_local = max_local_payload(page_type, usable_size);
+    if payload_len <= max_local {
+        return payload_len;
+    }
+    let min_local = (usable - 12) * 32 / 255 - 23;
+    let k = min_local + (payload_len - min_local) % (usable - 4);
+    match k <= max_local {
+        true => k,
+        false => min_local,
+    }
+}
+
+/// returns the payload of `payload_len` bytes of a cell on a page of type `page_type`, where `local` starts with the
+/// part of the payload in the cell, followed by the overflow page number if the payload spills.  `local` is at
+/// `offset` of page `pgnum`, which errors refer to.
+///
+/// An error is returned if the cell is too short for the payload, or if the overflow pages do not hold the rest of it.
+pub fn read_payload(
+    db: &StoredDb,
+    pgnum: PageNum,
+    offset: usize,
+    page_type: &PageType,
+    local: &[u8],
+    payload_len: usize,
+) -> core::result::Result<Vec<u8>, Error> {
+    let usable_size = db.get_usable_size() as usize;
+    let n = local_len(page_type, payload_len, usable_size as u32);
+    if local.len() < n || (n < payload_len && local.len() < n + 4) {
+        return Err(corrupt(pgnum, offset, format!("cell is too short for its payload of {} bytes", payload_len)));
+    }
+    let mut payload = local[..n].to_vec();
+    if n == payload_len {
+        return Ok(payload);
+    }
+    // Each overflow page is a different page, so a payload that needs more pages than the database has is not read.
+    if (payload_len - n).div_ceil(usable_size - 4) > db.get_numpages() {
+        return Err(corrupt(pgnum, offset, format!("payload of {} bytes is bigger than the database", payload_len)));
+    }
+    // The page, and the offset on it, of the page number of the next overflow page.
+    let (mut from, mut from_offset) = (pgnum, offset + n);
+    let mut next = BigEndian::read_u32(&local[n..]) as PageNum;
+    while payload.len() < payload_len {
+        if next == 0 {
+            let detail = format!("overflow page chain ends before the end of a payload of {} bytes", payload_len);
+            return Err(corrupt(from, from_offset, detail));
+        }
+        if next > db.get_numpages() {
+            return Err(corrupt(from, from_offset, format!("overflow page {} is past the end of the database", next)));
+        }
+        let page = db.get_page_ro(next)?;
+        let take = (payload_len - payload.len()).min(usable_size - 4);
+        payload.extend_from_slice(&page[4..4 + take]);
+        (from, from_offset) = (next, 0);
+        next = BigEndian::read_u32(&page[0..]) as PageNum;
+    }
+    Ok(payload)
+}
+
+/// returns the part of `payload` that is kept in a cell on a page of type `page_type`, writing the rest, if any, to
+/// newly allocated overflow pages, whose first page number follows the local part.
+pub fn spill(db: &mut StoredDb, page_type: &PageType, payload: &[u8]) -> Result<Vec<u8>> {
+    let usable_size = db.get_usable_size();
+    let n = local_len(page_type, payload.len(), usable_size);
+    let mut local = payload[..n].to_vec();
+    if n == payload.len() {
+        return Ok(local);
+    }
+    let chunks: Vec<&[u8]> = payload[n..].chunks(usable_size as usize - 4).collect();
+    let pages = chunks.iter().map(|_| db.alloc_new_page()).collect::<Result<Vec<PageNum>, _>>()?;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let next = pages.get(i + 1).copied().unwrap_or(0);
+        let page = db.get_page_rw(pages[i])?;
+        BigEndian::write_u32(&mut page[0..], next as u32);
+        page[4..4 + chunk.len()].copy_from_slice(chunk);
+    }
+    local.extend_from_slice(&(pages[0] as u32).to_be_bytes());
+    Ok(local)
+}
+
+/// puts the overflow pages of a payload of `payload_len` bytes on the freelist, where `local` is as for
+/// `read_payload`.
+pub fn free(db: &mut StoredDb, page_type: &PageType, local: &[u8], payload_len: usize) -> Result<()> {
+    let usable_size = db.get_usable_size();
+    let n = local_len(page_type, payload_len, usable_size);
+    if n == payload_len {
+        return Ok(());
+    }
+    let mut next = BigEndian::read_u32(&local[n..]) as PageNum;
+    let mut left = payload_len - n;
+    while next != 0 && left > 0 {
+        let following = BigEndian::read_u32(&db.get_page_ro(next)?[0..]) as PageNum;
+        db.free_page(next)?;
+        left = left.saturating_sub(usable_size as usize - 4);
+        next = following;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_local_len() {
+    // On 512 byte pages, table leaf cells keep up to 477 bytes, and index cells up to 102 bytes, and both keep at least
+    // 39 bytes.
+    assert_eq!(local_len(&PageType::TableLeaf, 477, 512), 477);
+    assert_eq!(local_len(&PageType::TableLeaf, 478, 512), 39);
+    assert_eq!(local_len(&PageType::TableLeaf, 39 + 508 + 10, 512), 49);
+    assert_eq!(local_len(&PageType::IndexLeaf, 102, 512), 102);
+    assert_eq!(local_len(&PageType::IndexInterior, 1000, 512), 39);
+}
+
+#[test]
+fn test_spill_and_read_payload() {
+    let path = crate::stored_db::copy_of_testdata("minimal.db", "overflow");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    let usable_size = db.get_usable_size() as usize;
+    let numpages = db.get_numpages();
+    let payload: Vec<u8> = (0..3 * usable_size).map(|i| (i % 251) as u8).collect();
+    let local = spill(&mut db, &PageType::TableLeaf, &payload).unwrap();
+    assert_eq!(local.len(), local_len(&PageType::TableLeaf, payload.len(), usable_size as u32) + 4);
+    assert_eq!(db.get_numpages(), numpages + 3);
+    assert_eq!(read_payload(&db, 2, 0, &PageType::TableLeaf, &local, payload.len()).unwrap(), payload);
+    assert!(read_payload(&db, 2, 0, &PageType::TableLeaf, &local, payload.len() + usable_size).is_err());
+    free(&mut db, &PageType::TableLeaf, &local, payload.len()).unwrap();
+    assert_eq!(crate::dbheader::freelist(&db.get_page_ro(1).unwrap()).1, 3);
+}
@@ -1,34 +1,24 @@
 //! table provides iterators over Table type btrees.  
 //! It hides the fact that btrees span several pages.
+//!
+//! An iterator can be limited to a range of rowids.  Then it searches down the btree for the first rowid in the range,
+//! rather than starting at the leftmost leaf, and it stops after the last rowid in the range.
 
-use super::{interior, leaf, PageType, RowId};
-use crate::stored_db::PageNum;
+use super::{interior, leaf, PageType, RowId, MAX_DEPTH};
+use crate::stored_db::{Error, PageNum};
 
 enum EitherIter<'z> {
     Leaf(super::leaf::Iterator<'z>),
     Interior(super::interior::ScanIterator<'z>),
 }
 
-impl<'z> EitherIter<'z> {
-    #[allow(dead_code)] // Use for SearchIterator
-    pub fn unwrap_leaf(&mut self) -> &mut super::leaf::Iterator<'z> {
-        match self {
-            EitherIter::Leaf(l) => l,
-            EitherIter::Interior(_) => panic!("Incorrect enum variant in unwrap_leaf"),
-        }
-    }
-    pub fn unwrap_interior(&mut self) -> &mut super::interior::ScanIterator<'z> {
-        match self {
-            EitherIter::Leaf(_) => panic!("Incorrect enum variant in unwrap_interior"),
-            EitherIter::Interior(i) => i,
-        }
-    }
-}
-
 pub struct Iterator<'p> {
     root_page: crate::stored_db::PageNum,
     pager: &'p crate::stored_db::StoredDb,
-    stack: Vec<EitherIter<'p>>, // The lifetime of the references in the inner iterators is good as long as the pager is, since the pager holds the pages.
+    stack: Vec<EitherIter<'p>>, // The inner iterators keep the pages they iterate over in memory until they are dropped.
+    min_rowid: RowId,
+    max_rowid: RowId,
+    done: bool,
 }
 
 impl<'p> Iterator<'p> {
@@ -37,6 +27,8 @@ impl<'p> Iterator<'p> {
     /// Iterator produces cells which are slices of bytes, which contain a record.  
     /// The called needs to interpret the record as a database row.
     ///
+    /// If a page of the btree is corrupt, the iterator produces an error, and then ends.
+    ///
     /// When you call new, the iterator does an in-order traversal of the table and records
     /// all the page numbers it needs during its scan.  
     ///
@@ -49,11 +41,26 @@ impl<'p> Iterator<'p> {
         // during the iteration (excluding overflow pages).  This approach avoids having a stack of
         // iterators which are multiple borrows against  approach avoids having page references
         //  during the iteration phase, which allows the next() c
+        Self::new_rowid_range(root_page, pager, RowId::MIN, RowId::MAX)
+    }
 
+    /// Creates an iterator over the records of a Table-typed btree whose rowids are between `min_rowid` and `max_rowid`,
+    /// inclusive.
+    ///
+    /// Only the pages on the path to `min_rowid`, and the leaves holding the range, are visited.
+    pub fn new_rowid_range(
+        root_page: crate::stored_db::PageNum,
+        pager: &'p crate::stored_db::StoredDb,
+        min_rowid: RowId,
+        max_rowid: RowId,
+    ) -> Iterator<'p> {
         Iterator {
             root_page,
             pager,
             stack: vec![],
+            min_rowid,
+            max_rowid,
+            done: false,
         }
     }
 
@@ -64,69 +71,67 @@ impl<'p> Iterator<'p> {
         }
     }
 
-    fn seek_leftmost_leaf(&mut self, starting_page: PageNum) {
+    /// Descends from `starting_page` to the leaf that would hold `self.min_rowid`, pushing an iterator for each page
+    /// on the way onto the stack.
+    ///
+    /// Each subtree below `starting_page` that is visited after the first holds only larger rowids, so for those the
+    /// descent goes to the leftmost leaf.
+    fn seek_leaf(&mut self, starting_page: PageNum) -> Result<(), Error> {
         let mut next_page = starting_page;
         loop {
+            let start = Self::btree_start_offset(next_page);
+            if self.stack.len() >= MAX_DEPTH {
+                return Err(super::corrupt(next_page, start, format!("btree has more than {} levels", MAX_DEPTH)));
+            }
             let page_type = {
-                let page = self.pager.get_page_ro(next_page).unwrap();
-                // TODO: if the borrow checker gets confused by this loop, then the stack could be made to
-                // have a maximum height, e.g. 12, given that there are at most 2^64 pages and it is balanced.
-                let hdr = super::header::check_header(page, Self::btree_start_offset(next_page));
+                let page = self.pager.get_page_ro(next_page)?;
+                let hdr = super::header::check_header(&page, next_page, start)?;
                 hdr.btree_page_type
             };
             match page_type {
                 PageType::TableLeaf => {
                     self.stack
-                        .push(EitherIter::Leaf(leaf::Iterator::new(self.pager, next_page),
+                        .push(EitherIter::Leaf(leaf::Iterator::new(self.pager, next_page)?,
                         ));
-                    return;
+                    return Ok(());
                 }
                 PageType::TableInterior => {
-                    self.stack
-                        .push(EitherIter::Interior(interior::ScanIterator::new(self.pager, next_page)));
-                    let top_of_stack_iter = self.stack.last_mut().unwrap();
-                    next_page = top_of_stack_iter
-                        .unwrap_interior()
-                        .next()
-                        .expect("Interior page should have at least 1 child always");
+                    let mut interior = interior::ScanIterator::new(self.pager, next_page)?;
+                    let child = interior.seek(self.min_rowid)?;
+                    self.stack.push(EitherIter::Interior(interior));
+                    next_page = child;
                 }
                 PageType::IndexInterior | PageType::IndexLeaf => {
-                    unreachable!("Should not have index pages in table btree.");
+                    return Err(super::corrupt(next_page, start, String::from("index page in a table btree")));
                 }
             }
         }
     }
-}
-
-impl<'p> core::iter::Iterator for Iterator<'p> {
-    // The iterator returns a tuple of (rowid, cell_payload).
-    // Overflowing payloads are not supported.
-    type Item = (RowId, &'p [u8]);
 
-    /// Returns the next item, which is a tuple of (k, v), where
-    ///   `k` is a key, the row number (u64)
-    ///   `v` is a value, &[u8].
-    fn next(&mut self) -> Option<Self::Item> {
+    /// returns the next row in the range, if there is one.
+    fn advance(&mut self) -> Result<Option<(RowId, Vec<u8>)>, Error> {
         if self.stack.is_empty() {
-            self.seek_leftmost_leaf(self.root_page)
+            self.seek_leaf(self.root_page)?;
         }
-        assert!(!self.stack.is_empty(), "Internal logical error");
-        while !self.stack.is_empty() {
-            match self.stack.last_mut().unwrap() {
-                EitherIter::Leaf(l) => match l.next() {
+        while let Some(top) = self.stack.last_mut() {
+            match top {
+                EitherIter::Leaf(l) => match l.next().transpose()? {
+                    // The leaf found by the seek may start with rowids before the range.
+                    Some((rowid, _)) if rowid < self.min_rowid => continue,
+                    Some((rowid, _)) if rowid > self.max_rowid => break,
                     // When we are iterating over a leaf and aren't done, return items from the leaf.
-                    Some(x) => return Some(x),
+                    Some(x) => return Ok(Some(x)),
                     // When we are iterating over a leaf and finish done, go up to the previous interior page, if any.
                     // We will process that on the next iteration of the loop.
                     None => {
-                        self.stack.pop().unwrap();
+                        self.stack.pop();
                         continue;
                     }
                 },
-                EitherIter::Interior(i) => match i.next() {
+                EitherIter::Interior(i) => match i.next().transpose()? {
                     // When we are still iterating on in an interior page, explore down the next child pointer to a leaf.
                     Some(x) => {
-                        self.seek_leftmost_leaf(x);
+                        self.seek_leaf(x)?;
                         continue;
                     }
                     // If we ran out of items on an interior page, go up to its parent.
@@ -137,7 +142,29 @@ impl<'p> core::iter::Iterator for Iterator<'p> {
                 },
             }
         }
-        None
+        Ok(None)
+    }
+}
+
+impl<'p> core::iter::Iterator for Iterator<'p> {
+    // The iterator returns a tuple of (rowid, cell_payload).
+    // Payloads that spill are read from their overflow pages.
+    type Item = Result<(RowId, Vec<u8>), Error>;
+
+    /// Returns the next item, which is a tuple of (k, v), where
+    ///   `k` is a key, the row number (u64)
+    ///   `v` is a value, the payload of the cell.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.advance();
+        // The iteration ends after the last row, or after an error.
+        if !matches!(result, Ok(Some(_))) {
+            self.stack.clear();
+            self.done = true;
+        }
+        result.transpose()
     }
 }
 
@@ -156,9 +183,9 @@ fn test_table_iterator_on_minimal_db() {
     let pgnum = db.get_root_pagenum("a").expect("Should have gotten page number.");
     let pager = db;
     let mut ri = crate::new_table_iterator(&pager, pgnum);
-    let first_item = ri.next().clone();
+    let first_item = ri.next();
     assert!(first_item.is_some());
-    assert_eq!(first_item.unwrap().0, 1);
+    assert_eq!(first_item.unwrap().unwrap().0, 1);
     assert!(ri.next().is_none());
 }
 
@@ -176,10 +203,34 @@ fn test_table_iterator_on_three_level_db() {
     let ri = crate::new_table_iterator(&pager, pgnum);
     let mut last_rowid = 0;
     for e in ri.enumerate() {
-        let (expected, (rowid, _)) = e;
+        let (expected, item) = e;
+        let (rowid, _) = item.unwrap();
         println!("Visiting rowid {} on iteration {}", rowid, expected);
         assert_eq!(expected + 1, rowid as usize);
         last_rowid = rowid
     }
     assert_eq!(last_rowid, 100000);
 }
+
+#[test]
+fn test_rowid_range_iterator_on_three_level_db() {
+    let path = path_to_testdata("threelevel.db");
+    let db =
+        crate::stored_db::StoredDb::open(path.as_str()).expect("Should have opened db with pager.");
+    let pgnum = db.get_root_pagenum("t").expect("Should have found root pagenum.");
+    let cases = vec![
+        // (min, max, expected rowids)
+        (1, 1, vec![1]),
+        (54321, 54325, vec![54321, 54322, 54323, 54324, 54325]),
+        (99998, RowId::MAX, vec![99998, 99999, 100000]),
+        (RowId::MIN, 2, vec![1, 2]),
+        (100001, RowId::MAX, vec![]),
+        (10, 9, vec![]),
+    ];
+    for (min, max, expected) in cases {
+        let mut ri = Iterator::new_rowid_range(pgnum, &db, min, max);
+        let actual: Vec<RowId> = ri.by_ref().map(|item| item.unwrap().0).collect();
+        assert_eq!(actual, expected);
+        assert!(ri.next().is_none());
+    }
+}
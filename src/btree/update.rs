@@ -0,0 +1,102 @@
+//! update replaces the contents of rows of table btrees.
+//!
+//! A row's cell is replaced in its leaf.  A row that grows may no longer fit on its page, which is then split as for
+//! an insert, so that some of the page's rows, possibly including the changed one, move to a new page.  Pages of rows
+//! that shrink are not merged.
+
+use anyhow::Result;
+
+use super::insert;
+use super::node;
+use super::RowId;
+use crate::stored_db::{PageNum, StoredDb};
+
+/// replaces the contents of the row with `rowid`, in the table btree rooted at `root`, with the serialized `record`,
+/// returning false if there was no such row.
+pub fn update(db: &mut StoredDb, root: PageNum, rowid: RowId, record: &[u8]) -> Result<bool> {
+    let (path, pgnum, mut n) = node::find_table_leaf(db, root, rowid)?;
+    let pos = node::table_cell_position(&n, rowid);
+    if n.cells.get(pos).is_none_or(|c| node::table_cell_rowid(&n.page_type, c) != rowid) {
+        return Ok(false);
+    }
+    node::free_table_leaf_overflow(db, &n.cells[pos])?;
+    n.cells[pos] = node::table_leaf_cell(db, rowid, record)?;
+    insert::write_splitting(db, path, pgnum, n, false)?;
+    Ok(true)
+}
+
+#[test]
+fn test_update_moves_rows_that_grow() {
+    use crate::stored_db::copy_of_testdata;
+    // The table has 1000 rows on 512 byte pages, so growing rows splits pages.
+    let path = copy_of_testdata("multipage-512B-page.db", "update");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    let root = db.get_root_pagenum("thousandrows").expect("Should have found table.");
+    let numpages = db.get_numpages();
+    // A record holding one text value of `len` bytes.
+    let record = |len: usize| [vec![2, 13 + 2 * len as u8], vec![b'x'; len]].concat();
+
+    for rowid in (1..=1000).step_by(3) {
+        assert!(update(&mut db, root, rowid, &record(60)).unwrap());
+    }
+    for rowid in (2..=1000).step_by(3) {
+        assert!(update(&mut db, root, rowid, &record(0)).unwrap());
+    }
+    assert!(!update(&mut db, root, 1001, &record(0)).unwrap());
+    // A record too big for a page spills to overflow pages.
+    assert!(update(&mut db, root, 3, &[7; 1000]).unwrap());
+    db.commit().expect("Should have written db.");
+    assert!(db.get_numpages() > numpages);
+
+    let db = StoredDb::open(&path).expect("Should have reopened db.");
+    let rows: Vec<(RowId, Vec<u8>)> =
+        super::table::Iterator::new(root, &db).collect::<Result<_, _>>().unwrap();
+    assert_eq!(rows.iter().map(|(rowid, _)| *rowid).collect::<Vec<RowId>>(), (1..=1000).collect::<Vec<RowId>>());
+    assert!(rows.iter().all(|(rowid, r)| match rowid % 3 {
+        1 => *r == record(60),
+        2 => *r == record(0),
+        _ => r.len() > 3,
+    }));
+    assert_eq!(rows[2].1, vec![7; 1000]);
+}
+
+#[test]
+fn test_update_grows_middle_row_to_max_local_payload() {
+    use crate::stored_db::copy_of_testdata;
+    use super::PageType;
+    // The leaves of the table are full of small rows, and a row in the middle of a leaf grows to the largest record
+    // that does not spill, so neither part of a split in two would fit on a page.
+    let path = copy_of_testdata("multipage-512B-page.db", "update-max-local");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    let root = db.get_root_pagenum("thousandrows").expect("Should have found table.");
+    let max_local = super::overflow::max_local_payload(&PageType::TableLeaf, db.get_usable_size());
+    // A record holding one text value, which is `len` bytes in all.
+    let record = |len: usize| {
+        let text_len = len - 3;
+        let mut r = vec![3];
+        r.extend(sqlite_varint::serialize_to_varint(13 + 2 * text_len as i64));
+        r.extend(vec![b'x'; text_len]);
+        r
+    };
+    assert_eq!(record(max_local).len(), max_local);
+
+    let rowids: Vec<RowId> = (100..1000).step_by(97).collect();
+    for rowid in &rowids {
+        assert!(update(&mut db, root, *rowid, &record(max_local)).unwrap());
+    }
+    assert!(update(&mut db, root, 500, &record(max_local - 50)).unwrap());
+    db.commit().expect("Should have written db.");
+    assert_eq!(db.integrity_check(), Vec::<String>::new());
+
+    let db = StoredDb::open(&path).expect("Should have reopened db.");
+    let rows: Vec<(RowId, Vec<u8>)> =
+        super::table::Iterator::new(root, &db).collect::<Result<_, _>>().unwrap();
+    assert_eq!(rows.iter().map(|(rowid, _)| *rowid).collect::<Vec<RowId>>(), (1..=1000).collect::<Vec<RowId>>());
+    for (rowid, r) in &rows {
+        match (*rowid == 500, rowids.contains(rowid)) {
+            (true, _) => assert_eq!(*r, record(max_local - 50)),
+            (false, true) => assert_eq!(*r, record(max_local)),
+            (false, false) => assert!(r.len() < 20),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! buffer_pool holds the pages of a database file that are in memory, up to a budget of pages.
+//!
+//! Pages are read into the pool when they are first used.  When the pool is full, a page that is not in use is evicted
+//! to make room, chosen with the CLOCK algorithm: each page has a "referenced" bit which is set when the page is used.
+//! The clock hand sweeps over the pages, clearing the bit of referenced pages, and evicts the first page whose bit was
+//! already clear.  This approximates evicting the least recently used page, without reordering a list on every use.
+//!
+//! A page is in use, or "pinned", while a `PageRef` to it is held.  Pages that were changed but not yet written are
+//! not evicted either, since the file does not have their contents.  If every page is pinned or changed, the pool
+//! grows past its budget rather than fail.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::stored_db::PageNum;
+
+/// A `PageRef` gives read access to a page in a buffer pool, and pins the page while it is held.
+///
+/// The lifetime `'a` is that of the `StoredDb` the page was got from, so that the page cannot be changed while it is
+/// being read.
+pub struct PageRef<'a> {
+    page: Arc<Vec<u8>>,
+    _db: PhantomData<&'a ()>,
+}
+
+impl<'a> Deref for PageRef<'a> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.page
+    }
+}
+
+struct Frame {
+    pn: PageNum,
+    // The pool holds one reference.  Each `PageRef` to the page holds another.
+    page: Arc<Vec<u8>>,
+    referenced: bool,
+}
+
+impl Frame {
+    fn pinned(&self) -> bool {
+        Arc::strong_count(&self.page) > 1
+    }
+}
+
+pub struct BufferPool {
+    frames: Vec<Frame>,
+    /// map from page number to the index of the page's frame.
+    index: HashMap<PageNum, usize>,
+    /// the index of the frame that the clock hand points to.
+    hand: usize,
+    max_frames: usize,
+}
+
+impl BufferPool {
+    /// makes an empty pool which holds up to `max_frames` pages that are not in use.
+    pub fn new(max_frames: usize) -> BufferPool {
+        BufferPool {
+            frames: vec![],
+            index: HashMap::new(),
+            hand: 0,
+            max_frames: max_frames.max(1),
+        }
+    }
+
+    /// changes the number of pages the pool holds.  Extra pages are evicted when pages are next added.
+    pub fn set_max_frames(&mut self, max_frames: usize) {
+        self.max_frames = max_frames.max(1);
+    }
+
+    /// returns the number of pages in the pool.
+    pub fn num_pages(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// returns a `PageRef` to page `pn`, or None if it is not in the pool.
+    pub fn get<'a>(&mut self, pn: PageNum) -> Option<PageRef<'a>> {
+        let frame = &mut self.frames[*self.index.get(&pn)?];
+        frame.referenced = true;
+        Some(PageRef { page: frame.page.clone(), _db: PhantomData })
+    }
+
+    /// returns page `pn`, or None if it is not in the pool, without counting it as used.
+    pub fn peek(&self, pn: PageNum) -> Option<&Vec<u8>> {
+        self.index.get(&pn).map(|i| &*self.frames[*i].page)
+    }
+
+    /// returns page `pn` for modification, or None if it is not in the pool.
+    pub fn get_mut(&mut self, pn: PageNum) -> Option<&mut Vec<u8>> {
+        let frame = &mut self.frames[*self.index.get(&pn)?];
+        frame.referenced = true;
+        // A page is not changed while it is pinned, since `PageRef`s borrow the `StoredDb` that the pool is in.
+        Some(Arc::make_mut(&mut frame.page))
+    }
+
+    /// adds `page` to the pool as page `pn`, replacing any page already there, and returns a `PageRef` to it.
+    ///
+    /// If the pool is full, unpinned pages for which `is_dirty` is false are evicted first.
+    pub fn insert<'a>(&mut self, pn: PageNum, page: Vec<u8>, is_dirty: impl Fn(PageNum) -> bool) -> PageRef<'a> {
+        self.remove(pn);
+        while self.frames.len() >= self.max_frames && self.evict_one(&is_dirty) {}
+        self.index.insert(pn, self.frames.len());
+        self.frames.push(Frame { pn, page: Arc::new(page), referenced: true });
+        self.get(pn).expect("Should have found the page just added.")
+    }
+
+    /// removes page `pn` from the pool, if it is there.
+    pub fn remove(&mut self, pn: PageNum) {
+        if let Some(i) = self.index.remove(&pn) {
+            self.frames.swap_remove(i);
+            if let Some(moved) = self.frames.get(i) {
+                self.index.insert(moved.pn, i);
+            }
+        }
+    }
+
+    /// evicts the page under the clock hand that was not used since the hand last passed it, and returns false if no
+    /// page can be evicted.
+    fn evict_one(&mut self, is_dirty: &impl Fn(PageNum) -> bool) -> bool {
+        // If one sweep only finds referenced pages, clearing their bits, a second sweep would evict the first of them,
+        // so it is evicted without sweeping again.  If the sweep finds no page that could be evicted, it stops there.
+        let mut first_cleared = None;
+        for _ in 0..self.frames.len() {
+            if self.hand >= self.frames.len() {
+                self.hand = 0;
+            }
+            let frame = &mut self.frames[self.hand];
+            if frame.pinned() || is_dirty(frame.pn) {
+                self.hand += 1;
+            } else if frame.referenced {
+                frame.referenced = false;
+                first_cleared.get_or_insert(self.hand);
+                self.hand += 1;
+            } else {
+                // The hand now points at the frame moved into this one's place.
+                let pn = frame.pn;
+                self.remove(pn);
+                return true;
+            }
+        }
+        match first_cleared {
+            Some(i) => {
+                self.hand = i;
+                let pn = self.frames[i].pn;
+                self.remove(pn);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[test]
+fn test_evicts_unpinned_pages() {
+    let clean = |_| false;
+    let mut pool = BufferPool::new(2);
+    let p1 = pool.insert(1, vec![1], clean);
+    pool.insert(2, vec![2], clean);
+    // Page 1 is pinned, so page 2 is evicted to make room for page 3.
+    pool.insert(3, vec![3], clean);
+    assert_eq!(pool.num_pages(), 2);
+    assert!(pool.peek(2).is_none());
+    assert_eq!(*p1, vec![1]);
+    // Once page 1 is released, it can be evicted.
+    drop(p1);
+    let _p3 = pool.get(3);
+    pool.insert(4, vec![4], clean);
+    assert_eq!((pool.peek(1), pool.peek(3)), (None, Some(&vec![3])));
+    // Changed pages are not evicted, even if the pool goes over its budget.
+    let dirty = |pn| pn != 5;
+    pool.insert(5, vec![5], dirty);
+    pool.insert(6, vec![6], dirty);
+    assert_eq!(pool.num_pages(), 3);
+    assert!(pool.peek(5).is_none());
+}
+
+#[test]
+fn test_sweeps_once_when_nothing_is_evictable() {
+    use std::cell::Cell;
+    let checked = Cell::new(0);
+    let dirty = |_| {
+        checked.set(checked.get() + 1);
+        true
+    };
+    let mut pool = BufferPool::new(1);
+    for pn in 1..=100 {
+        pool.insert(pn, vec![], dirty);
+    }
+    assert_eq!(pool.num_pages(), 100);
+    // Each page added is checked by one sweep over the pages already there.
+    assert_eq!(checked.get(), (0..100).sum::<usize>());
+}
@@ -0,0 +1,48 @@
+//! checksum computes and checks the page checksums of the checksum VFS.
+//!
+//! The checksum VFS (https://www.sqlite.org/cksumvfs.html) keeps an 8 byte checksum of each page in the last 8 bytes
+//! of the page, which are reserved bytes, so database files that use it have 8 reserved bytes per page.  The
+//! checksum is two running sums of the 32-bit little-endian words of the rest of the page, each stored little-endian.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// the number of reserved bytes that hold the checksum of a page.
+pub const CHECKSUM_LEN: usize = 8;
+
+/// returns the checksum of `page`, which is computed over all but its last 8 bytes.
+pub fn compute(page: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let (mut s1, mut s2) = (0_u32, 0_u32);
+    for pair in page[..page.len() - CHECKSUM_LEN].chunks_exact(8) {
+        s1 = s1.wrapping_add(LittleEndian::read_u32(&pair[0..])).wrapping_add(s2);
+        s2 = s2.wrapping_add(LittleEndian::read_u32(&pair[4..])).wrapping_add(s1);
+    }
+    let mut sum = [0_u8; CHECKSUM_LEN];
+    LittleEndian::write_u32(&mut sum[0..], s1);
+    LittleEndian::write_u32(&mut sum[4..], s2);
+    sum
+}
+
+/// returns true if the last 8 bytes of `page` are its checksum.
+pub fn verify(page: &[u8]) -> bool {
+    page[page.len() - CHECKSUM_LEN..] == compute(page)
+}
+
+/// stores the checksum of `page` in its last 8 bytes.
+pub fn set(page: &mut [u8]) {
+    let sum = compute(page);
+    let len = page.len();
+    page[len - CHECKSUM_LEN..].copy_from_slice(&sum);
+}
+
+#[test]
+fn test_checksum() {
+    let mut page: Vec<u8> = (0..512).map(|i| (i * 7 % 256) as u8).collect();
+    assert!(!verify(&page));
+    set(&mut page);
+    assert!(verify(&page));
+    page[100] ^= 1;
+    assert!(!verify(&page));
+    // The sums wrap around, and are stored little-endian.
+    let page = [[0xff_u8; 8].to_vec(), vec![0; 8]].concat();
+    assert_eq!(compute(&page), [0xff, 0xff, 0xff, 0xff, 0xfe, 0xff, 0xff, 0xff]);
+}
@@ -2,6 +2,8 @@
 
 use std::io::{Read, Seek, SeekFrom};
 
+use crate::serial_type::TextEncoding;
+
 #[derive(thiserror::Error, Debug, Clone)]
 pub enum Error {
     #[error("The magic bytes for this file are wrong.")]
@@ -10,8 +12,6 @@ pub enum Error {
     Unsupported { field: &'static str, value: u64 },
     #[error("The pagesize is not supported by this code, though it may be valid Sqlite format.")]
     UnsupportedPagesize,
-    #[error("A field value specified a free list that is not supported by this code, though it may be valid Sqlite format.")]
-    UnsupportedFreelistUse,
     #[error("A field value specified a schema type that is not supported by this code, though it may be valid Sqlite format.")]
     UnsupportedSchema,
     #[error("A field value is invalid per the Sqlite format spec (version 3.0.0).")]
@@ -30,6 +30,29 @@ pub struct DbfileHeader {
     pub numpages: u32,
     pub changecnt: u32,
     pub sqlite_version_number: u32,
+    /// the number of bytes at the end of each page that are reserved for extensions, such as checksums or encryption,
+    /// and so are not used for btrees.
+    pub reserved_bytes: u8,
+    /// true if the database is in WAL mode, so that changes may be in the write-ahead log rather than the file.
+    pub wal_mode: bool,
+    /// the page number of the first freelist trunk page, or 0 if there are no freelist pages.
+    pub freelist_trunk: u32,
+    /// the total number of freelist trunk and leaf pages.
+    pub freelist_count: u32,
+    pub schema_cookie: u32,
+    /// the suggested page cache size, which is only advice to readers of the file.  0 means the default.
+    pub default_cache_size: i32,
+    /// the page number of the largest root btree page, in auto-vacuum or incremental-vacuum mode, or 0 otherwise.
+    /// Databases in either mode have pointer map pages.
+    pub largest_root_btree_page: u32,
+    /// true if the database is in incremental-vacuum mode rather than auto-vacuum mode.
+    pub incremental_vacuum: bool,
+    /// the value of `PRAGMA user_version`, which SQLite does not use itself.
+    pub user_version: i32,
+    /// the value of `PRAGMA application_id`, which identifies the application that the file is for.
+    pub application_id: i32,
+    /// the encoding of all the text values in the database.
+    pub encoding: TextEncoding,
 }
 
 // The database file header as stored in a sqlite file.
@@ -112,6 +135,81 @@ fn bytes_identical<T: Ord>(a: &[T], b: &[T]) -> bool {
     true
 }
 
+// The SQLITE_VERSION_NUMBER written to the header of new files: the version of SQLite that made the test files that
+// this code was checked against.
+const SQLITE_VERSION_NUMBER_WRITTEN: u32 = 3_040_001;
+
+/// returns the header of a new, empty database file with `pagesize` byte pages, which has just page 1.
+pub fn new_header(pagesize: u32) -> Result<[u8; SQLITE_DB_HEADER_BYTES], Error> {
+    let encoded_pagesize: u16 = match pagesize {
+        512 | 1024 | 2048 | 4096 | 8192 | 16384 | 32768 => pagesize as u16,
+        65536 => 1,
+        _ => return Err(Error::UnsupportedPagesize),
+    };
+    let mut h = [0_u8; SQLITE_DB_HEADER_BYTES];
+    h[0..16].copy_from_slice(SQLITE3_MAGIC_STRING);
+    h[16..18].copy_from_slice(&encoded_pagesize.to_be_bytes());
+    // File format write and read versions (legacy, not WAL), then the payload fractions, which must be these values.
+    h[18..24].copy_from_slice(&[0x01, 0x01, 0x00, 0x40, 0x20, 0x20]);
+    // The change counter and the version-valid-for number are both zero, so the page count is valid.
+    set_numpages(&mut h, 1);
+    // Schema format 4, and UTF-8 text encoding.
+    h[44..48].copy_from_slice(&4_u32.to_be_bytes());
+    h[56..60].copy_from_slice(&1_u32.to_be_bytes());
+    h[96..100].copy_from_slice(&SQLITE_VERSION_NUMBER_WRITTEN.to_be_bytes());
+    Ok(h)
+}
+
+// Offsets of the header fields that are changed when the database is written.
+const CHANGE_COUNTER_OFFSET: usize = 24;
+const NUMPAGES_OFFSET: usize = 28;
+const FREELIST_TRUNK_OFFSET: usize = 32;
+const FREELIST_COUNT_OFFSET: usize = 36;
+const SCHEMA_COOKIE_OFFSET: usize = 40;
+const VERSION_VALID_FOR_OFFSET: usize = 92;
+
+/// sets the in-header database size, in the header at the start of `page1`.
+pub fn set_numpages(page1: &mut [u8], numpages: u32) {
+    write_u32(page1, NUMPAGES_OFFSET, numpages);
+}
+
+/// returns the page number of the first freelist trunk page, which is 0 if there is none, and the total number of
+/// freelist pages, from the header at the start of `page1`.
+pub fn freelist(page1: &[u8]) -> (u32, u32) {
+    (read_u32(page1, FREELIST_TRUNK_OFFSET), read_u32(page1, FREELIST_COUNT_OFFSET))
+}
+
+/// sets the first freelist trunk page and the total number of freelist pages, in the header at the start of `page1`.
+pub fn set_freelist(page1: &mut [u8], first_trunk: u32, count: u32) {
+    write_u32(page1, FREELIST_TRUNK_OFFSET, first_trunk);
+    write_u32(page1, FREELIST_COUNT_OFFSET, count);
+}
+
+/// increments the file change counter, in the header at the start of `page1`.
+///
+/// The version-valid-for number is set to the new counter too, which tells SQLite that the in-header database size
+/// is valid.
+pub fn increment_change_counter(page1: &mut [u8]) {
+    let counter = read_u32(page1, CHANGE_COUNTER_OFFSET).wrapping_add(1);
+    write_u32(page1, CHANGE_COUNTER_OFFSET, counter);
+    write_u32(page1, VERSION_VALID_FOR_OFFSET, counter);
+}
+
+/// increments the schema cookie, in the header at the start of `page1`, which tells other readers of the file that
+/// the schema has changed.
+pub fn increment_schema_cookie(page1: &mut [u8]) {
+    let cookie = read_u32(page1, SCHEMA_COOKIE_OFFSET).wrapping_add(1);
+    write_u32(page1, SCHEMA_COOKIE_OFFSET, cookie);
+}
+
+fn read_u32(h: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(h[offset..offset + 4].try_into().expect("Should have sliced 4 bytes."))
+}
+
+fn write_u32(h: &mut [u8], offset: usize, value: u32) {
+    h[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
 pub fn get_header_clone(f: &mut std::fs::File) -> Result<DbfileHeader, Error> {
     let mut v = [0_u8; SQLITE_DB_HEADER_BYTES];
     f.seek(SeekFrom::Start(0)).map_err(|_| Error::ReadFailed)?;
@@ -142,23 +240,22 @@ pub fn get_header(h: &[u8; SQLITE_DB_HEADER_BYTES]) -> Result<DbfileHeader, Erro
         1 => 65536,
         _ => return Err(Error::UnsupportedPagesize),
     };
-    if hdri.ffwv != 0x01 {
+    // Version 1 is for the legacy rollback journal, and 2 is for WAL mode.
+    if hdri.ffwv != 0x01 && hdri.ffwv != 0x02 {
         return Err(Error::Unsupported {
             field: "File format write version",
             value: hdri.ffwv as u64,
         });
     }
-    if hdri.ffrv != 0x01 {
+    if hdri.ffrv != 0x01 && hdri.ffrv != 0x02 {
         return Err(Error::Unsupported {
             field: "File format read version",
             value: hdri.ffrv as u64,
         });
     }
-    if hdri.reserved_end != 0x00 {
-        return Err(Error::Unsupported {
-            field: "Bytes of unused reserved space at the end of each page",
-            value: hdri.reserved_end as u64,
-        });
+    // The usable size of a page is not allowed to be less than 480 bytes.
+    if pagesize - (hdri.reserved_end as u32) < 480 {
+        return Err(Error::Invalid);
     }
     if hdri.maxepf != 0x40 {
         return Err(Error::Invalid);
@@ -171,58 +268,36 @@ pub fn get_header(h: &[u8; SQLITE_DB_HEADER_BYTES]) -> Result<DbfileHeader, Erro
     }
     let changecnt: u32 = u32::from_be_bytes(hdri.fcc);
     let numpages: u32 = u32::from_be_bytes(hdri.numpages);
-    if u32::from_be_bytes(hdri.pnfftp) != 0x0 {
-        return Err(Error::UnsupportedFreelistUse);
-    }
-    if u32::from_be_bytes(hdri.nflp) != 0x0 {
-        return Err(Error::UnsupportedFreelistUse);
+    // There is a first freelist trunk page exactly when there are freelist pages.
+    let freelist_trunk = u32::from_be_bytes(hdri.pnfftp);
+    let freelist_count = u32::from_be_bytes(hdri.nflp);
+    if (freelist_trunk == 0) != (freelist_count == 0) {
+        return Err(Error::Invalid);
     }
-    let _schema_cookie = u32::from_be_bytes(hdri.sc);
+    let schema_cookie = u32::from_be_bytes(hdri.sc);
     if u32::from_be_bytes(hdri.sfn) != 0x4 {
         return Err(Error::UnsupportedSchema);
     }
-    let dpcs = u32::from_be_bytes(hdri.dpcs);
-    if dpcs != 0x0 {
-        return Err(Error::Unsupported {
-            field: "Default page cache size",
-            value: dpcs as u64,
-        });
-    }
-    let lrbpv = u32::from_be_bytes(hdri.lrbpv);
-    if lrbpv != 0x0 {
-        return Err(Error::Unsupported {
-            field: "largest root b-tree page for vacuuming",
-            value: lrbpv as u64,
-        });
-    }
-    let encoding = u32::from_be_bytes(hdri.encoding);
-    if encoding != 0x1 {
-        return Err(Error::Unsupported {
-            field: "text encoding",
-            value: encoding as u64,
-        });
-    }
-    let userversion = u32::from_be_bytes(hdri.userversion);
-    if userversion != 0x0 {
-        return Err(Error::Unsupported {
-            field: "User version",
-            value: userversion as u64,
-        });
-    }
-    let ivm = u32::from_be_bytes(hdri.ivm);
-    if ivm != 0x0 {
-        return Err(Error::Unsupported {
-            field: "incremental vacuum mode",
-            value: ivm as u64,
-        });
-    }
-    let appid = u32::from_be_bytes(hdri.appid);
-    if appid != 0x0 {
-        return Err(Error::Unsupported {
-            field: "Application ID",
-            value: appid as u64,
-        });
+    let default_cache_size = i32::from_be_bytes(hdri.dpcs);
+    let largest_root_btree_page = u32::from_be_bytes(hdri.lrbpv);
+    let encoding = match u32::from_be_bytes(hdri.encoding) {
+        1 => TextEncoding::Utf8,
+        2 => TextEncoding::Utf16le,
+        3 => TextEncoding::Utf16be,
+        x => {
+            return Err(Error::Unsupported {
+                field: "text encoding",
+                value: x as u64,
+            })
+        }
+    };
+    let user_version = i32::from_be_bytes(hdri.userversion);
+    // Incremental-vacuum mode is a variant of auto-vacuum mode, so it needs a largest root btree page.
+    let incremental_vacuum = u32::from_be_bytes(hdri.ivm) != 0x0;
+    if incremental_vacuum && largest_root_btree_page == 0 {
+        return Err(Error::Invalid);
     }
+    let application_id = i32::from_be_bytes(hdri.appid);
     if !bytes_identical(&hdri.reserved, TWENTY_ZEROS) {
         return Err(Error::WrongMagic);
     }
@@ -234,5 +309,57 @@ pub fn get_header(h: &[u8; SQLITE_DB_HEADER_BYTES]) -> Result<DbfileHeader, Erro
         changecnt,
         numpages,
         sqlite_version_number,
+        reserved_bytes: hdri.reserved_end,
+        wal_mode: hdri.ffrv == 0x02,
+        freelist_trunk,
+        freelist_count,
+        schema_cookie,
+        default_cache_size,
+        largest_root_btree_page,
+        incremental_vacuum,
+        user_version,
+        application_id,
+        encoding,
     })
 }
+
+#[test]
+fn test_new_header() {
+    let h = new_header(1024).unwrap();
+    let parsed = get_header(&h).unwrap();
+    assert_eq!(parsed.pagesize, 1024);
+    assert_eq!(parsed.numpages, 1);
+    assert_eq!(get_header(&new_header(65536).unwrap()).unwrap().pagesize, 65536);
+    assert!(new_header(1000).is_err());
+}
+
+#[test]
+fn test_get_header_fields() {
+    let mut h = new_header(4096).unwrap();
+    h[48..52].copy_from_slice(&(-2000_i32).to_be_bytes());
+    h[60..64].copy_from_slice(&7_u32.to_be_bytes());
+    h[68..72].copy_from_slice(&0x0f0e_0d0c_u32.to_be_bytes());
+    set_freelist(&mut h, 5, 2);
+    let parsed = get_header(&h).unwrap();
+    assert_eq!((parsed.freelist_trunk, parsed.freelist_count), (5, 2));
+    assert_eq!((parsed.default_cache_size, parsed.user_version, parsed.application_id), (-2000, 7, 0x0f0e_0d0c));
+    assert_eq!((parsed.largest_root_btree_page, parsed.incremental_vacuum), (0, false));
+    // Incremental-vacuum mode is only valid in a database with pointer map pages.
+    h[64..68].copy_from_slice(&1_u32.to_be_bytes());
+    assert!(get_header(&h).is_err());
+    h[52..56].copy_from_slice(&3_u32.to_be_bytes());
+    let parsed = get_header(&h).unwrap();
+    assert_eq!((parsed.largest_root_btree_page, parsed.incremental_vacuum), (3, true));
+    assert_eq!(parsed.encoding, TextEncoding::Utf8);
+    h[56..60].copy_from_slice(&3_u32.to_be_bytes());
+    assert_eq!(get_header(&h).unwrap().encoding, TextEncoding::Utf16be);
+    h[56..60].copy_from_slice(&4_u32.to_be_bytes());
+    assert!(get_header(&h).is_err());
+    h[56..60].copy_from_slice(&1_u32.to_be_bytes());
+    // Pages may have reserved bytes, as long as at least 480 bytes of each page are usable.
+    h[20] = 8;
+    assert_eq!(get_header(&h).unwrap().reserved_bytes, 8);
+    h[16..18].copy_from_slice(&512_u16.to_be_bytes());
+    h[20] = 33;
+    assert!(get_header(&h).is_err());
+}
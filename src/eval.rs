@@ -0,0 +1,491 @@
+//! evaluates expressions on rows at runtime.
+//!
+//! An `ast::Expr` is first bound to the input of the block that will evaluate it, producing a `BoundExpr`.
+//! Binding does the checks that can be done once per query, so that evaluating a `BoundExpr` on each row is cheap.
+
+use anyhow::{bail, Result};
+
+use crate::ast;
+use crate::sql_value::SqlValue;
+use crate::typed_row::Row;
+
+/// holds an expression that is ready to be evaluated on input rows.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BoundExpr {
+    Constant(SqlValue),
+    /// the value of the input row's column at this index.
+    Column(usize),
+    BinOp {
+        lhs: Box<BoundExpr>,
+        op: ast::Op,
+        rhs: Box<BoundExpr>,
+    },
+    UnaryOp {
+        op: ast::UnaryOp,
+        expr: Box<BoundExpr>,
+    },
+    IsNull {
+        expr: Box<BoundExpr>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<BoundExpr>,
+        low: Box<BoundExpr>,
+        high: Box<BoundExpr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<BoundExpr>,
+        list: Vec<BoundExpr>,
+        negated: bool,
+    },
+}
+
+/// builds a `BoundExpr` from an AST expression, which is evaluated on rows with columns `column_names`.
+/// `table_names` holds the name (or alias) of the table that each column comes from, so that qualified
+/// column references like `t.a` can be resolved when rows hold columns from several tables.
+pub fn bind(expr: &ast::Expr, table_names: &[String], column_names: &[String]) -> Result<BoundExpr> {
+    Ok(match expr {
+        ast::Expr::Constant(c) => BoundExpr::Constant(crate::sql_value::from_ast_constant(c)),
+        ast::Expr::ColRef { tablename, colname } => {
+            let mut matches = column_names
+                .iter()
+                .zip(table_names)
+                .enumerate()
+                .filter(|(_, (c, t))| *c == colname && tablename.as_ref().is_none_or(|tn| tn == *t))
+                .map(|(idx, _)| idx);
+            match (matches.next(), matches.next()) {
+                (Some(idx), None) => BoundExpr::Column(idx),
+                (Some(_), Some(_)) => bail!("Ambiguous column name: {}", expr),
+                (None, _) => bail!("No such column: {}", expr),
+            }
+        }
+        ast::Expr::BinOp { lhs, op, rhs } => BoundExpr::BinOp {
+            lhs: Box::new(bind(lhs, table_names, column_names)?),
+            op: op.clone(),
+            rhs: Box::new(bind(rhs, table_names, column_names)?),
+        },
+        ast::Expr::Aggregate { .. } => bail!("Misuse of aggregate function: {}", expr),
+        ast::Expr::UnaryOp { op, expr } => BoundExpr::UnaryOp {
+            op: op.clone(),
+            expr: Box::new(bind(expr, table_names, column_names)?),
+        },
+        ast::Expr::IsNull { expr, negated } => BoundExpr::IsNull {
+            expr: Box::new(bind(expr, table_names, column_names)?),
+            negated: *negated,
+        },
+        ast::Expr::Between { expr, low, high, negated } => BoundExpr::Between {
+            expr: Box::new(bind(expr, table_names, column_names)?),
+            low: Box::new(bind(low, table_names, column_names)?),
+            high: Box::new(bind(high, table_names, column_names)?),
+            negated: *negated,
+        },
+        ast::Expr::InList { expr, list, negated } => BoundExpr::InList {
+            expr: Box::new(bind(expr, table_names, column_names)?),
+            list: list.iter().map(|e| bind(e, table_names, column_names)).collect::<Result<Vec<_>>>()?,
+            negated: *negated,
+        },
+    })
+}
+
+impl BoundExpr {
+    /// evaluates the expression on one input row.
+    pub fn eval(&self, row: &Row) -> Result<SqlValue> {
+        match self {
+            BoundExpr::Constant(v) => Ok(v.clone()),
+            BoundExpr::Column(idx) => match row.items.get(*idx) {
+                Some(v) => Ok(v.clone()),
+                None => bail!("Row has no column {}", idx),
+            },
+            BoundExpr::BinOp { lhs, op: ast::Op::And, rhs } => {
+                // Don't evaluate the right side if the left side already decides the outcome.
+                let l = to_bool(&lhs.eval(row)?);
+                if l == Some(false) {
+                    return Ok(from_bool(Some(false)));
+                }
+                Ok(from_bool(and(l, to_bool(&rhs.eval(row)?))))
+            }
+            BoundExpr::BinOp { lhs, op: ast::Op::Or, rhs } => {
+                let l = to_bool(&lhs.eval(row)?);
+                if l == Some(true) {
+                    return Ok(from_bool(Some(true)));
+                }
+                Ok(from_bool(or(l, to_bool(&rhs.eval(row)?))))
+            }
+            BoundExpr::BinOp { lhs, op, rhs } => {
+                let l = lhs.eval(row)?;
+                let r = rhs.eval(row)?;
+                binop(&l, op, &r)
+            }
+            BoundExpr::UnaryOp { op: ast::UnaryOp::Not, expr } => {
+                Ok(from_bool(to_bool(&expr.eval(row)?).map(|b| !b)))
+            }
+            BoundExpr::IsNull { expr, negated } => {
+                let is_null = matches!(expr.eval(row)?, SqlValue::Null());
+                Ok(from_bool(Some(is_null != *negated)))
+            }
+            BoundExpr::Between { expr, low, high, negated } => {
+                let v = expr.eval(row)?;
+                let ge_low = compare(&v, &ast::Op::GtEq, &low.eval(row)?);
+                let le_high = compare(&v, &ast::Op::LtEq, &high.eval(row)?);
+                Ok(from_bool(and(ge_low, le_high).map(|b| b != *negated)))
+            }
+            BoundExpr::InList { expr, list, negated } => {
+                let v = expr.eval(row)?;
+                // Like SQLite: true if any item is equal, else NULL if any comparison was NULL, else false.
+                let mut found: Option<bool> = Some(false);
+                for item in list {
+                    found = or(found, compare(&v, &ast::Op::Eq, &item.eval(row)?));
+                    if found == Some(true) {
+                        break;
+                    }
+                }
+                Ok(from_bool(found.map(|b| b != *negated)))
+            }
+        }
+    }
+}
+
+/// applies a binary operator, other than AND and OR, to two values.
+pub fn binop(l: &SqlValue, op: &ast::Op, r: &SqlValue) -> Result<SqlValue> {
+    use ast::Op::*;
+    match op {
+        Add | Subtract | Multiply | Divide => arithmetic(l, op, r),
+        Eq | NotEq | Lt | LtEq | Gt | GtEq => Ok(from_bool(compare(l, op, r))),
+        Concat => Ok(match (l, r) {
+            (SqlValue::Null(), _) | (_, SqlValue::Null()) => SqlValue::Null(),
+            _ => SqlValue::Text(to_text(l) + &to_text(r)),
+        }),
+        Like => Ok(match (l, r) {
+            (SqlValue::Null(), _) | (_, SqlValue::Null()) => SqlValue::Null(),
+            _ => from_bool(Some(like(&to_text(r), &to_text(l)))),
+        }),
+        And => Ok(from_bool(and(to_bool(l), to_bool(r)))),
+        Or => Ok(from_bool(or(to_bool(l), to_bool(r)))),
+    }
+}
+
+/// does arithmetic on two values.
+///
+/// Like SQLite, integer arithmetic which overflows is done in floating point instead, and division by zero is NULL.
+/// Unlike SQLite, arithmetic on Text or Blob values is an error rather than an implicit conversion.
+fn arithmetic(l: &SqlValue, op: &ast::Op, r: &SqlValue) -> Result<SqlValue> {
+    use ast::Op::*;
+    use SqlValue::*;
+    match (l, r) {
+        (Null(), _) | (_, Null()) => Ok(Null()),
+        (Int(i), Int(j)) => {
+            let checked = match op {
+                Add => i.checked_add(*j),
+                Subtract => i.checked_sub(*j),
+                Multiply => i.checked_mul(*j),
+                Divide => {
+                    if *j == 0 {
+                        return Ok(Null());
+                    }
+                    i.checked_div(*j)
+                }
+                _ => bail!("Not an arithmetic operator: {}", op),
+            };
+            match checked {
+                Some(k) => Ok(Int(k)),
+                None => real_arithmetic(*i as f64, op, *j as f64),
+            }
+        }
+        (Int(i), Real(j)) => real_arithmetic(*i as f64, op, *j),
+        (Real(i), Int(j)) => real_arithmetic(*i, op, *j as f64),
+        (Real(i), Real(j)) => real_arithmetic(*i, op, *j),
+        _ => bail!("Invalid types in binary expression: {} {} {}", l, op, r),
+    }
+}
+
+fn real_arithmetic(i: f64, op: &ast::Op, j: f64) -> Result<SqlValue> {
+    use ast::Op::*;
+    Ok(match op {
+        Add => SqlValue::Real(i + j),
+        Subtract => SqlValue::Real(i - j),
+        Multiply => SqlValue::Real(i * j),
+        Divide => {
+            if j == 0.0 {
+                return Ok(SqlValue::Null());
+            }
+            SqlValue::Real(i / j)
+        }
+        _ => bail!("Not an arithmetic operator: {}", op),
+    })
+}
+
+/// compares two values, returning `None` if either is NULL.
+///
+/// Values of different types are ordered as by `sql_value::collate`, so for example `1 = '1'` is false.
+fn compare(l: &SqlValue, op: &ast::Op, r: &SqlValue) -> Option<bool> {
+    use std::cmp::Ordering::*;
+    if matches!(l, SqlValue::Null()) || matches!(r, SqlValue::Null()) {
+        return None;
+    }
+    let o = crate::sql_value::collate(l, r);
+    Some(match op {
+        ast::Op::Eq => o == Equal,
+        ast::Op::NotEq => o != Equal,
+        ast::Op::Lt => o == Less,
+        ast::Op::LtEq => o != Greater,
+        ast::Op::Gt => o == Greater,
+        ast::Op::GtEq => o != Less,
+        _ => unreachable!("compare called with non-comparison operator"),
+    })
+}
+
+/// three-valued logical AND, where `None` means NULL.
+fn and(l: Option<bool>, r: Option<bool>) -> Option<bool> {
+    match (l, r) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }
+}
+
+/// three-valued logical OR, where `None` means NULL.
+fn or(l: Option<bool>, r: Option<bool>) -> Option<bool> {
+    match (l, r) {
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (Some(false), Some(false)) => Some(false),
+        _ => None,
+    }
+}
+
+/// returns the values of `row` after setting the columns at the positions in `assignments` to the values of their
+/// expressions.  Like in SQLite, every expression is evaluated on the original row.
+pub fn updated_row(row: &Row, assignments: &[(usize, BoundExpr)]) -> Result<Vec<SqlValue>> {
+    let mut items = row.items.clone();
+    for (i, e) in assignments {
+        items[*i] = e.eval(row)?;
+    }
+    Ok(items)
+}
+
+/// represents a truth value the way SQLite does: as integer 1 or 0, or NULL.
+fn from_bool(b: Option<bool>) -> SqlValue {
+    match b {
+        None => SqlValue::Null(),
+        Some(b) => SqlValue::Int(b as i64),
+    }
+}
+
+/// converts a non-null value to text, as for `||` and `LIKE`.
+pub fn to_text(v: &SqlValue) -> String {
+    match v {
+        // SQLite shows integral reals with a decimal point, unlike Rust's `Display` for f64.
+        SqlValue::Real(f) if f.fract() == 0.0 && f.abs() < 1e15 => format!("{:.1}", f),
+        SqlValue::Blob(b) => String::from_utf8_lossy(b).into_owned(),
+        v => v.to_string(),
+    }
+}
+
+/// matches `text` against a LIKE `pattern`, where `%` matches any sequence of characters and `_` matches any one character.
+/// Like SQLite, matching ignores the case of ASCII letters.
+fn like(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let t: Vec<char> = text.chars().map(|c| c.to_ascii_lowercase()).collect();
+    // Iterative matching with backtracking to the most recent `%`.
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '_' || (p[pi] != '%' && p[pi] == t[ti])) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '%' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            pi = bp + 1;
+            ti = bt + 1;
+            backtrack = Some((bp, bt + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '%')
+}
+
+/// interprets a value as a truth value, the way SQLite does for `WHERE` clauses.
+///
+/// Returns `None` for NULL, which is neither true nor false.
+/// Numbers are true when they are non-zero.  Text is true when it begins with a non-zero number.
+pub fn to_bool(v: &SqlValue) -> Option<bool> {
+    match v {
+        SqlValue::Null() => None,
+        SqlValue::Bool(b) => Some(*b),
+        SqlValue::Int(i) => Some(*i != 0),
+        SqlValue::Real(f) => Some(*f != 0.0),
+        SqlValue::Text(s) => Some(leading_number(s) != 0.0),
+        SqlValue::Blob(_) => Some(false),
+    }
+}
+
+/// returns the value of the longest prefix of `s` that is a number, or zero.
+fn leading_number(s: &str) -> f64 {
+    let s = s.trim_start();
+    let end = s
+        .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+        .unwrap_or(s.len());
+    (1..=end)
+        .rev()
+        .find_map(|i| s[..i].parse::<f64>().ok())
+        .unwrap_or(0.0)
+}
+
+#[test]
+fn test_eval_arithmetic() {
+    use ast::Op::*;
+    use SqlValue::*;
+    let cases = vec![
+        (Int(1), Add, Int(2), Int(3)),
+        (Int(7), Divide, Int(2), Int(3)),
+        (Int(7), Divide, Int(0), Null()),
+        (Real(1.5), Multiply, Int(2), Real(3.0)),
+        (Int(1), Subtract, Null(), Null()),
+        (Int(i64::MAX), Add, Int(1), Real(i64::MAX as f64 + 1.0)),
+    ];
+    let empty = Row { items: vec![] };
+    for (l, op, r, expected) in cases {
+        let e = BoundExpr::BinOp {
+            lhs: Box::new(BoundExpr::Constant(l)),
+            op,
+            rhs: Box::new(BoundExpr::Constant(r)),
+        };
+        assert_eq!(e.eval(&empty).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_eval_arithmetic_on_text_is_error() {
+    let e = BoundExpr::BinOp {
+        lhs: Box::new(BoundExpr::Constant(SqlValue::Text("foo".to_string()))),
+        op: ast::Op::Add,
+        rhs: Box::new(BoundExpr::Constant(SqlValue::Int(1))),
+    };
+    assert!(e.eval(&Row { items: vec![] }).is_err());
+}
+
+#[test]
+fn test_to_bool() {
+    use SqlValue::*;
+    assert_eq!(to_bool(&Null()), None);
+    assert_eq!(to_bool(&Int(0)), Some(false));
+    assert_eq!(to_bool(&Int(-3)), Some(true));
+    assert_eq!(to_bool(&Real(0.5)), Some(true));
+    assert_eq!(to_bool(&Text("12abc".to_string())), Some(true));
+    assert_eq!(to_bool(&Text("abc".to_string())), Some(false));
+}
+
+#[cfg(test)]
+fn eval_parsed(expr: &str) -> SqlValue {
+    use crate::parser::{parse_expr, Rule, SQLParser};
+    use pest::Parser;
+    let pairs = SQLParser::parse(Rule::expr, expr).unwrap().next().unwrap().into_inner();
+    bind(&parse_expr(pairs), &[], &[]).unwrap().eval(&Row { items: vec![] }).unwrap()
+}
+
+#[test]
+fn test_eval_operators() {
+    use SqlValue::*;
+    let cases = vec![
+        ("1 = 1", Int(1)),
+        ("1 = 1.0", Int(1)),
+        ("1 <> 2", Int(1)),
+        ("1 != 1", Int(0)),
+        ("1 = '1'", Int(0)),
+        ("'abc' < 'abd'", Int(1)),
+        ("2 <= 2", Int(1)),
+        ("3 > 2.5", Int(1)),
+        ("3 >= 4", Int(0)),
+        ("1 = NULL", Null()),
+        ("1 < 2 AND 2 < 3", Int(1)),
+        ("1 < 2 AND 3 < 2", Int(0)),
+        ("NULL AND 0", Int(0)),
+        ("NULL AND 1", Null()),
+        ("NULL OR 1", Int(1)),
+        ("NULL OR 0", Null()),
+        ("NOT 0", Int(1)),
+        ("NOT NULL", Null()),
+        ("NOT 1 = 2", Int(1)),
+        ("1 OR 0 AND 0", Int(1)),
+        ("(1 OR 0) AND 0", Int(0)),
+        ("NULL IS NULL", Int(1)),
+        ("1 IS NULL", Int(0)),
+        ("1 IS NOT NULL", Int(1)),
+        ("2 BETWEEN 1 AND 3", Int(1)),
+        ("2 BETWEEN 1 + 2 AND 3 AND 1", Int(0)),
+        ("4 NOT BETWEEN 1 AND 3", Int(1)),
+        ("2 BETWEEN NULL AND 1", Int(0)),
+        ("2 BETWEEN NULL AND 3", Null()),
+        ("2 IN (1, 2, 3)", Int(1)),
+        ("5 IN (1, 2, 3)", Int(0)),
+        ("5 IN (1, NULL)", Null()),
+        ("1 IN (1, NULL)", Int(1)),
+        ("5 NOT IN (1, 2)", Int(1)),
+        ("NULL IN (1, 2)", Null()),
+        ("'Hello' LIKE 'h%o'", Int(1)),
+        ("'Hello' LIKE 'h_llo'", Int(1)),
+        ("'Hello' LIKE 'h_lo'", Int(0)),
+        ("'Hello' NOT LIKE '%x%'", Int(1)),
+        ("'a' || 'b' || 1", Text("ab1".to_string())),
+        ("'a' || 1.0", Text("a1.0".to_string())),
+        ("'a' || NULL", Null()),
+        ("1 + 2 = 3", Int(1)),
+        ("1 + 2 * 3 = 7", Int(1)),
+        ("(1 + 2) * 3", Int(9)),
+        ("1 < 2 = 1", Int(1)),
+    ];
+    for (expr, expected) in cases {
+        println!("Case: {}", expr);
+        assert_eq!(eval_parsed(expr), expected);
+    }
+}
+
+#[test]
+fn test_like() {
+    let cases = vec![
+        ("%", "", true),
+        ("", "", true),
+        ("", "a", false),
+        ("a%", "abc", true),
+        ("%c", "abc", true),
+        ("%b%", "abc", true),
+        ("a%c%e", "abcde", true),
+        ("a%c%e", "abcdf", false),
+        ("_", "", false),
+        ("ABC", "abc", true),
+    ];
+    for (pattern, text, expected) in cases {
+        assert_eq!(like(pattern, text), expected, "{} LIKE {}", text, pattern);
+    }
+}
+
+#[test]
+fn test_eval_column_refs() {
+    use crate::parser::{parse_expr, Rule, SQLParser};
+    use pest::Parser;
+    use SqlValue::*;
+    // Rows of `t` joined with `u`, which both have a `qty` column.
+    let table_names: Vec<String> = vec!["t".to_string(), "t".to_string(), "u".to_string()];
+    let column_names: Vec<String> = vec!["price".to_string(), "qty".to_string(), "qty".to_string()];
+    let row = Row { items: vec![Real(1.5), Int(4), Int(7)] };
+    let cases = vec![
+        ("price * t.qty", Ok(Real(6.0))),
+        ("t.qty + 1", Ok(Int(5))),
+        ("u.qty", Ok(Int(7))),
+        ("t.qty > 3 AND price < 2", Ok(Int(1))),
+        ("qty", Err(())),
+        ("v.qty", Err(())),
+        ("nosuchcolumn", Err(())),
+    ];
+    for (expr, expected) in cases {
+        println!("Case: {}", expr);
+        let pairs = SQLParser::parse(Rule::expr, expr).unwrap().next().unwrap().into_inner();
+        match bind(&parse_expr(pairs), &table_names, &column_names) {
+            Ok(b) => assert_eq!(b.eval(&row).unwrap(), expected.unwrap()),
+            Err(_) => assert!(expected.is_err()),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! provides helper functions for the filter block of a query.
+
+use crate::eval::{self, BoundExpr};
+use crate::Row;
+use anyhow::Result;
+
+use crate::ast;
+
+/// builds the information needed to filter the rows of a table at runtime.
+pub fn build_filter(in_tablenames: &[String], in_colnames: &[String], predicate: &ast::Expr) -> Result<BoundExpr> {
+    eval::bind(predicate, in_tablenames, in_colnames)
+}
+
+/// does the "Select" action of the relational algebra, using a pre-built predicate.
+///
+/// Returns true if the row should be kept.  Rows for which the predicate is NULL are not kept.
+pub fn filter_row(predicate: &BoundExpr, input: &Row) -> Result<bool> {
+    Ok(eval::to_bool(&predicate.eval(input)?) == Some(true))
+}
+
+#[test]
+fn test_filter_row_constant_predicates() {
+    use crate::sql_value::SqlValue::*;
+    let input = Row {
+        items: vec![Int(0), Int(10)],
+    };
+    let cases = vec![
+        (ast::Constant::Int(1), true),
+        (ast::Constant::Int(0), false),
+        (ast::Constant::Bool(true), true),
+        (ast::Constant::Bool(false), false),
+        (ast::Constant::Null(), false),
+    ];
+    for (c, expected) in cases {
+        let predicate = build_filter(&[], &[], &ast::Expr::Constant(c)).unwrap();
+        assert_eq!(filter_row(&predicate, &input).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_filter_row_column_predicate() {
+    use crate::sql_value::SqlValue::*;
+    let colnames = vec!["a".to_string(), "b".to_string()];
+    let tablenames = vec![String::from("t"); colnames.len()];
+    let predicate = ast::Expr::BinOp {
+        lhs: Box::new(ast::Expr::ColRef { tablename: None, colname: "b".to_string() }),
+        op: ast::Op::Gt,
+        rhs: Box::new(ast::Expr::Constant(ast::Constant::Int(5))),
+    };
+    let predicate = build_filter(&tablenames, &colnames, &predicate).unwrap();
+    assert!(filter_row(&predicate, &Row { items: vec![Int(0), Int(10)] }).unwrap());
+    assert!(!filter_row(&predicate, &Row { items: vec![Int(0), Int(1)] }).unwrap());
+    assert!(!filter_row(&predicate, &Row { items: vec![Int(0), Null()] }).unwrap());
+}
@@ -45,20 +45,31 @@ use crate::ast;
 
 use enum_as_inner::EnumAsInner;
 use std::boxed::Box;
+use std::ops::Bound;
 
 /// `Block` represents any of the IR blocks that can be chained together.
 /// A Block takes rows in from 0, one or more sources, and emits rows to a parent block.
 #[derive(Debug, Clone, PartialEq, EnumAsInner)]
 pub enum Block {
     Scan(Scan),
+    SeekRowid(SeekRowid),
+    RowidRange(RowidRange),
+    IndexSeekEq(IndexSeekEq),
+    IndexRange(IndexRange),
     Project(Project),
     ConstantRow(ConstantRow),
+    Filter(Filter),
+    Sort(Sort),
+    Limit(Limit),
+    Aggregate(Aggregate),
+    Join(Join),
 }
 
 /// `ConstantRow` represents a table that has one row.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ConstantRow {
     pub row: Vec<ast::Constant>,
+    pub colnames: Vec<String>,
 }
 
 /// `Scan` represents a one-pass scan over all the rows of a table.
@@ -66,6 +77,52 @@ pub struct ConstantRow {
 pub struct Scan {
     pub databasename: String,
     pub tablename: String,
+    /// the name that expressions above the scan use to qualify the table's columns, if not the table name.
+    pub alias: Option<String>,
+}
+
+/// `SeekRowid` represents finding the one row of a stored table with a given rowid, by searching the table's btree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeekRowid {
+    pub databasename: String,
+    pub tablename: String,
+    pub alias: Option<String>,
+    pub rowid: i64,
+}
+
+/// `RowidRange` represents a scan over only the rows of a stored table with rowids from `min` to `max`, inclusive.
+/// The scan starts by searching the table's btree for `min`, and stops after `max`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowidRange {
+    pub databasename: String,
+    pub tablename: String,
+    pub alias: Option<String>,
+    pub min: i64,
+    pub max: i64,
+}
+
+/// `IndexSeekEq` represents finding the rows of a stored table whose first indexed columns equal `key`, by searching
+/// the index `indexname` for `key`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexSeekEq {
+    pub databasename: String,
+    pub tablename: String,
+    pub alias: Option<String>,
+    pub indexname: String,
+    pub key: Vec<ast::Constant>,
+}
+
+/// `IndexRange` represents finding the rows of a stored table whose first indexed columns equal `eq`, and whose next
+/// indexed column is between `lower` and `upper`, by searching the index `indexname`.  A NULL is never in the range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexRange {
+    pub databasename: String,
+    pub tablename: String,
+    pub alias: Option<String>,
+    pub indexname: String,
+    pub eq: Vec<ast::Constant>,
+    pub lower: Bound<ast::Constant>,
+    pub upper: Bound<ast::Constant>,
 }
 
 /// `Project` represents the projection operation: taking a subset of columns, and computing new columns.
@@ -74,3 +131,67 @@ pub struct Project {
     pub outcols: Vec<ast::SelItem>,
     pub input: Box<Block>,
 }
+
+/// `Filter` represents the selection operation: passing on only those rows for which `predicate` is true.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub predicate: ast::Expr,
+    pub input: Box<Block>,
+}
+
+/// `Sort` represents ordering the rows of the input by one or more keys.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sort {
+    pub keys: Vec<SortKey>,
+    pub input: Box<Block>,
+}
+
+/// `SortKey` is an expression to sort by, evaluated on the rows of the input of a `Sort`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub expr: ast::Expr,
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+/// `Limit` represents skipping the first `offset` rows of the input, and then passing on at most `limit` rows.
+/// A `limit` of `None` means no limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub limit: Option<usize>,
+    pub offset: usize,
+    pub input: Box<Block>,
+}
+
+/// `Aggregate` represents grouping the rows of the input by the values of the `group_by` expressions, and computing
+/// aggregate functions over each group.
+/// It emits one row per group, with a column for each group by expression and then a column for each aggregate.
+/// With no group by expressions, all input rows are in one group, and exactly one row is emitted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    pub group_by: Vec<ast::Expr>,
+    pub aggregates: Vec<AggregateCall>,
+    pub colnames: Vec<String>,
+    pub input: Box<Block>,
+}
+
+/// `AggregateCall` is one aggregate function to compute over each group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateCall {
+    pub func: ast::AggregateFunc,
+    pub args: Vec<ast::Expr>,
+}
+
+/// `Join` represents combining each row of `left` with each row of `right` for which the `on` condition is true.
+/// The output rows have the columns of `left` followed by the columns of `right`.
+///
+/// A left join also emits each row of `left` that matched no row of `right`, with NULLs for the columns of `right`.
+/// When the condition includes equalities between the two sides, the join is done by hashing the rows of `right` on the
+/// values they are compared by; otherwise, `right` is scanned again for every row of `left` (a nested-loop join).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub kind: ast::JoinKind,
+    pub on: Option<ast::Expr>,
+    pub left: Box<Block>,
+    pub right: Box<Block>,
+}
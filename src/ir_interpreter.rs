@@ -1,97 +1,751 @@
 //! executes SQL intermediate representation (IR).
+//!
+//! Each IR block is converted into a `RowStream`, which pulls rows from the streams of its child blocks.
+//! The stream for the top block is then run to completion, collecting its rows into a `TempTable`.
 
-use anyhow::{Context, Result};
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
 use streaming_iterator::StreamingIterator;
 
-use crate::ast;
+use crate::aggregate;
+use crate::btree::RowId;
+use crate::eval::BoundExpr;
+use crate::filter;
 use crate::ir;
+use crate::join;
 use crate::project;
-use crate::sql_type;
-use crate::sql_value;
-use crate::table_traits::TableMeta;
+use crate::sort;
+use crate::sql_type::{self, SqlType};
+use crate::sql_value::{self, SqlValue};
 use crate::stored_table::StoredTable;
+use crate::table_traits::{RowStream, TableMeta};
 use crate::typed_row::Row;
 use crate::TempTable;
 
-fn project_any_table_into_temp_table<T, I>(in_tbl: &T, in_it: I, out_cols: &[ast::SelItem]) -> Result<crate::TempTable>
-where
-    T: TableMeta,
-    I: StreamingIterator<Item = Row>,
-{
-    let (actions, column_names, column_types) =
-    project::build_project(&in_tbl.column_names(), &in_tbl.column_types(), out_cols)?;
-    let mut it = in_it.map(|row| project::project_row(&actions, row));
-    let mut rows: Vec<Row> = vec![];
-    loop {
-        it.advance();
-        if it.get().is_none() {
-            break;
+/// holds the rows produced by an IR block, along with the names and types of its columns.
+struct BlockStream<'a> {
+    rows: Box<dyn RowStream + 'a>,
+    /// the name of the table that each column comes from, which can differ between columns after a join.
+    table_names: Vec<String>,
+    column_names: Vec<String>,
+    column_types: Vec<SqlType>,
+    /// whether each column is hidden, like the rowid of a stored table, which can be referred to by name but is not
+    /// selected by `*`.
+    hidden: Vec<bool>,
+}
+
+/// iterates over rows which are owned by the iterator, such as constant rows.
+struct RowVecStream {
+    it: std::vec::IntoIter<Row>,
+    item: Option<Row>,
+}
+
+impl StreamingIterator for RowVecStream {
+    type Item = Row;
+
+    fn advance(&mut self) {
+        self.item = self.it.next();
+    }
+
+    fn get(&self) -> Option<&Row> {
+        self.item.as_ref()
+    }
+}
+
+impl RowStream for RowVecStream {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        None
+    }
+}
+
+/// passes on only the rows of the input for which a predicate is true.
+struct FilterStream<'a> {
+    input: Box<dyn RowStream + 'a>,
+    predicate: BoundExpr,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> StreamingIterator for FilterStream<'a> {
+    type Item = Row;
+
+    fn advance(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        loop {
+            self.input.advance();
+            let keep = match self.input.get() {
+                None => return,
+                Some(row) => filter::filter_row(&self.predicate, row),
+            };
+            match keep {
+                Ok(true) => return,
+                Ok(false) => continue,
+                Err(e) => {
+                    self.error = Some(e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn get(&self) -> Option<&Row> {
+        match self.error {
+            Some(_) => None,
+            None => self.input.get(),
+        }
+    }
+}
+
+impl<'a> RowStream for FilterStream<'a> {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take().or_else(|| self.input.take_error())
+    }
+}
+
+/// computes the output columns of each row of the input.
+struct ProjectStream<'a> {
+    input: Box<dyn RowStream + 'a>,
+    actions: Vec<project::ProjectAction>,
+    item: Option<Row>,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> StreamingIterator for ProjectStream<'a> {
+    type Item = Row;
+
+    fn advance(&mut self) {
+        if self.error.is_some() {
+            return;
         }
-        let res = it.get().unwrap().as_ref();
-        match res {
+        self.input.advance();
+        self.item = match self.input.get() {
+            None => None,
+            Some(row) => match project::project_row(&self.actions, row) {
+                Ok(r) => Some(r),
+                Err(e) => {
+                    self.error = Some(anyhow::anyhow!("Not able to convert value: {}", e));
+                    None
+                }
+            },
+        }
+    }
+
+    fn get(&self) -> Option<&Row> {
+        self.item.as_ref()
+    }
+}
+
+impl<'a> RowStream for ProjectStream<'a> {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take().or_else(|| self.input.take_error())
+    }
+}
+
+/// passes on the rows of the input in sorted order.
+///
+/// The whole input has to be read before the first row can be returned, so that is done on the first call to `advance`.
+struct SortStream<'a> {
+    input: Box<dyn RowStream + 'a>,
+    sorter: Option<sort::Sorter>,
+    sorted: Option<sort::SortedRows>,
+    item: Option<Row>,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> SortStream<'a> {
+    fn sort_input(&mut self) -> Result<sort::SortedRows> {
+        let mut sorter = self.sorter.take().unwrap();
+        while let Some(row) = self.input.next() {
+            sorter.push(row)?;
+        }
+        if let Some(e) = self.input.take_error() {
+            return Err(e);
+        }
+        sorter.finish()
+    }
+}
+
+impl<'a> StreamingIterator for SortStream<'a> {
+    type Item = Row;
+
+    fn advance(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        if self.sorted.is_none() {
+            if self.sorter.is_none() {
+                // Already done, or failed.
+                self.item = None;
+                return;
+            }
+            match self.sort_input() {
+                Ok(s) => self.sorted = Some(s),
+                Err(e) => {
+                    self.error = Some(e);
+                    self.item = None;
+                    return;
+                }
+            }
+        }
+        match self.sorted.as_mut().unwrap().next_row() {
+            Ok(r) => self.item = r,
             Err(e) => {
-                return Err(anyhow::anyhow!(format!("Not able to convert value: {}", e)))
+                self.error = Some(e);
+                self.item = None;
             }
-            Ok(r) => rows.push(r.clone()),
         }
     }
-    Ok(TempTable {
-        rows,
-        table_name: String::from("?unnamed?"),
-        column_names,
-        column_types,
-        strict: false,  // SQLite defaults to non-strict, so result tables (without an explicit CREATE) shall be non-strict.
-    })
+
+    fn get(&self) -> Option<&Row> {
+        self.item.as_ref()
+    }
 }
 
-/// Run an IR representation of a query, returning a TempTable with the results of the query.
-pub fn run_ir(server_state: &crate::DbServerState, ir: &ir::Block) -> Result<crate::TempTable> {
-    match ir {
-        ir::Block::Project(p) => {
-            let child = p
-                .input
-                .as_scan()
-                .context("Project should only have Scan as child")?;
-            match child.databasename == "temp" {
-                true => {
-                    let tbl = server_state.temp_db.get_table(&child.tablename)?;
-                    let base_it = tbl.streaming_iterator();
-                    project_any_table_into_temp_table(tbl, base_it, &p.outcols)
+impl<'a> RowStream for SortStream<'a> {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take().or_else(|| self.input.take_error())
+    }
+}
+
+/// groups the rows of the input and passes on one row of aggregates for each group.
+///
+/// Like sorting, the whole input has to be read first, which is done on the first call to `advance`.
+struct AggregateStream<'a> {
+    input: Box<dyn RowStream + 'a>,
+    group_by: Vec<BoundExpr>,
+    aggregates: Vec<aggregate::BoundAggregate>,
+    rows: Option<std::vec::IntoIter<Row>>,
+    item: Option<Row>,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> AggregateStream<'a> {
+    fn aggregate_input(&mut self) -> Result<Vec<Row>> {
+        let rows = aggregate::hash_aggregate(&mut *self.input, &self.group_by, &self.aggregates)?;
+        if let Some(e) = self.input.take_error() {
+            return Err(e);
+        }
+        Ok(rows)
+    }
+}
+
+impl<'a> StreamingIterator for AggregateStream<'a> {
+    type Item = Row;
+
+    fn advance(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        if self.rows.is_none() {
+            match self.aggregate_input() {
+                Ok(rows) => self.rows = Some(rows.into_iter()),
+                Err(e) => {
+                    self.error = Some(e);
+                    self.item = None;
+                    return;
                 }
-                false => {
-                    // TODO: this should be a reference to a Table held by the DB, not a Table created here on the stack.
-                    if server_state.stored_db.is_none() { anyhow::bail!("No open database, but main database referenced in query.") };
-                    let db = server_state.stored_db.as_ref().unwrap();
-                    let tbl: StoredTable<'_> = db.open_table_for_read(child.tablename.as_str())?;
-                    let base_it = tbl.streaming_iterator();
-                    project_any_table_into_temp_table(&tbl, base_it, &p.outcols)
+            }
+        }
+        self.item = self.rows.as_mut().unwrap().next();
+    }
+
+    fn get(&self) -> Option<&Row> {
+        self.item.as_ref()
+    }
+}
+
+impl<'a> RowStream for AggregateStream<'a> {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take().or_else(|| self.input.take_error())
+    }
+}
+
+/// passes on at most `limit` rows of the input, after skipping `offset` rows.
+///
+/// Once the limit is reached, the input is not advanced any further, so the scans under it stop reading the table.
+struct LimitStream<'a> {
+    input: Box<dyn RowStream + 'a>,
+    limit: Option<usize>,
+    offset: usize,
+    returned: usize,
+    done: bool,
+}
+
+impl<'a> StreamingIterator for LimitStream<'a> {
+    type Item = Row;
+
+    fn advance(&mut self) {
+        if self.done {
+            return;
+        }
+        while self.offset > 0 {
+            self.offset -= 1;
+            self.input.advance();
+            if self.input.get().is_none() {
+                self.done = true;
+                return;
+            }
+        }
+        if Some(self.returned) == self.limit {
+            self.done = true;
+            return;
+        }
+        self.input.advance();
+        self.returned += 1;
+    }
+
+    fn get(&self) -> Option<&Row> {
+        match self.done {
+            true => None,
+            false => self.input.get(),
+        }
+    }
+}
+
+impl<'a> RowStream for LimitStream<'a> {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.input.take_error()
+    }
+}
+
+/// is where a `JoinStream` gets the rows of its right input from.
+enum JoinRight<'a> {
+    /// opens the right input again, so that it can be scanned once for every row of the left input.
+    NestedLoop(Box<dyn Fn() -> Result<Box<dyn RowStream + 'a>> + 'a>),
+    /// the right input, which is read into a hash table on the first call to `advance`.
+    Hash {
+        input: Option<Box<dyn RowStream + 'a>>,
+        table: Option<join::HashTable>,
+    },
+}
+
+/// passes on the rows of the left input joined with the matching rows of the right input.
+struct JoinStream<'a> {
+    left: Box<dyn RowStream + 'a>,
+    right: JoinRight<'a>,
+    join: join::BoundJoin,
+    /// the joined rows for the current left row which have not been returned yet.
+    matches: std::vec::IntoIter<Row>,
+    item: Option<Row>,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> JoinStream<'a> {
+    fn join_left_row(&mut self, left: &Row) -> Result<Vec<Row>> {
+        match &mut self.right {
+            JoinRight::NestedLoop(open) => {
+                let mut right = open()?;
+                let rows = self.join.join_row(left, &mut *right)?;
+                if let Some(e) = right.take_error() {
+                    return Err(e);
                 }
+                Ok(rows)
+            }
+            JoinRight::Hash { input, table } => {
+                if table.is_none() {
+                    let mut input = input.take().unwrap();
+                    let t = join::HashTable::build(&mut *input, &self.join)?;
+                    if let Some(e) = input.take_error() {
+                        return Err(e);
+                    }
+                    *table = Some(t);
+                }
+                let candidates = table.as_ref().unwrap().candidates(&self.join, left)?;
+                self.join.join_row(left, &mut streaming_iterator::convert_ref(candidates))
+            }
+        }
+    }
+}
+
+impl<'a> StreamingIterator for JoinStream<'a> {
+    type Item = Row;
+
+    fn advance(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        loop {
+            if let Some(row) = self.matches.next() {
+                self.item = Some(row);
+                return;
+            }
+            self.left.advance();
+            let left = match self.left.get() {
+                None => {
+                    self.item = None;
+                    return;
+                }
+                Some(row) => row.clone(),
+            };
+            match self.join_left_row(&left) {
+                Ok(rows) => self.matches = rows.into_iter(),
+                Err(e) => {
+                    self.error = Some(e);
+                    self.item = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn get(&self) -> Option<&Row> {
+        self.item.as_ref()
+    }
+}
+
+impl<'a> RowStream for JoinStream<'a> {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take().or_else(|| self.left.take_error())
+    }
+}
+
+/// describes which rows of a table to read, and how to find them.
+enum TableAccess {
+    /// the rows with rowids from the first to the second, inclusive, found by searching the table's btree.
+    RowidRange(RowId, RowId),
+    /// the rows found by searching an index, whose indexed columns start with the values `eq`, and then, if `range` is
+    /// given, have a value in the range.
+    Index {
+        indexname: String,
+        eq: Vec<SqlValue>,
+        range: Option<(Bound<SqlValue>, Bound<SqlValue>)>,
+    },
+}
+
+impl TableAccess {
+    fn all_rows() -> TableAccess {
+        TableAccess::RowidRange(RowId::MIN, RowId::MAX)
+    }
+}
+
+/// builds the stream of rows for a scan of a table, limited to the rows selected by `access`.
+///
+/// The rows of a stored table have its rowid as a hidden last column, unless it has a column named `rowid`, as in SQLite.
+fn scan_stream<'a>(
+    server_state: &'a crate::DbServerState,
+    databasename: &str,
+    tablename: &str,
+    alias: &Option<String>,
+    access: TableAccess,
+) -> Result<BlockStream<'a>> {
+    let whole_table = matches!(access, TableAccess::RowidRange(RowId::MIN, RowId::MAX));
+    match databasename == "temp" {
+        true => {
+            if !whole_table {
+                bail!("Temporary table {} cannot be searched.", tablename);
+            }
+            let tbl = server_state.temp_db.get_table(&tablename.to_string())?;
+            Ok(BlockStream {
+                rows: Box::new(tbl.streaming_iterator()),
+                table_names: vec![alias.clone().unwrap_or_else(|| tbl.table_name()); tbl.column_names().len()],
+                column_names: tbl.column_names(),
+                column_types: tbl.column_types(),
+                hidden: vec![false; tbl.column_names().len()],
+            })
+        }
+        false => {
+            // TODO: lock the table in the pager when opening the table for read.
+            // TODO: if we previously loaded the schema speculatively during IR optimization, verify unchanged now, e.g. with hash.
+            if server_state.stored_db.is_none() { anyhow::bail!("No open database, but main database referenced in query.") };
+            let db = server_state.stored_db.as_ref().unwrap();
+            let tbl: StoredTable<'a> = db.open_table_for_read(tablename)?;
+            let mut column_names = tbl.column_names();
+            let mut column_types = tbl.column_types();
+            let mut hidden = vec![false; column_names.len()];
+            let with_rowid = !column_names.iter().any(|c| c == "rowid");
+            if with_rowid {
+                column_names.push(String::from("rowid"));
+                column_types.push(SqlType::Int);
+                hidden.push(true);
             }
+            let rows: Box<dyn RowStream + 'a> = match access {
+                TableAccess::RowidRange(..) if !with_rowid && !whole_table => {
+                    bail!("Internal error: table {} has a column named rowid, so it cannot be searched by rowid.", tablename)
+                }
+                TableAccess::RowidRange(..) if !with_rowid => Box::new(tbl.streaming_iterator()),
+                TableAccess::RowidRange(min, max) => Box::new(tbl.rowid_range_streaming_iterator(min, max)),
+                TableAccess::Index { indexname, eq, range } => {
+                    let index = db.get_index(tablename, &indexname)?;
+                    Box::new(tbl.index_streaming_iterator(index.root_pagenum, eq, range, with_rowid)?)
+                }
+            };
+            Ok(BlockStream {
+                rows,
+                table_names: vec![alias.clone().unwrap_or_else(|| tbl.table_name()); column_names.len()],
+                column_names,
+                column_types,
+                hidden,
+            })
+        }
+    }
+}
+
+/// builds the stream of rows for `block`, including the streams of any blocks it takes input from.
+fn build_stream<'a>(server_state: &'a crate::DbServerState, block: &ir::Block) -> Result<BlockStream<'a>> {
+    match block {
+        ir::Block::Scan(s) => scan_stream(server_state, &s.databasename, &s.tablename, &s.alias, TableAccess::all_rows()),
+        ir::Block::SeekRowid(s) => {
+            let access = TableAccess::RowidRange(s.rowid, s.rowid);
+            scan_stream(server_state, &s.databasename, &s.tablename, &s.alias, access)
+        }
+        ir::Block::RowidRange(r) => {
+            let access = TableAccess::RowidRange(r.min, r.max);
+            scan_stream(server_state, &r.databasename, &r.tablename, &r.alias, access)
+        }
+        ir::Block::IndexSeekEq(i) => {
+            let access = TableAccess::Index {
+                indexname: i.indexname.clone(),
+                eq: i.key.iter().map(sql_value::from_ast_constant).collect(),
+                range: None,
+            };
+            scan_stream(server_state, &i.databasename, &i.tablename, &i.alias, access)
         }
-        ir::Block::ConstantRow(cr) => {
-            return Ok(TempTable {
-                rows: vec![Row {
+        ir::Block::IndexRange(i) => {
+            let access = TableAccess::Index {
+                indexname: i.indexname.clone(),
+                eq: i.eq.iter().map(sql_value::from_ast_constant).collect(),
+                range: Some((
+                    i.lower.as_ref().map(sql_value::from_ast_constant),
+                    i.upper.as_ref().map(sql_value::from_ast_constant),
+                )),
+            };
+            scan_stream(server_state, &i.databasename, &i.tablename, &i.alias, access)
+        }
+        ir::Block::ConstantRow(cr) => Ok(BlockStream {
+            rows: Box::new(RowVecStream {
+                it: vec![Row {
                     items: cr.row.iter().map(sql_value::from_ast_constant).collect(),
-                }],
-                table_name: String::from("?unnamed?"),
-                column_names: (0..cr.row.len()).map(|i| format!("_f{i}")).collect(),
-                column_types: cr.row.iter().map(sql_type::from_ast_constant).collect(),
-                strict: false,  // SQLite defaults to non-strict, so result tables (without an explicit CREATE) shall be non-strict.
-            });
-        }
-        ir::Block::Scan(s) => {
-            match s.databasename == "temp" {
-                true => Ok(server_state.temp_db.get_table(&s.tablename)?.clone()),
+                }]
+                .into_iter(),
+                item: None,
+            }),
+            table_names: vec![String::from("?unnamed?"); cr.colnames.len()],
+            column_names: cr.colnames.clone(),
+            column_types: cr.row.iter().map(sql_type::from_ast_constant).collect(),
+            hidden: vec![false; cr.colnames.len()],
+        }),
+        ir::Block::Filter(f) => {
+            let input = build_stream(server_state, &f.input)?;
+            let predicate = filter::build_filter(&input.table_names, &input.column_names, &f.predicate)?;
+            Ok(BlockStream {
+                rows: Box::new(FilterStream {
+                    input: input.rows,
+                    predicate,
+                    error: None,
+                }),
+                ..input
+            })
+        }
+        ir::Block::Sort(s) => {
+            let input = build_stream(server_state, &s.input)?;
+            let keys = sort::build_sort(&input.table_names, &input.column_names, &s.keys)?;
+            Ok(BlockStream {
+                rows: Box::new(SortStream {
+                    input: input.rows,
+                    sorter: Some(sort::Sorter::new(keys, sort::DEFAULT_SORT_MEMORY_BYTES)),
+                    sorted: None,
+                    item: None,
+                    error: None,
+                }),
+                ..input
+            })
+        }
+        ir::Block::Aggregate(a) => {
+            let input = build_stream(server_state, &a.input)?;
+            let (group_by, aggregates, column_types) =
+                aggregate::build_aggregate(&input.table_names, &input.column_names, &input.column_types, a)?;
+            // A group by column keeps the name of its table, so that qualified references to it still work.
+            let table_names = group_by
+                .iter()
+                .map(|e| match e {
+                    BoundExpr::Column(idx) => input.table_names[*idx].clone(),
+                    _ => String::from("?unnamed?"),
+                })
+                .chain(std::iter::repeat(String::from("?unnamed?")))
+                .take(a.colnames.len())
+                .collect();
+            Ok(BlockStream {
+                rows: Box::new(AggregateStream {
+                    input: input.rows,
+                    group_by,
+                    aggregates,
+                    rows: None,
+                    item: None,
+                    error: None,
+                }),
+                table_names,
+                column_names: a.colnames.clone(),
+                column_types,
+                hidden: vec![false; a.colnames.len()],
+            })
+        }
+        ir::Block::Join(j) => {
+            let left = build_stream(server_state, &j.left)?;
+            let right = build_stream(server_state, &j.right)?;
+            let join = join::build_join(
+                j.kind,
+                &left.table_names,
+                &left.column_names,
+                &right.table_names,
+                &right.column_names,
+                j.on.as_ref(),
+            )?;
+            let right_rows = match join.is_hash_join() {
+                true => JoinRight::Hash { input: Some(right.rows), table: None },
                 false => {
-                // TODO: lock the table in the pager when opening the table for read.
-                // TODO: if we previously loaded the schema speculatively during IR optimization, verify unchanged now, e.g. with hash.
-                if server_state.stored_db.is_none() { anyhow::bail!("No open database, but main database referenced in query.") };
-                let db = server_state.stored_db.as_ref().unwrap();
-                db.open_table_for_read(s.tablename.as_str())?
-                    .to_temp_table()
-                    .map_err(|e| anyhow::anyhow!(e))
+                    let block = (*j.right).clone();
+                    JoinRight::NestedLoop(Box::new(move || Ok(build_stream(server_state, &block)?.rows)))
                 }
-            }
+            };
+            Ok(BlockStream {
+                rows: Box::new(JoinStream {
+                    left: left.rows,
+                    right: right_rows,
+                    join,
+                    matches: vec![].into_iter(),
+                    item: None,
+                    error: None,
+                }),
+                table_names: left.table_names.into_iter().chain(right.table_names).collect(),
+                column_names: left.column_names.into_iter().chain(right.column_names).collect(),
+                column_types: left.column_types.into_iter().chain(right.column_types).collect(),
+                hidden: left.hidden.into_iter().chain(right.hidden).collect(),
+            })
+        }
+        ir::Block::Limit(l) => {
+            let input = build_stream(server_state, &l.input)?;
+            Ok(BlockStream {
+                rows: Box::new(LimitStream {
+                    input: input.rows,
+                    limit: l.limit,
+                    offset: l.offset,
+                    returned: 0,
+                    done: false,
+                }),
+                ..input
+            })
+        }
+        ir::Block::Project(p) => {
+            let input = build_stream(server_state, &p.input)?;
+            let (actions, column_names, column_types) =
+                project::build_project(
+                &input.table_names,
+                &input.column_names,
+                &input.column_types,
+                &input.hidden,
+                &p.outcols,
+            )?;
+            Ok(BlockStream {
+                rows: Box::new(ProjectStream {
+                    input: input.rows,
+                    actions,
+                    item: None,
+                    error: None,
+                }),
+                table_names: vec![String::from("?unnamed?"); column_names.len()],
+                hidden: vec![false; column_names.len()],
+                column_names,
+                column_types,
+            })
         }
     }
-}
\ No newline at end of file
+}
+
+/// Run an IR representation of a query, returning a TempTable with the results of the query.
+pub fn run_ir(server_state: &crate::DbServerState, ir: &ir::Block) -> Result<crate::TempTable> {
+    let mut stream = build_stream(server_state, ir)?;
+    // Hidden columns are left out of the results, as when a table is scanned without a Project block.
+    let visible: Vec<usize> = (0..stream.hidden.len()).filter(|i| !stream.hidden[*i]).collect();
+    let mut rows: Vec<Row> = vec![];
+    while let Some(row) = stream.rows.next() {
+        // A row that does not have a value for each column would give wrong values, or none, for the columns.
+        if row.items.len() != stream.column_names.len() {
+            bail!(
+                "Internal error: a row has {} values, but the query has {} columns.",
+                row.items.len(),
+                stream.column_names.len()
+            );
+        }
+        rows.push(match visible.len() == row.items.len() {
+            true => row.clone(),
+            false => Row { items: visible.iter().map(|i| row.items[*i].clone()).collect() },
+        });
+    }
+    if let Some(e) = stream.rows.take_error() {
+        return Err(e);
+    }
+    Ok(TempTable {
+        rows,
+        // The result is a single table, which can only have one name.
+        table_name: stream.table_names.first().cloned().unwrap_or_else(|| String::from("?unnamed?")),
+        column_names: visible.iter().map(|i| stream.column_names[*i].clone()).collect(),
+        column_types: visible.iter().map(|i| stream.column_types[*i]).collect(),
+        strict: false,  // SQLite defaults to non-strict, so result tables (without an explicit CREATE) shall be non-strict.
+    })
+}
+
+/// counts how many times it is advanced, to check that streams stop pulling rows early.
+#[cfg(test)]
+struct CountingStream {
+    rows: RowVecStream,
+    advances: std::rc::Rc<std::cell::Cell<usize>>,
+}
+
+#[cfg(test)]
+impl StreamingIterator for CountingStream {
+    type Item = Row;
+
+    fn advance(&mut self) {
+        self.advances.set(self.advances.get() + 1);
+        self.rows.advance();
+    }
+
+    fn get(&self) -> Option<&Row> {
+        self.rows.get()
+    }
+}
+
+#[cfg(test)]
+impl RowStream for CountingStream {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        None
+    }
+}
+
+#[test]
+fn test_limit_stream_stops_early() {
+    use crate::sql_value::SqlValue::Int;
+    let cases = vec![
+        // (limit, offset, expected rows, expected advances of the input)
+        (Some(3), 0, vec![0, 1, 2], 3),
+        (Some(2), 5, vec![5, 6], 7),
+        (Some(0), 0, vec![], 0),
+        (None, 97, vec![97, 98, 99], 101),
+        (Some(5), 200, vec![], 101),
+    ];
+    for (limit, offset, expected, expected_advances) in cases {
+        let advances = std::rc::Rc::new(std::cell::Cell::new(0));
+        let input = CountingStream {
+            rows: RowVecStream {
+                it: (0..100).map(|i| Row { items: vec![Int(i)] }).collect::<Vec<_>>().into_iter(),
+                item: None,
+            },
+            advances: advances.clone(),
+        };
+        let mut stream = LimitStream {
+            input: Box::new(input),
+            limit,
+            offset,
+            returned: 0,
+            done: false,
+        };
+        let mut actual = vec![];
+        while let Some(row) = stream.next() {
+            actual.push(row.items[0].clone());
+        }
+        assert_eq!(actual, expected.into_iter().map(Int).collect::<Vec<_>>());
+        assert_eq!(advances.get(), expected_advances);
+    }
+}
@@ -0,0 +1,207 @@
+//! provides helper functions for the join block of a query.
+//!
+//! The terms of the join condition which are equalities between an expression on the left input and one on the right
+//! input are used as hash keys: the rows of the right input are put in a hash table by their key values, and each row of
+//! the left input is only compared with the rows that have the same key values.  Without such terms, each row of the left
+//! input is compared with every row of the right input.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use streaming_iterator::StreamingIterator;
+
+use crate::aggregate::{key_value, KeyValue};
+use crate::ast;
+use crate::eval::{self, BoundExpr};
+use crate::sql_value::SqlValue;
+use crate::Row;
+
+/// holds a join condition that is ready to be evaluated on input rows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundJoin {
+    kind: ast::JoinKind,
+    /// expressions on left rows, which must equal the expressions at the same positions in `right_keys`.
+    left_keys: Vec<BoundExpr>,
+    /// expressions on right rows.
+    right_keys: Vec<BoundExpr>,
+    /// the rest of the condition, which is evaluated on joined rows.
+    residual: Option<BoundExpr>,
+    /// the number of columns of the right input.
+    right_width: usize,
+}
+
+/// builds the information needed to join the rows of two tables at runtime.
+pub fn build_join(
+    kind: ast::JoinKind,
+    left_tablenames: &[String],
+    left_colnames: &[String],
+    right_tablenames: &[String],
+    right_colnames: &[String],
+    on: Option<&ast::Expr>,
+) -> Result<BoundJoin> {
+    let tablenames: Vec<String> = left_tablenames.iter().chain(right_tablenames).cloned().collect();
+    let colnames: Vec<String> = left_colnames.iter().chain(right_colnames).cloned().collect();
+    let terms = on.map_or(vec![], |e| e.conjuncts());
+    let mut join = BoundJoin {
+        kind,
+        left_keys: vec![],
+        right_keys: vec![],
+        residual: None,
+        right_width: right_colnames.len(),
+    };
+    for term in terms {
+        // Binding the whole term first reports columns that don't exist or that are ambiguous.
+        let bound = eval::bind(term, &tablenames, &colnames)?;
+        if let ast::Expr::BinOp { lhs, op: ast::Op::Eq, rhs } = term {
+            let on_left = |e: &ast::Expr| eval::bind(e, left_tablenames, left_colnames).ok();
+            let on_right = |e: &ast::Expr| eval::bind(e, right_tablenames, right_colnames).ok();
+            let keys = match (on_left(lhs), on_right(rhs)) {
+                (Some(l), Some(r)) => Some((l, r)),
+                _ => on_left(rhs).zip(on_right(lhs)),
+            };
+            if let Some((l, r)) = keys {
+                join.left_keys.push(l);
+                join.right_keys.push(r);
+                continue;
+            }
+        }
+        join.residual = Some(match join.residual.take() {
+            None => bound,
+            Some(r) => BoundExpr::BinOp { lhs: Box::new(r), op: ast::Op::And, rhs: Box::new(bound) },
+        });
+    }
+    Ok(join)
+}
+
+impl BoundJoin {
+    /// returns true if the join is done with a hash table, rather than by scanning the right input for every left row.
+    pub fn is_hash_join(&self) -> bool {
+        !self.left_keys.is_empty()
+    }
+
+    /// joins one row of the left input with the rows of the right input in `right`, returning the joined rows.
+    ///
+    /// For a left join, a left row which matches no right rows is returned with NULLs for the right columns.
+    pub fn join_row<I>(&self, left: &Row, right: &mut I) -> Result<Vec<Row>>
+    where
+        I: StreamingIterator<Item = Row> + ?Sized,
+    {
+        let mut joined = vec![];
+        while let Some(r) = right.next() {
+            let row = Row { items: left.items.iter().chain(r.items.iter()).cloned().collect() };
+            let keep = match &self.residual {
+                None => true,
+                Some(e) => eval::to_bool(&e.eval(&row)?) == Some(true),
+            };
+            if keep {
+                joined.push(row);
+            }
+        }
+        if joined.is_empty() && self.kind == ast::JoinKind::Left {
+            let nulls = std::iter::repeat_n(SqlValue::Null(), self.right_width);
+            joined.push(Row { items: left.items.iter().cloned().chain(nulls).collect() });
+        }
+        Ok(joined)
+    }
+}
+
+/// evaluates key expressions on a row, returning `None` if any of the values is NULL, since NULL equals nothing.
+fn eval_key(keys: &[BoundExpr], row: &Row) -> Result<Option<Vec<KeyValue>>> {
+    let mut key = vec![];
+    for e in keys {
+        match e.eval(row)? {
+            SqlValue::Null() => return Ok(None),
+            v => key.push(key_value(&v)),
+        }
+    }
+    Ok(Some(key))
+}
+
+/// holds the rows of the right input of a hash join, looked up by their key values.
+pub struct HashTable {
+    buckets: HashMap<Vec<KeyValue>, Vec<Row>>,
+}
+
+impl HashTable {
+    /// reads all the rows of `input` into a hash table on the right keys of `join`.
+    pub fn build<I>(input: &mut I, join: &BoundJoin) -> Result<HashTable>
+    where
+        I: StreamingIterator<Item = Row> + ?Sized,
+    {
+        let mut buckets: HashMap<Vec<KeyValue>, Vec<Row>> = HashMap::new();
+        while let Some(row) = input.next() {
+            // Rows with a NULL key can't match any left row, even in a left join, so they are not kept.
+            if let Some(key) = eval_key(&join.right_keys, row)? {
+                buckets.entry(key).or_default().push(row.clone());
+            }
+        }
+        Ok(HashTable { buckets })
+    }
+
+    /// returns the rows whose key values equal those of the left row `left`.
+    pub fn candidates(&self, join: &BoundJoin, left: &Row) -> Result<&[Row]> {
+        Ok(match eval_key(&join.left_keys, left)? {
+            None => &[],
+            Some(key) => self.buckets.get(&key).map_or(&[], |rows| &rows[..]),
+        })
+    }
+}
+
+#[cfg(test)]
+fn bind_test_join(kind: ast::JoinKind, on: &str) -> BoundJoin {
+    use crate::parser::{parse_expr, Rule, SQLParser};
+    use pest::Parser;
+    let names = |v: &[&str]| v.iter().map(|s| String::from(*s)).collect::<Vec<_>>();
+    let on = parse_expr(SQLParser::parse(Rule::expr, on).unwrap().next().unwrap().into_inner());
+    build_join(kind, &names(&["t", "t"]), &names(&["a", "b"]), &names(&["u", "u"]), &names(&["a", "c"]), Some(&on)).unwrap()
+}
+
+#[test]
+fn test_build_join_finds_hash_keys() {
+    let join = bind_test_join(ast::JoinKind::Inner, "t.a = u.a AND u.c + 1 = b AND t.b < u.c AND t.a = t.b");
+    assert_eq!(join.left_keys, vec![BoundExpr::Column(0), BoundExpr::Column(1)]);
+    assert_eq!(join.right_keys.len(), 2);
+    assert_eq!(join.right_keys[0], BoundExpr::Column(0));
+    assert!(join.residual.is_some());
+    assert!(join.is_hash_join());
+
+    assert!(!bind_test_join(ast::JoinKind::Inner, "t.a < u.a OR t.b = u.c").is_hash_join());
+}
+
+#[test]
+fn test_hash_join_matches_nested_loop_join() {
+    use SqlValue::*;
+    let left: Vec<Row> = vec![
+        Row { items: vec![Int(1), Int(10)] },
+        Row { items: vec![Null(), Int(20)] },
+        Row { items: vec![Real(2.0), Int(30)] },
+        Row { items: vec![Int(4), Int(40)] },
+    ];
+    let right: Vec<Row> = vec![
+        Row { items: vec![Int(2), Int(100)] },
+        Row { items: vec![Int(1), Int(200)] },
+        Row { items: vec![Null(), Int(300)] },
+        Row { items: vec![Int(1), Int(5)] },
+    ];
+    for kind in [ast::JoinKind::Inner, ast::JoinKind::Left] {
+        let hash = bind_test_join(kind, "t.a = u.a AND u.c > t.b");
+        // The same condition, written so that it has no hash keys.
+        let nested = bind_test_join(kind, "t.a - u.a = 0 AND u.c > t.b");
+        assert!(hash.is_hash_join());
+        assert!(!nested.is_hash_join());
+        let table = HashTable::build(&mut streaming_iterator::convert_ref(&right), &hash).unwrap();
+        let mut expected: Vec<Row> = vec![];
+        let mut actual: Vec<Row> = vec![];
+        for l in &left {
+            expected.extend(nested.join_row(l, &mut streaming_iterator::convert_ref(&right)).unwrap());
+            let candidates = table.candidates(&hash, l).unwrap();
+            actual.extend(hash.join_row(l, &mut streaming_iterator::convert_ref(candidates)).unwrap());
+        }
+        assert_eq!(actual, expected);
+        let expected_len = match kind {
+            ast::JoinKind::Inner => 2,
+            ast::JoinKind::Left => 4,
+        };
+        assert_eq!(actual.len(), expected_len);
+    }
+}
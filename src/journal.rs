@@ -0,0 +1,226 @@
+//! journal reads and writes the rollback journal of a database file.
+//!
+//! Before pages of a database file are changed, their original contents are copied into the rollback journal, which is
+//! a file named like the database file with `-journal` appended.  The journal is deleted once all the changed pages
+//! are written, and deleting it is what commits the change.  If the writer crashes before then, the journal is "hot",
+//! and the next time the database is opened, the original pages are copied back from it.
+//!
+//! The journal format is described at https://www.sqlite.org/fileformat.html#the_rollback_journal
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::stored_db::PageNum;
+
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+// The header is padded to the sector size, so that page records do not share a sector with it.
+const SECTOR_SIZE: u32 = 512;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Journal: Error accessing journal file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// returns the path of the rollback journal of the database file at `db_path`.
+pub fn path_for(db_path: &str) -> String {
+    format!("{}-journal", db_path)
+}
+
+// The header of a journal.
+struct Header {
+    // The number of page records in the journal.
+    numrecords: u32,
+    // A random number that is added to the checksum of every page record.
+    nonce: u32,
+    // The size of the database file, in pages, before the changes.
+    initial_numpages: u32,
+    page_size: u32,
+}
+
+fn read_header(b: &[u8]) -> Option<Header> {
+    if b.len() < 28 || b[0..8] != JOURNAL_MAGIC {
+        return None;
+    }
+    Some(Header {
+        numrecords: BigEndian::read_u32(&b[8..]),
+        nonce: BigEndian::read_u32(&b[12..]),
+        initial_numpages: BigEndian::read_u32(&b[16..]),
+        page_size: BigEndian::read_u32(&b[24..]),
+    })
+}
+
+// The checksum of a page record is the nonce plus every 200th byte of the page, counting back from the end.
+fn checksum(nonce: u32, page: &[u8]) -> u32 {
+    page.iter()
+        .rev()
+        .skip(199)
+        .step_by(200)
+        .fold(nonce, |sum, b| sum.wrapping_add(*b as u32))
+}
+
+/// returns the contents of a journal that holds `originals`, the page numbers and contents of the pages about to be
+/// changed, of a database file of `initial_numpages` pages of `page_size` bytes.
+///
+/// The caller writes the journal, and syncs it before changing the database file.
+pub fn contents(page_size: u32, initial_numpages: u32, originals: &[(PageNum, Vec<u8>)]) -> Vec<u8> {
+    let nonce = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let mut b = vec![0_u8; SECTOR_SIZE as usize];
+    b[0..8].copy_from_slice(&JOURNAL_MAGIC);
+    BigEndian::write_u32(&mut b[8..], originals.len() as u32);
+    BigEndian::write_u32(&mut b[12..], nonce);
+    BigEndian::write_u32(&mut b[16..], initial_numpages);
+    BigEndian::write_u32(&mut b[20..], SECTOR_SIZE);
+    BigEndian::write_u32(&mut b[24..], page_size);
+    for (pn, page) in originals {
+        b.extend_from_slice(&(*pn as u32).to_be_bytes());
+        b.extend_from_slice(page);
+        b.extend_from_slice(&checksum(nonce, page).to_be_bytes());
+    }
+    b
+}
+
+/// returns the page size in the header of the database file `db`, or None if the file is too short to have one.
+fn db_page_size(db: &mut std::fs::File) -> Result<Option<u32>, Error> {
+    let mut b = [0_u8; 18];
+    db.seek(SeekFrom::Start(0))?;
+    match db.read_exact(&mut b) {
+        // A page size of 65536 is stored as 1.
+        Ok(()) => Ok(Some(match BigEndian::read_u16(&b[16..]) {
+            1 => 65536,
+            n => n as u32,
+        })),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// copies the original pages in the journal at `path`, if it is hot, back into the database file `db`, and then deletes
+/// the journal.  Returns true if the journal was hot.
+///
+/// A journal without a valid header was not finished before the database file was changed, so it is just deleted.
+/// So is a journal whose header does not match the database file, since replaying it would truncate or overwrite the
+/// file.  Page records after the first one with a bad checksum were not fully written, so they are ignored.
+pub fn replay(path: &str, db: &mut std::fs::File) -> Result<bool, Error> {
+    let mut b = vec![];
+    std::fs::File::open(path)?.read_to_end(&mut b)?;
+    let db_page_size = db_page_size(db)?;
+    let h = match read_header(&b) {
+        Some(h)
+            if h.page_size.is_power_of_two()
+                && (512..=65536).contains(&h.page_size)
+                && db_page_size.is_none_or(|p| p == h.page_size)
+                && h.initial_numpages > 0 =>
+        {
+            h
+        }
+        _ => {
+            std::fs::remove_file(path)?;
+            return Ok(false);
+        }
+    };
+    let record_len = h.page_size as usize + 8;
+    // The header may have been written without all of the padding after it.
+    let records = b.get(SECTOR_SIZE as usize..).unwrap_or_default();
+    for record in records.chunks_exact(record_len).take(h.numrecords as usize) {
+        let pn = BigEndian::read_u32(record) as u64;
+        let page = &record[4..record_len - 4];
+        if pn == 0 || BigEndian::read_u32(&record[record_len - 4..]) != checksum(h.nonce, page) {
+            break;
+        }
+        db.seek(SeekFrom::Start((pn - 1) * h.page_size as u64))?;
+        db.write_all(page)?;
+    }
+    // Pages added by the change are removed.
+    db.set_len(h.initial_numpages as u64 * h.page_size as u64)?;
+    db.sync_all()?;
+    std::fs::remove_file(path)?;
+    Ok(true)
+}
+
+/// returns a test database file of two pages of `page_size` bytes, and the file open for reading and writing.
+#[cfg(test)]
+fn test_db(test: &str, page_size: u32) -> (crate::stored_db::TestFile, std::fs::File) {
+    let path = crate::stored_db::TestFile::new(test);
+    let mut page1 = vec![1_u8; page_size as usize];
+    // The database header has just the page size.
+    BigEndian::write_u16(&mut page1[16..], page_size as u16);
+    std::fs::write(&path, [page1, vec![2_u8; page_size as usize]].concat()).unwrap();
+    let db = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    (path, db)
+}
+
+#[cfg(test)]
+fn read_all(db: &mut std::fs::File) -> Vec<u8> {
+    let mut b = vec![];
+    db.seek(SeekFrom::Start(0)).unwrap();
+    db.read_to_end(&mut b).unwrap();
+    b
+}
+
+#[test]
+fn test_write_and_replay() {
+    let (db_path, mut db) = test_db("journal", 512);
+    let jpath = path_for(&db_path);
+    let original = read_all(&mut db);
+    std::fs::write(&jpath, contents(512, 2, &[(2, vec![2_u8; 512])])).unwrap();
+    // Page 2 is changed, and page 3 is added.
+    db.seek(SeekFrom::Start(512)).unwrap();
+    db.write_all(&[[5_u8; 512], [6_u8; 512]].concat()).unwrap();
+    assert!(replay(&jpath, &mut db).unwrap());
+    assert!(!std::path::Path::new(&jpath).exists());
+    assert_eq!(read_all(&mut db), original);
+}
+
+#[test]
+fn test_replay_cut_off_journals() {
+    let (db_path, mut db) = test_db("journal-cut-off", 512);
+    let jpath = path_for(&db_path);
+    let original = read_all(&mut db);
+    // A journal that was cut off before its header was written is not replayed.
+    std::fs::write(&jpath, &JOURNAL_MAGIC[..4]).unwrap();
+    assert!(!replay(&jpath, &mut db).unwrap());
+    assert!(!std::path::Path::new(&jpath).exists());
+    // A journal that was cut off before the end of the padding after its header has no pages to copy back.
+    std::fs::write(&jpath, &contents(512, 2, &[(2, vec![3_u8; 512])])[..100]).unwrap();
+    assert!(replay(&jpath, &mut db).unwrap());
+    assert!(!std::path::Path::new(&jpath).exists());
+    assert_eq!(read_all(&mut db), original);
+}
+
+#[test]
+fn test_replay_ignores_headers_that_do_not_match_the_db() {
+    let (db_path, mut db) = test_db("journal-header", 1024);
+    let jpath = path_for(&db_path);
+    let original = read_all(&mut db);
+    // A journal for 1024 byte pages, whose header has a bad page size or page count.
+    let journal = contents(1024, 2, &[(2, vec![3_u8; 1024])]);
+    let set_u32 = |offset: usize, value: u32| {
+        let mut j = journal.clone();
+        BigEndian::write_u32(&mut j[offset..], value);
+        j
+    };
+    for bad in [set_u32(24, 0), set_u32(24, 1000), set_u32(24, 512), set_u32(24, 131072), set_u32(16, 0)] {
+        std::fs::write(&jpath, bad).unwrap();
+        assert!(!replay(&jpath, &mut db).unwrap());
+        assert!(!std::path::Path::new(&jpath).exists());
+        assert_eq!(read_all(&mut db), original);
+    }
+    // The journal as written is replayed.
+    std::fs::write(&jpath, &journal).unwrap();
+    assert!(replay(&jpath, &mut db).unwrap());
+}
+
+#[test]
+fn test_checksum() {
+    let mut page = vec![0_u8; 512];
+    assert_eq!(checksum(7, &page), 7);
+    // The bytes at offsets 312 and 112 are summed.
+    page[312] = 1;
+    page[112] = 2;
+    page[113] = 4;
+    assert_eq!(checksum(7, &page), 10);
+}
@@ -1,10 +1,19 @@
+mod aggregate;
 mod ast;
 mod ast_to_ir;
 mod btree;
+mod buffer_pool;
+mod checksum;
 mod dbheader;
+mod eval;
+mod filter;
+mod sort;
 mod ir;
 mod ir_interpreter;
+mod join;
+mod journal;
 mod optimize_ast;
+mod optimize_ir;
 pub mod stored_db;
 pub mod parser;
 mod project;
@@ -17,6 +26,7 @@ mod table_traits;
 mod temp_db;
 mod stored_table;
 mod temp_table;
+mod wal;
 pub mod typed_row;
 extern crate pest;
 #[macro_use]
@@ -33,6 +43,8 @@ use typed_row::Row;
 pub struct DbServerState {
     pub stored_db: Option<crate::stored_db::StoredDb>,  // Try to make this private.
     pub temp_db: crate::temp_db::TempDb,
+    /// the savepoints of the open transaction, innermost last.  There is a transaction exactly when this is not empty.
+    savepoints: Vec<Savepoint>,
 }
 
 impl DbServerState {
@@ -40,17 +52,185 @@ impl DbServerState {
         DbServerState { 
             stored_db: None,
             temp_db: crate::temp_db::TempDb::new(),
+            savepoints: vec![],
         }
     }
+
+    /// returns true if a transaction has been started and not yet committed or rolled back.
+    pub fn in_transaction(&self) -> bool {
+        !self.savepoints.is_empty()
+    }
+
+    /// starts a savepoint named `name` of the temp database and the open database.
+    fn savepoint(&mut self, name: Option<String>) -> Savepoint {
+        Savepoint {
+            name,
+            temp_db: self.temp_db.savepoint(),
+            stored_db: self.stored_db.as_mut().map(|db| db.savepoint()),
+        }
+    }
+
+    /// discards the changes made to the temp database and the open database since `savepoint`.
+    fn rollback_to(&mut self, savepoint: &Savepoint) -> anyhow::Result<()> {
+        self.temp_db.rollback_to(&savepoint.temp_db);
+        if let (Some(db), Some(sp)) = (self.stored_db.as_mut(), savepoint.stored_db.as_ref()) {
+            db.rollback_to(sp)?;
+        }
+        Ok(())
+    }
+
+    /// ends `savepoint`, keeping the changes made since it.
+    fn release(&mut self, savepoint: &Savepoint) {
+        self.temp_db.release(&savepoint.temp_db);
+        if let (Some(db), Some(sp)) = (self.stored_db.as_mut(), savepoint.stored_db.as_ref()) {
+            db.release(sp);
+        }
+    }
+
+    /// ends the transaction, writing the changes to the open database to its file.
+    fn commit(&mut self) -> anyhow::Result<()> {
+        if let Some(db) = self.stored_db.as_mut() {
+            db.commit()?;
+        }
+        self.temp_db.commit();
+        self.savepoints.clear();
+        Ok(())
+    }
+
+    /// commits the transaction started at `savepoint`, the outermost savepoint, or if the commit fails, rolls the
+    /// transaction back.
+    ///
+    /// A commit that stopped part way may have written some pages to the file, which the rollback journal puts back.
+    fn commit_or_roll_back(&mut self, savepoint: &Savepoint) -> anyhow::Result<()> {
+        let Err(e) = self.commit() else {
+            return Ok(());
+        };
+        self.rollback_to(savepoint)?;
+        self.release(savepoint);
+        if let Some(db) = self.stored_db.as_mut() {
+            db.rollback()?;
+        }
+        self.savepoints.clear();
+        Err(e)
+    }
+}
+
+/// A `Savepoint` marks a point in a transaction, which the changes to the temp database and the open database can be
+/// rolled back to.
+///
+/// Temp tables and pages of the open database are copied when they are first changed after the savepoint.
+struct Savepoint {
+    /// the name given with SAVEPOINT, or None for the start of a transaction with BEGIN.
+    name: Option<String>,
+    temp_db: crate::temp_db::Savepoint,
+    stored_db: Option<crate::stored_db::Savepoint>,
+}
+
+/// runs `f`, which changes `server_state`, so that either all of its changes are made or, if it fails, none are.
+///
+/// Outside of a transaction, the changes to the open database are written to its file when `f` succeeds.  In a
+/// transaction, they are written when it is committed.
+fn run_atomically(
+    server_state: &mut DbServerState,
+    f: impl FnOnce(&mut DbServerState) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let savepoint = server_state.savepoint(None);
+    if let Err(e) = f(server_state) {
+        server_state.rollback_to(&savepoint)?;
+        server_state.release(&savepoint);
+        return Err(e);
+    }
+    if server_state.in_transaction() {
+        server_state.release(&savepoint);
+        return Ok(());
+    }
+    server_state.commit_or_roll_back(&savepoint)
+}
+
+/// Run a BEGIN, COMMIT, ROLLBACK, SAVEPOINT or RELEASE statement.
+///
+/// A SAVEPOINT outside of a transaction starts one, and releasing its outermost savepoint commits it.
+pub fn run_transaction_statement(server_state: &mut DbServerState, stmt: &str) -> anyhow::Result<()> {
+    use ast::TransactionStatement as Stmt;
+    let position = |ss: &DbServerState, name: &str| {
+        ss.savepoints
+            .iter()
+            .rposition(|sp| sp.name.as_ref().is_some_and(|n| n.eq_ignore_ascii_case(name)))
+            .ok_or_else(|| anyhow::anyhow!("No such savepoint: {}", name))
+    };
+    match pt_to_ast::pt_transaction_statement_to_ast(stmt)? {
+        Stmt::Begin if server_state.in_transaction() => bail!("Cannot start a transaction within a transaction."),
+        Stmt::Begin => {
+            let sp = server_state.savepoint(None);
+            server_state.savepoints.push(sp);
+        }
+        Stmt::Savepoint(name) => {
+            let sp = server_state.savepoint(Some(name));
+            server_state.savepoints.push(sp);
+        }
+        Stmt::Commit if !server_state.in_transaction() => bail!("Cannot commit: no transaction is active."),
+        Stmt::Commit => {
+            let savepoints = std::mem::take(&mut server_state.savepoints);
+            server_state.commit_or_roll_back(&savepoints[0])?;
+        }
+        Stmt::Rollback(None) if !server_state.in_transaction() => bail!("Cannot roll back: no transaction is active."),
+        Stmt::Rollback(None) => {
+            let savepoints = std::mem::take(&mut server_state.savepoints);
+            server_state.rollback_to(&savepoints[0])?;
+            server_state.release(&savepoints[0]);
+        }
+        // The savepoint stays, so that the transaction can be rolled back to it again.
+        Stmt::Rollback(Some(name)) => {
+            let i = position(server_state, &name)?;
+            server_state.savepoints.truncate(i + 1);
+            let sp = server_state.savepoints.pop().expect("Should have the savepoint.");
+            server_state.rollback_to(&sp)?;
+            server_state.savepoints.push(sp);
+        }
+        Stmt::Release(name) => {
+            let i = position(server_state, &name)?;
+            let released = server_state.savepoints.split_off(i);
+            match server_state.in_transaction() {
+                true => server_state.release(&released[0]),
+                false => server_state.commit_or_roll_back(&released[0])?,
+            }
+        }
+    }
+    Ok(())
 }
 // Open a database file, and hold it in the DbServerState.
 pub fn open_db(server_state: &mut DbServerState, path: &str) -> anyhow::Result<()> {
     if server_state.stored_db.is_some() { bail!("Database file already open.  Close the old one first.  Close might be supported in the future.")}
+    if server_state.in_transaction() { bail!("Cannot open a database within a transaction.")}
     server_state.stored_db = Some(crate::stored_db::StoredDb::open(path)?);
     Ok(())
 }
 
-pub fn new_table_iterator(pgr: &stored_db::StoredDb, pgnum: usize) -> btree::table::Iterator {
+// Create a new database file, and hold it in the DbServerState.
+pub fn create_db(server_state: &mut DbServerState, path: &str, page_size: u32) -> anyhow::Result<()> {
+    if server_state.stored_db.is_some() { bail!("Database file already open.  Close the old one first.  Close might be supported in the future.")}
+    if server_state.in_transaction() { bail!("Cannot create a database within a transaction.")}
+    server_state.stored_db = Some(crate::stored_db::StoredDb::create(path, page_size)?);
+    Ok(())
+}
+
+// Copy the changes in the write-ahead log of the open database into its file.
+pub fn checkpoint(server_state: &mut DbServerState) -> anyhow::Result<()> {
+    match server_state.stored_db.as_mut() {
+        None => bail!("No open database to checkpoint."),
+        Some(db) => Ok(db.checkpoint()?),
+    }
+}
+
+// Check the open database for corruption, returning a description of each problem found.
+pub fn integrity_check(server_state: &DbServerState) -> anyhow::Result<Vec<String>> {
+    match server_state.stored_db.as_ref() {
+        None => bail!("No open database to check."),
+        Some(db) => Ok(db.integrity_check()),
+    }
+}
+
+pub fn new_table_iterator(pgr: &stored_db::StoredDb, pgnum: usize) -> btree::table::Iterator<'_> {
     crate::btree::table::Iterator::new(pgnum, pgr)
 }
 
@@ -72,27 +252,42 @@ pub fn run_query(server_state: &DbServerState, query: &str) -> anyhow::Result<()
 
 pub fn run_insert(server_state: &mut DbServerState, stmt: &str) -> anyhow::Result<()> {
     let is: ast::InsertStatement = pt_to_ast::pt_insert_statement_to_ast(stmt)?;
+    run_atomically(server_state, |server_state| insert(is, server_state))
+}
+
+fn insert(is: ast::InsertStatement, server_state: &mut DbServerState) -> anyhow::Result<()> {
     // TODO: use helper functions or "impl Trait" argument types to reduce how much code is duplicated
     // across these two match arms.
     match is.databasename == "temp" {
         true /* temporary table */ => {
-            let tbl = server_state.temp_db.get_table_mut(&is.tablename)?;
             for row in is.values {
                 // Convert row from AST constants to SQL values.
                 let row: Vec<SqlValue> = row.iter().map(sql_value::from_ast_constant).collect();
-                tbl.append_row(&row)?;
+                server_state.temp_db.append_row(&is.tablename, &row)?;
             }
             // Writing to disk not needed for temp tables.
         }
         false /* Persistent, SQLite table */ => {
-            bail!("Inserting into persistent (SQLite-format) tables is not supported yet.  Try a temporary table.");
+            let db = match server_state.stored_db.as_mut() {
+                None => bail!("No open database, but main database referenced in insert."),
+                Some(db) => db,
+            };
+            let table = db.get_table_info(&is.tablename)?;
+            for row in is.values {
+                let row: Vec<SqlValue> = row.iter().map(sql_value::from_ast_constant).collect();
+                db.append_row(&table, &row)?;
+            }
         }
     }
     Ok(())
 }
 
 pub fn run_create(server_state: &mut DbServerState, stmt: &str) -> anyhow::Result<()> {
-    let cs: ast::CreateStatement = pt_to_ast::pt_create_statement_to_ast(stmt);
+    let cs: ast::CreateStatement = pt_to_ast::pt_create_statement_to_ast(stmt)?;
+    run_atomically(server_state, |server_state| create(cs, server_state))
+}
+
+fn create(cs: ast::CreateStatement, server_state: &mut DbServerState) -> anyhow::Result<()> {
     // TODO: use helper functions or "impl Trait" argument types to reduce how much code is duplicated
     // across these two match arms.
     match cs.databasename == "temp" {
@@ -105,21 +300,214 @@ pub fn run_create(server_state: &mut DbServerState, stmt: &str) -> anyhow::Resul
             )?;
         }
         false /* Persistent, SQLite table */ => {
-            bail!("Creation of persistent (SQLite-format) tables is not supported yet.  Try 'CREATE TEMP TABLE ...;' instead.");
+            let db = match server_state.stored_db.as_mut() {
+                None => bail!("No open database, but main database referenced in create."),
+                Some(db) => db,
+            };
+            for c in &cs.coldefs {
+                // Columns need types that can be read back, and SQLite can't open files with STRICT tables that have
+                // types other than these.
+                sql_type::SqlType::from_str(c.coltype.as_str())?;
+                if cs.strict && !["int", "integer", "real", "text", "blob"].contains(&c.coltype.to_lowercase().as_str()) {
+                    bail!("Type {} is not allowed in STRICT tables.", c.coltype);
+                }
+            }
+            db.create_table(&cs.tablename, &cs.to_string())?;
         }
     }
     Ok(())
 }
 
+pub fn run_delete(server_state: &mut DbServerState, stmt: &str) -> anyhow::Result<()> {
+    let ds: ast::DeleteStatement = pt_to_ast::pt_delete_statement_to_ast(stmt)?;
+    run_atomically(server_state, |server_state| delete(ds, server_state))
+}
+
+fn delete(ds: ast::DeleteStatement, server_state: &mut DbServerState) -> anyhow::Result<()> {
+    match ds.databasename == "temp" {
+        true /* temporary table */ => {
+            let tbl = server_state.temp_db.get_table_mut(&ds.tablename)?;
+            let tablenames = vec![tbl.table_name.clone(); tbl.column_names.len()];
+            let predicate = match &ds.r#where {
+                None => None,
+                Some(w) => Some(eval::bind(&w.expr, &tablenames, &tbl.column_names)?),
+            };
+            tbl.delete_rows(predicate.as_ref())?;
+        }
+        false /* Persistent, SQLite table */ => {
+            let db = match server_state.stored_db.as_ref() {
+                None => bail!("No open database, but main database referenced in delete."),
+                Some(db) => db,
+            };
+            // The rows to delete are found by a query for their rowids, which can use the same ways of finding rows as
+            // any other query.
+            let table = db.get_table_info(&ds.tablename)?;
+            if table.column_names.iter().any(|c| c == "rowid") {
+                bail!("Deleting from tables with a column named rowid is not supported.");
+            }
+            let ss = ast::SelectStatement {
+                select: ast::SelectClause { items: vec![ast::SelItem::ColName(ast::ColName { name: String::from("rowid") })] },
+                from: Some(ast::FromClause {
+                    table: ast::TableRef { databasename: ds.databasename, tablename: ds.tablename.clone(), alias: None },
+                    joins: vec![],
+                }),
+                r#where: ds.r#where,
+                group_by: None,
+                order_by: None,
+                having: None,
+                limit: None,
+            };
+            let rowids = run_select_statement(server_state, ss)?;
+            let db = server_state.stored_db.as_mut().expect("Should still have an open database.");
+            rowids.rows.iter().try_for_each(|row| match row.items[0] {
+                SqlValue::Int(rowid) => db.delete_row(&table, rowid).map(|_| ()),
+                _ => bail!("Internal error: rowid should be an integer."),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+pub fn run_update(server_state: &mut DbServerState, stmt: &str) -> anyhow::Result<()> {
+    let us: ast::UpdateStatement = pt_to_ast::pt_update_statement_to_ast(stmt)?;
+    run_atomically(server_state, |server_state| update(us, server_state))
+}
+
+fn update(us: ast::UpdateStatement, server_state: &mut DbServerState) -> anyhow::Result<()> {
+    match us.databasename == "temp" {
+        true /* temporary table */ => {
+            let tbl = server_state.temp_db.get_table_mut(&us.tablename)?;
+            let tablenames = vec![tbl.table_name.clone(); tbl.column_names.len()];
+            let predicate = match &us.r#where {
+                None => None,
+                Some(w) => Some(eval::bind(&w.expr, &tablenames, &tbl.column_names)?),
+            };
+            let assignments = bind_assignments(&us.assignments, &tablenames, &tbl.column_names)?;
+            tbl.update_rows(predicate.as_ref(), &assignments)?;
+        }
+        false /* Persistent, SQLite table */ => {
+            let db = match server_state.stored_db.as_ref() {
+                None => bail!("No open database, but main database referenced in update."),
+                Some(db) => db,
+            };
+            // As for DELETE, the rows to update are found by a query, which also returns their current values.
+            let table = db.get_table_info(&us.tablename)?;
+            if table.column_names.iter().any(|c| c == "rowid") {
+                bail!("Updating tables with a column named rowid is not supported.");
+            }
+            let tablenames = vec![us.tablename.clone(); table.column_names.len()];
+            let assignments = bind_assignments(&us.assignments, &tablenames, &table.column_names)?;
+            let ss = ast::SelectStatement {
+                select: ast::SelectClause {
+                    items: vec![ast::SelItem::ColName(ast::ColName { name: String::from("rowid") }), ast::SelItem::Star],
+                },
+                from: Some(ast::FromClause {
+                    table: ast::TableRef { databasename: us.databasename, tablename: us.tablename.clone(), alias: None },
+                    joins: vec![],
+                }),
+                r#where: us.r#where,
+                group_by: None,
+                order_by: None,
+                having: None,
+                limit: None,
+            };
+            let rows = run_select_statement(server_state, ss)?;
+            let db = server_state.stored_db.as_mut().expect("Should still have an open database.");
+            rows.rows.iter().try_for_each(|row| {
+                let rowid = match row.items[0] {
+                    SqlValue::Int(rowid) => rowid,
+                    _ => bail!("Internal error: rowid should be an integer."),
+                };
+                let items = eval::updated_row(&Row { items: row.items[1..].to_vec() }, &assignments)?;
+                db.update_row(&table, rowid, &items).map(|_| ())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// binds the expressions of the `SET` clause of an UPDATE to the columns of a table, pairing each with the position
+/// of the column that it sets.
+fn bind_assignments(
+    assignments: &[ast::Assignment],
+    tablenames: &[String],
+    colnames: &[String],
+) -> anyhow::Result<Vec<(usize, eval::BoundExpr)>> {
+    assignments
+        .iter()
+        .map(|a| match colnames.iter().position(|c| *c == a.colname.name) {
+            None => bail!("No such column: {}", a.colname),
+            Some(i) => Ok((i, eval::bind(&a.expr, tablenames, colnames)?)),
+        })
+        .collect()
+}
 
 pub fn run_query_no_print(server_state: &DbServerState, query: &str) -> anyhow::Result<TempTable> {
     // Convert parse tree to AST.
-    let mut ss: ast::SelectStatement = pt_to_ast::pt_select_statement_to_ast(query)?;
+    let ss: ast::SelectStatement = pt_to_ast::pt_select_statement_to_ast(query)?;
+    run_select_statement(server_state, ss)
+}
+
+/// runs a parsed select statement, returning its results.
+fn run_select_statement(server_state: &DbServerState, mut ss: ast::SelectStatement) -> anyhow::Result<TempTable> {
     // Optimize the AST (in place).
     optimize_ast::simplify_ast_select_statement(&mut ss)?;
     // Convert the AST to IR.
     let ir: ir::Block = ast_to_ir::ast_select_statement_to_ir(&ss)?;
+    // Optimize the IR using the schema, such as by searching indexes.
+    let ir: ir::Block = optimize_ir::choose_indexes(server_state, ir)?;
     // Execute the IR.
     let tt: TempTable = ir_interpreter::run_ir(server_state, &ir)?;
     Ok(tt)
 }
+
+#[test]
+fn test_failed_commit_is_rolled_back() {
+    let path = stored_db::copy_of_testdata("multipage-512B-page.db", "failed-commit");
+    let mut ss = DbServerState::new();
+    open_db(&mut ss, &path).unwrap();
+    let letters = |ss: &DbServerState| -> Vec<String> {
+        let tt = run_query_no_print(ss, "select l from letters").unwrap();
+        tt.rows.iter().map(|r| r.items[0].to_string()).collect()
+    };
+
+    // The commit stops part way through writing the changed pages to the file, after writing the journal.
+    ss.stored_db.as_mut().unwrap().crash_after = Some(1800);
+    assert!(run_insert(&mut ss, "insert into letters values ('X')").is_err());
+    ss.stored_db.as_mut().unwrap().crash_after = None;
+    assert_eq!(letters(&ss).len(), 10);
+    // The next statement commits only its own changes.
+    run_insert(&mut ss, "insert into letters values ('Y')").unwrap();
+    let mut ss = DbServerState::new();
+    open_db(&mut ss, &path).unwrap();
+    let l = letters(&ss);
+    assert_eq!((l.len(), l.last().map(String::as_str)), (11, Some("Y")));
+}
+
+#[test]
+fn test_failed_commit_of_a_transaction_is_rolled_back() {
+    for end in ["commit", "release s"] {
+        let path = stored_db::copy_of_testdata("multipage-512B-page.db", "failed-transaction-commit");
+        let mut ss = DbServerState::new();
+        open_db(&mut ss, &path).unwrap();
+        let letters = |ss: &DbServerState| -> Vec<String> {
+            let tt = run_query_no_print(ss, "select l from letters").unwrap();
+            tt.rows.iter().map(|r| r.items[0].to_string()).collect()
+        };
+        run_transaction_statement(&mut ss, "savepoint s").unwrap();
+        run_insert(&mut ss, "insert into letters values ('X')").unwrap();
+        // The commit stops part way through writing the changed pages to the file, after writing the journal.
+        ss.stored_db.as_mut().unwrap().crash_after = Some(1800);
+        assert!(run_transaction_statement(&mut ss, end).is_err(), "{}", end);
+        ss.stored_db.as_mut().unwrap().crash_after = None;
+        assert!(!ss.in_transaction());
+        assert_eq!(letters(&ss).len(), 10, "{}", end);
+        // The journal put back the pages that were written, so the next commit does not keep the torn pages.
+        run_insert(&mut ss, "insert into letters values ('Y')").unwrap();
+        let mut ss = DbServerState::new();
+        open_db(&mut ss, &path).unwrap();
+        let l = letters(&ss);
+        assert_eq!((l.len(), l.last().map(String::as_str)), (11, Some("Y")), "{}", end);
+        assert_eq!(integrity_check(&ss).unwrap(), Vec::<String>::new());
+    }
+}
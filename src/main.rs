@@ -66,6 +66,8 @@ fn do_command(c: &mut CliContext, line: &str) {
         match line {
             ".schema" => do_schema(c),
             ".help" => do_help(c),
+            ".checkpoint" => do_checkpoint(c),
+            ".check" => do_check(c),
             l if l.starts_with(".help") => {
                 if let Some((_, command_for_help)) = line.split_once(" ") {
                     do_detailed_help(c, command_for_help)
@@ -81,6 +83,17 @@ fn do_command(c: &mut CliContext, line: &str) {
                     println!("Unspecified filename.");
                 }
             }
+            l if l.starts_with(".create") => {
+                let args: Vec<&str> = line.split_ascii_whitespace().skip(1).collect();
+                match args[..] {
+                    [path] => do_create_db(c, path, DEFAULT_PAGE_SIZE),
+                    [path, page_size] => match page_size.parse::<u32>() {
+                        Ok(page_size) => do_create_db(c, path, page_size),
+                        Err(_) => println!("Page size must be a number."),
+                    },
+                    _ => println!("Specify a filename, and optionally a page size."),
+                }
+            }
             _ => println!("Unknown command (2): `{}`", line),
         }
             return;
@@ -111,10 +124,22 @@ fn do_command(c: &mut CliContext, line: &str) {
         "CREATE" => {
             do_create(c, line)
         }
+        "DELETE" => {
+            do_delete(c, line)
+        }
+        "UPDATE" => {
+            do_update(c, line)
+        }
+        "BEGIN" | "COMMIT" | "END" | "ROLLBACK" | "SAVEPOINT" | "RELEASE" => {
+            do_transaction(c, line)
+        }
         _ => println!("Unknown SQL command: `{}`", line),
     }
 }
 
+// The page size of new databases, unless another is given.  This is also SQLite's default.
+const DEFAULT_PAGE_SIZE: u32 = 4096;
+
 struct CliContext {
     server_state: diydb::DbServerState, 
 }
@@ -125,10 +150,17 @@ fn do_help(_: &mut CliContext) {
 .help               to get this list.
 .help [command]     to get more help on a command.
 .open               to open a persistent database.
+.create             to create a new persistent database file, and open it.
 .schema             to list the tables and their definitions.
+.checkpoint         to copy the changes in the write-ahead log into the database file.
+.check              to check the open database for corruption.
 SELECT ...          to do a query.
 INSERT ...          to insert values into a table.
 CREATE ...          to create a table.
+DELETE ...          to delete rows from a table.
+UPDATE ...          to change rows of a table.
+BEGIN ...           to start a transaction, which COMMIT or ROLLBACK ends.
+SAVEPOINT ...       to mark a point in a transaction that can be rolled back to.
 "
     );
 }
@@ -140,16 +172,68 @@ fn do_detailed_help(_: &mut CliContext, word: &str) {
 Type `.help` with no argument to see all commands; Type `.help [argument]` (with a single argument) to get detailed help on that command.",
         ".open" =>      "\
 Use to open a persistent database.  There is always a temporary database called 'temp' available.  Just CREATE a table in it.",
+        ".create" =>    "\
+Use to create a new, empty persistent database file, and open it.  The file must not already exist.
+Usage: `.create [filename] [page size]`.  The page size is a power of two from 512 to 65536, and defaults to 4096.",
         ".schema" =>    "Use to list the tables in all databases and their definitions.",
+        ".checkpoint" => "\
+Use to copy the pages in the write-ahead log of the open database into the database file, and empty the log.
+Only databases in WAL mode have a write-ahead log.  Other databases write their changes to the file when they are made.",
+        ".check" =>     "\
+Use to check the btrees, overflow chains and freelist of the open database, like SQLite's `PRAGMA integrity_check`.
+Prints `ok` if no problems are found, and otherwise a line for each problem, up to 100 of them.",
         "SELECT" =>     "\
 Enter a SQL query beginning with 'SELECT' and ending with a semicolon.
 The supported subset of SQL includes: 
   SELECT * FROM mytable;
   SELECT 1 + 1;
   SELECT a, b, c, 2 * 3 FROM  temp.numbers;
-WHERE, AS, GROUP BY, and JOIN are not supported.",
-        "INSERT" =>      "Use to insert values into a table.",
-        "CREATE" =>      "Use to create a table.  Example: CREATE TEMP TABLE t (x real, y real);",
+  SELECT price * qty AS total FROM orders WHERE qty > 1 ORDER BY total DESC;
+  SELECT * FROM mytable WHERE 1 LIMIT 10 OFFSET 20;
+  SELECT 1 < 2 AND NOT NULL IS NULL, 'a' || 'b', 2 IN (1, 2), 'abc' LIKE 'A%';
+  SELECT customer, count(*), sum(price) FROM orders GROUP BY customer HAVING count(*) > 1;
+  SELECT o.qty, c.name FROM orders o LEFT JOIN temp.customers c ON o.customer = c.id;
+The aggregate functions are count, sum, avg, min, max and group_concat.
+Tables can be joined with JOIN, LEFT JOIN, CROSS JOIN or a comma.",
+        "INSERT" =>      "\
+Use to insert values into a table of the temp database, or of the open database, which is written to the file.
+Examples:
+  INSERT INTO temp.numbers VALUES (1, 2, 3);
+  INSERT INTO people VALUES ('Ann', 31, 'Boston'), ('Bob', 45, 'Denver');
+Rows are given the next unused rowid.  Tables of the open database that have indexes can't be inserted into yet.",
+        "CREATE" =>      "\
+Use to create a table in the temp database, or in the open database, which is written to the file.
+Examples:
+  CREATE TEMP TABLE t (x real, y real);
+  CREATE TABLE people (name text, age int) STRICT;",
+        "DELETE" =>      "\
+Use to delete rows from a table of the temp database, or of the open database, which is written to the file.
+Examples:
+  DELETE FROM temp.numbers WHERE a > 2;
+  DELETE FROM people;
+Without a WHERE clause, every row is deleted.  Tables of the open database that have indexes can't be deleted from yet.",
+        "UPDATE" =>      "\
+Use to change rows of a table of the temp database, or of the open database, which is written to the file.
+Examples:
+  UPDATE temp.numbers SET a = a + 1, b = 0 WHERE a > 2;
+  UPDATE people SET city = 'Boston';
+Without a WHERE clause, every row is changed.  The values of STRICT tables must have the column's type.
+Tables of the open database that have indexes can't be updated yet.",
+        "BEGIN" =>      "\
+Use to start a transaction.  Changes to the temp database and the open database are not written to the file until
+COMMIT, and ROLLBACK discards them all.  Without a transaction, each statement is written when it succeeds.
+Examples:
+  BEGIN;
+  INSERT INTO people VALUES ('Ann', 31, 'Boston');
+  COMMIT;",
+        "SAVEPOINT" =>  "\
+Use to mark a point in a transaction, which ROLLBACK TO discards the later changes back to, and RELEASE forgets.
+A SAVEPOINT outside a transaction starts one, and releasing that savepoint commits it.
+Examples:
+  SAVEPOINT before_update;
+  UPDATE people SET age = age + 1;
+  ROLLBACK TO before_update;
+  RELEASE before_update;",
         _ => {
             println!("Unknown command: '{}'", word); 
             return;
@@ -167,6 +251,26 @@ fn do_open(c: &mut CliContext, path: &str) {
     }
 }
 
+fn do_create_db(c: &mut CliContext, path: &str, page_size: u32) {
+    if let Err(e) = diydb::create_db(&mut c.server_state, path, page_size) {
+        println!("Error creating database {path} : {}", e);
+    }
+}
+
+fn do_checkpoint(c: &mut CliContext) {
+    if let Err(e) = diydb::checkpoint(&mut c.server_state) {
+        println!("Error checkpointing database: {}", e);
+    }
+}
+
+fn do_check(c: &mut CliContext) {
+    match diydb::integrity_check(&c.server_state) {
+        Ok(problems) if problems.is_empty() => println!("ok"),
+        Ok(problems) => problems.iter().for_each(|p| println!("{}", p)),
+        Err(e) => println!("Error checking database: {}", e),
+    }
+}
+
 fn do_schema(c: &mut CliContext) {
     if let Err(e) = diydb::print_schema(&c.server_state) {
         println!("Error printing schemas: {}", e);
@@ -189,4 +293,22 @@ fn do_create(c: &mut CliContext, l: &str) {
     if let Err(e) = diydb::run_create(&mut c.server_state, l) {
         println!("Error running statement: {}", e);
     }
+}
+
+fn do_delete(c: &mut CliContext, l: &str) {
+    if let Err(e) = diydb::run_delete(&mut c.server_state, l) {
+        println!("Error running statement: {}", e);
+    }
+}
+
+fn do_transaction(c: &mut CliContext, l: &str) {
+    if let Err(e) = diydb::run_transaction_statement(&mut c.server_state, l) {
+        println!("Error running statement: {}", e);
+    }
+}
+
+fn do_update(c: &mut CliContext, l: &str) {
+    if let Err(e) = diydb::run_update(&mut c.server_state, l) {
+        println!("Error running statement: {}", e);
+    }
 }
\ No newline at end of file
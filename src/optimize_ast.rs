@@ -1,44 +1,27 @@
 //! simplifies ast trees.
-//! - evaluates constant expressions in select items.
+//! - evaluates constant expressions, and the constant parts of expressions with column references, in select items and where clauses.
 
 use anyhow::{bail, Result};
 
 use crate::ast;
-
-fn do_int_binop(i: i64, op: &ast::Op, j: i64) -> i64 {
-    use ast::Op::*;
-    match op {
-        Add => i + j,
-        Subtract => i - j,
-        Multiply => i * j,
-        Divide => i / j,
-    }
-}
-
-fn do_real_binop(i: f64, op: &ast::Op, j: f64) -> f64 {
-    use ast::Op::*;
-    match op {
-        Add => i + j,
-        Subtract => i - j,
-        Multiply => i * j,
-        Divide => i / j,
-    }
-}
+use crate::eval;
+use crate::sql_value::{self, SqlValue};
+use crate::typed_row::Row;
 
 fn do_binop(i: ast::Constant, op: &ast::Op, j: ast::Constant) -> Result<ast::Constant> {
-    use ast::Constant::*;
-    let icopy = i.clone();
-    let jcopy = j.clone();
-    match (i, j) {
-        (Int(i), Int(j)) => Ok(ast::Constant::Int(do_int_binop(i.clone(), op, j))),
-        (Real(i), Real(j)) => Ok(ast::Constant::Real(do_real_binop(i, op, j))),
-        (Int(i), Real(j)) => Ok(ast::Constant::Real(do_real_binop(i as f64, op, j))),
-        (Real(i), Int(j)) => Ok(ast::Constant::Real(do_real_binop(i, op, j as f64))),
-        (Null(), _) => Ok(ast::Constant::Null()),
-        (_, Null()) => Ok(ast::Constant::Null()),
-        _ => bail!("Invalid types in binary expression: {} {} {}", icopy, op, jcopy),
-    }
+    let v = eval::binop(&sql_value::from_ast_constant(&i), op, &sql_value::from_ast_constant(&j))?;
+    to_ast_constant(v)
+}
 
+fn to_ast_constant(v: SqlValue) -> Result<ast::Constant> {
+    Ok(match v {
+        SqlValue::Int(i) => ast::Constant::Int(i),
+        SqlValue::Real(f) => ast::Constant::Real(f),
+        SqlValue::Text(s) => ast::Constant::String(s),
+        SqlValue::Bool(b) => ast::Constant::Bool(b),
+        SqlValue::Null() => ast::Constant::Null(),
+        SqlValue::Blob(_) => bail!("Blob constants are not supported"),
+    })
 }
 
 #[test]
@@ -47,6 +30,9 @@ fn test_do_binop_ok() {
     use ast::Op::*;
     let cases = vec![
         (Int(1), Add, Int(1), Int(2)),
+        (Int(1), Lt, Int(2), Int(1)),
+        (String("a".to_string()), Concat, Int(1), String("a1".to_string())),
+        (Null(), Eq, Int(1), Null()),
     ];
     for case in cases {
         let res = do_binop(case.0, &case.1, case.2);
@@ -66,21 +52,57 @@ fn test_do_binop_err() {
     }
 }
 
-// TODO: handle expressions which contain column references too.
-// TODO: just call this simplify_expr.  There isn't a clear case where we need to get the Constant.
-fn try_simplify_expr_to_constant(expr: &ast::Expr) -> Result<ast::Constant>{
-    match expr {
-        ast::Expr::Constant(c) => return Ok(c.clone()),
-        ast::Expr::BinOp { lhs, op, rhs } => {
-            if let Ok(l) = try_simplify_expr_to_constant(lhs) {
-                if let Ok(r) = try_simplify_expr_to_constant(rhs) {
-                    return do_binop(l, op, r)
-                }
+/// simplifies an expression by evaluating the parts of it which do not refer to columns.
+fn simplify_expr(expr: &ast::Expr) -> Result<ast::Expr> {
+    use ast::Expr;
+    let simplified = match expr {
+        Expr::Constant(_) | Expr::ColRef { .. } => return Ok(expr.clone()),
+        Expr::BinOp { lhs, op, rhs } => {
+            let l = simplify_expr(lhs)?;
+            let r = simplify_expr(rhs)?;
+            if let (Expr::Constant(lc), Expr::Constant(rc)) = (&l, &r) {
+                return Ok(Expr::Constant(do_binop(lc.clone(), op, rc.clone())?));
             }
+            Expr::BinOp { lhs: Box::new(l), op: op.clone(), rhs: Box::new(r) }
         }
-        // ast::Expr::ColumnName => Ok(None) // meaning no errors, but not able to simplify to a constant.
+        Expr::Aggregate { func, args } => Expr::Aggregate {
+            func: *func,
+            args: args.iter().map(simplify_expr).collect::<Result<Vec<_>>>()?,
+        },
+        Expr::UnaryOp { op, expr } => Expr::UnaryOp { op: op.clone(), expr: Box::new(simplify_expr(expr)?) },
+        Expr::IsNull { expr, negated } => Expr::IsNull { expr: Box::new(simplify_expr(expr)?), negated: *negated },
+        Expr::Between { expr, low, high, negated } => Expr::Between {
+            expr: Box::new(simplify_expr(expr)?),
+            low: Box::new(simplify_expr(low)?),
+            high: Box::new(simplify_expr(high)?),
+            negated: *negated,
+        },
+        Expr::InList { expr, list, negated } => Expr::InList {
+            expr: Box::new(simplify_expr(expr)?),
+            list: list.iter().map(simplify_expr).collect::<Result<Vec<_>>>()?,
+            negated: *negated,
+        },
+    };
+    if !is_constant(&simplified) {
+        return Ok(simplified);
+    }
+    // With no column references, there is no input row needed to evaluate it.
+    let c = eval::bind(&simplified, &[], &[])?.eval(&Row { items: vec![] })?;
+    Ok(Expr::Constant(to_ast_constant(c)?))
+}
+
+/// returns true if the expression does not refer to any columns.
+fn is_constant(expr: &ast::Expr) -> bool {
+    use ast::Expr;
+    match expr {
+        Expr::Constant(_) => true,
+        // Aggregates depend on the input rows even if their arguments are constant, like `count(1)`.
+        Expr::ColRef { .. } | Expr::Aggregate { .. } => false,
+        Expr::BinOp { lhs, rhs, .. } => is_constant(lhs) && is_constant(rhs),
+        Expr::UnaryOp { expr, .. } | Expr::IsNull { expr, .. } => is_constant(expr),
+        Expr::Between { expr, low, high, .. } => is_constant(expr) && is_constant(low) && is_constant(high),
+        Expr::InList { expr, list, .. } => is_constant(expr) && list.iter().all(is_constant),
     }
-    unreachable!();
 }
 
 pub fn simplify_ast_select_statement(ss: &mut ast::SelectStatement) -> Result<()> {
@@ -91,14 +113,24 @@ pub fn simplify_ast_select_statement(ss: &mut ast::SelectStatement) -> Result<()
             match &mut ss.select.items[i] {
                 ast::SelItem::Star => ast::SelItem::Star,
                 x @ ast::SelItem::ColName(_) => x.clone(),
-                ast::SelItem::Expr(e) => {
-                    let c = try_simplify_expr_to_constant(e)?;
-                    ast::SelItem::Expr(ast::Expr::Constant(c.clone()))
-                }
+                ast::SelItem::Expr(e) => ast::SelItem::Expr(simplify_expr(e)?),
+                ast::SelItem::Aliased { expr, alias } => ast::SelItem::Aliased { expr: simplify_expr(expr)?, alias: alias.clone() },
             }
         );
     }
     ss.select.items = newitems;
+    if let Some(w) = &ss.r#where {
+        ss.r#where = Some(ast::WhereClause { expr: simplify_expr(&w.expr)? });
+    }
+    if let Some(h) = &ss.having {
+        ss.having = Some(ast::HavingClause { expr: simplify_expr(&h.expr)? });
+    }
+    if let Some(l) = &ss.limit {
+        ss.limit = Some(ast::LimitClause {
+            limit: simplify_expr(&l.limit)?,
+            offset: l.offset.as_ref().map(simplify_expr).transpose()?,
+        });
+    }
     Ok(())
 }
 
@@ -125,12 +157,22 @@ fn test_simplify_ast_select_statement() {
                     ],
                 },
                 from: None,
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
             expected: ast::SelectStatement {
                 select: ast::SelectClause {
                     items: vec![ast::SelItem::Expr(ast::Expr::Constant(ast::Constant::Int(2)))],
                 },
                 from: None,
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
         },
         Case {
@@ -140,18 +182,103 @@ fn test_simplify_ast_select_statement() {
                     items: vec![ast::SelItem::Expr(ast::Expr::Constant(ast::Constant::Int(2)))],
                 },
                 from: Some(ast::FromClause {
-                    databasename: String::from("db"),
-                    tablename: String::from("t"),
+                    table: ast::TableRef {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    },
+                    joins: vec![],
                 }),
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
             expected: ast::SelectStatement {
                 select: ast::SelectClause {
                     items: vec![ast::SelItem::Expr(ast::Expr::Constant(ast::Constant::Int(2)))],
                 },
                 from: Some(ast::FromClause {
-                    databasename: String::from("db"),
-                    tablename: String::from("t"),
+                    table: ast::TableRef {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    },
+                    joins: vec![],
+                }),
+                r#where: None,
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
+            },
+        },
+        Case {
+            desc: "Select a + (1 + 2) from t where a > 2 * 3;".to_string(),
+            input: ast::SelectStatement {
+                select: ast::SelectClause {
+                    items: vec![ast::SelItem::Expr(ast::Expr::BinOp {
+                        lhs: Box::new(ast::Expr::ColRef { tablename: None, colname: String::from("a") }),
+                        op: ast::Op::Add,
+                        rhs: Box::new(ast::Expr::BinOp {
+                            lhs: Box::new(ast::Expr::Constant(ast::Constant::Int(1))),
+                            op: ast::Op::Add,
+                            rhs: Box::new(ast::Expr::Constant(ast::Constant::Int(2))),
+                        }),
+                    })],
+                },
+                from: Some(ast::FromClause {
+                    table: ast::TableRef {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    },
+                    joins: vec![],
+                }),
+                r#where: Some(ast::WhereClause {
+                    expr: ast::Expr::BinOp {
+                        lhs: Box::new(ast::Expr::ColRef { tablename: None, colname: String::from("a") }),
+                        op: ast::Op::Gt,
+                        rhs: Box::new(ast::Expr::BinOp {
+                            lhs: Box::new(ast::Expr::Constant(ast::Constant::Int(2))),
+                            op: ast::Op::Multiply,
+                            rhs: Box::new(ast::Expr::Constant(ast::Constant::Int(3))),
+                        }),
+                    },
+                }),
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
+            },
+            expected: ast::SelectStatement {
+                select: ast::SelectClause {
+                    items: vec![ast::SelItem::Expr(ast::Expr::BinOp {
+                        lhs: Box::new(ast::Expr::ColRef { tablename: None, colname: String::from("a") }),
+                        op: ast::Op::Add,
+                        rhs: Box::new(ast::Expr::Constant(ast::Constant::Int(3))),
+                    })],
+                },
+                from: Some(ast::FromClause {
+                    table: ast::TableRef {
+                        databasename: String::from("db"),
+                        tablename: String::from("t"),
+                        alias: None,
+                    },
+                    joins: vec![],
+                }),
+                r#where: Some(ast::WhereClause {
+                    expr: ast::Expr::BinOp {
+                        lhs: Box::new(ast::Expr::ColRef { tablename: None, colname: String::from("a") }),
+                        op: ast::Op::Gt,
+                        rhs: Box::new(ast::Expr::Constant(ast::Constant::Int(6))),
+                    },
                 }),
+                order_by: None,
+                group_by: None,
+                having: None,
+                limit: None,
             },
         },
     ];
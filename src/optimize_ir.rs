@@ -0,0 +1,360 @@
+//! optimizes IR trees using the schema of the stored database.
+//! - replaces a scan of a stored table, whose rows are filtered by comparing the rowid with integer constants, like
+//!   `rowid = 5` or `rowid BETWEEN 1 AND 10`, with a search of the table's btree for those rowids.  In a table that
+//!   declares a column named `rowid`, `rowid` names that column, as in SQLite, so the table is not searched by rowid.
+//! - replaces a scan of a stored table, whose rows are filtered by comparing indexed columns with constants, with a
+//!   search of the index.
+//!
+//! An index can be used for the terms of the filter's predicate which are equalities on its first columns, and then
+//! optionally for the terms which bound the next column.  Those terms are removed from the predicate.
+
+use std::ops::Bound;
+
+use anyhow::Result;
+
+use crate::ast;
+use crate::ir;
+use crate::stored_db::{IndexInfo, StoredDb};
+
+/// replaces filtered scans of stored tables in `block` with searches of an index, where a suitable index exists.
+pub fn choose_indexes(server_state: &crate::DbServerState, block: ir::Block) -> Result<ir::Block> {
+    let db = match &server_state.stored_db {
+        None => return Ok(block),
+        Some(db) => db,
+    };
+    let recurse = |b: Box<ir::Block>| -> Result<Box<ir::Block>> { Ok(Box::new(choose_indexes(server_state, *b)?)) };
+    Ok(match block {
+        ir::Block::Filter(f) => match *f.input {
+            ir::Block::Scan(s) if s.databasename != "temp" => filtered_scan_to_ir(db, s, f.predicate),
+            input => ir::Block::Filter(ir::Filter { predicate: f.predicate, input: recurse(Box::new(input))? }),
+        },
+        ir::Block::Project(p) => ir::Block::Project(ir::Project { input: recurse(p.input)?, ..p }),
+        ir::Block::Sort(s) => ir::Block::Sort(ir::Sort { input: recurse(s.input)?, ..s }),
+        ir::Block::Limit(l) => ir::Block::Limit(ir::Limit { input: recurse(l.input)?, ..l }),
+        ir::Block::Aggregate(a) => ir::Block::Aggregate(ir::Aggregate { input: recurse(a.input)?, ..a }),
+        ir::Block::Join(j) => ir::Block::Join(ir::Join { left: recurse(j.left)?, right: recurse(j.right)?, ..j }),
+        b @ (ir::Block::Scan(_)
+        | ir::Block::SeekRowid(_)
+        | ir::Block::RowidRange(_)
+        | ir::Block::IndexSeekEq(_)
+        | ir::Block::IndexRange(_)
+        | ir::Block::ConstantRow(_)) => b,
+    })
+}
+
+/// returns the expression that is true when both `lhs` and `rhs` are.
+fn and(lhs: ast::Expr, rhs: ast::Expr) -> ast::Expr {
+    ast::Expr::BinOp { lhs: Box::new(lhs), op: ast::Op::And, rhs: Box::new(rhs) }
+}
+
+/// the terms of a predicate that an index can be searched for.
+struct IndexUse<'a> {
+    index: &'a IndexInfo,
+    /// the positions of the terms that fix the values of the first indexed columns, and those values.
+    eq: Vec<(usize, ast::Constant)>,
+    /// the positions of the terms that bound the next indexed column, and the bounds.
+    lower: Option<(usize, Bound<ast::Constant>)>,
+    upper: Option<(usize, Bound<ast::Constant>)>,
+}
+
+impl IndexUse<'_> {
+    fn has_range(&self) -> bool {
+        self.lower.is_some() || self.upper.is_some()
+    }
+}
+
+/// returns the IR for the rows of the scan `s` for which `predicate` is true, using the terms on the rowid to search
+/// the table if there are any, or else the index that handles the most terms of the predicate, if any.
+fn filtered_scan_to_ir(db: &StoredDb, s: ir::Scan, predicate: ast::Expr) -> ir::Block {
+    let qualifier = s.alias.clone().unwrap_or_else(|| s.tablename.clone());
+    let terms = predicate.conjuncts();
+    // If the schema cannot be read, the table is scanned, and reading the table reports the error.
+    let declares_rowid = db
+        .open_table_for_read(&s.tablename)
+        .is_ok_and(|t| crate::table_traits::TableMeta::column_names(&t).iter().any(|c| c == "rowid"));
+    if !declares_rowid {
+        if let Some(block) = rowid_search_to_ir(&s, &terms, &qualifier) {
+            return block;
+        }
+    }
+    let indexes = db.get_indexes(&s.tablename).unwrap_or_default();
+    let best = indexes
+        .iter()
+        .map(|index| match_index(index, &terms, &qualifier))
+        .filter(|u| !u.eq.is_empty() || u.has_range())
+        .max_by_key(|u| (u.eq.len(), u.has_range()));
+    let u = match best {
+        None => return ir::Block::Filter(ir::Filter { predicate, input: Box::new(ir::Block::Scan(s)) }),
+        Some(u) => u,
+    };
+    let used: Vec<usize> = u.eq.iter().map(|(i, _)| *i)
+        .chain(u.lower.iter().chain(&u.upper).map(|(i, _)| *i))
+        .collect();
+    let eq: Vec<ast::Constant> = u.eq.iter().map(|(_, c)| c.clone()).collect();
+    let block = match u.has_range() {
+        false => ir::Block::IndexSeekEq(ir::IndexSeekEq {
+            databasename: s.databasename,
+            tablename: s.tablename,
+            alias: s.alias,
+            indexname: u.index.name.clone(),
+            key: eq,
+        }),
+        true => ir::Block::IndexRange(ir::IndexRange {
+            databasename: s.databasename,
+            tablename: s.tablename,
+            alias: s.alias,
+            indexname: u.index.name.clone(),
+            eq,
+            lower: u.lower.map_or(Bound::Unbounded, |(_, b)| b),
+            upper: u.upper.map_or(Bound::Unbounded, |(_, b)| b),
+        }),
+    };
+    let rest = terms
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !used.contains(i))
+        .map(|(_, t)| t.clone())
+        .reduce(and);
+    match rest {
+        None => block,
+        Some(predicate) => ir::Block::Filter(ir::Filter { predicate, input: Box::new(block) }),
+    }
+}
+
+/// returns a `SeekRowid` or `RowidRange` block for the rows of the scan `s` whose rowids are allowed by the terms of
+/// `terms` that compare the rowid with integer constants, filtered by the other terms, or None if there are no such
+/// terms.
+fn rowid_search_to_ir(s: &ir::Scan, terms: &[&ast::Expr], qualifier: &str) -> Option<ir::Block> {
+    let mut range: Option<(i64, i64)> = None;
+    let mut rest: Vec<ast::Expr> = vec![];
+    for term in terms {
+        match (rowid_bounds(term, qualifier), range) {
+            (Some(b), None) => range = Some(b),
+            (Some((min, max)), Some((min0, max0))) => range = Some((min.max(min0), max.min(max0))),
+            (None, _) => rest.push((*term).clone()),
+        }
+    }
+    let block = match range? {
+        (min, max) if min == max => ir::Block::SeekRowid(ir::SeekRowid {
+            databasename: s.databasename.clone(),
+            tablename: s.tablename.clone(),
+            alias: s.alias.clone(),
+            rowid: min,
+        }),
+        (min, max) => ir::Block::RowidRange(ir::RowidRange {
+            databasename: s.databasename.clone(),
+            tablename: s.tablename.clone(),
+            alias: s.alias.clone(),
+            min,
+            max,
+        }),
+    };
+    Some(match rest.into_iter().reduce(and) {
+        None => block,
+        Some(predicate) => ir::Block::Filter(ir::Filter { predicate, input: Box::new(block) }),
+    })
+}
+
+/// returns the smallest and largest rowids of the table named `qualifier` for which `term` can be true, if `term` only
+/// compares the rowid with integer constants.
+fn rowid_bounds(term: &ast::Expr, qualifier: &str) -> Option<(i64, i64)> {
+    let int = |b: Bound<ast::Constant>, unbounded: i64, step: i64| match b {
+        Bound::Unbounded => Some(unbounded),
+        Bound::Included(ast::Constant::Int(i)) => Some(i),
+        Bound::Excluded(ast::Constant::Int(i)) => i.checked_add(step),
+        _ => None,
+    };
+    let (lower, upper) = column_bounds(term, qualifier, "rowid")?;
+    Some((int(lower, i64::MIN, 1)?, int(upper, i64::MAX, -1)?))
+}
+
+/// finds the terms that `index` can be searched for: an equality on each of its first columns, for as many columns
+/// as there are such terms, and then bounds on the next column.
+fn match_index<'a>(index: &'a IndexInfo, terms: &[&ast::Expr], qualifier: &str) -> IndexUse<'a> {
+    let mut u = IndexUse { index, eq: vec![], lower: None, upper: None };
+    // Columns sorted in descending order are not supported, so only the leading ascending columns are used.
+    for col in index.columns.iter().take_while(|c| !c.descending) {
+        let bounds: Vec<(usize, Bound<ast::Constant>, Bound<ast::Constant>)> = terms
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| column_bounds(t, qualifier, &col.colname).map(|(l, h)| (i, l, h)))
+            .collect();
+        let eq = bounds.iter().find_map(|(i, l, h)| match (l, h) {
+            (Bound::Included(a), Bound::Included(b)) if a == b => Some((*i, a.clone())),
+            _ => None,
+        });
+        if let Some(eq) = eq {
+            u.eq.push(eq);
+            continue;
+        }
+        // A term that bounds both sides, like BETWEEN, is removed from the predicate when it is used, so it must be
+        // used for both bounds or for neither.
+        let lower = bounds.iter().find(|(_, l, _)| *l != Bound::Unbounded);
+        let upper = match lower {
+            Some(t) if t.2 != Bound::Unbounded => Some(t),
+            _ => bounds.iter().find(|(_, l, h)| *l == Bound::Unbounded && *h != Bound::Unbounded),
+        };
+        u.lower = lower.map(|(i, l, _)| (*i, l.clone()));
+        u.upper = upper.map(|(i, _, h)| (*i, h.clone()));
+        break;
+    }
+    u
+}
+
+/// returns the bounds on column `colname` of the table named `qualifier` for which `term` can be true, if `term` only
+/// compares that column with non-NULL constants.
+fn column_bounds(
+    term: &ast::Expr,
+    qualifier: &str,
+    colname: &str,
+) -> Option<(Bound<ast::Constant>, Bound<ast::Constant>)> {
+    use ast::Op::*;
+    let is_col = |e: &ast::Expr| match e {
+        ast::Expr::ColRef { tablename, colname: c } => c == colname && tablename.as_ref().is_none_or(|n| n == qualifier),
+        _ => false,
+    };
+    let constant = |e: &ast::Expr| match e {
+        ast::Expr::Constant(ast::Constant::Null()) => None,
+        ast::Expr::Constant(c) => Some(c.clone()),
+        _ => None,
+    };
+    match term {
+        ast::Expr::BinOp { lhs, op, rhs } => {
+            // Put the comparison in the form `column op constant`.
+            let (op, c) = match (is_col(lhs), is_col(rhs)) {
+                (true, false) => (op.clone(), constant(rhs)?),
+                (false, true) => (
+                    match op {
+                        Lt => Gt,
+                        LtEq => GtEq,
+                        Gt => Lt,
+                        GtEq => LtEq,
+                        o => o.clone(),
+                    },
+                    constant(lhs)?,
+                ),
+                _ => return None,
+            };
+            match op {
+                Eq => Some((Bound::Included(c.clone()), Bound::Included(c))),
+                Gt => Some((Bound::Excluded(c), Bound::Unbounded)),
+                GtEq => Some((Bound::Included(c), Bound::Unbounded)),
+                Lt => Some((Bound::Unbounded, Bound::Excluded(c))),
+                LtEq => Some((Bound::Unbounded, Bound::Included(c))),
+                _ => None,
+            }
+        }
+        ast::Expr::Between { expr, low, high, negated: false } if is_col(expr) => {
+            Some((Bound::Included(constant(low)?), Bound::Included(constant(high)?)))
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_choose_indexes() {
+    use ast::Constant::Int;
+    let path = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set") + "/resources/test/indexes.db";
+    let mut ss = crate::DbServerState::new();
+    crate::open_db(&mut ss, &path).expect("Should have opened db.");
+    let to_ir = |q: &str| {
+        let stmt = crate::pt_to_ast::pt_select_statement_to_ast(q).unwrap();
+        choose_indexes(&ss, crate::ast_to_ir::ast_select_statement_to_ir(&stmt).unwrap()).unwrap()
+    };
+    let expr = |e: &str| {
+        use pest::Parser;
+        let pairs = crate::parser::SQLParser::parse(crate::parser::Rule::expr, e).unwrap().next().unwrap().into_inner();
+        crate::parser::parse_expr(pairs)
+    };
+
+    assert_eq!(
+        to_ir("select * from people where 37 = age"),
+        ir::Block::IndexSeekEq(ir::IndexSeekEq {
+            databasename: String::from("main"),
+            tablename: String::from("people"),
+            alias: None,
+            indexname: String::from("people_age"),
+            key: vec![Int(37)],
+        })
+    );
+    // The index that handles the most terms is used, and the terms it doesn't handle filter its rows.
+    assert_eq!(
+        to_ir("select * from people p where p.age > 30 and name = 'p5' and city = 'Austin'"),
+        ir::Block::Filter(ir::Filter {
+            predicate: expr("name = 'p5'"),
+            input: Box::new(ir::Block::IndexRange(ir::IndexRange {
+                databasename: String::from("main"),
+                tablename: String::from("people"),
+                alias: Some(String::from("p")),
+                indexname: String::from("people_city_age"),
+                eq: vec![ast::Constant::String(String::from("Austin"))],
+                lower: Bound::Excluded(Int(30)),
+                upper: Bound::Unbounded,
+            })),
+        })
+    );
+    assert_eq!(
+        to_ir("select * from people where age between 1 and 3"),
+        ir::Block::IndexRange(ir::IndexRange {
+            databasename: String::from("main"),
+            tablename: String::from("people"),
+            alias: None,
+            indexname: String::from("people_age"),
+            eq: vec![],
+            lower: Bound::Included(Int(1)),
+            upper: Bound::Included(Int(3)),
+        })
+    );
+    // Terms that are not on the leading indexed columns, or that compare with NULL, can't use an index.
+    for q in ["select * from people where name = 'p5'", "select * from people where age = null"] {
+        assert!(matches!(to_ir(q), ir::Block::Filter(ir::Filter { input, .. }) if matches!(*input, ir::Block::Scan(_))));
+    }
+}
+
+#[test]
+fn test_rowid_terms_to_ir() {
+    let path = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set") + "/resources/test/indexes.db";
+    let mut ss = crate::DbServerState::new();
+    crate::open_db(&mut ss, &path).expect("Should have opened db.");
+    crate::run_create(&mut ss, "create temp table t (a int)").unwrap();
+    let to_ir = |q: &str| {
+        let stmt = crate::pt_to_ast::pt_select_statement_to_ast(q).unwrap();
+        choose_indexes(&ss, crate::ast_to_ir::ast_select_statement_to_ir(&stmt).unwrap()).unwrap()
+    };
+    let expr = |e: &str| {
+        use pest::Parser;
+        let pairs = crate::parser::SQLParser::parse(crate::parser::Rule::expr, e).unwrap().next().unwrap().into_inner();
+        crate::parser::parse_expr(pairs)
+    };
+    assert_eq!(
+        to_ir("select * from people where rowid = 3"),
+        ir::Block::SeekRowid(ir::SeekRowid {
+            databasename: String::from("main"),
+            tablename: String::from("people"),
+            alias: None,
+            rowid: 3,
+        })
+    );
+    // Terms on the rowid are combined, and are used rather than an index.  The other terms filter the rows of the
+    // range.
+    assert_eq!(
+        to_ir("select * from people p where 10 > p.rowid and age = 1 and rowid between 2 and 20"),
+        ir::Block::Filter(ir::Filter {
+            predicate: expr("age = 1"),
+            input: Box::new(ir::Block::RowidRange(ir::RowidRange {
+                databasename: String::from("main"),
+                tablename: String::from("people"),
+                alias: Some(String::from("p")),
+                min: 2,
+                max: 9,
+            })),
+        })
+    );
+    // Comparisons with constants that are not integers filter the rows.
+    assert!(matches!(
+        to_ir("select * from people where rowid > 2.5"),
+        ir::Block::Filter(ir::Filter { input, .. }) if matches!(*input, ir::Block::Scan(_))
+    ));
+    // Temporary tables are always scanned.
+    assert!(matches!(to_ir("select * from temp.t where rowid = 3"), ir::Block::Filter(_)));
+}
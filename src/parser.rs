@@ -17,11 +17,21 @@ lazy_static::lazy_static! {
         use pest::pratt_parser::{Assoc::*, Op};
         use Rule::*;
 
-        // Precedence is defined lowest to highest
+        // Precedence is defined lowest to highest, following https://www.sqlite.org/lang_expr.html#operators
         PrattParser::new()
+            .op(Op::infix(or, Left))
+            .op(Op::infix(and, Left))
+            .op(Op::prefix(not))
+            // Equality-like operators have equal precedence
+            .op(Op::infix(eq, Left) | Op::infix(neq, Left) | Op::infix(like, Left) | Op::infix(not_like, Left)
+                | Op::postfix(is_null) | Op::postfix(is_not_null)
+                | Op::postfix(between) | Op::postfix(not_between)
+                | Op::postfix(in_list) | Op::postfix(not_in_list))
+            .op(Op::infix(lt, Left) | Op::infix(lte, Left) | Op::infix(gt, Left) | Op::infix(gte, Left))
             // Addition and subtract have equal precedence
             .op(Op::infix(add, Left) | Op::infix(subtract, Left))
             .op(Op::infix(multiply, Left) | Op::infix(divide, Left))
+            .op(Op::infix(concat, Left))
     };
 }
 
@@ -35,7 +45,70 @@ pub fn parse_expr(pairs: Pairs<Rule>) -> ast::Expr {
             | Rule::integer_literal
             | Rule::decimal_literal
             | Rule::single_quoted_string => ast::Expr::Constant(crate::pt_to_ast::parse_literal_from_rule(primary)),
-            rule => unreachable!("parse_expr expected literal, found {:?}", rule),
+            Rule::column_ref => {
+                let parts: Vec<_> = primary.into_inner().collect();
+                let (tablename, colname) = match parts.len() {
+                    1 => (None, parts[0].as_str()),
+                    2 => (Some(String::from(parts[0].as_str())), parts[1].as_str()),
+                    _ => unreachable!(),
+                };
+                ast::Expr::ColRef {
+                    tablename,
+                    colname: String::from(colname),
+                }
+            }
+            Rule::aggregate_call => {
+                let mut parts = primary.into_inner();
+                let name = parts.next().unwrap().as_rule();
+                let args: Vec<_> = parts.collect();
+                let func = match name {
+                    Rule::count if args.len() == 1 && args[0].as_rule() == Rule::star => ast::AggregateFunc::CountStar,
+                    Rule::count => ast::AggregateFunc::Count,
+                    Rule::sum => ast::AggregateFunc::Sum,
+                    Rule::avg => ast::AggregateFunc::Avg,
+                    Rule::min => ast::AggregateFunc::Min,
+                    Rule::max => ast::AggregateFunc::Max,
+                    Rule::group_concat => ast::AggregateFunc::GroupConcat,
+                    rule => unreachable!("parse_expr expected aggregate function name, found {:?}", rule),
+                };
+                ast::Expr::Aggregate {
+                    func,
+                    // The argument of a `count(*)` is the star, which is not an expression.
+                    args: args.into_iter().filter(|p| p.as_rule() == Rule::expr).map(|p| parse_expr(p.into_inner())).collect(),
+                }
+            }
+            Rule::expr => parse_expr(primary.into_inner()),
+            rule => unreachable!("parse_expr expected literal or column reference, found {:?}", rule),
+        })
+        .map_prefix(|op, rhs| match op.as_rule() {
+            Rule::not => ast::Expr::UnaryOp {
+                op: ast::UnaryOp::Not,
+                expr: Box::new(rhs),
+            },
+            rule => unreachable!("Expr::parse expected prefix operation, found {:?}", rule),
+        })
+        .map_postfix(|lhs, op| {
+            let rule = op.as_rule();
+            let negated = matches!(rule, Rule::is_not_null | Rule::not_between | Rule::not_in_list);
+            let mut operands = op.into_inner().map(|p| parse_expr(p.into_inner()));
+            match rule {
+                Rule::is_null | Rule::is_not_null => ast::Expr::IsNull {
+                    expr: Box::new(lhs),
+                    negated,
+                },
+                Rule::between | Rule::not_between => ast::Expr::Between {
+                    expr: Box::new(lhs),
+                    low: Box::new(operands.next().unwrap()),
+                    high: Box::new(operands.next().unwrap()),
+                    negated,
+                },
+                Rule::in_list | Rule::not_in_list => ast::Expr::InList {
+                    expr: Box::new(lhs),
+                    list: operands.collect(),
+                    negated,
+                },
+                rule => unreachable!("Expr::parse expected postfix operation, found {:?}", rule),
+            }
         })
         .map_infix(|lhs, op, rhs| {
             let op = match op.as_rule() {
@@ -43,6 +116,26 @@ pub fn parse_expr(pairs: Pairs<Rule>) -> ast::Expr {
                 Rule::subtract => ast::Op::Subtract,
                 Rule::multiply => ast::Op::Multiply,
                 Rule::divide => ast::Op::Divide,
+                Rule::concat => ast::Op::Concat,
+                Rule::eq => ast::Op::Eq,
+                Rule::neq => ast::Op::NotEq,
+                Rule::lt => ast::Op::Lt,
+                Rule::lte => ast::Op::LtEq,
+                Rule::gt => ast::Op::Gt,
+                Rule::gte => ast::Op::GtEq,
+                Rule::and => ast::Op::And,
+                Rule::or => ast::Op::Or,
+                Rule::like => ast::Op::Like,
+                Rule::not_like => {
+                    return ast::Expr::UnaryOp {
+                        op: ast::UnaryOp::Not,
+                        expr: Box::new(ast::Expr::BinOp {
+                            lhs: Box::new(lhs),
+                            op: ast::Op::Like,
+                            rhs: Box::new(rhs),
+                        }),
+                    }
+                }
                 rule => unreachable!("Expr::parse expected infix operation, found {:?}", rule),
             };
             ast::Expr::BinOp {
@@ -100,6 +193,22 @@ fn test_parse_expr() {
         ("3 * 4"),
         ("5 * 6 + 7"),
         ("8 + 9 * 10"), 
+        ("1 < 2 AND 2 <= 3"),
+        ("1 = 1 OR NOT 1 <> 2"),
+        ("NULL IS NULL"),
+        ("1 IS NOT NULL"),
+        ("2 BETWEEN 1 AND 3"),
+        ("2 NOT BETWEEN 1 AND 3"),
+        ("2 IN (1, 2, 3)"),
+        ("2 NOT IN (1)"),
+        ("'abc' LIKE 'a%'"),
+        ("'abc' NOT LIKE 'a%'"),
+        ("'a' || 'b'"),
+        ("(1 + 2) * 3"),
+        ("a"),
+        ("t.a + 1"),
+        ("price * qty"),
+        ("nullable IS NULL"),
     ];
 
     for case in cases {
@@ -214,6 +323,18 @@ fn test_parse_select_statement() {
         ("select 1.01"),
         ("select 'hi'"),
         ("select 1 + 1"),
+        ("select * from tbl where 1"),
+        ("select a, b from tbl where 1 + 1"),
+        ("select 1 < 2, 1 is null, 2 in (1, 2)"),
+        ("select * from tbl where 1 < 2 and not 0"),
+        ("select a, b from tbl order by a"),
+        ("select a, b from tbl where a > 1 order by b desc, a asc nulls last, 1 nulls first"),
+        ("select a from tbl limit 10"),
+        ("select a from tbl order by a limit 10 offset 2"),
+        ("select a from tbl limit 2, 10"),
+        ("select count(*) from tbl"),
+        ("select a, sum(b), group_concat(c, ';') from tbl group by a having count(*) > 1 order by 2"),
+        ("select count from tbl"),
     ];
 
     for case in cases {
@@ -227,6 +348,16 @@ fn test_not_parse_invalid_select_statement() {
         ("CREATE * FROM tbl"),
         ("FROM blahblah"),
         ("select \"hi\""), // Double quotes are invalid as literals in std SQL.
+        ("select * from tbl where"),
+        ("select 1 between 2"),
+        ("select 1 in ()"),
+        ("select a from tbl order by"),
+        ("select a from tbl order by a nulls"),
+        ("select a from tbl limit"),
+        ("select a from tbl limit 1 order by a"),
+        ("select count(*) from tbl having count(*) > 1 group by a"),
+        ("select sum() from tbl"),
+        ("select a from tbl group by"),
     ];
 
     for case in cases {
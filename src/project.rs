@@ -1,9 +1,10 @@
 //! provides helper functions for the projection block of a query.
 
+use crate::eval::{self, BoundExpr};
 use crate::sql_type::SqlType;
 use crate::sql_value::SqlValue;
 use crate::Row;
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 // TODO: SelItem can be defined again in IR.
 use crate::ast;
@@ -13,23 +14,20 @@ use crate::ast;
 pub enum ProjectAction {
     Take(usize), // let Take(x) ; 0 <= x < input_row.len(); take index x from input row.
     Constant(SqlValue), // put constant value into output row.
-                 // Expr(),
+    Expr(BoundExpr), // evaluate expression on the input row, and put the result into output row.
 }
 
 /// builds the information needed to do a project of a table at runtime.
 pub fn build_project(
+    in_tablenames: &[String],
     in_colnames: &[String],
     in_coltypes: &[SqlType],
+    in_hidden: &[bool],
     out_cols: &[ast::SelItem],
 ) -> Result<(Vec<ProjectAction>, Vec<String>, Vec<SqlType>)> {
     let mut actions = vec![];
     let mut out_colnames = vec![];
     let mut out_coltypes = vec![];
-    use std::collections::HashMap;
-    let mut input_indexes: HashMap<&str, usize> = HashMap::new();
-    for (i, c) in in_colnames.iter().enumerate() {
-        input_indexes.insert(c, i);
-    }
     for out_item in out_cols.iter() {
         match out_item {
             ast::SelItem::Expr(ast::Expr::Constant(c)) => {
@@ -48,7 +46,7 @@ pub fn build_project(
                     }
                     ast::Constant::String(s) => SqlValue::Text(s.clone()),
                 }));
-                // TODO: handle AS statements.
+
                 // Sqlite3 names columns after the literal expression used, like "sum(1)"; postgres calls it "?column?"
                 out_colnames.push("?column?".to_string());
                 // TODO: check if columns can reference other columns by number.
@@ -68,26 +66,49 @@ pub fn build_project(
                     ast::Constant::String(_) => SqlType::Text,
                 });
             }
-            ast::SelItem::Expr(_) => {
-                unimplemented!("Only constant items supported in expressions at this time");
-            }
+            ast::SelItem::Expr(e) => match eval::bind(e, in_tablenames, in_colnames)? {
+                // A qualified column name, like `t.a`.
+                BoundExpr::Column(idx) => {
+                    actions.push(ProjectAction::Take(idx));
+                    out_colnames.push(in_colnames[idx].clone());
+                    out_coltypes.push(in_coltypes[idx]);
+                }
+                b => {
+                    out_coltypes.push(expr_type(&b, in_coltypes));
+                    actions.push(ProjectAction::Expr(b));
+                    out_colnames.push("?column?".to_string());
+                }
+            },
             ast::SelItem::ColName(n) => {
-                let idx: usize = match input_indexes.get(n.name.as_str()) {
-                    Some(idx) => *idx,
-                    None => panic!(
-                        "Column name not found: {} not in {:?}",
-                        n,
-                        input_indexes.keys()
-                    ),
+                // Binding reports a name that matches columns of more than one joined table as ambiguous.
+                let colref = ast::Expr::ColRef {
+                    tablename: None,
+                    colname: n.name.clone(),
+                };
+                let idx: usize = match eval::bind(&colref, in_tablenames, in_colnames)? {
+                    BoundExpr::Column(idx) => idx,
+                    _ => unreachable!(),
                 };
                 actions.push(ProjectAction::Take(idx));
-                out_colnames.push(in_colnames[idx].clone()); // TODO: handle AS statements.
+                out_colnames.push(in_colnames[idx].clone());
                 out_coltypes.push(in_coltypes[idx]);
             }
+            ast::SelItem::Aliased { expr, alias } => {
+                let (a, _, t) = build_project(
+                    in_tablenames,
+                    in_colnames,
+                    in_coltypes,
+                    in_hidden,
+                    &[ast::SelItem::Expr(expr.clone())],
+                )?;
+                actions.extend(a);
+                out_colnames.push(alias.clone());
+                out_coltypes.extend(t);
+            }
             ast::SelItem::Star => {
-                for i in 0..in_colnames.len() {
+                for i in (0..in_colnames.len()).filter(|i| !in_hidden[*i]) {
                     actions.push(ProjectAction::Take(i));
-                    out_colnames.push(in_colnames[i].clone()); // TODO: handle AS statements.
+                    out_colnames.push(in_colnames[i].clone());
                     out_coltypes.push(in_coltypes[i]);
                 }
             }
@@ -96,6 +117,25 @@ pub fn build_project(
     Ok((actions, out_colnames, out_coltypes))
 }
 
+/// determines the type of the values that an expression produces, as far as it can be known before running it.
+pub fn expr_type(e: &BoundExpr, in_coltypes: &[SqlType]) -> SqlType {
+    use ast::Op::*;
+    match e {
+        BoundExpr::Constant(v) => crate::sql_type::from_sql_value(v),
+        BoundExpr::Column(idx) => in_coltypes[*idx],
+        BoundExpr::BinOp { lhs, op: Add | Subtract | Multiply | Divide, rhs } => {
+            match (expr_type(lhs, in_coltypes), expr_type(rhs, in_coltypes)) {
+                (SqlType::Null, _) | (_, SqlType::Null) => SqlType::Null,
+                (SqlType::Int, SqlType::Int) => SqlType::Int,
+                _ => SqlType::Real,
+            }
+        }
+        BoundExpr::BinOp { op: Concat, .. } => SqlType::Text,
+        // Comparisons and logical operators produce 1, 0, or NULL.
+        _ => SqlType::Int,
+    }
+}
+
 #[cfg(test)]
 fn make_ast_colname(s: &str) -> ast::SelItem {
     ast::SelItem::ColName(ast::ColName {
@@ -116,6 +156,8 @@ fn test_build_project_colnames_only() {
         .iter()
         .map(|i| String::from(*i))
         .collect();
+    let tablenames = vec![String::from("t"); colnames.len()];
+    let hidden = vec![false; colnames.len()];
     let coltypes: Vec<SqlType> = vec![Int, Int, Real, Real, Text];
     let out_cols = vec![
         make_ast_colname("a"),
@@ -130,7 +172,7 @@ fn test_build_project_colnames_only() {
         .collect();
     let expected_coltypes = vec![Int, Real, Int, Int];
     let (actual_actions, actual_colnames, actual_coltypes) =
-        build_project(&colnames, &coltypes, &out_cols).unwrap();
+        build_project(&tablenames, &colnames, &coltypes, &hidden, &out_cols).unwrap();
     assert_eq!(actual_actions, expected_actions);
     assert_eq!(actual_colnames, expected_colnames);
     assert_eq!(actual_coltypes, expected_coltypes);
@@ -144,6 +186,8 @@ fn test_build_project_constant_expression() {
         .iter()
         .map(|i| String::from(*i))
         .collect();
+    let tablenames = vec![String::from("t"); colnames.len()];
+    let hidden = vec![false; colnames.len()];
     let coltypes: Vec<SqlType> = vec![Int, Int, Real, Real, Text];
     let out_cols = vec![make_ast_constant(1)];
     let expected_actions = vec![Constant(SqlValue::Int(1))];
@@ -151,7 +195,7 @@ fn test_build_project_constant_expression() {
         vec!["?column?"].iter().map(|i| String::from(*i)).collect();
     let expected_coltypes = vec![Int];
     let (actual_actions, actual_colnames, actual_coltypes) =
-        build_project(&colnames, &coltypes, &out_cols).unwrap();
+        build_project(&tablenames, &colnames, &coltypes, &hidden, &out_cols).unwrap();
     assert_eq!(actual_actions, expected_actions);
     assert_eq!(actual_colnames, expected_colnames);
     assert_eq!(actual_coltypes, expected_coltypes);
@@ -165,6 +209,8 @@ fn test_build_project_multiple_star() {
         .iter()
         .map(|i| String::from(*i))
         .collect();
+    let tablenames = vec![String::from("t"); colnames.len()];
+    let hidden = vec![false; colnames.len()];
     let coltypes: Vec<SqlType> = vec![Int, Int, Real, Real, Text];
     let out_cols = vec![
         ast::SelItem::Star,
@@ -191,19 +237,67 @@ fn test_build_project_multiple_star() {
             .collect();
     let expected_coltypes = vec![Int, Int, Real, Real, Text, Int, Int, Int, Real, Real, Text];
     let (actual_actions, actual_colnames, actual_coltypes) =
-        build_project(&colnames, &coltypes, &out_cols).unwrap();
+        build_project(&tablenames, &colnames, &coltypes, &hidden, &out_cols).unwrap();
     assert_eq!(actual_actions, expected_actions);
     assert_eq!(actual_colnames, expected_colnames);
     assert_eq!(actual_coltypes, expected_coltypes);
 }
 
+#[test]
+fn test_build_project_column_expression() {
+    use crate::sql_type::SqlType::*;
+    let colnames: Vec<String> = vec!["price", "qty"]
+        .iter()
+        .map(|i| String::from(*i))
+        .collect();
+    let tablenames = vec![String::from("t"); colnames.len()];
+    let hidden = vec![false; colnames.len()];
+    let coltypes: Vec<SqlType> = vec![Real, Int];
+    let colref = |t: Option<&str>, c: &str| ast::Expr::ColRef {
+        tablename: t.map(String::from),
+        colname: String::from(c),
+    };
+    let out_cols = vec![
+        ast::SelItem::Expr(ast::Expr::BinOp {
+            lhs: Box::new(colref(None, "price")),
+            op: ast::Op::Multiply,
+            rhs: Box::new(colref(None, "qty")),
+        }),
+        ast::SelItem::Expr(colref(Some("t"), "qty")),
+    ];
+    let (actions, actual_colnames, actual_coltypes) =
+        build_project(&tablenames, &colnames, &coltypes, &hidden, &out_cols).unwrap();
+    assert_eq!(actions[1], ProjectAction::Take(1));
+    assert_eq!(actual_colnames, vec!["?column?".to_string(), "qty".to_string()]);
+    assert_eq!(actual_coltypes, vec![Real, Int]);
+    let output = project_row(&actions, &Row { items: vec![SqlValue::Real(2.5), SqlValue::Int(4)] }).unwrap();
+    assert_eq!(output.items, vec![SqlValue::Real(10.0), SqlValue::Int(4)]);
+
+    let aliased_cols = vec![
+        ast::SelItem::Aliased { expr: colref(None, "qty"), alias: String::from("quantity") },
+        ast::SelItem::Aliased { expr: ast::Expr::Constant(ast::Constant::Int(1)), alias: String::from("one") },
+    ];
+    let (actions, actual_colnames, actual_coltypes) =
+        build_project(&tablenames, &colnames, &coltypes, &hidden, &aliased_cols).unwrap();
+    assert_eq!(actions, vec![ProjectAction::Take(1), ProjectAction::Constant(SqlValue::Int(1))]);
+    assert_eq!(actual_colnames, vec!["quantity".to_string(), "one".to_string()]);
+    assert_eq!(actual_coltypes, vec![Int, Int]);
+
+    let bad_cols = vec![ast::SelItem::Expr(colref(Some("u"), "qty"))];
+    assert!(build_project(&tablenames, &colnames, &coltypes, &hidden, &bad_cols).is_err());
+}
+
 /// does the "Project" action of the relational algebra, using a pre-built set of actions.
 pub fn project_row(actions: &Vec<ProjectAction>, input: &Row) -> Result<Row> {
     let mut ret: Vec<SqlValue> = vec![];
     for action in actions {
         ret.push(match action {
-            ProjectAction::Take(idx) => input.items[*idx].clone(),
+            ProjectAction::Take(idx) => match input.items.get(*idx) {
+                Some(v) => v.clone(),
+                None => bail!("Internal error: a row has {} values, but column {} was needed.", input.items.len(), idx),
+            },
             ProjectAction::Constant(v) => v.clone(),
+            ProjectAction::Expr(e) => e.eval(input)?,
         })
     }
     Ok(Row {
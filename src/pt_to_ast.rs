@@ -11,10 +11,9 @@ use crate::parser::SQLParser;
 use crate::parser::parse_expr;
 use crate::pest::Parser;
 
-pub fn pt_create_statement_to_ast(c: &str) -> ast::CreateStatement {
+pub fn pt_create_statement_to_ast(c: &str) -> Result<ast::CreateStatement> {
     use itertools::Itertools;
-    let create_stmt = SQLParser::parse(Rule::create_stmt, c)
-        .expect("unsuccessful parse") // unwrap the parse result
+    let create_stmt = SQLParser::parse(Rule::create_stmt, c)?
         .next()
         .unwrap();
 
@@ -65,7 +64,7 @@ pub fn pt_create_statement_to_ast(c: &str) -> ast::CreateStatement {
             _ => unreachable!(),
         }
     }
-    ast::CreateStatement { databasename, tablename, coldefs, strict }
+    Ok(ast::CreateStatement { databasename, tablename, coldefs, strict })
 }
 
 #[test]
@@ -157,7 +156,7 @@ fn test_pt_create_statement_to_ast() {
     ];
     for case in cases {
         println!("Case: {}", case.0);
-        let actual = pt_create_statement_to_ast(case.0);
+        let actual = pt_create_statement_to_ast(case.0).unwrap();
         let expected = case.1;
         assert_eq!(actual, expected);    
     }
@@ -249,7 +248,7 @@ pub fn parse_constant_expr_list(pair: pest::iterators::Pair<'_, Rule>) -> Result
                 match expr {
                     ast::Expr::Constant(c) => row.push(c),
                     // TODO: simplify constant expressions, e.g. "INSERT INTO t VALUES (1+1)"
-                    ast::Expr::BinOp{..} => bail!("Operators not supported in constant expression lists."),
+                    _ => bail!("Operators not supported in constant expression lists."),
                 }
             }
             _ => bail!("Unexpected syntax in expression list"),
@@ -320,6 +319,67 @@ fn test_parse_constant_expr_list_list() {
     }
 }
 
+pub fn pt_create_index_statement_to_ast(stmt: &str) -> Result<ast::CreateIndexStatement> {
+    let create_index_stmt = SQLParser::parse(Rule::create_index_stmt, stmt)?.next().unwrap();
+    let mut cs = ast::CreateIndexStatement {
+        databasename: String::from("main"),
+        indexname: String::new(),
+        tablename: String::new(),
+        unique: false,
+        columns: vec![],
+    };
+    for c in create_index_stmt.into_inner() {
+        match c.as_rule() {
+            Rule::unique => cs.unique = true,
+            Rule::table_identifier_with_optional_db => {
+                let t: Vec<_> = c.into_inner().collect();
+                if t.len() == 2 {
+                    cs.databasename = String::from(t[0].as_str());
+                }
+                cs.indexname = String::from(t[t.len() - 1].as_str());
+            }
+            Rule::table_identifier => cs.tablename = String::from(c.as_str()),
+            Rule::indexed_column => {
+                let mut parts = c.into_inner();
+                cs.columns.push(ast::IndexedColumn {
+                    colname: String::from(parts.next().unwrap().as_str()),
+                    descending: parts.next().is_some_and(|p| p.as_rule() == Rule::desc),
+                });
+            }
+            Rule::EOI => (),
+            _ => unreachable!(),
+        }
+    }
+    Ok(cs)
+}
+
+#[test]
+fn test_pt_create_index_statement_to_ast() {
+    let col = |colname: &str, descending: bool| ast::IndexedColumn { colname: String::from(colname), descending };
+    assert_eq!(
+        pt_create_index_statement_to_ast("CREATE INDEX t_a on t (a)").unwrap(),
+        ast::CreateIndexStatement {
+            databasename: String::from("main"),
+            indexname: String::from("t_a"),
+            tablename: String::from("t"),
+            unique: false,
+            columns: vec![col("a", false)],
+        }
+    );
+    assert_eq!(
+        pt_create_index_statement_to_ast("create unique index main.t_ba on t (b desc, a asc)").unwrap(),
+        ast::CreateIndexStatement {
+            databasename: String::from("main"),
+            indexname: String::from("t_ba"),
+            tablename: String::from("t"),
+            unique: true,
+            columns: vec![col("b", true), col("a", false)],
+        }
+    );
+    // Indexes on expressions are not supported.
+    assert!(pt_create_index_statement_to_ast("create index t_a on t (a + 1)").is_err());
+}
+
 pub fn pt_insert_statement_to_ast(stmt: &str) -> Result<ast::InsertStatement> {
     let insert_stmt = SQLParser::parse(Rule::insert_stmt, stmt)?
         .next()
@@ -370,6 +430,136 @@ fn test_parse_insert_statements() {
     }
 }
 
+pub fn pt_delete_statement_to_ast(stmt: &str) -> Result<ast::DeleteStatement> {
+    let delete_stmt = SQLParser::parse(Rule::delete_stmt, stmt)?.next().unwrap();
+    let mut pairs = delete_stmt.into_inner();
+    let t: Vec<_> = pairs.next().unwrap().into_inner().collect();
+    let (databasename, tablename) = match t.len() {
+        1 => ("main".to_owned(), String::from(t[0].as_str())),
+        2 => (String::from(t[0].as_str()), String::from(t[1].as_str())),
+        _ => unreachable!(),
+    };
+    let r#where = pairs
+        .find(|p| p.as_rule() == Rule::where_clause)
+        .map(|w| ast::WhereClause { expr: crate::parser::parse_expr(w.into_inner().next().unwrap().into_inner()) });
+    Ok(ast::DeleteStatement { databasename, tablename, r#where })
+}
+
+pub fn pt_update_statement_to_ast(stmt: &str) -> Result<ast::UpdateStatement> {
+    let update_stmt = SQLParser::parse(Rule::update_stmt, stmt)?.next().unwrap();
+    let mut pairs = update_stmt.into_inner();
+    let t: Vec<_> = pairs.next().unwrap().into_inner().collect();
+    let (databasename, tablename) = match t.len() {
+        1 => ("main".to_owned(), String::from(t[0].as_str())),
+        2 => (String::from(t[0].as_str()), String::from(t[1].as_str())),
+        _ => unreachable!(),
+    };
+    let mut us = ast::UpdateStatement { databasename, tablename, assignments: vec![], r#where: None };
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::assignment => {
+                let mut parts = pair.into_inner();
+                let colname = ast::ColName { name: String::from(parts.next().unwrap().as_str()) };
+                let expr = crate::parser::parse_expr(parts.next().unwrap().into_inner());
+                us.assignments.push(ast::Assignment { colname, expr });
+            }
+            Rule::where_clause => {
+                let e = pair.into_inner().next().unwrap();
+                us.r#where = Some(ast::WhereClause { expr: crate::parser::parse_expr(e.into_inner()) });
+            }
+            Rule::EOI => (),
+            _ => bail!("Unexpected syntax in UPDATE statement."),
+        }
+    }
+    Ok(us)
+}
+
+pub fn pt_transaction_statement_to_ast(stmt: &str) -> Result<ast::TransactionStatement> {
+    let transaction_stmt = SQLParser::parse(Rule::transaction_stmt, stmt)?.next().unwrap();
+    let pair = transaction_stmt.into_inner().next().unwrap();
+    let savepoint_name = |p: pest::iterators::Pair<'_, Rule>| p.into_inner().next().map(|n| String::from(n.as_str()));
+    Ok(match pair.as_rule() {
+        Rule::begin => ast::TransactionStatement::Begin,
+        Rule::commit => ast::TransactionStatement::Commit,
+        Rule::rollback => ast::TransactionStatement::Rollback(savepoint_name(pair)),
+        Rule::savepoint => ast::TransactionStatement::Savepoint(savepoint_name(pair).unwrap()),
+        Rule::release => ast::TransactionStatement::Release(savepoint_name(pair).unwrap()),
+        _ => bail!("Unexpected syntax in transaction statement."),
+    })
+}
+
+#[test]
+fn test_parse_transaction_statements() {
+    use ast::TransactionStatement::*;
+    let cases = [
+        ("BEGIN", Begin),
+        ("begin immediate transaction", Begin),
+        ("COMMIT", Commit),
+        ("end transaction", Commit),
+        ("ROLLBACK", Rollback(None)),
+        ("rollback transaction to savepoint sp1", Rollback(Some(String::from("sp1")))),
+        ("rollback to sp1", Rollback(Some(String::from("sp1")))),
+        ("SAVEPOINT sp1", Savepoint(String::from("sp1"))),
+        ("release savepoint sp1", Release(String::from("sp1"))),
+        ("release sp1", Release(String::from("sp1"))),
+    ];
+    for (stmt, expected) in cases {
+        assert_eq!(pt_transaction_statement_to_ast(stmt).unwrap(), expected, "{}", stmt);
+    }
+    assert!(pt_transaction_statement_to_ast("savepoint").is_err());
+    assert!(pt_transaction_statement_to_ast("begin work").is_err());
+    assert!(pt_transaction_statement_to_ast("committed").is_err());
+}
+
+#[test]
+fn test_parse_update_statements() {
+    let us = pt_update_statement_to_ast("UPDATE temp.t SET a = a + 1, b = 'x' WHERE a > 1").unwrap();
+    assert_eq!(us.databasename, "temp");
+    assert_eq!(us.tablename, "t");
+    let colnames: Vec<&str> = us.assignments.iter().map(|a| a.colname.name.as_str()).collect();
+    assert_eq!(colnames, vec!["a", "b"]);
+    assert!(matches!(us.assignments[0].expr, ast::Expr::BinOp { op: ast::Op::Add, .. }));
+    assert!(us.r#where.is_some());
+
+    let us = pt_update_statement_to_ast("update t set a = b = 1").unwrap();
+    assert_eq!(us.databasename, "main");
+    assert!(matches!(us.assignments[0].expr, ast::Expr::BinOp { op: ast::Op::Eq, .. }));
+    assert_eq!(us.r#where, None);
+
+    assert!(pt_update_statement_to_ast("update t set where a = 1").is_err());
+    assert!(pt_update_statement_to_ast("update t a = 1").is_err());
+}
+
+#[test]
+fn test_parse_delete_statements() {
+    let ds = pt_delete_statement_to_ast("DELETE FROM temp.t WHERE a > 1 AND b = 'x'").unwrap();
+    assert_eq!(ds.databasename, "temp");
+    assert_eq!(ds.tablename, "t");
+    assert!(matches!(ds.r#where, Some(ast::WhereClause { expr: ast::Expr::BinOp { op: ast::Op::And, .. } })));
+
+    let ds = pt_delete_statement_to_ast("delete from t").unwrap();
+    assert_eq!(ds.databasename, "main");
+    assert_eq!(ds.r#where, None);
+
+    assert!(pt_delete_statement_to_ast("delete t where a = 1").is_err());
+}
+
+/// parses a `table_ref`: a table name, optionally qualified by a database name and followed by an alias.
+fn parse_table_ref(pair: pest::iterators::Pair<'_, Rule>) -> ast::TableRef {
+    let mut parts = pair.into_inner();
+    let t: Vec<_> = parts.next().unwrap().into_inner().collect();
+    let (databasename, tablename) = match t.len() {
+        1 => ("main".to_owned(), String::from(t[0].as_str())),
+        2 => (String::from(t[0].as_str()), String::from(t[1].as_str())),
+        _ => unreachable!(),
+    };
+    ast::TableRef {
+        databasename,
+        tablename,
+        alias: parts.next().map(|a| String::from(a.as_str())),
+    }
+}
+
 pub fn pt_select_statement_to_ast(query: &str) -> Result<ast::SelectStatement> {
     let select_stmt = SQLParser::parse(Rule::select_stmt, query)?
         .next()
@@ -378,33 +568,32 @@ pub fn pt_select_statement_to_ast(query: &str) -> Result<ast::SelectStatement> {
     let mut ast = ast::SelectStatement {
         select: ast::SelectClause { items: vec![] },
         from: None,
+        r#where: None,
+        order_by: None,
+        group_by: None,
+        having: None,
+        limit: None,
     };
 
     // Confirm it is a select statement.
     for s in select_stmt.into_inner() {
         match s.as_rule() {
-            Rule::table_identifier_with_optional_db => {    
-                if ast.from.is_none() {    
-                    let t: Vec<_> = s.into_inner().collect();
-                    ast.from = Some(
-                        match t.len() {
-                            1 => {
-                                ast::FromClause {
-                                    databasename: "main".to_owned(),
-                                    tablename: String::from(t[0].as_str()),
-                                }
-                            }
-                            2 => {
-                                ast::FromClause {
-                                    databasename: String::from(t[0].as_str()),
-                                    tablename: String::from(t[1].as_str()),
-                                }
-                            }
-                            _ => unreachable!(),
-                        });    
-                } else {
-                    bail!("Too many tables in from.")
+            Rule::from_clause => {
+                let mut parts = s.into_inner();
+                let table = parse_table_ref(parts.next().unwrap());
+                let mut joins = vec![];
+                for join in parts {
+                    let mut join_parts = join.into_inner();
+                    let kind = match join_parts.next().unwrap().as_rule() {
+                        Rule::left_join => ast::JoinKind::Left,
+                        Rule::inner_join => ast::JoinKind::Inner,
+                        rule => unreachable!("expected join operator, found {:?}", rule),
+                    };
+                    let table = parse_table_ref(join_parts.next().unwrap());
+                    let on = join_parts.next().map(|c| crate::parser::parse_expr(c.into_inner().next().unwrap().into_inner()));
+                    joins.push(ast::Join { kind, table, on });
                 }
+                ast.from = Some(ast::FromClause { table, joins });
             }
             Rule::select_items => {
                 // println!("s: {}", s);
@@ -415,17 +604,81 @@ pub fn pt_select_statement_to_ast(query: &str) -> Result<ast::SelectStatement> {
                 // For each select item.
                 for t in s.into_inner() {
                     use ast::{ColName, SelItem};
-                    let u = t.into_inner().next().unwrap();
+                    let mut parts = t.into_inner();
+                    let u = parts.next().unwrap();
+                    if let Some(alias) = parts.next() {
+                        ast.select.items.push(SelItem::Aliased {
+                            expr: crate::parser::parse_expr(u.into_inner()),
+                            alias: String::from(alias.as_str()),
+                        });
+                        continue;
+                    }
                     ast.select.items.push(match u.as_rule() {
                         Rule::column_name => SelItem::ColName(ColName {
                             name: String::from(u.as_str()),
                         }),
                         Rule::star => SelItem::Star,
-                        Rule::expr => SelItem::Expr(crate::parser::parse_expr(u.into_inner())),
+                        Rule::expr => match crate::parser::parse_expr(u.into_inner()) {
+                            // A lone unqualified column is parsed as an expression, but is handled like a column name.
+                            ast::Expr::ColRef { tablename: None, colname } => SelItem::ColName(ColName { name: colname }),
+                            e => SelItem::Expr(e),
+                        },
                         _ => bail!("Parse error in select item"),
                     });
                 }
             }
+            Rule::where_clause => {
+                let e = s.into_inner().next().unwrap();
+                ast.r#where = Some(ast::WhereClause {
+                    expr: crate::parser::parse_expr(e.into_inner()),
+                });
+            }
+            Rule::group_by_clause => {
+                ast.group_by = Some(ast::GroupByClause {
+                    exprs: s.into_inner().map(|e| crate::parser::parse_expr(e.into_inner())).collect(),
+                });
+            }
+            Rule::having_clause => {
+                let e = s.into_inner().next().unwrap();
+                ast.having = Some(ast::HavingClause {
+                    expr: crate::parser::parse_expr(e.into_inner()),
+                });
+            }
+            Rule::order_by_clause => {
+                let mut terms = vec![];
+                for t in s.into_inner() {
+                    let mut parts = t.into_inner();
+                    let expr = crate::parser::parse_expr(parts.next().unwrap().into_inner());
+                    let mut term = ast::OrderingTerm { expr, descending: false, nulls_first: None };
+                    for p in parts {
+                        match p.as_rule() {
+                            Rule::asc => term.descending = false,
+                            Rule::desc => term.descending = true,
+                            Rule::nulls_first => term.nulls_first = Some(true),
+                            Rule::nulls_last => term.nulls_first = Some(false),
+                            _ => bail!("Parse error in ORDER BY"),
+                        }
+                    }
+                    terms.push(term);
+                }
+                ast.order_by = Some(ast::OrderByClause { terms });
+            }
+            Rule::limit_clause => {
+                let mut parts = s.into_inner();
+                let first = crate::parser::parse_expr(parts.next().unwrap().into_inner());
+                ast.limit = Some(match parts.next() {
+                    None => ast::LimitClause { limit: first, offset: None },
+                    Some(p) => {
+                        let rule = p.as_rule();
+                        let second = crate::parser::parse_expr(p.into_inner().next().unwrap().into_inner());
+                        match rule {
+                            Rule::offset => ast::LimitClause { limit: first, offset: Some(second) },
+                            // In `LIMIT m, n`, the offset comes first.
+                            _ => ast::LimitClause { limit: second, offset: Some(first) },
+                        }
+                    }
+                });
+            }
             Rule::EOI => (),
             _ => bail!("Unable to parse expr:  {} ", s.as_str()),
         }
@@ -438,9 +691,10 @@ pub fn pt_select_statement_to_ast(query: &str) -> Result<ast::SelectStatement> {
 fn ast_select_statement_to_tuple(ss: &ast::SelectStatement) -> (Vec<String>, Vec<String>) {
     (
         match &ss.from {
-            Some(fromclause) => {
-                vec![fromclause.tablename.clone()]
-            }
+            Some(fromclause) => std::iter::once(&fromclause.table)
+                .chain(fromclause.joins.iter().map(|j| &j.table))
+                .map(|t| t.tablename.clone())
+                .collect(),
             None => vec![],
         },
         ss.select.items.iter().map(|i| format!("{}", i)).collect(),
@@ -475,6 +729,9 @@ fn test_parse_select_statement() {
             "select 123.456, 'seven', 8, 9, NULL",
             (vec![], vec!["123.456", "seven", "8", "9", "NULL"]),
         ),
+        ("select 1 AS one, a b, t.c AS c2, a + 1 as a1 from t", (vec!["t"], vec!["1 AS one", "a AS b", "t.c AS c2", "a+1 AS a1"])),
+        ("select a as from_a, b from t", (vec!["t"], vec!["a AS from_a", "b"])),
+        ("select * from t, u join v on 1 left join w", (vec!["t", "u", "v", "w"], vec!["*"])),
     ];
 
     for case in cases {
@@ -488,3 +745,49 @@ fn test_parse_select_statement() {
         assert_eq!(actual, expected);
     }
 }
+
+#[test]
+fn test_parse_order_by() {
+    let ss = pt_select_statement_to_ast("select a, b from t order by a desc, b nulls last, 2").unwrap();
+    let terms = ss.order_by.unwrap().terms;
+    assert_eq!(terms.len(), 3);
+    assert_eq!((terms[0].descending, terms[0].nulls_first), (true, None));
+    assert_eq!((terms[1].descending, terms[1].nulls_first), (false, Some(false)));
+    assert_eq!(terms[2].expr, ast::Expr::Constant(ast::Constant::Int(2)));
+}
+
+#[test]
+fn test_parse_limit() {
+    let int = |i: i64| ast::Expr::Constant(ast::Constant::Int(i));
+    let ss = pt_select_statement_to_ast("select a from t limit 10 offset 3").unwrap();
+    assert_eq!(ss.limit, Some(ast::LimitClause { limit: int(10), offset: Some(int(3)) }));
+    let ss = pt_select_statement_to_ast("select a from t limit 3, 10").unwrap();
+    assert_eq!(ss.limit, Some(ast::LimitClause { limit: int(10), offset: Some(int(3)) }));
+    let ss = pt_select_statement_to_ast("select a from t limit 10").unwrap();
+    assert_eq!(ss.limit, Some(ast::LimitClause { limit: int(10), offset: None }));
+}
+
+#[test]
+fn test_parse_joins() {
+    let ss = pt_select_statement_to_ast(
+        "select * from temp.t as x join u on x.a = u.a left outer join main.v y on y.b = u.b, w cross join z",
+    )
+    .unwrap();
+    let from = ss.from.unwrap();
+    assert_eq!(
+        from.table,
+        ast::TableRef { databasename: String::from("temp"), tablename: String::from("t"), alias: Some(String::from("x")) }
+    );
+    let joins: Vec<_> = from.joins.iter().map(|j| (j.kind, j.table.alias.as_deref().unwrap_or(&j.table.tablename), j.on.as_ref().map(|e| e.to_string()))).collect();
+    assert_eq!(
+        joins,
+        vec![
+            (ast::JoinKind::Inner, "u", Some(String::from("x.a=u.a"))),
+            (ast::JoinKind::Left, "y", Some(String::from("y.b=u.b"))),
+            (ast::JoinKind::Inner, "w", None),
+            (ast::JoinKind::Inner, "z", None),
+        ]
+    );
+    assert_eq!(from.joins[1].table.databasename, "main");
+    assert_eq!(from.joins[2].table.databasename, "main");
+}
@@ -3,18 +3,27 @@
 
 use crate::serial_type;
 
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum Error {
+    #[error("Record is corrupt at offset {offset}: {detail}")]
+    Corrupt { offset: usize, detail: String },
+}
+
 pub struct ValueIterator<'a> {
     // Borrow the byte slice
     data: &'a [u8],
     hdr_offset: usize,
     hdr_len: usize,
     value_offset: usize,
+    /// set once the record is found to be corrupt, after which no more values are returned.
+    failed: bool,
 }
 
 impl<'a> ValueIterator<'a> {
     /// Creates an iterator over a slice of bytes in SQLite record format.
     ///
-    /// Iterator produces tuples (t, bs).
+    /// Iterator produces tuples (t, bs), or an error for a record whose header does not fit it, or which has a value
+    /// that extends past its end.  Nothing is returned after an error.
     ///
     /// `t` is a SQLite serial type code
     /// See: <https://www.sqlite.org/fileformat.html#record_format>
@@ -27,7 +36,7 @@ impl<'a> ValueIterator<'a> {
     ///         slives ends with the last byte of the record body.
     ///
 
-    pub fn new(s: &[u8]) -> ValueIterator {
+    pub fn new(s: &[u8]) -> ValueIterator<'_> {
         // "A record contains a header and a body, in that order.
         // The header begins with a single varint which determines the total number of bytes in the header"
         // - https://www.sqlite.org/fileformat.html#record_format
@@ -37,29 +46,47 @@ impl<'a> ValueIterator<'a> {
             hdr_offset: hdr_len_len,
             hdr_len: hdr_len as usize,
             value_offset: hdr_len as usize,
+            failed: false,
         }
     }
+
+    fn corrupt(&mut self, offset: usize, detail: String) -> Option<<Self as Iterator>::Item> {
+        self.failed = true;
+        Some(Err(Error::Corrupt { offset, detail }))
+    }
 }
 
 impl<'a> Iterator for ValueIterator<'a> {
     // The iterator returns a reference to each item in the record as as a byte slice the value in the data
-    type Item = (i64, &'a [u8]);
+    type Item = Result<(i64, &'a [u8]), Error>;
 
     /// Returns the next item, which is a tuple of (type, &[u8] - a reference to a slice of bytes for this value).
     ///
     fn next(&mut self) -> Option<Self::Item> {
-        if self.hdr_offset >= self.hdr_len {
+        if self.failed || self.hdr_offset >= self.hdr_len {
             return None;
         }
-        let (serial_type, bytes_read) = sqlite_varint::read_varint(&self.data[self.hdr_offset..]);
+        if self.hdr_len > self.data.len() {
+            let detail = format!("header length {} is longer than the record of {} bytes", self.hdr_len, self.data.len());
+            return self.corrupt(0, detail);
+        }
+        let type_offset = self.hdr_offset;
+        let (serial_type, bytes_read) = sqlite_varint::read_varint(&self.data[self.hdr_offset..self.hdr_len]);
         self.hdr_offset += bytes_read;
-        let value_len = serial_type::serialized_size(serial_type);
+        let value_len = match serial_type::serialized_size(serial_type) {
+            Ok(len) => len,
+            Err(e) => return self.corrupt(type_offset, format!("serial type {}: {}", serial_type, e)),
+        };
         let old_value_offset = self.value_offset;
+        if value_len > self.data.len() - old_value_offset {
+            let detail = format!("value of {} bytes extends past the end of the record", value_len);
+            return self.corrupt(old_value_offset, detail);
+        }
         self.value_offset += value_len;
-        Some((
+        Some(Ok((
             serial_type,
             &self.data[old_value_offset..old_value_offset + value_len],
-        ))
+        )))
     }
 }
 
@@ -69,7 +96,7 @@ fn test_value_iterator_one_byte_int() {
     let test_record: &[u8] = &[0x02, 0x09];
 
     let mut hi = ValueIterator::new(&test_record);
-    assert_eq!(hi.next(), Some((9, &[][..])));
+    assert_eq!(hi.next(), Some(Ok((9, &[][..]))));
     assert_eq!(hi.next(), None);
 }
 
@@ -81,11 +108,11 @@ fn test_value_iterator_five_one_byte_ints_value_ten_to_fourteen() {
 
     let mut hi = ValueIterator::new(&test_record);
 
-    assert_eq!(hi.next(), Some((1, &[10][..])));
-    assert_eq!(hi.next(), Some((1, &[11][..])));
-    assert_eq!(hi.next(), Some((1, &[12][..])));
-    assert_eq!(hi.next(), Some((1, &[13][..])));
-    assert_eq!(hi.next(), Some((1, &[14][..])));
+    assert_eq!(hi.next(), Some(Ok((1, &[10][..]))));
+    assert_eq!(hi.next(), Some(Ok((1, &[11][..]))));
+    assert_eq!(hi.next(), Some(Ok((1, &[12][..]))));
+    assert_eq!(hi.next(), Some(Ok((1, &[13][..]))));
+    assert_eq!(hi.next(), Some(Ok((1, &[14][..]))));
     assert_eq!(hi.next(), None);
 }
 
@@ -99,13 +126,28 @@ fn test_value_iterator_various_types() {
 
     let mut hi = ValueIterator::new(&test_record);
 
-    assert_eq!(hi.next(), Some((8, &[][..]))); // Literal 0
-    assert_eq!(hi.next(), Some((9, &[][..]))); // Literal 1
+    assert_eq!(hi.next(), Some(Ok((8, &[][..])))); // Literal 0
+    assert_eq!(hi.next(), Some(Ok((9, &[][..])))); // Literal 1
     assert_eq!(
         hi.next(),
-        Some((7, &[0x40, 0x09, 0x21, 0xca, 0xc0, 0x83, 0x12, 0x6f][..]))
+        Some(Ok((7, &[0x40, 0x09, 0x21, 0xca, 0xc0, 0x83, 0x12, 0x6f][..])))
     ); // Float 64
-    assert_eq!(hi.next(), Some((0x13, &b"Ten"[..]))); // String of length 3; (19-13)/2 = 3
-    assert_eq!(hi.next(), Some((0, &[][..]))); // NULL
+    assert_eq!(hi.next(), Some(Ok((0x13, &b"Ten"[..])))); // String of length 3; (19-13)/2 = 3
+    assert_eq!(hi.next(), Some(Ok((0, &[][..])))); // NULL
     assert_eq!(hi.next(), None);
-}
\ No newline at end of file
+}
+#[test]
+fn test_value_iterator_corrupt_records() {
+    // The header is longer than the record.
+    let mut hi = ValueIterator::new(&[0x05, 0x01]);
+    assert!(matches!(hi.next(), Some(Err(Error::Corrupt { offset: 0, .. }))));
+    assert_eq!(hi.next(), None);
+    // Serial type 10 is reserved, and is never in a database file.
+    let mut hi = ValueIterator::new(&[0x03, 0x09, 0x0a]);
+    assert_eq!(hi.next(), Some(Ok((9, &[][..]))));
+    assert!(matches!(hi.next(), Some(Err(Error::Corrupt { offset: 2, .. }))));
+    assert_eq!(hi.next(), None);
+    // A 4 byte integer, with only 2 bytes left in the record.
+    let mut hi = ValueIterator::new(&[0x02, 0x04, 0x00, 0x01]);
+    assert!(matches!(hi.next(), Some(Err(Error::Corrupt { offset: 2, .. }))));
+}
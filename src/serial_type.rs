@@ -18,6 +18,8 @@ pub enum Error {
     InvalidSerialTypeCode,
     #[error("Byte were not a valid string valid encoding.")]
     InvalidStringEncoding(#[from] std::string::FromUtf8Error),
+    #[error("Bytes were not valid UTF-16.")]
+    InvalidUtf16,
     #[error("Null found where non-null value required.")]
     Null,
     #[error("Code which was thought unreachable was reached.")]
@@ -26,6 +28,49 @@ pub enum Error {
     NotStorageClassType
 }
 
+/// the encoding of the text values of a database, which is the same for the whole file, and is set by its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    Utf16le,
+    Utf16be,
+}
+
+/// returns the string held by `bytes`, which are text in `encoding`.
+fn decode_text(bytes: Vec<u8>, encoding: TextEncoding) -> Result<String, Error> {
+    let units = |to_u16: fn([u8; 2]) -> u16| -> Result<Vec<u16>, Error> {
+        if !bytes.len().is_multiple_of(2) {
+            return Err(Error::InvalidUtf16);
+        }
+        Ok(bytes.chunks_exact(2).map(|c| to_u16([c[0], c[1]])).collect())
+    };
+    match encoding {
+        TextEncoding::Utf8 => String::from_utf8(bytes).map_err(Error::InvalidStringEncoding),
+        TextEncoding::Utf16le => String::from_utf16(&units(u16::from_le_bytes)?).map_err(|_| Error::InvalidUtf16),
+        TextEncoding::Utf16be => String::from_utf16(&units(u16::from_be_bytes)?).map_err(|_| Error::InvalidUtf16),
+    }
+}
+
+/// returns the bytes of `s` in `encoding`.
+fn encode_text(s: &str, encoding: TextEncoding) -> Vec<u8> {
+    match encoding {
+        TextEncoding::Utf8 => s.as_bytes().to_vec(),
+        TextEncoding::Utf16le => s.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        TextEncoding::Utf16be => s.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+    }
+}
+
+/// compares two strings by their bytes in `encoding`, which is how SQLite's `BINARY` collation orders text, and so
+/// the order of text in the indexes of a database in that encoding.
+pub fn compare_text(a: &str, b: &str, encoding: TextEncoding) -> std::cmp::Ordering {
+    match encoding {
+        TextEncoding::Utf8 => a.as_bytes().cmp(b.as_bytes()),
+        TextEncoding::Utf16le => a.encode_utf16().flat_map(u16::to_le_bytes).cmp(b.encode_utf16().flat_map(u16::to_le_bytes)),
+        TextEncoding::Utf16be => a.encode_utf16().cmp(b.encode_utf16()),
+    }
+}
+
 /// Convert a serial type number to a string describing the type suitable for debug printing.
 ///
 /// # Arguments
@@ -78,9 +123,10 @@ pub fn typecode_to_string(serial_type: i64) -> &'static str {
     }
 }
 
-/// returns the length in bytes implied by a SQLite serial type code. 
-pub fn serialized_size(serial_type: i64) -> usize {
-    match serial_type {
+/// returns the length in bytes implied by a SQLite serial type code, or an error for the codes that are never found in
+/// database files.
+pub fn serialized_size(serial_type: i64) -> Result<usize, Error> {
+    Ok(match serial_type {
         // Serial Type	Content Size	Meaning
         // 0	        0	            Value is a NULL.
         0 => 0,
@@ -104,8 +150,8 @@ pub fn serialized_size(serial_type: i64) -> usize {
         // N≥12 & even	(N-12)/2	    Value is a BLOB that is (N-12)/2 bytes in length.
         // N≥13 & odd	(N-13)/2	    Value is a string in the text encoding and (N-13)/2 bytes in length. The nul terminator is not stored.
         x if x >= 12 => (x as usize - 12 - (x % 2) as usize) / 2,
-        _ => unimplemented!(),
-    }
+        _ => return Err(Error::InvalidSerialTypeCode),
+    })
 }
 
 /// Deserialize bytes in "SQLIte serial type" format into one of a few native types (`SqlValue`).
@@ -115,6 +161,7 @@ pub fn serialized_size(serial_type: i64) -> usize {
 ///  # Arguments
 /// * `serial_type` - A SQLite serial type code applying to `data`
 /// * `data` - A slice of bytes.
+/// * `encoding` - The text encoding of the database that `data` comes from.
 ///
 /// The possible types produced are:
 /// - SqlValue::Null
@@ -137,6 +184,7 @@ pub fn serialized_size(serial_type: i64) -> usize {
 pub fn to_sql_value(
     serial_type: &i64,
     data: &[u8],
+    encoding: TextEncoding,
 ) -> Result<SqlValue, Error> {
     use SqlValue::*;
 
@@ -166,7 +214,7 @@ pub fn to_sql_value(
                     Ok(Int(i32::from_be_bytes(bytes) as i64))
                 }
                 4 => Ok(Int(c.read_i32::<BigEndian>().map_err(Error::Io)? as i64)),
-                5 => Err(Error::Unimplemented),
+                5 => Ok(Int(c.read_i48::<BigEndian>().map_err(Error::Io)?)),
                 6 => Ok(Int(c.read_i64::<BigEndian>().map_err(Error::Io)?)),
                 _ => Err(Error::Unreachable),
             }
@@ -191,8 +239,7 @@ pub fn to_sql_value(
                 false /* even */ => {
                     let mut buf = vec![0_u8; (*x as usize - 13) / 2];
                     c.read_exact(&mut buf[..]).map_err(Error::Io)?;
-                    let s = String::from_utf8(buf).map_err(Error::InvalidStringEncoding)?;
-                    Ok(Text(s))
+                    Ok(Text(decode_text(buf, encoding)?))
                 }
             }
         }
@@ -222,6 +269,11 @@ fn test_to_sql_value() {
         (&3, &[0x00, 0x00, 0x01], Int(1)),
         (&3, &[0x00, 0x01, 0x00], Int(256)),
         (&3, &[0x01, 0x00, 0x00], Int(65536)),
+        // six byte ints
+        (&5, &[0x00, 0x00, 0x00, 0x00, 0x00, 0x7f], Int(127)),
+        (&5, &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff], Int(-1)),
+        (&5, &[0x01, 0x8b, 0xcf, 0xe5, 0x68, 0x00], Int(1_700_000_000_000)),
+        (&5, &[0x80, 0x00, 0x00, 0x00, 0x00, 0x00], Int(-(1 << 47))),
         // TODO: larger ints and float.
         // Literal 0 and 1
         (&8, b"", Int(0)),
@@ -235,7 +287,7 @@ fn test_to_sql_value() {
     ];
     for (i, case) in cases.iter().enumerate() {
         println!("Testing case {}: deserialize typecode {}", i, case.0);
-        assert_eq!(to_sql_value(case.0, case.1).unwrap(), case.2);
+        assert_eq!(to_sql_value(case.0, case.1, TextEncoding::Utf8).unwrap(), case.2);
     }
 }
 
@@ -251,7 +303,7 @@ fn test_to_sql_value_errors() {
 
     for (i, case) in cases.iter().enumerate() {
         println!("Testing case {}: deserializetypecode {} , should error", i, case.0);
-        assert!(to_sql_value(case.0, case.1).is_err());
+        assert!(to_sql_value(case.0, case.1, TextEncoding::Utf8).is_err());
     }
 }
 
@@ -471,7 +523,7 @@ fn test_value_to_sql_typed_value_errors() {
 /// # Panics
 ///
 /// Does not panic.
-pub fn to_serial_type<'a>(v: &'a SqlValue) -> Result<(Vec<u8>, i64, usize), Error> {
+pub fn to_serial_type<'a>(v: &'a SqlValue, encoding: TextEncoding) -> Result<(Vec<u8>, i64, usize), Error> {
     use SqlValue::*;
     match v {
         Null() => Ok((Vec::new(), 0, 0)),
@@ -483,7 +535,7 @@ pub fn to_serial_type<'a>(v: &'a SqlValue) -> Result<(Vec<u8>, i64, usize), Erro
                 1 => {
                     Ok((Vec::new(), 9, 0))
                 }
-                -127..=128 => {
+                -128..=127 => {
                     Ok(((*x as u8).to_be_bytes().to_vec(), 1, 1))
                 }
                 -32_768..=32_767 => {
@@ -497,7 +549,7 @@ pub fn to_serial_type<'a>(v: &'a SqlValue) -> Result<(Vec<u8>, i64, usize), Erro
         }
         Real(x) => Ok((x.to_be_bytes().to_vec(), 7, 8)),
         Text(x) => {
-            let b = x.as_bytes().to_vec();
+            let b = encode_text(x, encoding);
             let l = b.len();
             Ok((b, (l as i64)*2 + 13, l))
         }
@@ -509,7 +561,7 @@ pub fn to_serial_type<'a>(v: &'a SqlValue) -> Result<(Vec<u8>, i64, usize), Erro
 
 #[test]
 fn test_to_serial_type_simple() {
-    let (data, typecode, length) = to_serial_type(&SqlValue::Int(37)).unwrap();
+    let (data, typecode, length) = to_serial_type(&SqlValue::Int(37), TextEncoding::Utf8).unwrap();
     assert_eq!(typecode, 1);
     assert_eq!(data, &[37_u8; 1]);
     assert_eq!(length, 1);
@@ -533,9 +585,48 @@ fn test_to_serial_type() {
     let mut casenum = 1;
     for case in cases {
         println!("Case {} of {}", casenum, numcases);
-        let (data, typecode, _) = to_serial_type(&case.0).unwrap();
+        let (data, typecode, _) = to_serial_type(&case.0, TextEncoding::Utf8).unwrap();
         assert_eq!(typecode, case.2);
         assert_eq!(data.to_vec(), case.1);
         casenum +=1;
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_to_serial_type_integer_boundaries() {
+    // Each value on either side of the boundary of each integer width reads back as itself.
+    let mut values = vec![i64::MIN, i64::MAX];
+    for bits in [8, 16, 24, 32, 48] {
+        let max: i64 = (1 << (bits - 1)) - 1;
+        values.extend([max, max + 1, -max - 1, -max - 2]);
+    }
+    for x in values {
+        let (data, typecode, len) = to_serial_type(&SqlValue::Int(x), TextEncoding::Utf8).unwrap();
+        assert_eq!(data.len(), len);
+        assert_eq!(to_sql_value(&typecode, &data, TextEncoding::Utf8).unwrap(), SqlValue::Int(x), "{}", x);
+    }
+    assert_eq!(to_serial_type(&SqlValue::Int(127), TextEncoding::Utf8).unwrap().1, 1);
+    assert_eq!(to_serial_type(&SqlValue::Int(128), TextEncoding::Utf8).unwrap().1, 2);
+    assert_eq!(to_serial_type(&SqlValue::Int(-128), TextEncoding::Utf8).unwrap().1, 1);
+    assert_eq!(to_serial_type(&SqlValue::Int(-129), TextEncoding::Utf8).unwrap().1, 2);
+}
+
+#[test]
+fn test_utf16_text() {
+    use TextEncoding::*;
+    // "é" is one UTF-16 code unit, and "😀" is a surrogate pair.
+    let s = SqlValue::Text("hé😀".to_string());
+    let cases: Vec<(TextEncoding, Vec<u8>)> = vec![
+        (Utf8, vec![0x68, 0xc3, 0xa9, 0xf0, 0x9f, 0x98, 0x80]),
+        (Utf16le, vec![0x68, 0x00, 0xe9, 0x00, 0x3d, 0xd8, 0x00, 0xde]),
+        (Utf16be, vec![0x00, 0x68, 0x00, 0xe9, 0xd8, 0x3d, 0xde, 0x00]),
+    ];
+    for (encoding, bytes) in cases {
+        let (data, typecode, len) = to_serial_type(&s, encoding).unwrap();
+        assert_eq!((&data, len), (&bytes, bytes.len()));
+        assert_eq!(typecode, bytes.len() as i64 * 2 + 13);
+        assert_eq!(to_sql_value(&typecode, &data, encoding).unwrap(), s);
+    }
+    // An odd number of bytes, or an unpaired surrogate, is not valid UTF-16.
+    assert!(to_sql_value(&15, &[0x68], Utf16le).is_err());
+    assert!(to_sql_value(&17, &[0x3d, 0xd8], Utf16le).is_err());
+}
@@ -0,0 +1,355 @@
+//! provides helper functions for the sort block of a query.
+//!
+//! Rows are sorted in memory until their estimated size passes a budget.  Then the sorted rows are written to a temporary
+//! file as a "run", and the following rows are sorted in memory again.  When the input is used up, the runs are merged.
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::eval::{self, BoundExpr};
+use crate::ir;
+use crate::sql_value::{self, SqlValue};
+use crate::Row;
+
+/// is the number of bytes of rows that a sort holds in memory before writing them to disk.
+pub const DEFAULT_SORT_MEMORY_BYTES: usize = 16 * 1024 * 1024;
+
+/// holds a sort key that is ready to be evaluated on input rows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundSortKey {
+    expr: BoundExpr,
+    descending: bool,
+    nulls_first: bool,
+}
+
+/// builds the information needed to sort the rows of a table at runtime.
+pub fn build_sort(in_tablenames: &[String], in_colnames: &[String], keys: &[ir::SortKey]) -> Result<Vec<BoundSortKey>> {
+    keys.iter()
+        .map(|k| {
+            Ok(BoundSortKey {
+                expr: eval::bind(&k.expr, in_tablenames, in_colnames)?,
+                descending: k.descending,
+                nulls_first: k.nulls_first,
+            })
+        })
+        .collect()
+}
+
+/// compares the key values of two rows, using SQLite's ordering of values of different types.
+pub fn compare_keys(keys: &[BoundSortKey], a: &[SqlValue], b: &[SqlValue]) -> Ordering {
+    for (k, (x, y)) in keys.iter().zip(a.iter().zip(b.iter())) {
+        // The position of NULLs is chosen separately from the direction of the sort.
+        let o = match (x, y) {
+            (SqlValue::Null(), SqlValue::Null()) => Ordering::Equal,
+            (SqlValue::Null(), _) => if k.nulls_first { Ordering::Less } else { Ordering::Greater },
+            (_, SqlValue::Null()) => if k.nulls_first { Ordering::Greater } else { Ordering::Less },
+            _ if k.descending => sql_value::collate(y, x),
+            _ => sql_value::collate(x, y),
+        };
+        if o != Ordering::Equal {
+            return o;
+        }
+    }
+    Ordering::Equal
+}
+
+/// is a row along with the values of its sort keys.
+pub struct SortItem {
+    keys: Vec<SqlValue>,
+    row: Row,
+}
+
+/// accepts rows in any order, and then returns them sorted.
+pub struct Sorter {
+    keys: Vec<BoundSortKey>,
+    memory_bytes: usize,
+    items: Vec<SortItem>,
+    items_bytes: usize,
+    runs: Vec<Run>,
+}
+
+impl Sorter {
+    /// makes a sorter which holds up to about `memory_bytes` of rows in memory.
+    pub fn new(keys: Vec<BoundSortKey>, memory_bytes: usize) -> Sorter {
+        Sorter {
+            keys,
+            memory_bytes,
+            items: vec![],
+            items_bytes: 0,
+            runs: vec![],
+        }
+    }
+
+    /// adds a row to be sorted.
+    pub fn push(&mut self, row: &Row) -> Result<()> {
+        let keys = self.keys.iter().map(|k| k.expr.eval(row)).collect::<Result<Vec<_>>>()?;
+        self.items_bytes += estimated_size(&keys) + estimated_size(&row.items);
+        self.items.push(SortItem { keys, row: row.clone() });
+        if self.items_bytes > self.memory_bytes {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// sorts the rows held in memory and writes them to a new run.
+    fn spill(&mut self) -> Result<()> {
+        self.sort_items();
+        let path = temp_path();
+        let mut w = BufWriter::new(File::create(&path)?);
+        // The run is made before writing, so that the file is removed even if writing fails.
+        let run = Run { path, reader: None, remaining: self.items.len() };
+        for item in self.items.drain(..) {
+            write_values(&mut w, &item.keys)?;
+            write_values(&mut w, &item.row.items)?;
+        }
+        w.flush()?;
+        self.runs.push(run);
+        self.items_bytes = 0;
+        Ok(())
+    }
+
+    fn sort_items(&mut self) {
+        let keys = &self.keys;
+        // A stable sort keeps rows with equal keys in input order.
+        self.items.sort_by(|a, b| compare_keys(keys, &a.keys, &b.keys));
+    }
+
+    /// returns the rows that were pushed, in sorted order.
+    pub fn finish(mut self) -> Result<SortedRows> {
+        if self.runs.is_empty() {
+            self.sort_items();
+            return Ok(SortedRows::InMemory(self.items.into_iter()));
+        }
+        if !self.items.is_empty() {
+            self.spill()?;
+        }
+        let mut heads = vec![];
+        for run in self.runs.iter_mut() {
+            run.reader = Some(BufReader::new(File::open(&run.path)?));
+            heads.push(run.next_item()?);
+        }
+        Ok(SortedRows::Merge {
+            keys: self.keys,
+            runs: self.runs,
+            heads,
+        })
+    }
+}
+
+/// iterates over sorted rows, either from memory, or by merging runs from disk.
+pub enum SortedRows {
+    InMemory(std::vec::IntoIter<SortItem>),
+    Merge {
+        keys: Vec<BoundSortKey>,
+        runs: Vec<Run>,
+        /// holds the next unreturned item of each run.
+        heads: Vec<Option<SortItem>>,
+    },
+}
+
+impl SortedRows {
+    /// returns the next row in sorted order, or None when there are no more.
+    pub fn next_row(&mut self) -> Result<Option<Row>> {
+        match self {
+            SortedRows::InMemory(it) => Ok(it.next().map(|i| i.row)),
+            SortedRows::Merge { keys, runs, heads } => {
+                // There are usually few runs, so finding the smallest head by looking at each one is fast enough.
+                // On ties, the earlier run wins, which keeps the sort stable.
+                let mut min: Option<usize> = None;
+                for (i, h) in heads.iter().enumerate() {
+                    if let Some(item) = h {
+                        let smaller = match min {
+                            None => true,
+                            Some(m) => compare_keys(keys, &item.keys, &heads[m].as_ref().unwrap().keys) == Ordering::Less,
+                        };
+                        if smaller {
+                            min = Some(i);
+                        }
+                    }
+                }
+                match min {
+                    None => Ok(None),
+                    Some(m) => {
+                        let next = runs[m].next_item()?;
+                        let item = std::mem::replace(&mut heads[m], next);
+                        Ok(item.map(|i| i.row))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// is a temporary file holding sorted rows.
+pub struct Run {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    remaining: usize,
+}
+
+impl Run {
+    fn next_item(&mut self) -> Result<Option<SortItem>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let r = self.reader.as_mut().unwrap();
+        let keys = read_values(r)?;
+        let items = read_values(r)?;
+        Ok(Some(SortItem { keys, row: Row { items } }))
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn temp_path() -> PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT_RUN: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT_RUN.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("diydb-sort-{}-{}", std::process::id(), n))
+}
+
+/// estimates how many bytes of memory some values use.
+fn estimated_size(values: &[SqlValue]) -> usize {
+    values
+        .iter()
+        .map(|v| {
+            std::mem::size_of::<SqlValue>()
+                + match v {
+                    SqlValue::Text(s) => s.len(),
+                    SqlValue::Blob(b) => b.len(),
+                    _ => 0,
+                }
+        })
+        .sum()
+}
+
+// Runs use their own simple format, rather than the SQLite record format, since they are only read back by this module,
+// and they need to hold every kind of value.
+fn write_values(w: &mut impl Write, values: &[SqlValue]) -> Result<()> {
+    w.write_u32::<BigEndian>(values.len() as u32)?;
+    for v in values {
+        match v {
+            SqlValue::Null() => w.write_u8(0)?,
+            SqlValue::Int(i) => {
+                w.write_u8(1)?;
+                w.write_i64::<BigEndian>(*i)?;
+            }
+            SqlValue::Real(f) => {
+                w.write_u8(2)?;
+                w.write_f64::<BigEndian>(*f)?;
+            }
+            SqlValue::Text(s) => {
+                w.write_u8(3)?;
+                w.write_u32::<BigEndian>(s.len() as u32)?;
+                w.write_all(s.as_bytes())?;
+            }
+            SqlValue::Blob(b) => {
+                w.write_u8(4)?;
+                w.write_u32::<BigEndian>(b.len() as u32)?;
+                w.write_all(b)?;
+            }
+            SqlValue::Bool(b) => {
+                w.write_u8(5)?;
+                w.write_u8(*b as u8)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_values(r: &mut impl Read) -> Result<Vec<SqlValue>> {
+    let n = r.read_u32::<BigEndian>()?;
+    let mut values = Vec::with_capacity(n as usize);
+    for _ in 0..n {
+        values.push(match r.read_u8()? {
+            0 => SqlValue::Null(),
+            1 => SqlValue::Int(r.read_i64::<BigEndian>()?),
+            2 => SqlValue::Real(r.read_f64::<BigEndian>()?),
+            3 => {
+                let mut buf = vec![0; r.read_u32::<BigEndian>()? as usize];
+                r.read_exact(&mut buf)?;
+                SqlValue::Text(String::from_utf8(buf)?)
+            }
+            4 => {
+                let mut buf = vec![0; r.read_u32::<BigEndian>()? as usize];
+                r.read_exact(&mut buf)?;
+                SqlValue::Blob(buf)
+            }
+            5 => SqlValue::Bool(r.read_u8()? != 0),
+            t => bail!("Invalid value tag {} in sort run", t),
+        });
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+fn sort_key(colname: &str, descending: bool, nulls_first: bool) -> ir::SortKey {
+    ir::SortKey {
+        expr: crate::ast::Expr::ColRef { tablename: None, colname: String::from(colname) },
+        descending,
+        nulls_first,
+    }
+}
+
+#[cfg(test)]
+fn sort_rows(keys: &[ir::SortKey], rows: &[Row], memory_bytes: usize) -> Vec<Row> {
+    let colnames = vec![String::from("a"), String::from("b")];
+    let tablenames = vec![String::from("t"); colnames.len()];
+    let mut sorter = Sorter::new(build_sort(&tablenames, &colnames, keys).unwrap(), memory_bytes);
+    for row in rows {
+        sorter.push(row).unwrap();
+    }
+    let mut sorted = sorter.finish().unwrap();
+    let mut out = vec![];
+    while let Some(row) = sorted.next_row().unwrap() {
+        out.push(row);
+    }
+    out
+}
+
+#[test]
+fn test_sort_keys() {
+    use SqlValue::*;
+    let row = |a: SqlValue, b: SqlValue| Row { items: vec![a, b] };
+    let rows = vec![
+        row(Int(2), Text("x".to_string())),
+        row(Null(), Int(1)),
+        row(Text("a".to_string()), Int(2)),
+        row(Real(1.5), Int(3)),
+        row(Int(2), Text("w".to_string())),
+        row(Blob(vec![0]), Int(4)),
+    ];
+    let asc = sort_rows(&[sort_key("a", false, true), sort_key("b", false, true)], &rows, DEFAULT_SORT_MEMORY_BYTES);
+    assert_eq!(asc, vec![rows[1].clone(), rows[3].clone(), rows[4].clone(), rows[0].clone(), rows[2].clone(), rows[5].clone()]);
+    let desc = sort_rows(&[sort_key("a", true, false), sort_key("b", false, true)], &rows, DEFAULT_SORT_MEMORY_BYTES);
+    assert_eq!(desc, vec![rows[5].clone(), rows[2].clone(), rows[4].clone(), rows[0].clone(), rows[3].clone(), rows[1].clone()]);
+    let nulls_last = sort_rows(&[sort_key("a", false, false)], &rows, DEFAULT_SORT_MEMORY_BYTES);
+    assert_eq!(nulls_last.last().unwrap(), &rows[1]);
+}
+
+#[test]
+fn test_sort_spills_and_merges_runs() {
+    use SqlValue::*;
+    // Pseudo-random order, with repeated keys to check that the sort is stable across runs.
+    let rows: Vec<Row> = (0..1000)
+        .map(|i| Row { items: vec![Int((i * 7919) % 101), Text(format!("row{}", i))] })
+        .collect();
+    let keys = [sort_key("a", false, true)];
+    let in_memory = sort_rows(&keys, &rows, DEFAULT_SORT_MEMORY_BYTES);
+    // A small budget forces many runs.
+    let spilled = sort_rows(&keys, &rows, 2000);
+    assert_eq!(spilled, in_memory);
+    let mut expected = rows.clone();
+    expected.sort_by(|x, y| sql_value::collate(&x.items[0], &y.items[0]));
+    assert_eq!(spilled, expected);
+}
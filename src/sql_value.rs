@@ -26,6 +26,68 @@ impl std::fmt::Display for SqlValue {
     }
 }
 
+/// compares two values using SQLite's sort order, which is defined across values of different types.
+///
+/// NULLs come first, then numbers (with `Int` and `Real` compared by value), then Text, then Blobs.
+/// Text is compared byte-wise, which matches SQLite's default `BINARY` collation.
+pub fn collate(a: &SqlValue, b: &SqlValue) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+    use SqlValue::*;
+    fn class(v: &SqlValue) -> u8 {
+        match v {
+            Null() => 0,
+            Int(_) | Real(_) | Bool(_) => 1,
+            Text(_) => 2,
+            Blob(_) => 3,
+        }
+    }
+    fn as_f64(v: &SqlValue) -> f64 {
+        match v {
+            Int(i) => *i as f64,
+            Real(f) => *f,
+            Bool(b) => *b as i64 as f64,
+            _ => 0.0,
+        }
+    }
+    match (a, b) {
+        (Int(i), Int(j)) => i.cmp(j),
+        (Text(s), Text(t)) => s.as_bytes().cmp(t.as_bytes()),
+        (Blob(x), Blob(y)) => x.cmp(y),
+        _ => match class(a).cmp(&class(b)) {
+            Ordering::Equal if class(a) == 1 => as_f64(a).partial_cmp(&as_f64(b)).unwrap_or(Ordering::Equal),
+            o => o,
+        },
+    }
+}
+
+/// compares two values as `collate` does, except that text is compared by its bytes in `encoding`, which is the order
+/// of text in the indexes of a database in that encoding.
+pub fn collate_in(a: &SqlValue, b: &SqlValue, encoding: crate::stored_db::TextEncoding) -> std::cmp::Ordering {
+    match (a, b) {
+        (SqlValue::Text(s), SqlValue::Text(t)) => crate::serial_type::compare_text(s, t, encoding),
+        _ => collate(a, b),
+    }
+}
+
+#[test]
+fn test_collate() {
+    use std::cmp::Ordering::*;
+    use SqlValue::*;
+    let cases = vec![
+        (Null(), Null(), Equal),
+        (Null(), Int(-5), Less),
+        (Int(1), Real(1.0), Equal),
+        (Int(2), Real(1.5), Greater),
+        (Real(1e10), Text("0".to_string()), Less),
+        (Text("a".to_string()), Text("b".to_string()), Less),
+        (Text("b".to_string()), Text("B".to_string()), Greater),
+        (Text("z".to_string()), Blob(vec![0]), Less),
+    ];
+    for (a, b, expected) in cases {
+        assert_eq!(collate(&a, &b), expected, "comparing {} and {}", a, b);
+    }
+}
+
 use crate::ast;
 pub fn from_ast_constant(c: &ast::Constant) -> SqlValue {
     match c {
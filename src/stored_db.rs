@@ -6,21 +6,22 @@
 
 // TODO:
 //  - Use OS locking to lock the opened database file.
-//  - Support accessing pages for modification by locking the entire Pager.
 //  - Support concurrent access for read and write via table or page-level locking.
-//  - Support adding pages to the database.
-//  - Support reading pages on demand.
-//  - Support dropping unused pages when memory is low.
 //  - When there are multiple pagers (multiple open files), coordinating to stay under a total memory limit.
 
 use std::boxed::Box;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::cell::RefCell;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::str::FromStr;
 
+use byteorder::{BigEndian, ByteOrder};
 use streaming_iterator::StreamingIterator;
 
+use crate::btree::RowId;
+pub use crate::buffer_pool::PageRef;
+pub use crate::dbheader::DbfileHeader;
+pub use crate::serial_type::TextEncoding;
 use crate::sql_type::SqlType;
 use crate::sql_value::SqlValue;
 
@@ -34,13 +35,15 @@ const SCHEMA_SCHEMA: &str =
     "CREATE TABLE sqlite_schema (type text, name text, tbl_name text, rootpage integer, sql text)";
 const SCHEMA_TABLE_COL_NAMES: [&str; 5] = ["type", "name", "tbl_name", "rootpage", "sql"];
 const SCHEMA_TABLE_COL_TYPES: [SqlType; 5] = [SqlType::Text, SqlType::Text, SqlType::Text, SqlType::Int, SqlType::Text];
+const SCHEMA_TABLE_TYPE_COLIDX: usize = 0;
+const SCHEMA_TABLE_NAME_COLIDX: usize = 1;
 const SCHEMA_TABLE_TBL_NAME_COLIDX: usize = 2;
 const SCHEMA_TABLE_ROOTPAGE_COLIDX: usize = 3;
 const SCHEMA_TABLE_SQL_COLIDX: usize = 4;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Pager: Page number greater than maximum supported page number.")]
+    #[error("Pager: Page number beyond the end of the database.")]
     PageNumberBeyondLimits,
     #[error("Pager: Internal error.")]
     Internal,
@@ -50,23 +53,56 @@ pub enum Error {
     DbHdr(#[from] crate::dbheader::Error),
     #[error("Default database pager requested when no databases loaded.")]
     NoDefaultDB,
-    #[error("Too many pages open for write at once.")]
-    TooManyPagesOpenForWrite,
+    #[error("Database file was opened read-only.")]
+    ReadOnly,
     #[error("Table {0} not found in database.")]
     TableNameNotFound(String),
+    #[error("Table {0} already exists.")]
+    TableExists(String),
+    #[error("Table name {0} is reserved for internal use.")]
+    ReservedTableName(String),
+    #[error("Index {0} not found in database.")]
+    IndexNameNotFound(String),
     #[error("Error opening stored table.")]
     OpeningStoredTable,
+    #[error("Pager: Error in rollback journal: {0}")]
+    Journal(#[from] crate::journal::Error),
+    #[error("Database file has a hot journal, and was opened read-only, so the journal cannot be rolled back.")]
+    HotJournalReadOnly,
+    #[error("Pager: Error in write-ahead log: {0}")]
+    Wal(#[from] crate::wal::Error),
+    #[error("Pager: Simulated crash.")]
+    SimulatedCrash,
+    #[error("Changing databases in auto-vacuum mode is not supported, since their pointer map pages are not kept up to date.")]
+    AutoVacuum,
+    #[error("Pager: Checksum of page {0} does not match its contents.")]
+    ChecksumMismatch(PageNum),
+    #[error("Checksums need 8 reserved bytes at the end of each page, but pages of this database have {0}.")]
+    ChecksumsNotSupported(u32),
+    #[error("Database is corrupt: page {page}, offset {offset}: {detail}")]
+    Corrupt { page: PageNum, offset: usize, detail: String },
+    #[error("Table {table}, row {rowid}: {detail}")]
+    Row { table: String, rowid: RowId, detail: crate::typed_row::Error },
+    #[error("Database is corrupt: {0}")]
+    Inconsistent(String),
+    #[error("Table {0} has a row with the largest possible rowid, so there is no rowid for a new row.")]
+    RowIdsExhausted(String),
 }
 
 /// A `StoredDb` manages the file locking and the memory use for one open database file.
 /// 
-/// Currently, a StoredDb only supports single-threaded read-only access a database file. It reads all the pages into memory at once.
+/// Currently, a StoredDb only supports single-threaded access to a database file.  Pages are read into a buffer pool when
+/// they are first used, and pages that are not in use are evicted to stay within a memory budget.
+/// Pages that are changed are kept in memory until `commit()` writes them to the file, or `rollback()` reads them again.
+/// A commit copies the original pages to a rollback journal first, so that the pages of a commit are either all written
+/// or, after a crash, all put back when the file is next opened.
 ///
-/// A full implementation of a StoredDb would support concurrent read and write accesses, with demand paging and multiple files,
-/// with the necessary reference counting and locking.
+/// A full implementation of a StoredDb would support concurrent read and write accesses, and multiple files sharing
+/// one memory budget, with the necessary locking.
 ///
 /// A StoredDb is responsible for opening and locking a database file at the OS level.  A StoredDb owns the data in each page,
-/// and allows callers to access it for reading without copying.
+/// and allows callers to access it for reading without copying, through a `PageRef`, which keeps the page in memory
+/// until it is dropped.
 ///
 /// There are a number of page types in a SQLite database: Summarizing the SQLite documentation:
 /// > The complete state of an SQLite database is usually contained in a single file on disk called the "main database file".
@@ -85,7 +121,7 @@ pub enum Error {
 /// >    -   A pointer map page
 /// 
 /// However, simple database files only contain table btree pages.
-/// Freelist pages will be managed by the Pager once supported.
+/// Pages that are no longer used are put on the freelist by `free_page()`, and `alloc_new_page()` uses them again.
 // A `PagerSet` manages zero or more Pagers, one per open database.
 /// # Examples
 /// 
@@ -100,7 +136,7 @@ pub enum Error {
 /// ```
 /// 
 // The following doc is here as a test, to ensure that borrow checking enforces the expected invariants.
-/// At present, you cannot hold one page for read and one page for write at the same time.  This doesn't work:
+/// You cannot hold one page for read and one page for write at the same time.  This doesn't work:
 /// ```compile_fail
 /// # let path = (std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set") + "/resources/test/" + "minimal.db");
 /// # use diydb::stored_db::StoredDb;
@@ -117,131 +153,550 @@ pub enum Error {
 /// let p1 = sdb.get_page_rw(1).unwrap();
 /// let p2 = sdb.get_page_rw(2).unwrap();
 /// ```
-///  These limits will be fixed in the future.
+///  Instead, change one page at a time.
 pub struct StoredDb {
     // This would be per DB.
     f: Box<RefCell<std::fs::File>>,
 
-    // TODO: pages could return a RefCell so that pages can be paged in on demand.
-    // When implementing that, some things to consider are:
-    // - The memory overhead: I think it should be low, given that pages (512B-4kB) are much larger than the overhead (16B-24B?).
-    // - The cpu overhead: Is it paid by every function in the stack of iterators and , or once per at allocation and de-allocation, or on every access?  Perhaps benchmark it.
-    //   - Scan Preloaded pages without RefCell vs scan preloaded pages acessed via RefCell.
-    // Does the need to deal with a type other than byte slice hurt the readability of all the downstream code?
-    // - Does RefCell allow locally converting to a readonly byte slice, within a scope?  Does that help?
-    // Should `get_page_ro()` return a PageHandle?
-    //   - Or should downstream code just be generic enough (require Traits it needs) that it can deal with
-    //     a RefCell<...> or whatever locking wrapper is needed next?
-    //   - Will I end up with RefCell<...<RefCell<...>>...>  since both the list needs locking to expand, and the
-    //     pages need locking for presence?
-    // Do I need a way to deal with failure other than panicing (which is what RefCell does?)  Like waiting, or logging
-    // specific information?
-
-    // TODO: This can be per-table - a table has its btree pages, and any overflow pages.  When there is freelist support, that would be at the Db level.
-    /// Map from page number to the page data, or key not found if page not in memory.
-    pages: HashMap<PageNum, Vec<u8>>,
+    /// the pages that are in memory.  Pages are read into the pool by `get_page_ro()`, which only borrows the
+    /// StoredDb, so the pool is in a RefCell.
+    pool: RefCell<crate::buffer_pool::BufferPool>,
     // This goes into the StoredDB.
     page_size: u32,
-    // This could be per table, though there might need to be special consideration for the first page when the header changes.
-    open_rw_page: Option<PageNum>,
-    // This could be per table, though there might need to be special consideration for the first page when the headers changes.
-    num_open_rw_pages: usize,
+    /// the number of bytes at the end of each page that are reserved for extensions, and not used by btrees.
+    reserved_bytes: u32,
+    /// true if pages have checksums in their reserved bytes, which are checked when pages are read, and set when they
+    /// are written.
+    checksums: bool,
+    /// the number of pages in the database, including pages allocated since the last commit.
+    numpages: PageNum,
+    /// pages changed since the last commit.
+    dirty: BTreeSet<PageNum>,
+    /// the savepoints started since the last commit, innermost last.
+    savepoints: Vec<SavepointPages>,
+    read_only: bool,
+    /// true if the database is in auto-vacuum or incremental-vacuum mode, so that it has pointer map pages.
+    auto_vacuum: bool,
+    /// the encoding of the text values in the database, which is fixed when the database is created.
+    encoding: TextEncoding,
+    /// the path of the database file, which the path of its rollback journal is made from.
+    path: String,
+    /// the write-ahead log, if the database is in WAL mode.
+    wal: Option<crate::wal::Wal>,
+    /// for testing, the number of bytes that `commit()` writes to the journal and the file before it stops as if the
+    /// process had crashed, leaving the files as they are.
+    pub(crate) crash_after: Option<usize>,
 }
 
 // Page numbers are 1-based, to match how Sqlite numbers pages.  PageNum ensures people pass something that is meant to be a page number
 // to a function that expects a page number.
 pub type PageNum = usize;
 
-// TODO: support databases with more on-disk pages, limiting memory usage by paging out unused pages.
-const MAX_PAGE_NUM: PageNum = 10_000; // 10_000 * 4k page ~= 40MB
+/// the number of bytes of pages that a StoredDb keeps in memory, unless changed with `set_memory_budget()`.
+pub const DEFAULT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
 
 impl StoredDb {
-    /// opens a database file and verfies it is a SQLite db file, and reads in an unspecified number of pages of the database.
+    /// opens a database file and verfies it is a SQLite db file.
     ///
-    /// Additional pages may be read in as needed later.
+    /// Pages are read in as they are needed.
+    ///
+    /// The file is opened for writing too, unless that is not permitted, in which case changes cannot be committed.
     pub fn open(path: &str) -> Result<Self, Error> {
-        let file =
-                // TODO: Lock file when opening so that other processes do not also
-                // open and modify it, and so that is not modified while reading.
-                // I tried  https://docs.rs/file-lock/latest/file_lock/ but it doesn't support opening readonly and locking at the same time.
-                //  Instead, try https://crates.io/crates/fd-lock to see if it is any better.
-                RefCell::new(
-                    std::fs::OpenOptions::new()
-                        .read(true)
-                        .write(false)
-                        .create(false)
-                        .open(path)
-                        .map_err(Error::Io)?
-                );
+        // TODO: Lock file when opening so that other processes do not also
+        // open and modify it, and so that is not modified while reading.
+        // I tried  https://docs.rs/file-lock/latest/file_lock/ but it doesn't support opening readonly and locking at the same time.
+        //  Instead, try https://crates.io/crates/fd-lock to see if it is any better.
+        let open = |write: bool| std::fs::OpenOptions::new().read(true).write(write).create(false).open(path);
+        let (file, read_only) = match open(true) {
+            Ok(f) => (f, false),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => (open(false).map_err(Error::Io)?, true),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let mut file = file;
+        let journal_path = crate::journal::path_for(path);
+        if std::path::Path::new(&journal_path).exists() {
+            if read_only {
+                return Err(Error::HotJournalReadOnly);
+            }
+            crate::journal::replay(&journal_path, &mut file)?;
+        }
+        let file = RefCell::new(file);
         let h = crate::dbheader::get_header_clone(&mut file.borrow_mut()).map_err(Error::DbHdr)?;
         file.borrow_mut()
             .seek(SeekFrom::Start(0))
             .map_err(Error::Io)?;
-        if h.numpages > MAX_PAGE_NUM as u32 {
-            return Err(Error::PageNumberBeyondLimits);
-        }
-        //TODO: read these in on demand.
-        let mut pages: HashMap<PageNum, Vec<u8>> = HashMap::new();
-        for pn in 1_usize..(h.numpages as usize) + 1 {
-            let mut v = vec![0_u8; h.pagesize as usize];
-            file.borrow_mut()
-                .seek(SeekFrom::Start((pn - 1) as u64 * h.pagesize as u64))
-                .map_err(Error::Io)?;
-            file.borrow_mut()
-                .read_exact(&mut v[..])
-                .map_err(Error::Io)?;
-            pages.insert(pn, v.into());
-        }
+        // In WAL mode, pages committed since the last checkpoint are read from the write-ahead log instead of the file.
+        let wal = match h.wal_mode {
+            true => Some(crate::wal::Wal::open(&crate::wal::path_for(path), h.pagesize, read_only)?),
+            false => None,
+        };
+        let numpages = wal.as_ref().and_then(|w| w.numpages()).unwrap_or(h.numpages as PageNum);
         Ok(StoredDb {
             f: Box::new(file),
-            pages,
+            pool: RefCell::new(crate::buffer_pool::BufferPool::new(DEFAULT_MEMORY_BUDGET / h.pagesize as usize)),
             page_size: h.pagesize,
-            open_rw_page: None,
-            num_open_rw_pages: 0,
+            reserved_bytes: h.reserved_bytes as u32,
+            checksums: false,
+            numpages,
+            dirty: BTreeSet::new(),
+            savepoints: vec![],
+            read_only,
+            auto_vacuum: h.largest_root_btree_page != 0,
+            encoding: h.encoding,
+            path: path.to_owned(),
+            wal,
+            crash_after: None,
         })
     }
 
+    /// sets the number of bytes of pages kept in memory.
+    ///
+    /// Pages that are in use, or changed since the last commit, are kept in memory even if that goes over the budget.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.pool.get_mut().set_max_frames(bytes / self.page_size as usize);
+    }
+
+    /// turns the checking of page checksums on or off.
+    ///
+    /// Checksums are kept in the last 8 bytes of each page by the checksum VFS (https://www.sqlite.org/cksumvfs.html),
+    /// so they can only be turned on for databases with 8 reserved bytes per page.  When they are on, a page whose
+    /// checksum does not match is an error when it is read, and the checksums of changed pages are set when they are
+    /// committed.
+    pub fn set_checksums(&mut self, on: bool) -> Result<(), Error> {
+        if on && self.reserved_bytes as usize != crate::checksum::CHECKSUM_LEN {
+            return Err(Error::ChecksumsNotSupported(self.reserved_bytes));
+        }
+        self.checksums = on;
+        Ok(())
+    }
+
+    /// returns the number of pages in memory.
+    pub fn num_pages_in_memory(&self) -> usize {
+        self.pool.borrow().num_pages()
+    }
+
+    /// creates a new database file, with `page_size` byte pages and an empty schema table, and opens it.
+    ///
+    /// It is an error if the file already exists.
+    pub fn create(path: &str, page_size: u32) -> anyhow::Result<Self> {
+        let mut page1 = vec![0_u8; page_size as usize];
+        page1[..100].copy_from_slice(&crate::dbheader::new_header(page_size)?);
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)?
+            .write_all(&page1)?;
+        // The schema table is a table btree whose root is page 1.
+        let result = StoredDb::open(path).map_err(anyhow::Error::from).and_then(|mut db| {
+            crate::btree::insert::init_table_btree(&mut db, SCHEMA_BTREE_ROOT_PAGENUM)?;
+            db.commit()?;
+            Ok(db)
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(path);
+        }
+        result
+    }
+
+    /// returns the schema table, which describes the tables and indexes of the database.
+    fn schema_table(&self) -> StoredTable<'_> {
+        StoredTable::new(
+            self,
+            String::from(SCHEMA_TABLE_NAME),
+            SCHEMA_BTREE_ROOT_PAGENUM,
+            SCHEMA_TABLE_COL_NAMES.iter().map(|x| x.to_string()).collect(),
+            Vec::from(SCHEMA_TABLE_COL_TYPES),
+            true,
+        )
+    }
+
+    /// returns the rows of the schema table, each of which has a value for every column.
+    fn schema_rows(&self) -> Result<Vec<crate::typed_row::Row>, Error> {
+        let rows = self.schema_table().to_temp_table().map_err(|crate::stored_table::Error::Read(e)| e)?.rows;
+        match rows.iter().find(|row| row.items.len() != SCHEMA_TABLE_COL_NAMES.len()) {
+            Some(row) => Err(Error::Inconsistent(format!("schema table has a row with {} values", row.items.len()))),
+            None => Ok(rows),
+        }
+    }
+
+    /// returns the row of the schema table that describes `table_name`.
+    fn schema_row_for_table(&self, table_name: &str) -> Result<crate::typed_row::Row, Error> {
+        // The schema table also has rows for the indexes of the table, which have the same `tbl_name`.
+        self.schema_rows()?
+            .into_iter()
+            .find(|row| is_schema_row_for_table(row, table_name))
+            .ok_or(Error::TableNameNotFound(table_name.to_owned()))
+    }
+
     /// Get the root page number for `table_name`.
-    pub fn get_root_pagenum(&self, table_name: &str) -> Option<PageNum> {
+    pub fn get_root_pagenum(&self, table_name: &str) -> Result<PageNum, Error> {
         if table_name == SCHEMA_TABLE_NAME {
-            return Some(SCHEMA_BTREE_ROOT_PAGENUM);
-        } else {
-            let schema_table = StoredTable::new(
-                self,
-                String::from(SCHEMA_TABLE_NAME),
-                SCHEMA_BTREE_ROOT_PAGENUM,
-                SCHEMA_TABLE_COL_NAMES.iter().map(|x| x.to_string()).collect(),
-                Vec::from(SCHEMA_TABLE_COL_TYPES),
-                true,
-            );   
-            let mut it = schema_table.streaming_iterator();
-            while let Some(row) = it.next() {
-                let this_table_name = match &row.items[SCHEMA_TABLE_TBL_NAME_COLIDX] {
-                    SqlValue::Text(s) => s.clone(),
-                    _ => panic!("Type mismatch in schema table column {}, expected Text", SCHEMA_TABLE_TBL_NAME_COLIDX),
-                };
-                if this_table_name != table_name {
-                    continue;
+            return Ok(SCHEMA_BTREE_ROOT_PAGENUM);
+        }
+        match &self.schema_row_for_table(table_name)?.items[SCHEMA_TABLE_ROOTPAGE_COLIDX] {
+            SqlValue::Int(i) => Ok(*i as PageNum),
+            v => Err(Error::Inconsistent(format!("root page of table {} is {}, not an integer", table_name, v))),
+        }
+    }
+
+    /// adds a zeroed page to the end of the database, and returns its page number.
+    ///
+    /// The page count in the database header is updated when the page is committed.
+    pub fn alloc_new_page(&mut self) -> Result<PageNum, Error> {
+        self.check_writable()?;
+        if let Some(pn) = self.alloc_free_page()? {
+            let usable_size = self.get_usable_size() as usize;
+            self.get_page_rw(pn)?[..usable_size].fill(0);
+            return Ok(pn);
+        }
+        let mut pn = self.numpages + 1;
+        if self.is_lock_byte_page(pn) {
+            pn += 1;
+        }
+        self.save_original(pn);
+        let dirty = &self.dirty;
+        self.pool.get_mut().insert(pn, vec![0_u8; self.page_size as usize], |pn| dirty.contains(&pn));
+        self.dirty.insert(pn);
+        self.numpages = pn;
+        Ok(pn)
+    }
+
+    /// takes a page off the freelist, if it has any, and returns its page number.
+    ///
+    /// The freelist is a list of trunk pages.  Each trunk page holds the page number of the next trunk page, the
+    /// number of leaf pages it lists, and their page numbers.  Leaf pages are taken first, and then the trunk page
+    /// itself.
+    fn alloc_free_page(&mut self) -> Result<Option<PageNum>, Error> {
+        let (trunk, count) = crate::dbheader::freelist(&self.get_page_ro(1)?);
+        if trunk == 0 {
+            return Ok(None);
+        }
+        let t = self.get_page_rw(trunk as PageNum)?;
+        let (next_trunk, num_leaves) = (BigEndian::read_u32(&t[0..]), BigEndian::read_u32(&t[4..]));
+        let (pn, first_trunk) = match num_leaves {
+            0 => (trunk, next_trunk),
+            n => {
+                BigEndian::write_u32(&mut t[4..], n - 1);
+                (BigEndian::read_u32(&t[4 + 4 * n as usize..]), trunk)
+            }
+        };
+        crate::dbheader::set_freelist(self.get_page_rw(1)?, first_trunk, count - 1);
+        Ok(Some(pn as PageNum))
+    }
+
+    /// puts page `pn`, which is no longer used, on the freelist, so that it can be allocated again.
+    pub fn free_page(&mut self, pn: PageNum) -> Result<(), Error> {
+        let (trunk, count) = crate::dbheader::freelist(&self.get_page_ro(1)?);
+        crate::dbheader::set_freelist(self.get_page_rw(1)?, trunk, count + 1);
+        // SQLite versions before 3.6.0 read trunk pages with more than this many leaves as corrupt.
+        let max_leaves = self.get_usable_size() / 4 - 8;
+        if trunk != 0 {
+            let t = self.get_page_rw(trunk as PageNum)?;
+            let num_leaves = BigEndian::read_u32(&t[4..]);
+            if num_leaves < max_leaves {
+                BigEndian::write_u32(&mut t[8 + 4 * num_leaves as usize..], pn as u32);
+                BigEndian::write_u32(&mut t[4..], num_leaves + 1);
+                return Ok(());
+            }
+        }
+        // The first trunk page is full, so the page becomes the first trunk page.
+        let usable_size = self.get_usable_size() as usize;
+        let t = self.get_page_rw(pn)?;
+        t[..usable_size].fill(0);
+        BigEndian::write_u32(&mut t[0..], trunk);
+        crate::dbheader::set_freelist(self.get_page_rw(1)?, pn as u32, count + 1);
+        Ok(())
+    }
+
+    /// writes the pages changed since the last commit to the file.
+    ///
+    /// The database header on page 1 is updated with the page count, and with a new file change counter, which tells
+    /// other readers of the file that it has changed.
+    ///
+    /// The original contents of the changed pages are written to the rollback journal and synced before any page of the
+    /// file is written.  The commit is done when the journal is deleted, after the file is synced.  A crash before
+    /// then leaves a hot journal, which `open()` uses to put the original pages back.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        if self.dirty.is_empty() {
+            self.savepoints.clear();
+            return Ok(());
+        }
+        let numpages = self.numpages as u32;
+        let page1 = self.get_page_rw(1)?;
+        crate::dbheader::set_numpages(page1, numpages);
+        crate::dbheader::increment_change_counter(page1);
+        if self.checksums {
+            let pool = self.pool.get_mut();
+            for pn in &self.dirty {
+                crate::checksum::set(pool.get_mut(*pn).ok_or(Error::Internal)?);
+            }
+        }
+        if self.wal.is_some() {
+            return self.commit_to_wal();
+        }
+        // Pages beyond the end of the file were added since the last commit, so they have no original contents.
+        let file_numpages = (self.f.borrow().metadata().map_err(Error::Io)?.len() / self.page_size as u64) as PageNum;
+        let originals = self
+            .dirty
+            .iter()
+            .filter(|pn| **pn <= file_numpages)
+            .map(|pn| Ok((*pn, self.read_page_from_file(*pn)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let journal_path = crate::journal::path_for(&self.path);
+        let mut journal = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&journal_path)
+            .map_err(Error::Io)?;
+        let contents = crate::journal::contents(self.page_size, file_numpages as u32, &originals);
+        write_at(&mut self.crash_after, &mut journal, 0, &contents)?;
+        journal.sync_all().map_err(Error::Io)?;
+        {
+            let mut f = self.f.borrow_mut();
+            let pool = self.pool.get_mut();
+            for pn in &self.dirty {
+                let offset = (pn - 1) as u64 * self.page_size as u64;
+                write_at(&mut self.crash_after, &mut f, offset, pool.peek(*pn).ok_or(Error::Internal)?)?;
+            }
+        }
+        self.f.borrow().sync_all().map_err(Error::Io)?;
+        self.crash_point()?;
+        std::fs::remove_file(&journal_path).map_err(Error::Io)?;
+        self.dirty.clear();
+        self.savepoints.clear();
+        Ok(())
+    }
+
+    /// appends the pages changed since the last commit to the write-ahead log, which commits them once it is synced.
+    fn commit_to_wal(&mut self) -> Result<(), Error> {
+        let pool = self.pool.get_mut();
+        let pages = self
+            .dirty
+            .iter()
+            .map(|pn| Ok((*pn, &pool.peek(*pn).ok_or(Error::Internal)?[..])))
+            .collect::<Result<Vec<(PageNum, &[u8])>, Error>>()?;
+        let wal = self.wal.as_mut().ok_or(Error::Internal)?;
+        wal.append_commit(&pages, self.numpages)?;
+        self.crash_point()?;
+        self.wal.as_ref().ok_or(Error::Internal)?.sync()?;
+        self.dirty.clear();
+        self.savepoints.clear();
+        Ok(())
+    }
+
+    /// copies the pages committed to the write-ahead log into the file, and empties the write-ahead log.
+    ///
+    /// Databases that are not in WAL mode have nothing to checkpoint.  Changes that are not committed are not copied.
+    pub fn checkpoint(&mut self) -> Result<(), Error> {
+        match &mut self.wal {
+            None => Ok(()),
+            Some(wal) => Ok(wal.checkpoint(&mut self.f.borrow_mut())?),
+        }
+    }
+
+    /// returns `Error::SimulatedCrash` if a test asked for `commit()` to stop after the bytes it has written so far.
+    fn crash_point(&self) -> Result<(), Error> {
+        match self.crash_after {
+            Some(0) => Err(Error::SimulatedCrash),
+            _ => Ok(()),
+        }
+    }
+
+    /// discards the changes made since the last commit, by reading the changed pages from the file again.
+    ///
+    /// If a commit failed part way, the original pages are put back in the file from the rollback journal first.
+    pub fn rollback(&mut self) -> Result<(), Error> {
+        let journal_path = crate::journal::path_for(&self.path);
+        if std::path::Path::new(&journal_path).exists() {
+            crate::journal::replay(&journal_path, &mut self.f.borrow_mut())?;
+        }
+        let numpages = match self.wal.as_ref().and_then(|w| w.numpages()) {
+            Some(n) => n,
+            None => crate::dbheader::get_header_clone(&mut self.f.borrow_mut())?.numpages as PageNum,
+        };
+        let pool = self.pool.get_mut();
+        for pn in std::mem::take(&mut self.dirty) {
+            pool.remove(pn);
+        }
+        self.savepoints.clear();
+        self.numpages = numpages;
+        Ok(())
+    }
+
+    /// starts a savepoint, so that `rollback_to()` can discard the changes made after it.
+    ///
+    /// Nothing is copied when the savepoint starts.  A page is copied when it is first changed after it.
+    pub fn savepoint(&mut self) -> Savepoint {
+        self.savepoints.push(SavepointPages { pages: HashMap::new(), numpages: self.numpages });
+        Savepoint { depth: self.savepoints.len() - 1 }
+    }
+
+    /// discards the changes made since `savepoint`, keeping the changes made before it.
+    ///
+    /// The savepoint stays, so that later changes can be rolled back to it too, while the savepoints started after
+    /// it end.  Pages that were not changed at the savepoint are dropped from memory, and read from the file again
+    /// when needed.
+    pub fn rollback_to(&mut self, savepoint: &Savepoint) -> Result<(), Error> {
+        if savepoint.depth >= self.savepoints.len() {
+            return Err(Error::Internal);
+        }
+        let pool = self.pool.get_mut();
+        for sp in self.savepoints.drain(savepoint.depth..).rev() {
+            for (pn, original) in sp.pages {
+                match original {
+                    Some(v) => {
+                        self.dirty.insert(pn);
+                        let dirty = &self.dirty;
+                        pool.insert(pn, v, |pn| dirty.contains(&pn));
+                    }
+                    None => {
+                        self.dirty.remove(&pn);
+                        pool.remove(pn);
+                    }
+                }
+            }
+            self.numpages = sp.numpages;
+        }
+        self.savepoints.push(SavepointPages { pages: HashMap::new(), numpages: self.numpages });
+        Ok(())
+    }
+
+    /// ends `savepoint`, and the savepoints started after it, keeping their changes.
+    ///
+    /// The changes can still be rolled back to the savepoints started before it.
+    pub fn release(&mut self, savepoint: &Savepoint) {
+        if savepoint.depth >= self.savepoints.len() {
+            return;
+        }
+        let released = self.savepoints.split_off(savepoint.depth);
+        if let Some(outer) = self.savepoints.last_mut() {
+            // The outer savepoint keeps the earliest copy of each page.
+            for sp in released {
+                for (pn, original) in sp.pages {
+                    outer.pages.entry(pn).or_insert(original);
                 }
-                // TODO: refactor code below to "get row element as type x or return nicely formatted Error", which can be used elsewhere too.
-                let root_pagenum = match &row.items[SCHEMA_TABLE_ROOTPAGE_COLIDX] {
-                    SqlValue::Int(i) => *i as PageNum,
-                    // TODO: return Result rather than panicing.
-                    _ => panic!("Type mismatch in schema table column {}, expected Int", SCHEMA_TABLE_ROOTPAGE_COLIDX),
-                };
-                return Some(root_pagenum);
             }
         }
-        None
     }
 
-    #[allow(dead_code)]
-    fn alloc_new_page(self) -> PageNum {
-        // TODO: to support writes, need to allocate new pages: write to the database header to increase the page count.
-        unimplemented!()
+    /// copies page `pn` for the innermost savepoint, if it has not been changed since the savepoint started, before
+    /// it is changed.
+    fn save_original(&mut self, pn: PageNum) {
+        let Some(sp) = self.savepoints.last_mut() else {
+            return;
+        };
+        if sp.pages.contains_key(&pn) {
+            return;
+        }
+        let original = match self.dirty.contains(&pn) {
+            // Changed pages are not evicted, so they are all in the pool.
+            true => self.pool.get_mut().peek(pn).cloned(),
+            // Unchanged pages can be read from the file again.
+            false => None,
+        };
+        sp.pages.insert(pn, original);
+    }
+
+    /// creates the table `table_name`, with an empty root page, and returns the root page number.
+    ///
+    /// `sql` is the CREATE TABLE statement for the table, which is kept in the schema table.  The table is not written to
+    /// the file until `commit()` is called.
+    pub fn create_table(&mut self, table_name: &str, sql: &str) -> anyhow::Result<PageNum> {
+        if table_name.to_lowercase().starts_with("sqlite_") {
+            return Err(Error::ReservedTableName(table_name.to_owned()).into());
+        }
+        // SQLite compares names without regard to case, so names that differ only in case can't both be used.
+        let mut it = self.schema_table().streaming_iterator();
+        while let Some(row) = it.next() {
+            if matches!(&row.items[SCHEMA_TABLE_NAME_COLIDX], SqlValue::Text(s) if s.eq_ignore_ascii_case(table_name)) {
+                return Err(Error::TableExists(table_name.to_owned()).into());
+            }
+        }
+        let root = crate::btree::insert::create_table_btree(self)?;
+        let row = vec![
+            SqlValue::Text(String::from("table")),
+            SqlValue::Text(table_name.to_owned()),
+            SqlValue::Text(table_name.to_owned()),
+            SqlValue::Int(root as i64),
+            SqlValue::Text(sql.to_owned()),
+        ];
+        let schema = self.get_table_info(SCHEMA_TABLE_NAME)?;
+        self.append_row(&schema, &row)?;
+        crate::dbheader::increment_schema_cookie(self.get_page_rw(1)?);
+        Ok(root)
     }
 
+    /// inserts a row into `table`, using the next unused rowid, and returns the rowid.
+    ///
+    /// The row is not written to the file until `commit()` is called.
+    pub fn append_row(&mut self, table: &TableInfo, row: &Vec<SqlValue>) -> anyhow::Result<RowId> {
+        crate::typed_row::validate_row_for_table(table, row)?;
+        let root = table.root_pagenum;
+        if !table.indexes.is_empty() {
+            anyhow::bail!("Inserting into tables with indexes is not supported yet.");
+        }
+        let record = self.serialize_row(row)?;
+        // SQLite picks an unused rowid at random once the largest rowid is used, but that is not supported here.
+        let rowid = match crate::btree::insert::max_rowid(self, root)? {
+            None => 1,
+            Some(r) => r.checked_add(1).ok_or_else(|| Error::RowIdsExhausted(table.name.clone()))?,
+        };
+        crate::btree::insert::insert(self, root, rowid, &record)?;
+        Ok(rowid)
+    }
+
+    /// replaces the values of the row of `table` with `rowid`, returning false if there was no such row.
+    ///
+    /// The change is not written to the file until `commit()` is called.
+    pub fn update_row(&mut self, table: &TableInfo, rowid: RowId, row: &Vec<SqlValue>) -> anyhow::Result<bool> {
+        if table.name == SCHEMA_TABLE_NAME {
+            return Err(Error::ReservedTableName(table.name.clone()).into());
+        }
+        crate::typed_row::validate_row_for_table(table, row)?;
+        if !table.indexes.is_empty() {
+            anyhow::bail!("Updating tables with indexes is not supported yet.");
+        }
+        let record = self.serialize_row(row)?;
+        crate::btree::update::update(self, table.root_pagenum, rowid, &record)
+    }
+
+    /// returns the record that holds the values of `row`.
+    fn serialize_row(&self, row: &[SqlValue]) -> anyhow::Result<Vec<u8>> {
+        // Records are built from the end of the buffer, and each value takes at most its length and a 9 byte varint.
+        // Text takes at most twice as many bytes in UTF-16 as in UTF-8.
+        let max_len: usize = row
+            .iter()
+            .map(|v| match v {
+                SqlValue::Text(s) => 9 + 2 * s.len(),
+                SqlValue::Blob(b) => 9 + b.len(),
+                _ => 9 + 8,
+            })
+            .sum();
+        let mut buf = vec![0_u8; 1 + max_len];
+        let len = crate::typed_row::to_serialized(&crate::typed_row::Row { items: row.to_vec() }, &mut buf, self.encoding)?;
+        Ok(buf.split_off(buf.len() - len))
+    }
+
+    /// deletes the row of `table` with `rowid`, returning false if there was no such row.
+    ///
+    /// The change is not written to the file until `commit()` is called.
+    pub fn delete_row(&mut self, table: &TableInfo, rowid: RowId) -> anyhow::Result<bool> {
+        if table.name == SCHEMA_TABLE_NAME {
+            return Err(Error::ReservedTableName(table.name.clone()).into());
+        }
+        if !table.indexes.is_empty() {
+            anyhow::bail!("Deleting from tables with indexes is not supported yet.");
+        }
+        crate::btree::delete::delete(self, table.root_pagenum, rowid)
+    }
+
+    /// reads the committed contents of page `pn`, from the write-ahead log if it is there, or else from the file.
     fn read_page_from_file(&self, pn: PageNum) -> Result<Vec<u8>, Error> {
+        if let Some(v) = self.wal.as_ref().map(|w| w.read_page(pn)).transpose()?.flatten() {
+            self.check_checksum(pn, &v)?;
+            return Ok(v);
+        }
         let mut v = vec![0_u8; self.page_size as usize];
         self.f
             .borrow_mut()
@@ -250,126 +705,349 @@ impl StoredDb {
         self.f
             .borrow_mut()
             .read_exact(&mut v[..])
-            .map_err(Error::Io)?;
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => Error::Corrupt {
+                    page: pn,
+                    offset: 0,
+                    detail: String::from("the file ends before the end of the page"),
+                },
+                _ => Error::Io(e),
+            })?;
+        self.check_checksum(pn, &v)?;
         Ok(v)
     }
 
-    // TODO: implement transparent paging in of pages.
-    pub fn make_page_present(&mut self, pn: PageNum) -> Result<(), Error> {
-        if pn > MAX_PAGE_NUM {
-            return Err(Error::PageNumberBeyondLimits);
+    /// returns an error if checksums are on, and the checksum of page `pn` does not match its contents.
+    fn check_checksum(&self, pn: PageNum, page: &[u8]) -> Result<(), Error> {
+        match self.checksums && !crate::checksum::verify(page) {
+            true => Err(Error::ChecksumMismatch(pn)),
+            false => Ok(()),
         }
-        if !self.pages.contains_key(&pn) {
-            // println!("Reading page {} on demand.", pn);
-            let v = self.read_page_from_file(pn)?;
-            self.pages.insert(pn, v.into()).expect("Should have inserted a page.");
+    }
+
+    /// returns page `pn` for reading, reading it from the file if it is not in memory.
+    ///
+    /// The page stays in memory while the returned `PageRef` is held.  The lifetime of the `PageRef` is bounded by the
+    /// borrow of the StoredDb, so pages cannot be changed while they are being read.
+    pub fn get_page_ro(&self, pn: PageNum) -> Result<PageRef<'_>, Error> {
+        self.check_pagenum(pn)?;
+        if let Some(page) = self.pool.borrow_mut().get(pn) {
+            return Ok(page);
         }
-        assert!(self.pages.contains_key(&pn));
+        let v = self.read_page_from_file(pn)?;
+        Ok(self.pool.borrow_mut().insert(pn, v, |pn| self.dirty.contains(&pn)))
+    }
 
-        Ok(())
+    /// returns a page for modification.  The page is written to the file by the next `commit()`.
+    ///
+    /// The page stays in memory until then.
+    pub fn get_page_rw(&mut self, pn: PageNum) -> Result<&mut Vec<u8>, Error> {
+        self.check_writable()?;
+        self.check_pagenum(pn)?;
+        if self.pool.get_mut().peek(pn).is_none() {
+            let v = self.read_page_from_file(pn)?;
+            let dirty = &self.dirty;
+            self.pool.get_mut().insert(pn, v, |pn| dirty.contains(&pn));
+        }
+        self.save_original(pn);
+        self.dirty.insert(pn);
+        self.pool.get_mut().get_mut(pn).ok_or(Error::Internal)
     }
 
-    // TODO: need way to decrement count when page use is done.  Therefore caller needs to hold some object to count that.
+    fn check_writable(&self) -> Result<(), Error> {
+        match (self.read_only, self.auto_vacuum) {
+            (true, _) => Err(Error::ReadOnly),
+            (false, true) => Err(Error::AutoVacuum),
+            (false, false) => Ok(()),
+        }
+    }
 
-    // I think this says that the self object, has lifetime 'b which must be longer than the lifetime of the returned reference
-    // to the vector it contains.
-    // That is currently true, since we don't get rid of or modify pages.
-    // Once we implement writing or paging-out, we will need to provide a shorter lifetime for the
-    // Page and/or use runtime locking to ensure we don't page out or write to something
-    // that is in use.  So, the returned object (say, struct PageRef?) will need to participate in reference
-    // counting.
-    pub fn get_page_ro<'a, 'b: 'a>(&'b self, pn: PageNum) -> Result<&'a Vec<u8>, Error> {
-        if pn > MAX_PAGE_NUM {
-            return Err(Error::PageNumberBeyondLimits);
+    fn check_pagenum(&self, pn: PageNum) -> Result<(), Error> {
+        match pn == 0 || pn > self.numpages {
+            true => Err(Error::PageNumberBeyondLimits),
+            false => Ok(()),
         }
-        let maybe_page_ref = self.pages.get(&pn);
-        println!("Found: {} PageNum: {}", maybe_page_ref.is_some(), pn);
-        maybe_page_ref.ok_or(Error::Internal)
     }
 
-    // TODO: need way to decrement count when page use is done.  Therefore caller needs to hold some object to count that.
-    pub fn get_page_rw<'a, 'b: 'a>(&'b mut self, pn: PageNum) -> Result<&'a mut Vec<u8>, Error>  {
-        if self.num_open_rw_pages > 0 {
-            // At this time, we cannot atomically write multiple pages (we don't have rollbacks or a writeahead log).
-            // Therefore, it is not supported to open multiple pages in rw mode.
-            // Opening one page still allows for limited INSERT and UPDATE operations.
-            return Err(Error::TooManyPagesOpenForWrite);
+    /// returns the database header, with the changes made since the last commit.
+    pub fn header(&self) -> Result<DbfileHeader, Error> {
+        let page1 = self.get_page_ro(1)?;
+        let h: [u8; 100] = page1[..100].try_into().map_err(|_| Error::Internal)?;
+        Ok(crate::dbheader::get_header(&h)?)
+    }
+
+    /// returns the encoding of the text values in the database.
+    pub fn text_encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// returns true if page `pn` is a pointer map page.
+    ///
+    /// Databases in auto-vacuum mode have pointer map pages, which record the parent of each page, so that pages can
+    /// be moved.  The first one is page 2, and each one is followed by the pages that it has entries for.
+    pub fn is_ptrmap_page(&self, pn: PageNum) -> bool {
+        if !self.auto_vacuum || pn < 2 {
+            return false;
         }
-        self.open_rw_page = Some(pn);
-        self.num_open_rw_pages = 1;
-        if pn > MAX_PAGE_NUM {
-            return Err(Error::PageNumberBeyondLimits);
+        let pages_per_map = self.get_usable_size() as usize / 5 + 1;
+        let mut map = (pn - 2) / pages_per_map * pages_per_map + 2;
+        if self.is_lock_byte_page(map) {
+            map += 1;
         }
-        self.pages.get_mut(&pn).ok_or(Error::Internal)
+        map == pn
+    }
+
+    /// returns true if page `pn` holds the bytes at offset 1GB of the file, which SQLite uses for file locks, and so
+    /// are never used for anything else.
+    pub fn is_lock_byte_page(&self, pn: PageNum) -> bool {
+        pn == (1 << 30) / self.page_size as usize + 1
+    }
+
+    /// returns the number of bytes of each page that btrees use, which is the page size less the reserved bytes.
+    pub fn get_usable_size(&self) -> u32 {
+        self.page_size - self.reserved_bytes
     }
 
     pub fn get_page_size(&self) -> u32 {
         self.page_size
     }
 
+    /// returns the number of pages in the database, including pages allocated since the last commit.
+    pub fn get_numpages(&self) -> PageNum {
+        self.numpages
+    }
+
     // opens a table for reading.
     pub fn open_table_for_read(&self, table_name: &str) -> Result<StoredTable<'_>, Error> {
-        let root_pagenum =
-            self.get_root_pagenum(table_name).ok_or(Error::TableNameNotFound(table_name.to_owned()))?;
-        let create_statement =
-            self.get_creation_sql(table_name).ok_or(Error::TableNameNotFound(table_name.to_owned()))?;
-        let cs = crate::pt_to_ast::pt_create_statement_to_ast(&create_statement);
+        let root_pagenum = self.get_root_pagenum(table_name)?;
+        let create_statement = self.get_creation_sql(table_name)?;
+        let unsupported = |e: String| Error::Inconsistent(format!("creation SQL of table {} is not supported: {}", table_name, e));
+        let cs = crate::pt_to_ast::pt_create_statement_to_ast(&create_statement).map_err(|e| unsupported(e.to_string()))?;
+        let column_types = cs
+            .coldefs
+            .iter()
+            .map(|x| SqlType::from_str(x.coltype.as_str()).map_err(|e| unsupported(e.to_string())))
+            .collect::<Result<Vec<SqlType>, Error>>()?;
         Ok(StoredTable::new(
             self,
             cs.tablename,
             root_pagenum,
             cs.coldefs.iter().map(|x| x.colname.name.clone()).collect(),
-            cs.coldefs.iter().map(|x| SqlType::from_str(x.coltype.as_str()).unwrap()).collect(),
+            column_types,
             cs.strict,
         ))    
     }
 
     pub fn main_schema(&self) -> Result<String, Error> {
         let mut result= String::new();
-        let tt = self.open_table_for_read(SCHEMA_TABLE_NAME)
-            .map_err(|_| Error::OpeningStoredTable)?
-            .to_temp_table()
-            .map_err(|_| Error::OpeningStoredTable)?;
-        for row in tt.rows {
+        for row in self.schema_rows()? {
             result.push_str(&format!("{};", row.items[SCHEMA_TABLE_SQL_COLIDX]));
         }
         Ok(result)
     }
 
-    /// Get the SQL CREATE statement used to create `table_name`, or None.
-    pub fn get_creation_sql(&self, table_name: &str) -> Option<String> {
+    /// Get the SQL CREATE statement used to create `table_name`.
+    /// returns what writing rows to `table_name` needs to know about it, so that statements that write many rows look
+    /// it up once.
+    pub fn get_table_info(&self, table_name: &str) -> Result<TableInfo, Error> {
+        use crate::table_traits::TableMeta;
+        let table = self.open_table_for_read(table_name)?;
+        Ok(TableInfo {
+            name: table_name.to_owned(),
+            root_pagenum: self.get_root_pagenum(table_name)?,
+            column_names: table.column_names(),
+            column_types: table.column_types(),
+            strict: table.strict(),
+            indexes: self.get_indexes(table_name)?,
+        })
+    }
+
+    pub fn get_creation_sql(&self, table_name: &str) -> Result<String, Error> {
         if table_name == SCHEMA_TABLE_NAME {
-            return Some(String::from(SCHEMA_SCHEMA));
-        } else {
-            let schema_table = StoredTable::new(
-                self,
-                String::from(SCHEMA_TABLE_NAME),
-                SCHEMA_BTREE_ROOT_PAGENUM,
-                SCHEMA_TABLE_COL_NAMES.iter().map(|x| x.to_string()).collect(),
-                Vec::from(SCHEMA_TABLE_COL_TYPES),
-                true,
-            );   
-            let mut it = schema_table.streaming_iterator();
-            while let Some(row) = it.next() {
-                let this_table_name = match &row.items[SCHEMA_TABLE_TBL_NAME_COLIDX] {
-                    SqlValue::Text(s) => s.clone(),
-                    _ => panic!("Type mismatch in schema table column {}, expected Text", SCHEMA_TABLE_TBL_NAME_COLIDX),
+            return Ok(String::from(SCHEMA_SCHEMA));
+        }
+        match &self.schema_row_for_table(table_name)?.items[SCHEMA_TABLE_SQL_COLIDX] {
+            SqlValue::Text(s) => Ok(s.clone()),
+            v => Err(Error::Inconsistent(format!("creation SQL of table {} is {}, not text", table_name, v))),
+        }
+    }
+
+    /// returns the indexes of `table_name`.
+    ///
+    /// Indexes whose creation SQL is not supported, like indexes on expressions, and the indexes that SQLite creates
+    /// itself for UNIQUE and PRIMARY KEY constraints, are left out.
+    pub fn get_indexes(&self, table_name: &str) -> Result<Vec<IndexInfo>, Error> {
+        let mut indexes = vec![];
+        for row in self.schema_rows()? {
+            let is_index_of_table = matches!(&row.items[SCHEMA_TABLE_TYPE_COLIDX], SqlValue::Text(s) if s == "index")
+                && matches!(&row.items[SCHEMA_TABLE_TBL_NAME_COLIDX], SqlValue::Text(s) if s == table_name);
+            if !is_index_of_table {
+                continue;
+            }
+            let (name, root_pagenum, sql) = match (
+                &row.items[SCHEMA_TABLE_NAME_COLIDX],
+                &row.items[SCHEMA_TABLE_ROOTPAGE_COLIDX],
+                &row.items[SCHEMA_TABLE_SQL_COLIDX],
+            ) {
+                (SqlValue::Text(name), SqlValue::Int(i), SqlValue::Text(sql)) => (name, *i as PageNum, sql),
+                _ => continue,
+            };
+            if let Ok(cs) = crate::pt_to_ast::pt_create_index_statement_to_ast(sql) {
+                indexes.push(IndexInfo {
+                    name: name.clone(),
+                    root_pagenum,
+                    columns: cs.columns,
+                });
+            }
+        }
+        Ok(indexes)
+    }
+
+    /// checks the structure of the database, as SQLite's `PRAGMA integrity_check` does, and returns a description of
+    /// each problem found, so that an empty list means the database is well formed.
+    ///
+    /// Every btree is walked, checking its pages, the order of its keys, its overflow chains, and that its records
+    /// decode.  The freelist is walked too, and every page must be used exactly once, except for pointer map pages
+    /// and the lock-byte page, which are never used.
+    pub fn integrity_check(&self) -> Vec<String> {
+        use crate::table_traits::TableMeta;
+        let mut checker = crate::btree::check::Checker::new(self);
+        // The other btrees are found from the schema table, so it must be sound before they can be checked.
+        checker.check_table(SCHEMA_TABLE_NAME, SCHEMA_BTREE_ROOT_PAGENUM, &Vec::from(SCHEMA_TABLE_COL_TYPES));
+        if !checker.problems().is_empty() {
+            return checker.problems().to_vec();
+        }
+        let schema = match self.schema_rows() {
+            Ok(rows) => rows,
+            Err(e) => return vec![format!("{}: {}", SCHEMA_TABLE_NAME, e)],
+        };
+        let mut num_rows: HashMap<String, usize> = HashMap::new();
+        // Tables are checked before indexes, so that the number of entries of each index can be compared with the
+        // number of rows of its table.
+        for kind in ["table", "index"] {
+            for row in &schema {
+                let (name, tbl_name, root) = match (
+                    &row.items[SCHEMA_TABLE_TYPE_COLIDX],
+                    &row.items[SCHEMA_TABLE_NAME_COLIDX],
+                    &row.items[SCHEMA_TABLE_TBL_NAME_COLIDX],
+                    &row.items[SCHEMA_TABLE_ROOTPAGE_COLIDX],
+                ) {
+                    (SqlValue::Text(t), SqlValue::Text(name), SqlValue::Text(tbl_name), SqlValue::Int(root))
+                        if t == kind =>
+                    {
+                        (name, tbl_name, *root as PageNum)
+                    }
+                    _ => continue,
                 };
-                if this_table_name != table_name {
+                if kind == "table" {
+                    match self.open_table_for_read(name) {
+                        Ok(t) => _ = num_rows.insert(name.clone(), checker.check_table(name, root, &t.column_types())),
+                        Err(e) => checker.problem(format!("{}: {}", name, e)),
+                    }
                     continue;
                 }
-                // TODO: refactor code below to "get row element as type x or return nicely formatted Error", which can be used elsewhere too.
-                let creation_sql = match &row.items[SCHEMA_TABLE_SQL_COLIDX] {
-                    SqlValue::Text(s) => s.clone(),
-                    _ => panic!("Type mismatch in schema table column {}, expected Text", SCHEMA_TABLE_SQL_COLIDX),
-                };
-                return Some(creation_sql);
+                let num_entries = checker.check_index(name, root);
+                // Partial indexes only have entries for some rows.
+                let partial = matches!(&row.items[SCHEMA_TABLE_SQL_COLIDX], SqlValue::Text(sql) if sql.to_uppercase().contains(" WHERE "));
+                match num_rows.get(tbl_name) {
+                    Some(n) if *n != num_entries && !partial => checker.problem(format!(
+                        "Index {} has {} entries, but table {} has {} rows",
+                        name, num_entries, tbl_name, n
+                    )),
+                    _ => (),
+                }
             }
         }
-        None
+        checker.check_freelist();
+        checker.finish()
     }
-    
 
+    /// returns the index `index_name` of `table_name`.
+    pub fn get_index(&self, table_name: &str, index_name: &str) -> Result<IndexInfo, Error> {
+        self.get_indexes(table_name)?
+            .into_iter()
+            .find(|i| i.name == index_name)
+            .ok_or(Error::IndexNameNotFound(index_name.to_owned()))
+    }
+}
+
+/// returns true if `row` of the schema table describes the table `table_name`.
+fn is_schema_row_for_table(row: &crate::typed_row::Row, table_name: &str) -> bool {
+    matches!(
+        (&row.items[SCHEMA_TABLE_TYPE_COLIDX], &row.items[SCHEMA_TABLE_TBL_NAME_COLIDX]),
+        (SqlValue::Text(t), SqlValue::Text(s)) if t == "table" && s == table_name
+    )
+}
+
+/// writes `bytes` to `f` at `offset`.
+///
+/// If a test asked for a crash after fewer bytes than that, as counted by `crash_after`, only those bytes are written,
+/// as when a crash tears a write, and `Error::SimulatedCrash` is returned.
+fn write_at(crash_after: &mut Option<usize>, f: &mut std::fs::File, offset: u64, bytes: &[u8]) -> Result<(), Error> {
+    let len = crash_after.map_or(bytes.len(), |n| n.min(bytes.len()));
+    f.seek(SeekFrom::Start(offset)).map_err(Error::Io)?;
+    f.write_all(&bytes[..len]).map_err(Error::Io)?;
+    match crash_after {
+        Some(n) if *n < bytes.len() => {
+            *n = 0;
+            Err(Error::SimulatedCrash)
+        }
+        Some(n) => {
+            *n -= bytes.len();
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// A `Savepoint` marks a point in the changes made to a `StoredDb` since its last commit, which the changes can be
+/// rolled back to.
+pub struct Savepoint {
+    /// the position of the savepoint in `StoredDb::savepoints`.
+    depth: usize,
+}
+
+/// holds the pages of a `StoredDb` changed since a savepoint, as they were before they were first changed after it.
+struct SavepointPages {
+    /// the contents of each changed page, or None for a page that had not been changed since the last commit.
+    pages: HashMap<PageNum, Option<Vec<u8>>>,
+    /// the number of pages at the savepoint.
+    numpages: PageNum,
+}
+
+/// describes a stored table, as found in the schema.
+#[derive(Debug, Clone)]
+pub struct TableInfo {
+    pub name: String,
+    pub root_pagenum: PageNum,
+    pub column_names: Vec<String>,
+    pub column_types: Vec<SqlType>,
+    pub strict: bool,
+    pub indexes: Vec<IndexInfo>,
+}
+
+impl crate::table_traits::TableMeta for TableInfo {
+    fn column_names(&self) -> Vec<String> {
+        self.column_names.clone()
+    }
+    fn column_types(&self) -> Vec<SqlType> {
+        self.column_types.clone()
+    }
+    fn table_name(&self) -> String {
+        self.name.clone()
+    }
+    fn strict(&self) -> bool {
+        self.strict
+    }
+}
+
+/// describes an index of a stored table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexInfo {
+    pub name: String,
+    pub root_pagenum: PageNum,
+    /// the indexed columns, in the order that entries are sorted by.
+    pub columns: Vec<crate::ast::IndexedColumn>,
 }
 
 #[cfg(test)]
@@ -458,4 +1136,543 @@ fn test_get_creation_sql_and_root_pagenum_using_schematable_db() {
     }
 }
 
-// Testing: Borrow check fails for multiple writers or read and write as expected.  This is tested in doc comments at the top of the file.
\ No newline at end of file
+#[test]
+fn test_append_row_after_largest_rowid() {
+    let path = TestFile::new("maxrowid");
+    let mut db = StoredDb::create(&path, 512).expect("Should have created db.");
+    db.create_table("t", "CREATE TABLE t (a int)").unwrap();
+    let root = db.get_root_pagenum("t").unwrap();
+    let record = db.serialize_row(&[SqlValue::Int(1)]).unwrap();
+    crate::btree::insert::insert(&mut db, root, RowId::MAX, &record).unwrap();
+    let err = db.append_row(&db.get_table_info("t").unwrap(), &vec![SqlValue::Int(2)]).unwrap_err();
+    assert!(matches!(err.downcast_ref::<Error>(), Some(Error::RowIdsExhausted(_))), "{}", err);
+    assert_eq!(count_rows(&db, "t"), 1);
+}
+
+#[test]
+fn test_savepoints_copy_pages_when_first_changed() {
+    let path = TestFile::new("savepoints");
+    let mut db = StoredDb::create(&path, 512).expect("Should have created db.");
+    db.create_table("t", "CREATE TABLE t (a int)").unwrap();
+    db.commit().unwrap();
+    let root = db.get_root_pagenum("t").unwrap();
+
+    let t = db.get_table_info("t").unwrap();
+    let outer = db.savepoint();
+    db.append_row(&t, &vec![SqlValue::Int(1)]).unwrap();
+    let inner = db.savepoint();
+    assert!(db.savepoints[1].pages.is_empty());
+    for i in 2..=100 {
+        db.append_row(&t, &vec![SqlValue::Int(i)]).unwrap();
+    }
+    // The root was changed before the inner savepoint, so the inner savepoint copied it.
+    assert_eq!(db.savepoints[1].pages.get(&root).map(Option::is_some), Some(true));
+    db.release(&inner);
+    assert_eq!(db.savepoints.len(), 1);
+    // The outer savepoint keeps its own copy, from before any row was added.
+    assert_eq!(db.savepoints[0].pages.get(&root), Some(&None));
+    assert_eq!(count_rows(&db, "t"), 100);
+
+    let inner = db.savepoint();
+    db.append_row(&t, &vec![SqlValue::Int(101)]).unwrap();
+    db.rollback_to(&inner).unwrap();
+    assert_eq!(count_rows(&db, "t"), 100);
+    db.rollback_to(&outer).unwrap();
+    assert_eq!(count_rows(&db, "t"), 0);
+    assert_eq!(db.get_numpages(), 2);
+    assert!(db.dirty.is_empty());
+    db.release(&outer);
+    db.commit().unwrap();
+    assert!(db.savepoints.is_empty());
+}
+
+#[test]
+fn test_create_db() {
+    let path = TestFile::new("create");
+    let mut db = StoredDb::create(&path, 512).expect("Should have created db.");
+    assert_eq!(db.get_page_size(), 512);
+    assert_eq!(db.main_schema().unwrap(), "");
+    db.create_table("t", "CREATE TABLE t (a int)").unwrap();
+    db.append_row(&db.get_table_info("t").unwrap(), &vec![SqlValue::Int(3)]).unwrap();
+    db.commit().unwrap();
+
+    let db = StoredDb::open(&path).expect("Should have reopened db.");
+    let h = crate::dbheader::get_header_clone(&mut std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!((h.pagesize, h.numpages), (512, 2));
+    assert_eq!(db.get_root_pagenum("t").unwrap(), 2);
+}
+
+#[test]
+fn test_create_db_does_not_replace_a_file() {
+    let path = copy_of_testdata("minimal.db", "create-existing");
+    assert!(StoredDb::create(&path, 512).is_err());
+    assert_eq!(count_rows(&StoredDb::open(&path).unwrap(), "a"), 1);
+}
+
+#[test]
+fn test_create_db_with_bad_page_size() {
+    let path = TestFile::new("create-bad-page-size");
+    assert!(StoredDb::create(&path, 1000).is_err());
+    assert!(!std::path::Path::new(&*path).exists());
+}
+
+/// A `TestFile` is the path of a database file in the temp directory that a test can change.  The file, and its
+/// journal and WAL, are removed when it is dropped, so that a test that fails does not leave them behind.
+#[cfg(test)]
+pub(crate) struct TestFile(String);
+
+#[cfg(test)]
+impl TestFile {
+    /// returns a path in the temp directory, named for `test`, with no file at it yet.
+    pub(crate) fn new(test: &str) -> TestFile {
+        let path = std::env::temp_dir().join(format!("diydb-{}-{}.db", std::process::id(), test));
+        let f = TestFile(path.to_str().unwrap().to_owned());
+        f.remove();
+        f
+    }
+
+    fn remove(&self) {
+        for path in [self.0.clone(), crate::journal::path_for(&self.0), crate::wal::path_for(&self.0)] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestFile {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<std::path::Path> for TestFile {
+    fn as_ref(&self) -> &std::path::Path {
+        self.0.as_ref()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// copies `filename` from the test data to a new file in the temp directory, named for `test`, so that the test can
+/// change it.
+#[cfg(test)]
+pub(crate) fn copy_of_testdata(filename: &str, test: &str) -> TestFile {
+    let path = TestFile::new(&format!("{}-{}", test, filename.trim_end_matches(".db")));
+    std::fs::copy(path_to_testdata(filename), &path).expect("Should have copied test db.");
+    path
+}
+
+#[cfg(test)]
+fn count_rows(db: &StoredDb, table_name: &str) -> usize {
+    db.open_table_for_read(table_name).unwrap().to_temp_table().unwrap().rows.len()
+}
+
+// Crash injection: a commit that splits many pages is stopped after each of many numbers of bytes written, as if the
+// process had been killed there, and the file must then open with either all or none of the change.
+#[test]
+fn test_commit_is_atomic_when_crashing() {
+    let path = copy_of_testdata("minimal.db", "crash");
+    let old_count = count_rows(&StoredDb::open(&path).unwrap(), "a");
+    // commits rows added to the file at `path`, stopping after `crash_after` bytes, and returns the result of the
+    // commit and the number of bytes written.
+    let commit_rows = |path: &str, crash_after: usize| -> (Result<(), Error>, usize) {
+        let mut db = StoredDb::open(path).expect("Should have opened db.");
+        assert_eq!(count_rows(&db, "a"), old_count);
+        let a = db.get_table_info("a").unwrap();
+        for _ in 0..500 {
+            db.append_row(&a, &vec![SqlValue::Int(12345)]).unwrap();
+        }
+        assert!(db.dirty.len() > 2);
+        db.crash_after = Some(crash_after);
+        let result = db.commit();
+        (result, crash_after - db.crash_after.unwrap())
+    };
+    // A commit to a copy that is not stopped shows how many bytes a commit writes.
+    let (result, total) = commit_rows(&copy_of_testdata("minimal.db", "crash-dry-run"), usize::MAX);
+    result.unwrap();
+
+    // The crashes tear the writes of the journal header, of journal page records, and of pages of the file, and the
+    // last one leaves all pages written but the journal not yet deleted.
+    let crash_points: Vec<usize> = (0..total).step_by(509).chain([total]).collect();
+    assert!(crash_points.len() > 10);
+    for crash_after in crash_points {
+        let (result, written) = commit_rows(&path, crash_after);
+        assert!(matches!(result, Err(Error::SimulatedCrash)), "{:?}", result);
+        assert_eq!(written, crash_after);
+        assert!(std::path::Path::new(&crate::journal::path_for(&path)).exists());
+    }
+    commit_rows(&path, usize::MAX).0.unwrap();
+    let db = StoredDb::open(&path).expect("Should have opened db.");
+    assert_eq!(count_rows(&db, "a"), old_count + 500);
+    assert!(!std::path::Path::new(&crate::journal::path_for(&path)).exists());
+}
+
+#[test]
+fn test_wal_mode() {
+    let path = copy_of_testdata("wal.db", "wal");
+    let wal_path = crate::wal::path_for(&path);
+    std::fs::copy(path_to_testdata("wal.db-wal"), &wal_path).expect("Should have copied test wal.");
+    let values = |db: &StoredDb| -> Vec<String> {
+        let tt = db.open_table_for_read("t").unwrap().to_temp_table().unwrap();
+        tt.rows.iter().map(|r| format!("{}", r.items[1])).collect()
+    };
+    // The committed changes in the WAL are seen, though they are not in the file yet.
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    let old_numpages = db.get_numpages();
+    assert_eq!(values(&db).len(), 99);
+    assert_eq!(values(&db)[..3], ["one", "TWO", "row 4"]);
+    // Commits are appended to the WAL.
+    let wal_len = std::fs::metadata(&wal_path).unwrap().len();
+    let t = db.get_table_info("t").unwrap();
+    db.append_row(&t, &vec![SqlValue::Int(101), SqlValue::Text(String::from("new"))]).unwrap();
+    db.commit().unwrap();
+    assert!(std::fs::metadata(&wal_path).unwrap().len() > wal_len);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 1024);
+    let mut db = StoredDb::open(&path).expect("Should have reopened db.");
+    assert_eq!(values(&db).len(), 100);
+    assert!(db.get_numpages() >= old_numpages);
+    // A checkpoint moves the pages into the file, and the WAL starts again.
+    db.checkpoint().unwrap();
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), db.get_numpages() as u64 * 512);
+    assert_eq!(values(&db).len(), 100);
+    let t = db.get_table_info("t").unwrap();
+    db.update_row(&t, 1, &vec![SqlValue::Int(1), SqlValue::Text(String::from("ONE"))]).unwrap();
+    db.commit().unwrap();
+    let db = StoredDb::open(&path).expect("Should have reopened db.");
+    assert_eq!(values(&db)[..2], ["ONE", "TWO"]);
+    assert_eq!(values(&db).len(), 100);
+}
+
+#[test]
+fn test_memory_budget() {
+    // Scanning a table much larger than the budget keeps only a few pages in memory.
+    let mut db = StoredDb::open(&path_to_testdata("threelevel.db")).expect("Should have opened db.");
+    db.set_memory_budget(8 * db.get_page_size() as usize);
+    assert!(db.get_numpages() > 100);
+    assert_eq!(count_rows(&db, "t"), 100000);
+    assert!(db.num_pages_in_memory() <= 8);
+    assert!(db.get_page_ro(db.get_numpages() + 1).is_err());
+}
+
+#[test]
+fn test_memory_budget_keeps_changed_pages() {
+    // Changed pages stay in memory until they are committed, even beyond the budget.
+    let path = copy_of_testdata("minimal.db", "budget");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    db.set_memory_budget(db.get_page_size() as usize);
+    let a = db.get_table_info("a").unwrap();
+    for i in 0..500 {
+        db.append_row(&a, &vec![SqlValue::Int(i)]).unwrap();
+    }
+    assert!(db.num_pages_in_memory() > 1);
+    db.commit().unwrap();
+    let db = StoredDb::open(&path).expect("Should have reopened db.");
+    assert_eq!(count_rows(&db, "a"), 501);
+}
+
+#[test]
+fn test_read_overflow_pages() {
+    // Rows and index entries that are too big for a page are read from overflow pages.
+    let db = StoredDb::open(&path_to_testdata("overflow.db")).expect("Should have opened db.");
+    let tt = db.open_table_for_read("t").unwrap().to_temp_table().unwrap();
+    let text_lens: Vec<usize> = tt.rows.iter().map(|r| format!("{}", r.items[1]).len()).collect();
+    assert_eq!(text_lens, [5, 1000, 480, 1]);
+    assert_eq!(tt.rows[1].items[1], SqlValue::Text("ab".repeat(500)));
+    assert_eq!(tt.rows[3].items[2], SqlValue::Blob(vec![0; 5000]));
+    let index = db.get_index("t", "t_b").unwrap();
+    let entries: Vec<Vec<SqlValue>> = crate::btree::index::Iterator::new_from(index.root_pagenum, &db, &[])
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries[0], vec![SqlValue::Text("ab".repeat(500)), SqlValue::Int(2)]);
+}
+
+#[test]
+fn test_write_overflow_pages() {
+    // Rows that are too big for a page are written to overflow pages, which are freed when the rows are deleted.
+    let path = TestFile::new("overflow");
+    let mut db = StoredDb::create(&path, 512).expect("Should have created db.");
+    db.create_table("t", "CREATE TABLE t (a int, b text)").unwrap();
+    let text = |i: usize| SqlValue::Text(format!("{}", i).repeat(100 * i));
+    let t = db.get_table_info("t").unwrap();
+    for i in 1..20 {
+        db.append_row(&t, &vec![SqlValue::Int(i as i64), text(i)]).unwrap();
+    }
+    db.commit().unwrap();
+    let mut db = StoredDb::open(&path).expect("Should have reopened db.");
+    let tt = db.open_table_for_read("t").unwrap().to_temp_table().unwrap();
+    assert!(tt.rows.iter().enumerate().all(|(i, r)| r.items[1] == text(i + 1)));
+    let numpages = db.get_numpages();
+    let t = db.get_table_info("t").unwrap();
+    db.update_row(&t, 19, &vec![SqlValue::Int(19), text(1)]).unwrap();
+    for rowid in 10..19 {
+        db.delete_row(&t, rowid).unwrap();
+    }
+    db.commit().unwrap();
+    assert_eq!(db.get_numpages(), numpages);
+    assert!(crate::dbheader::freelist(&db.get_page_ro(1).unwrap()).1 > 30);
+    assert_eq!(count_rows(&db, "t"), 10);
+}
+
+#[test]
+fn test_utf16_text() {
+    // Text in UTF-16 databases, including the schema's SQL, is decoded when read, and encoded when written.
+    let texts = ["hello", "héllo wörld", "日本語", "😀 smile"].map(|s| SqlValue::Text(s.to_string()));
+    for (filename, encoding) in [("utf16le.db", TextEncoding::Utf16le), ("utf16be.db", TextEncoding::Utf16be)] {
+        let path = copy_of_testdata(filename, "utf16");
+        let mut db = StoredDb::open(&path).expect("Should have opened db.");
+        assert_eq!(db.text_encoding(), encoding);
+        let tt = db.open_table_for_read("t").unwrap().to_temp_table().unwrap();
+        let values: Vec<SqlValue> = tt.rows.iter().map(|r| r.items[1].clone()).collect();
+        assert_eq!(values[..4], texts);
+        let index = db.get_index("t", "t_b").unwrap();
+        let entries: Vec<Vec<SqlValue>> =
+            crate::btree::index::Iterator::new_from(index.root_pagenum, &db, &[texts[2].clone()])
+                .unwrap()
+                .take(1)
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(entries, vec![vec![texts[2].clone(), SqlValue::Int(3)]]);
+
+        db.create_table("u", "CREATE TABLE u (a text)").unwrap();
+        db.append_row(&db.get_table_info("u").unwrap(), &vec![SqlValue::Text("ünïcödé".to_string())]).unwrap();
+        db.commit().unwrap();
+        let db = StoredDb::open(&path).expect("Should have reopened db.");
+        let tt = db.open_table_for_read("u").unwrap().to_temp_table().unwrap();
+        assert_eq!(tt.rows[0].items[0], SqlValue::Text("ünïcödé".to_string()));
+    }
+}
+
+#[test]
+fn test_read_reserved_bytes() {
+    // Btrees and overflow pages use only the usable part of each page.
+    let db = StoredDb::open(&path_to_testdata("reserved.db")).expect("Should have opened db.");
+    assert_eq!((db.get_page_size(), db.get_usable_size()), (512, 480));
+    let text_len = |r: &crate::typed_row::Row| format!("{}", r.items[1]).len();
+    let tt = db.open_table_for_read("t").unwrap().to_temp_table().unwrap();
+    assert_eq!(tt.rows.len(), 240);
+    assert_eq!(tt.rows.iter().map(text_len).sum::<usize>(), 72960);
+    let index = db.get_index("t", "t_b").unwrap();
+    assert_eq!(crate::btree::index::Iterator::new_from(index.root_pagenum, &db, &[]).unwrap().count(), 240);
+}
+
+#[test]
+fn test_write_reserved_bytes() {
+    // Pages written to a file with reserved bytes leave the reserved bytes at their ends alone.
+    let path = copy_of_testdata("reserved.db", "reserved");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    assert!(db.set_checksums(true).is_err());
+    db.create_table("u", "CREATE TABLE u (a int, b text)").unwrap();
+    let text = |i: usize| SqlValue::Text(format!("{}", i % 10).repeat(50 * i));
+    let u = db.get_table_info("u").unwrap();
+    for i in 1..40 {
+        db.append_row(&u, &vec![SqlValue::Int(i as i64), text(i)]).unwrap();
+    }
+    db.commit().unwrap();
+    let db = StoredDb::open(&path).expect("Should have reopened db.");
+    let tt = db.open_table_for_read("u").unwrap().to_temp_table().unwrap();
+    assert!(tt.rows.iter().enumerate().all(|(i, r)| r.items[1] == text(i + 1)));
+    for pn in 1..=db.get_numpages() {
+        assert_eq!(db.get_page_ro(pn).unwrap()[480..], [0; 32]);
+    }
+}
+
+#[test]
+fn test_checksums() {
+    // Checksums written by the checksum VFS are checked when pages are read, and set when pages are written.
+    let path = copy_of_testdata("checksums.db", "checksums");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    db.set_checksums(true).unwrap();
+    assert_eq!(count_rows(&db, "t"), 100);
+    let t = db.get_table_info("t").unwrap();
+    for i in 101..200 {
+        db.append_row(&t, &vec![SqlValue::Int(i), SqlValue::Text(format!("row {}", i))]).unwrap();
+    }
+    db.commit().unwrap();
+    let mut db = StoredDb::open(&path).expect("Should have reopened db.");
+    db.set_checksums(true).unwrap();
+    assert_eq!(count_rows(&db, "t"), 199);
+    assert!((1..=db.get_numpages()).all(|pn| crate::checksum::verify(&db.get_page_ro(pn).unwrap())));
+}
+
+#[test]
+fn test_checksum_mismatch() {
+    // A page that does not match its checksum is an error to read.
+    let path = copy_of_testdata("checksums.db", "checksum-mismatch");
+    let pn = StoredDb::open(&path).unwrap().get_root_pagenum("t").unwrap();
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[(pn - 1) * 512 + 300] ^= 0x01;
+    std::fs::write(&path, &bytes).unwrap();
+    let mut db = StoredDb::open(&path).expect("Should have reopened db.");
+    db.set_checksums(true).unwrap();
+    assert!(matches!(db.get_page_ro(pn), Err(Error::ChecksumMismatch(p)) if p == pn));
+    db.set_checksums(false).unwrap();
+    assert!(db.get_page_ro(pn).is_ok());
+}
+
+#[test]
+fn test_integrity_check() {
+    // Every test database, as written by SQLite, is well formed.
+    let mut filenames: Vec<String> = std::fs::read_dir(path_to_testdata(""))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|f| f.ends_with(".db"))
+        .collect();
+    filenames.sort();
+    assert!(filenames.len() > 10);
+    for filename in &filenames {
+        let db = StoredDb::open(&path_to_testdata(filename)).expect("Should have opened db.");
+        assert_eq!(db.integrity_check(), Vec::<String>::new(), "{}", filename);
+    }
+}
+
+#[test]
+fn test_integrity_check_after_changes() {
+    // A file is still well formed after rows are deleted and updated here.
+    let path = copy_of_testdata("multipage-512B-page.db", "integrity");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    let thousandrows = db.get_table_info("thousandrows").unwrap();
+    for rowid in (1..1000).filter(|r| r % 3 != 0) {
+        db.delete_row(&thousandrows, rowid).unwrap();
+    }
+    let row = vec![SqlValue::Int(1), SqlValue::Text("x".repeat(2000)), SqlValue::Null()];
+    db.update_row(&thousandrows, 999, &row).unwrap();
+    assert_eq!(db.integrity_check(), Vec::<String>::new());
+    db.commit().unwrap();
+    assert_eq!(StoredDb::open(&path).unwrap().integrity_check(), Vec::<String>::new());
+}
+
+#[test]
+fn test_header_fields() {
+    // A file with a freelist, a user version, an application ID and a default cache size can be read and changed.
+    let path = copy_of_testdata("header_fields.db", "header_fields");
+    let mut db = StoredDb::open(&path).expect("Should have opened db.");
+    let h = db.header().unwrap();
+    assert_eq!((h.user_version, h.application_id, h.default_cache_size), (7, 0x64696462, 100));
+    assert_eq!((h.freelist_trunk, h.freelist_count), (5, 7));
+    assert_eq!(count_rows(&db, "t"), 20);
+    let numpages = db.get_numpages();
+    let t = db.get_table_info("t").unwrap();
+    for i in 21..100 {
+        db.append_row(&t, &vec![SqlValue::Int(i), SqlValue::Text(format!("row {}", i))]).unwrap();
+    }
+    db.commit().unwrap();
+    assert_eq!(db.get_numpages(), numpages);
+    let db = StoredDb::open(&path).expect("Should have reopened db.");
+    let h = db.header().unwrap();
+    assert_eq!((h.user_version, h.application_id), (7, 0x64696462));
+    assert!(h.freelist_count < 7);
+    assert_eq!(count_rows(&db, "t"), 99);
+}
+
+#[test]
+fn test_incremental_vacuum_file_is_read_only() {
+    // A file in incremental-vacuum mode can be read, but not changed, since its pointer map pages would be wrong.
+    let mut db = StoredDb::open(&path_to_testdata("autovacuum.db")).expect("Should have opened db.");
+    let h = db.header().unwrap();
+    assert_eq!((h.largest_root_btree_page, h.incremental_vacuum, h.freelist_count), (4, true, 2));
+    assert_eq!(count_rows(&db, "t"), 250);
+    assert_eq!(count_rows(&db, "u"), 2);
+    assert!(db.is_ptrmap_page(2));
+    assert!(!db.is_ptrmap_page(3));
+    assert!(db.is_ptrmap_page(2 + 512 / 5 + 1));
+    assert!(db.append_row(&db.get_table_info("u").unwrap(), &vec![SqlValue::Int(3)]).is_err());
+}
+
+// Testing: Borrow check fails for multiple writers or read and write as expected.  This is tested in doc comments at the top of the file.
+#[test]
+fn test_get_indexes() {
+    let path = path_to_testdata("indexes.db");
+    let db = StoredDb::open(path.as_str()).expect("Should have opened db.");
+    // The schema table rows for the indexes of `people` don't hide the row for the table.
+    assert_eq!(db.get_root_pagenum("people").unwrap(), 2);
+    let indexes = db.get_indexes("people").unwrap();
+    let names: Vec<&str> = indexes.iter().map(|i| i.name.as_str()).collect();
+    assert_eq!(names, vec!["people_age", "people_city_age"]);
+    let index = db.get_index("people", "people_city_age").expect("Should have found index.");
+    let colnames: Vec<&str> = index.columns.iter().map(|c| c.colname.as_str()).collect();
+    assert_eq!(colnames, vec!["city", "age"]);
+    assert!(db.get_indexes("nosuchtable").unwrap().is_empty());
+    assert!(db.get_index("people", "nosuchindex").is_err());
+}
+
+// multipage.db has 4k pages.  The "thousandrows" table has its root, an interior page, on page 3, and leaves on pages 4
+// to 6.  These return the error from reading the table after changing the bytes of a copy with `f`.
+#[cfg(test)]
+fn corrupt_and_read(test: &str, f: &dyn Fn(&mut Vec<u8>)) -> Error {
+    let path = copy_of_testdata("multipage.db", test);
+    let mut bytes = std::fs::read(&path).unwrap();
+    f(&mut bytes);
+    std::fs::write(&path, bytes).unwrap();
+    let db = StoredDb::open(&path).expect("Should have opened db.");
+    let err = db.open_table_for_read("thousandrows").unwrap().to_temp_table().unwrap_err();
+    let crate::stored_table::Error::Read(err) = err;
+    err
+}
+
+#[cfg(test)]
+fn multipage_page_offset(pgnum: usize) -> usize {
+    (pgnum - 1) * 4096
+}
+
+#[test]
+fn test_corrupt_page_type() {
+    let err = corrupt_and_read("corrupt-type", &|b| b[multipage_page_offset(5)] = 0x07);
+    assert!(matches!(err, Error::Corrupt { page: 5, offset: 0, .. }), "{}", err);
+}
+
+#[test]
+fn test_corrupt_cell_pointer() {
+    // The first cell pointer of a leaf points past the end of the page.
+    let err = corrupt_and_read("corrupt-cellptr", &|b| {
+        let p = multipage_page_offset(4);
+        b[p + 8..p + 10].copy_from_slice(&[0xff, 0xf0]);
+    });
+    assert!(matches!(err, Error::Corrupt { page: 4, offset: 8, .. }), "{}", err);
+}
+
+#[test]
+fn test_corrupt_child_pointer() {
+    // The rightmost child of the root is not a page of the database.
+    let err = corrupt_and_read("corrupt-child", &|b| {
+        let p = multipage_page_offset(3);
+        b[p + 8..p + 12].copy_from_slice(&[0, 0, 0x10, 0]);
+    });
+    assert!(matches!(err, Error::Corrupt { page: 3, offset: 8, .. }), "{}", err);
+}
+
+#[test]
+fn test_corrupt_record() {
+    // The first column of row 1 has serial type 10, which is reserved.
+    let err = corrupt_and_read("corrupt-record", &|b| {
+        let p = multipage_page_offset(4);
+        let cell = p + u16::from_be_bytes([b[p + 8], b[p + 9]]) as usize;
+        // The payload length, rowid and record header length are one byte varints.
+        b[cell + 3] = 10;
+    });
+    assert!(matches!(err, Error::Row { rowid: 1, .. }), "{}", err);
+}
+
+#[test]
+fn test_truncated_file() {
+    // A file cut short in the middle of the table.
+    let err = corrupt_and_read("corrupt-truncated", &|b| b.truncate(multipage_page_offset(5) + 100));
+    assert!(matches!(err, Error::Corrupt { page: 5, offset: 0, .. }), "{}", err);
+}
+
+#[test]
+fn test_stored_db_is_send() {
+    // A StoredDb can be moved to another thread, though it is only used by one thread at a time.
+    fn assert_send<T: Send>() {}
+    assert_send::<StoredDb>();
+}
@@ -3,10 +3,16 @@
 //! Currently, only reading is supported.
 //! A subset of the SQLite file format is supported.
 
-use crate::table_traits::TableMeta;
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use crate::btree::index::compare_prefix;
+use crate::btree::RowId;
+use crate::table_traits::{RowStream, TableMeta};
 use crate::typed_row::Row;
-use crate::stored_db;
+use crate::stored_db::{self, TextEncoding};
 use crate::sql_type::SqlType;
+use crate::sql_value::{collate_in, SqlValue};
 use streaming_iterator::StreamingIterator;
 
 pub struct StoredTable<'a> {
@@ -18,34 +24,66 @@ pub struct StoredTable<'a> {
     strict: bool,
 }
 
-#[derive(thiserror::Error, Debug, Clone)]
+#[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("While converting persistent table to a temporary table, type casting failure.")]
-    CastingError,
+    #[error("Error reading stored table: {0}")]
+    Read(#[from] stored_db::Error),
+}
+
+/// returns the row of `table` with `rowid`, decoded from `record`, with the rowid appended as an extra column if
+/// `with_rowid` is true.
+fn decode_row(
+    table: &str,
+    column_types: &Vec<SqlType>,
+    encoding: TextEncoding,
+    rowid: RowId,
+    record: &[u8],
+    with_rowid: bool,
+) -> Result<Row, stored_db::Error> {
+    let mut row = crate::typed_row::from_serialized(column_types, record, encoding)
+        .map_err(|detail| stored_db::Error::Row { table: table.to_owned(), rowid, detail })?;
+    if with_rowid {
+        row.items.push(SqlValue::Int(rowid));
+    }
+    Ok(row)
 }
 
 /// iterates over the rows of a TempTable .
 /// The lifetime 'p is the lifetime of the pager used in the table::Iterator.
 ///
+/// A row that cannot be read, because the btree or the row is corrupt, ends the stream, and the error is available
+/// from `take_error()`.
 pub struct TableStreamingIterator<'p> {
     // Implementation note: Tried by could not get streaming_iterator::Convert
     // to work: because inscrutible compiler error when used with a non-default lifetime.
     // Also, we want to convert from raw data to typed data in the process.
     it: crate::btree::table::Iterator<'p>,
+    table_name: String,
     column_types: Vec<SqlType>,
-    raw_item: Option<<crate::btree::table::Iterator<'p> as IntoIterator>::Item>,
+    encoding: TextEncoding,
+    /// if true, the rowid is appended to each row as an extra column.
+    with_rowid: bool,
+    done: bool,
     item: Option<Row>,
+    error: Option<stored_db::Error>,
 }
 impl<'p> TableStreamingIterator<'p> {
     fn new(
         it: crate::btree::table::Iterator<'p>,
+        table_name: String,
         column_types: Vec<SqlType>,
+        encoding: TextEncoding,
+        with_rowid: bool,
     ) -> TableStreamingIterator<'p> {
         TableStreamingIterator {
             it,
+            table_name,
             column_types,
-            raw_item: None,
+            encoding,
+            with_rowid,
+            done: false,
             item: None,
+            error: None,
         }
     }
 }
@@ -55,14 +93,21 @@ impl<'p> StreamingIterator for TableStreamingIterator<'p> {
 
     #[inline]
     fn advance(&mut self) {
-        self.raw_item = self.it.next();
-        self.item = match self.raw_item {
-            None => None,
-            Some(raw) => Some(
-                crate::typed_row::from_serialized(&self.column_types, raw.1)
-                    .expect("Should have cast the row."),
-            ), // TODO: pass through errors?
-        }
+        let raw = match self.done {
+            true => None,
+            false => self.it.next(),
+        };
+        let result = raw.transpose().and_then(|raw| {
+            raw.map(|(rowid, record)| {
+                decode_row(&self.table_name, &self.column_types, self.encoding, rowid, &record, self.with_rowid)
+            })
+            .transpose()
+        });
+        self.item = result.unwrap_or_else(|e| {
+            self.error = Some(e);
+            None
+        });
+        self.done = self.item.is_none();
     }
 
     #[inline]
@@ -71,6 +116,114 @@ impl<'p> StreamingIterator for TableStreamingIterator<'p> {
     }
 }
 
+impl<'p> RowStream for TableStreamingIterator<'p> {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take().map(anyhow::Error::from)
+    }
+}
+
+/// iterates over the rows of a StoredTable which are found by searching one of its indexes.
+///
+/// As for `TableStreamingIterator`, an error ends the stream.
+pub struct IndexStreamingIterator<'p> {
+    entries: crate::btree::index::Iterator<'p>,
+    pager: &'p stored_db::StoredDb,
+    table_name: String,
+    root_pagenum: stored_db::PageNum,
+    column_types: Vec<SqlType>,
+    /// the values that the first indexed columns of each row are equal to.
+    eq: Vec<SqlValue>,
+    /// the bounds of the next indexed column, if it is limited to a range.
+    range: Option<(Bound<SqlValue>, Bound<SqlValue>)>,
+    /// if true, the rowid is appended to each row as an extra column.
+    with_rowid: bool,
+    done: bool,
+    item: Option<Row>,
+    error: Option<stored_db::Error>,
+}
+
+impl<'p> IndexStreamingIterator<'p> {
+    /// returns the rowid of the next index entry that is in the range, if any.
+    fn next_rowid(&mut self) -> Result<Option<RowId>, stored_db::Error> {
+        for entry in self.entries.by_ref() {
+            let entry = entry?;
+            // Entries are in order, and the search started at the first one that could be in the range, so the first
+            // entry that is past the end of the range ends the search.
+            if compare_prefix(&entry, &self.eq, self.pager.text_encoding()) != Ordering::Equal {
+                return Ok(None);
+            }
+            if let Some((lower, upper)) = &self.range {
+                let v = &entry[self.eq.len()];
+                let encoding = self.pager.text_encoding();
+                let above_lower = match lower {
+                    Bound::Included(l) => collate_in(v, l, encoding) != Ordering::Less,
+                    Bound::Excluded(l) => collate_in(v, l, encoding) == Ordering::Greater,
+                    Bound::Unbounded => true,
+                };
+                if matches!(v, SqlValue::Null()) || !above_lower {
+                    continue;
+                }
+                let below_upper = match upper {
+                    Bound::Included(u) => collate_in(v, u, encoding) != Ordering::Greater,
+                    Bound::Excluded(u) => collate_in(v, u, encoding) == Ordering::Less,
+                    Bound::Unbounded => true,
+                };
+                if !below_upper {
+                    return Ok(None);
+                }
+            }
+            // The index iterator only returns entries that end with a rowid.
+            match entry.last() {
+                Some(SqlValue::Int(rowid)) => return Ok(Some(*rowid)),
+                _ => return Err(stored_db::Error::Internal),
+            }
+        }
+        Ok(None)
+    }
+
+    /// returns the next row found by searching the index, if any.
+    fn next_row(&mut self) -> Result<Option<Row>, stored_db::Error> {
+        let rowid = match self.next_rowid()? {
+            None => return Ok(None),
+            Some(rowid) => rowid,
+        };
+        let (_, record) = crate::btree::table::Iterator::new_rowid_range(self.root_pagenum, self.pager, rowid, rowid)
+            .next()
+            .transpose()?
+            .ok_or_else(|| {
+                stored_db::Error::Inconsistent(format!("an index of table {} has an entry for missing row {}", self.table_name, rowid))
+            })?;
+        let encoding = self.pager.text_encoding();
+        decode_row(&self.table_name, &self.column_types, encoding, rowid, &record, self.with_rowid).map(Some)
+    }
+}
+
+impl<'p> StreamingIterator for IndexStreamingIterator<'p> {
+    type Item = Row;
+
+    fn advance(&mut self) {
+        let result = match self.done {
+            true => Ok(None),
+            false => self.next_row(),
+        };
+        self.item = result.unwrap_or_else(|e| {
+            self.error = Some(e);
+            None
+        });
+        self.done = self.item.is_none();
+    }
+
+    fn get(&self) -> Option<&Self::Item> {
+        self.item.as_ref()
+    }
+}
+
+impl<'p> RowStream for IndexStreamingIterator<'p> {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take().map(anyhow::Error::from)
+    }
+}
+
 impl<'a> TableMeta for StoredTable<'a> {
     fn column_names(&self) -> Vec<String> {
         self.column_names.clone()
@@ -108,24 +261,71 @@ impl<'a> StoredTable<'a> {
         }
     }
     
-    pub fn streaming_iterator(&'a self) -> TableStreamingIterator<'a> {
-        TableStreamingIterator::new(self.iter(), self.column_types())
+    /// iterates over the rows of the table.
+    ///
+    /// The iterator borrows the database, not this `StoredTable`, so it may outlive the `StoredTable`.
+    pub fn streaming_iterator(&self) -> TableStreamingIterator<'a> {
+        TableStreamingIterator::new(self.iter(), self.table_name(), self.column_types(), self.pager.text_encoding(), false)
+    }
+
+    /// iterates over the rows of the table whose rowids are between `min_rowid` and `max_rowid`, inclusive, with the
+    /// rowid appended to each row as an extra column.
+    ///
+    /// The btree is searched for `min_rowid`, so only the pages holding the range are read.
+    pub fn rowid_range_streaming_iterator(&self, min_rowid: RowId, max_rowid: RowId) -> TableStreamingIterator<'a> {
+        TableStreamingIterator::new(
+            crate::btree::table::Iterator::new_rowid_range(self.root_pagenum, self.pager, min_rowid, max_rowid),
+            self.table_name(),
+            self.column_types(),
+            self.pager.text_encoding(),
+            true,
+        )
+    }
+
+    /// iterates over the rows of the table whose entries in the index with root page `index_root_pagenum` start with
+    /// the values `eq`, and then, if `range` is given, have a value that is not NULL and is within the bounds of `range`.
+    /// If `with_rowid` is true, the rowid is appended to each row as an extra column.
+    ///
+    /// The index is searched for the first entry that could match, so only the pages holding the matching entries, and
+    /// the pages of the table holding their rows, are read.  An error is returned if the pages on the way to the first
+    /// entry are corrupt.
+    pub fn index_streaming_iterator(
+        &self,
+        index_root_pagenum: stored_db::PageNum,
+        eq: Vec<SqlValue>,
+        range: Option<(Bound<SqlValue>, Bound<SqlValue>)>,
+        with_rowid: bool,
+    ) -> Result<IndexStreamingIterator<'a>, stored_db::Error> {
+        let mut start = eq.clone();
+        if let Some((Bound::Included(l) | Bound::Excluded(l), _)) = &range {
+            start.push(l.clone());
+        }
+        Ok(IndexStreamingIterator {
+            entries: crate::btree::index::Iterator::new_from(index_root_pagenum, self.pager, &start)?,
+            pager: self.pager,
+            table_name: self.table_name(),
+            root_pagenum: self.root_pagenum,
+            column_types: self.column_types(),
+            eq,
+            range,
+            with_rowid,
+            done: false,
+            item: None,
+            error: None,
+        })
     }
 
     // TODO: hide this internal type using an impl Iterator or a simple wrapper?
-    fn iter(&self) -> crate::btree::table::Iterator {
+    fn iter(&self) -> crate::btree::table::Iterator<'a> {
         crate::btree::table::Iterator::new(self.root_pagenum, self.pager)
     }
 
     pub fn to_temp_table(&self) -> core::result::Result<crate::TempTable, Error> {
         let mut rows: Vec<Row> = vec![];
-        let mut it = self.iter();
-        while let Some((_rowid, serialized_row)) = it.next() {
-            if let Ok(row) = crate::typed_row::from_serialized(&self.column_types, serialized_row) {
-                rows.push(row.clone());
-            } else {
-                return Err(Error::CastingError)
-            }
+        for item in self.iter() {
+            let (rowid, serialized_row) = item?;
+            let encoding = self.pager.text_encoding();
+            rows.push(decode_row(&self.table_name, &self.column_types, encoding, rowid, &serialized_row, false)?);
         }
         Ok(crate::TempTable {
             // TODO: take() a limited number of rows when collect()ing them, and return error if they don't fit?
@@ -165,4 +365,47 @@ fn test_table() {
     );
     it.advance();
     assert_eq!(it.get(), None);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_rowid_range_streaming_iterator() {
+    use crate::sql_value::SqlValue::*;
+    let path = path_to_testdata("multipage.db");
+    let db = crate::stored_db::StoredDb::open(path.as_str()).expect("Should have opened db.");
+    let tbl = db.open_table_for_read("thousandrows").expect("Should have opened table.");
+    let mut it = tbl.rowid_range_streaming_iterator(351, 352);
+    let mut rowids = vec![];
+    while let Some(row) = it.next() {
+        assert_eq!(row.items.len(), 4);
+        rowids.push(row.items[3].clone());
+    }
+    assert_eq!(rowids, vec![Int(351), Int(352)]);
+}
+#[test]
+fn test_index_streaming_iterator() {
+    use crate::sql_value::SqlValue::*;
+    let path = path_to_testdata("indexes.db");
+    let db = crate::stored_db::StoredDb::open(path.as_str()).expect("Should have opened db.");
+    let tbl = db.open_table_for_read("people").expect("Should have opened table.");
+    let index = db.get_index("people", "people_city_age").expect("Should have found index.");
+    let rows = |eq: Vec<SqlValue>, range: Option<(Bound<SqlValue>, Bound<SqlValue>)>| {
+        let mut it = tbl.index_streaming_iterator(index.root_pagenum, eq, range, true).unwrap();
+        let mut rows = vec![];
+        while let Some(row) = it.next() {
+            rows.push(row.clone());
+        }
+        rows
+    };
+    let austin = || vec![Text(String::from("Austin"))];
+    assert_eq!(rows(austin(), None).len(), 200);
+    // Rows with a NULL age are not in any range.
+    assert_eq!(rows(austin(), Some((Bound::Unbounded, Bound::Unbounded))).len(), 180);
+    let found = rows(austin(), Some((Bound::Excluded(Int(30)), Bound::Included(Int(40)))));
+    assert!(!found.is_empty());
+    for row in found {
+        assert_eq!(row.items.len(), 4);
+        assert_eq!(row.items[2], Text(String::from("Austin")));
+        assert!(matches!(row.items[1], Int(age) if age > 30 && age <= 40));
+    }
+    assert!(rows(vec![Text(String::from("Zurich"))], None).is_empty());
+}
@@ -1,6 +1,8 @@
 //! Traits common to Table and TempTable.
 
 use crate::sql_type::SqlType;
+use crate::typed_row::Row;
+use streaming_iterator::StreamingIterator;
 
 pub trait TableMeta {
     /// Names of each column, excluding the table name. 
@@ -28,4 +30,12 @@ pub trait TableMeta {
     fn strict(&self) -> bool;
 
     // TODO: add "creation_sql()" as a default method.
-}
\ No newline at end of file
+}
+/// A `RowStream` is a streaming iterator over rows, such as the rows of a table, or the rows produced by an IR block.
+///
+/// Producing a row can fail part way through a stream, for example when an expression cannot be evaluated on some row.
+/// When that happens, the stream ends early, and the cause is available from `take_error()`.
+pub trait RowStream: StreamingIterator<Item = Row> {
+    /// Returns the error that ended the stream early, if any.
+    fn take_error(&mut self) -> Option<anyhow::Error>;
+}
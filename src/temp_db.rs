@@ -14,11 +14,14 @@ use std::collections::HashMap;
 
 use crate::temp_table::TempTable;
 use crate::sql_type::SqlType;
+use crate::sql_value::SqlValue;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Table name not found.")]
     TableNameNotFound,
+    #[error(transparent)]
+    Append(#[from] crate::temp_table::Error),
 }
 
 /// A `TempDb` holds one temporary database.
@@ -31,6 +34,24 @@ pub enum Error {
 ///
 pub struct TempDb {
     tables: HashMap<String, crate::temp_table::TempTable>, 
+    /// for each savepoint, innermost last, how to restore the tables changed since it.
+    savepoints: Vec<HashMap<String, Original>>,
+}
+
+/// records what a table was when a savepoint started, before it was first changed after it.
+enum Original {
+    /// the table did not exist.
+    Missing,
+    /// the table had this many rows, and rows have only been appended to it since.
+    Rows(usize),
+    /// the table, copied before it was changed other than by appending.
+    Table(TempTable),
+}
+
+/// A `Savepoint` marks a point in the changes made to a `TempDb`, which the changes can be rolled back to.
+pub struct Savepoint {
+    /// the position of the savepoint in `TempDb::savepoints`.
+    depth: usize,
 }
 
 impl TempDb {
@@ -38,9 +59,11 @@ impl TempDb {
     pub fn new() -> Self {
         TempDb { 
             tables: HashMap::new(), 
+            savepoints: vec![],
         }
     }
     pub fn new_temp_table(&mut self, table_name: String, column_names: Vec<String>, column_types: Vec<SqlType>, strict: bool) -> Result<(), Error> {
+        self.save_original(&table_name);
         self.tables.insert(
             table_name.clone(),
             TempTable {
@@ -59,9 +82,111 @@ impl TempDb {
     }
 
     pub fn get_table_mut(&mut self, tablename: &String) -> Result<&mut crate::temp_table::TempTable, Error> {
+        self.save_original(tablename);
         self.tables.get_mut(tablename).ok_or(Error::TableNameNotFound)
     }
 
+    /// appends `row` to table `tablename`.
+    ///
+    /// Savepoints only record how many rows the table had, so that appending does not copy the table.
+    pub fn append_row(&mut self, tablename: &String, row: &Vec<SqlValue>) -> Result<(), Error> {
+        let tbl = self.tables.get_mut(tablename).ok_or(Error::TableNameNotFound)?;
+        if let Some(sp) = self.savepoints.last_mut() {
+            sp.entry(tablename.clone()).or_insert(Original::Rows(tbl.rows.len()));
+        }
+        Ok(tbl.append_row(row)?)
+    }
+
+    /// starts a savepoint, so that `rollback_to()` can discard the changes made after it.
+    ///
+    /// A table is copied when it is first changed after the savepoint, other than by appending rows, rather than when
+    /// the savepoint starts.
+    pub fn savepoint(&mut self) -> Savepoint {
+        self.savepoints.push(HashMap::new());
+        Savepoint { depth: self.savepoints.len() - 1 }
+    }
+
+    /// discards the changes made since `savepoint`, keeping the changes made before it.
+    ///
+    /// The savepoint stays, so that later changes can be rolled back to it too, while the savepoints started after
+    /// it end.
+    pub fn rollback_to(&mut self, savepoint: &Savepoint) {
+        if savepoint.depth >= self.savepoints.len() {
+            return;
+        }
+        for sp in self.savepoints.drain(savepoint.depth..).rev() {
+            for (name, original) in sp {
+                match original {
+                    Original::Missing => {
+                        self.tables.remove(&name);
+                    }
+                    Original::Rows(n) => {
+                        if let Some(t) = self.tables.get_mut(&name) {
+                            t.rows.truncate(n);
+                        }
+                    }
+                    Original::Table(t) => {
+                        self.tables.insert(name, t);
+                    }
+                }
+            }
+        }
+        self.savepoints.push(HashMap::new());
+    }
+
+    /// ends `savepoint`, and the savepoints started after it, keeping their changes.
+    pub fn release(&mut self, savepoint: &Savepoint) {
+        if savepoint.depth >= self.savepoints.len() {
+            return;
+        }
+        let released = self.savepoints.split_off(savepoint.depth);
+        if let Some(outer) = self.savepoints.last_mut() {
+            // The outer savepoint keeps what each table was earliest.  A table that only had rows appended before the
+            // released savepoints, and was changed otherwise after them, is restored from their copy, without the rows
+            // appended since the outer savepoint.
+            for sp in released {
+                for (name, original) in sp {
+                    match (outer.get(&name), original) {
+                        (None, original) => {
+                            outer.insert(name, original);
+                        }
+                        (Some(Original::Rows(n)), Original::Table(mut t)) => {
+                            t.rows.truncate(*n);
+                            outer.insert(name, Original::Table(t));
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+
+    /// ends all savepoints, keeping the changes, since they can no longer be rolled back.
+    pub fn commit(&mut self) {
+        self.savepoints.clear();
+    }
+
+    /// copies table `name` for the innermost savepoint, before it is changed, if it has not been changed since the
+    /// savepoint started, or has only had rows appended.
+    fn save_original(&mut self, name: &str) {
+        let Some(sp) = self.savepoints.last_mut() else {
+            return;
+        };
+        let original = match (sp.get(name), self.tables.get(name)) {
+            (None, None) => Original::Missing,
+            (None, Some(t)) => Original::Table(t.clone()),
+            (Some(Original::Rows(n)), Some(t)) => Original::Table(TempTable {
+                rows: t.rows[..*n].to_vec(),
+                table_name: t.table_name.clone(),
+                column_names: t.column_names.clone(),
+                column_types: t.column_types.clone(),
+                strict: t.strict,
+            }),
+            (Some(_), _) => return,
+        };
+        sp.insert(name.to_owned(), original);
+    }
+
     // TODO: make "schema" part of db_traits::DBMeta.
     pub fn temp_schema(&self) -> Result<String, Error> {
         let mut result= String::new();
@@ -75,4 +200,30 @@ impl TempDb {
     pub fn get_creation_sql(&self, table_name: &str) -> Option<String> {
         self.tables.get(table_name).map(|t| t.creation_sql())
     }
-} 
\ No newline at end of file
+} 
+
+#[test]
+fn test_rollback_to_savepoints() {
+    let rows = |db: &TempDb| -> Vec<SqlValue> { db.get_table(&"t".to_string()).unwrap().rows.iter().map(|r| r.items[0].clone()).collect() };
+    let name = "t".to_string();
+    let mut db = TempDb::new();
+    let outer = db.savepoint();
+    db.new_temp_table(name.clone(), vec!["a".to_string()], vec![SqlType::Int], false).unwrap();
+    db.append_row(&name, &vec![SqlValue::Int(1)]).unwrap();
+    let middle = db.savepoint();
+    db.append_row(&name, &vec![SqlValue::Int(2)]).unwrap();
+    let inner = db.savepoint();
+    db.get_table_mut(&name).unwrap().rows.clear();
+    db.append_row(&name, &vec![SqlValue::Int(3)]).unwrap();
+    assert_eq!(rows(&db), vec![SqlValue::Int(3)]);
+
+    // The inner savepoint's copy replaces the middle savepoint's row count, but keeps only the rows it counted.
+    db.release(&inner);
+    db.rollback_to(&middle);
+    assert_eq!(rows(&db), vec![SqlValue::Int(1)]);
+    db.append_row(&name, &vec![SqlValue::Int(4)]).unwrap();
+    db.rollback_to(&middle);
+    assert_eq!(rows(&db), vec![SqlValue::Int(1)]);
+    db.rollback_to(&outer);
+    assert!(db.get_table(&name).is_err());
+}
@@ -13,7 +13,7 @@
 //! The assumption here is that the caller is an interactive user who wants a limited number of rows (thousands).
 //! For non-interactive bulk use, perhaps this needs to be revisted.
 
-use crate::table_traits::TableMeta;
+use crate::table_traits::{RowStream, TableMeta};
 use crate::typed_row::Row;
 use crate::sql_type::SqlType;
 use crate::sql_value::SqlValue;
@@ -52,7 +52,7 @@ impl TableMeta for TempTable {
 }
 
 impl TempTable {
-    pub fn streaming_iterator(&self) -> TempTableStreamingIterator {
+    pub fn streaming_iterator(&self) -> TempTableStreamingIterator<'_> {
         // Could not get streaming_iterator::convert or streaming_iterator::convert_ref to work here.
         TempTableStreamingIterator::new(self.rows.iter())
     }
@@ -65,6 +65,51 @@ impl TempTable {
         Ok(())
     }
 
+    /// deletes the rows for which `predicate` is true, or every row if there is no predicate, and returns how many
+    /// were deleted.
+    ///
+    /// The predicate is evaluated on every row before any are deleted, so an error leaves the table unchanged.
+    pub fn delete_rows(&mut self, predicate: Option<&crate::eval::BoundExpr>) -> anyhow::Result<usize> {
+        let mut keep = Vec::with_capacity(self.rows.len());
+        for row in &self.rows {
+            keep.push(match predicate {
+                None => false,
+                Some(e) => crate::eval::to_bool(&e.eval(row)?) != Some(true),
+            });
+        }
+        let before = self.rows.len();
+        let mut keep = keep.into_iter();
+        self.rows.retain(|_| keep.next().unwrap_or(true));
+        Ok(before - self.rows.len())
+    }
+
+    /// sets columns of the rows for which `predicate` is true, or of every row if there is no predicate, to the values
+    /// of the expressions in `assignments`, and returns how many rows were updated.
+    ///
+    /// The new rows are all computed and checked before any are changed, so an error leaves the table unchanged.
+    pub fn update_rows(
+        &mut self,
+        predicate: Option<&crate::eval::BoundExpr>,
+        assignments: &[(usize, crate::eval::BoundExpr)],
+    ) -> anyhow::Result<usize> {
+        let mut updates = vec![];
+        for (i, row) in self.rows.iter().enumerate() {
+            if let Some(e) = predicate {
+                if crate::eval::to_bool(&e.eval(row)?) != Some(true) {
+                    continue;
+                }
+            }
+            let items = crate::eval::updated_row(row, assignments)?;
+            crate::typed_row::validate_row_for_table(self, &items)?;
+            updates.push((i, items));
+        }
+        let n = updates.len();
+        for (i, items) in updates {
+            self.rows[i] = Row { items };
+        }
+        Ok(n)
+    }
+
     /// Printings out tables nicely.
     /// In the future, also csv output, etc.
     pub fn print(&self, detailed: bool) -> anyhow::Result<()> {
@@ -138,6 +183,12 @@ impl<'a> StreamingIterator for TempTableStreamingIterator<'a> {
     }
 }
 
+impl<'a> RowStream for TempTableStreamingIterator<'a> {
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        None
+    }
+}
+
 #[test]
 fn test_temp_table() {
     use crate::sql_value::SqlValue;
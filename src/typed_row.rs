@@ -8,6 +8,7 @@
 //! An error returned by the underlying table iterator (such as an error in the btree structure)
 //! will manifest as one row having an error, fewer rows being returned.
 // TODO: It might be better to treat casting errors differently from errors in the underlying iterator.
+use crate::serial_type::TextEncoding;
 use crate::sql_type::SqlType;
 use crate::sql_value::SqlValue;
 use crate::table_traits::TableMeta;
@@ -35,6 +36,8 @@ pub enum Error {
     },
     #[error("Type array and value array length mismatch.")]
     ArrayLenMismatch,
+    #[error("{0}")]
+    Record(#[from] crate::record::Error),
     #[error("Serialization error, column number {}, detail : {}", colnum, detail)]
     Serialization {
         detail: crate::serial_type::Error,
@@ -51,19 +54,26 @@ pub enum Error {
 }
 
 // TODO: if this took a Row, and Row held the RowID, then the error messages could provide the rowid where the error occured.
-pub fn from_serialized(column_types: &Vec<SqlType>, record: &[u8]) -> Result<Row, Error> {
+/// returns the row held by `record`, with a value for each of `column_types`.
+///
+/// A record may have fewer values than the table has columns, such as when it was written before the table had the
+/// columns added by `ALTER TABLE ... ADD COLUMN`.  The missing values are NULL.  A record with more values than the
+/// table has columns is an error.
+pub fn from_serialized(column_types: &Vec<SqlType>, record: &[u8], encoding: TextEncoding) -> Result<Row, Error> {
     use crate::record::ValueIterator;
     let mut ret: Vec<SqlValue> = vec![];
-    for (colnum, (serty, bytes)) in ValueIterator::new(record).enumerate() {
-        if colnum > column_types.len() {
+    for (colnum, value) in ValueIterator::new(record).enumerate() {
+        let (serty, bytes) = value?;
+        if colnum >= column_types.len() {
             return Err(Error::ArrayLenMismatch);
         }
-        let v = crate::serial_type::to_sql_value(&serty, bytes)
+        let v = crate::serial_type::to_sql_value(&serty, bytes, encoding)
             .map_err(|detail| Error::Deserialization { colnum, detail })?;
         let v = crate::serial_type::cast_to_schema_type(&v, column_types[colnum])
             .map_err(|detail| Error::Casting { colnum, detail })?;
         ret.push(v);
     }
+    ret.resize(column_types.len(), SqlValue::Null());
     Ok(Row {
         items: ret.to_vec(),
     })
@@ -84,13 +94,22 @@ fn test_from_serialized() {
         SqlType::Text,
         SqlType::Int,
     ];
-    let tr = from_serialized(&column_types, &test_record).unwrap();
+    let tr = from_serialized(&column_types, &test_record, TextEncoding::Utf8).unwrap();
     assert_eq!(tr.items.len(), 5);
     assert_eq!(tr.items[0], Int(0));
     assert_eq!(tr.items[1], Int(1));
     assert_eq!(tr.items[2], Real(3.1415));
     assert_eq!(tr.items[3], Text(String::from("Ten")));
     assert_eq!(tr.items[4], Null());
+
+    // A record with fewer values than the table has columns is padded with NULLs, but one with more is an error.
+    let short_record: &[u8] = &[0x03, 0x09, 0x01, 0x05];
+    let tr = from_serialized(&column_types, short_record, TextEncoding::Utf8).unwrap();
+    assert_eq!(tr.items, vec![Int(1), Int(5), Null(), Null(), Null()]);
+    assert!(matches!(
+        from_serialized(&vec![SqlType::Int], short_record, TextEncoding::Utf8),
+        Err(Error::ArrayLenMismatch)
+    ));
 }
 
 // TODO: rationalize why all of the serialization is in this file, but the deserialization is split between this file and record.rs.
@@ -103,6 +122,7 @@ fn test_from_serialized() {
 ///
 /// * `row` - a Row (vector of SqlValues).
 /// * `buf` - An empty (zeroed) byte slice with sufficient space to hold the whole row.  Borrowed for the lifetime of the writer.  
+/// * `encoding` - The text encoding of the database that the record is for.
 ///
 /// # Returns
 ///
@@ -113,7 +133,7 @@ fn test_from_serialized() {
 ///
 /// * `buf` contains the row's record header and record data, in the range `buf[0 .. len]`.  The remaining space is zeros, namely the range `buf[len .. buf.len()]`.
 ///   In the case of an error, `buf` is zeroed (though it may have been modified before the writer discovered that there was not enough space.)
-pub fn to_serialized<'a>(row: &Row, buf: &'a mut [u8]) -> Result<usize, Error> {
+pub fn to_serialized<'a>(row: &Row, buf: &'a mut [u8], encoding: TextEncoding) -> Result<usize, Error> {
     use sqlite_varint::serialize_to_varint;
     
     // "A record contains a header and a body, in that order.
@@ -124,7 +144,7 @@ pub fn to_serialized<'a>(row: &Row, buf: &'a mut [u8]) -> Result<usize, Error> {
     let mut body: Vec<u8> = vec![];
 
     for (colnum, v) in row.items.iter().enumerate() {
-        let (data, code, _) = crate::serial_type::to_serial_type(v).map_err(|detail| Error::Serialization{detail, colnum})?;
+        let (data, code, _) = crate::serial_type::to_serial_type(v, encoding).map_err(|detail| Error::Serialization{detail, colnum})?;
         header.append(&mut serialize_to_varint(code));
 
         body.append(&mut data.clone());
@@ -200,7 +220,7 @@ fn test_to_serialized() {
     for case in cases {
         println!("Case {} of {}", casenum, numcases);
         let mut buf = [0_u8; 18];
-        let res = to_serialized(&Row{ items: case.0 }, &mut buf);
+        let res = to_serialized(&Row{ items: case.0 }, &mut buf, TextEncoding::Utf8);
         assert!(res.is_ok());
         let bytes_added = res.unwrap();
         assert_eq!(buf, case.1);
@@ -232,7 +252,7 @@ fn test_to_serialized_errors() {
     ];
     for case in cases {
         let mut buf = [0_u8; 18];
-        let result = to_serialized(&Row{ items: case.0}, &mut buf);
+        let result = to_serialized(&Row{ items: case.0}, &mut buf, TextEncoding::Utf8);
         assert!(result.is_err());
     }
 }
@@ -0,0 +1,283 @@
+//! wal reads and writes the write-ahead log of a database file in WAL mode.
+//!
+//! In WAL mode, changed pages are not written to the database file when they are committed.  Instead, they are
+//! appended as frames to the write-ahead log, a file named like the database file with `-wal` appended.  The last frame
+//! of each commit records the size of the database after the commit.  A reader uses the latest committed frame of a page,
+//! if there is one, and otherwise the page in the database file.  A checkpoint copies the pages in the WAL back into the
+//! database file, after which the WAL can start again from empty.
+//!
+//! The WAL format is described at https://www.sqlite.org/fileformat.html#the_write_ahead_log
+//!
+//! The wal-index (the `-shm` file) is neither read nor written, so no other process may have the database open.
+
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::stored_db::PageNum;
+
+// The low bit of the magic number says whether checksums are computed from big-endian or little-endian words.
+const WAL_MAGIC: u32 = 0x377f0682;
+const WAL_FORMAT_VERSION: u32 = 3007000;
+const WAL_HEADER_BYTES: u64 = 32;
+const FRAME_HEADER_BYTES: u64 = 24;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("WAL: Error accessing write-ahead log file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("WAL: Write-ahead log file was opened read-only.")]
+    ReadOnly,
+    #[error("WAL: Internal error.")]
+    Internal,
+}
+
+/// returns the path of the write-ahead log of the database file at `db_path`.
+pub fn path_for(db_path: &str) -> String {
+    format!("{}-wal", db_path)
+}
+
+/// A `Wal` is an open write-ahead log, with the locations of the latest committed frame of each page in it.
+pub struct Wal {
+    path: String,
+    /// the WAL file, or None if there is no file yet.
+    f: Option<std::fs::File>,
+    read_only: bool,
+    page_size: u32,
+    big_endian_checksums: bool,
+    checkpoint_seq: u32,
+    salts: [u32; 2],
+    /// the checksum of the last committed frame, or of the header if there are no frames.
+    checksum: (u32, u32),
+    /// the offset just past the last committed frame, where the next frame is written, or 0 if there is no header.
+    end: u64,
+    /// map from page number to the offset of the page data of the page's latest committed frame.
+    frames: HashMap<PageNum, u64>,
+    /// the size of the database in pages after the last commit in the WAL, or None if there are no commits.
+    numpages: Option<PageNum>,
+}
+
+// returns the checksum of `b`, continuing from the checksum `s`.
+fn checksum(big_endian: bool, s: (u32, u32), b: &[u8]) -> (u32, u32) {
+    b.chunks_exact(8).fold(s, |(s0, s1), w| {
+        let (x0, x1) = match big_endian {
+            true => (BigEndian::read_u32(&w[0..]), BigEndian::read_u32(&w[4..])),
+            false => (LittleEndian::read_u32(&w[0..]), LittleEndian::read_u32(&w[4..])),
+        };
+        let s0 = s0.wrapping_add(x0).wrapping_add(s1);
+        (s0, s1.wrapping_add(x1).wrapping_add(s0))
+    })
+}
+
+// returns a number that is unlikely to be the same as the salt of an earlier WAL.
+fn random_salt() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos())
+}
+
+impl Wal {
+    /// opens the WAL at `path` of a database with `page_size` byte pages, and finds its committed frames.
+    ///
+    /// A missing WAL is the same as an empty one.  The file is created when the first frame is written.  Frames after
+    /// the last commit, and frames after one with a bad checksum or old salts, were not committed, so they are ignored.
+    pub fn open(path: &str, page_size: u32, read_only: bool) -> Result<Self, Error> {
+        let f = match std::fs::OpenOptions::new().read(true).write(!read_only).open(path) {
+            Ok(f) => Some(f),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let mut wal = Wal {
+            path: path.to_owned(),
+            f,
+            read_only,
+            page_size,
+            big_endian_checksums: true,
+            checkpoint_seq: 0,
+            salts: [random_salt(), random_salt()],
+            checksum: (0, 0),
+            end: 0,
+            frames: HashMap::new(),
+            numpages: None,
+        };
+        wal.read_frames()?;
+        Ok(wal)
+    }
+
+    fn read_frames(&mut self) -> Result<(), Error> {
+        let mut b = vec![];
+        match &mut self.f {
+            None => return Ok(()),
+            Some(f) => f.read_to_end(&mut b)?,
+        };
+        if b.len() < WAL_HEADER_BYTES as usize
+            || BigEndian::read_u32(&b[0..]) & !1 != WAL_MAGIC
+            || BigEndian::read_u32(&b[8..]) != self.page_size
+        {
+            return Ok(());
+        }
+        let big_endian = BigEndian::read_u32(&b[0..]) & 1 == 1;
+        let salts = [BigEndian::read_u32(&b[16..]), BigEndian::read_u32(&b[20..])];
+        let mut s = checksum(big_endian, (0, 0), &b[0..24]);
+        if s != (BigEndian::read_u32(&b[24..]), BigEndian::read_u32(&b[28..])) {
+            return Ok(());
+        }
+        self.big_endian_checksums = big_endian;
+        self.checkpoint_seq = BigEndian::read_u32(&b[12..]);
+        self.salts = salts;
+        self.checksum = s;
+        self.end = WAL_HEADER_BYTES;
+        // The frames of the transaction being read, which are used only if it was committed.
+        let mut pending = vec![];
+        let frame_bytes = FRAME_HEADER_BYTES as usize + self.page_size as usize;
+        for (i, frame) in b[WAL_HEADER_BYTES as usize..].chunks_exact(frame_bytes).enumerate() {
+            if [BigEndian::read_u32(&frame[8..]), BigEndian::read_u32(&frame[12..])] != salts {
+                break;
+            }
+            s = checksum(big_endian, s, &frame[0..8]);
+            s = checksum(big_endian, s, &frame[FRAME_HEADER_BYTES as usize..]);
+            if s != (BigEndian::read_u32(&frame[16..]), BigEndian::read_u32(&frame[20..])) {
+                break;
+            }
+            let offset = WAL_HEADER_BYTES + (i * frame_bytes) as u64;
+            pending.push((BigEndian::read_u32(&frame[0..]) as PageNum, offset + FRAME_HEADER_BYTES));
+            let commit_numpages = BigEndian::read_u32(&frame[4..]);
+            if commit_numpages != 0 {
+                self.frames.extend(pending.drain(..));
+                self.numpages = Some(commit_numpages as PageNum);
+                self.checksum = s;
+                self.end = offset + frame_bytes as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// returns the size of the database in pages as of the last commit in the WAL, or None if there are no commits.
+    pub fn numpages(&self) -> Option<PageNum> {
+        self.numpages
+    }
+
+    /// returns the contents of page `pn` from its latest committed frame, or None if the page is not in the WAL.
+    pub fn read_page(&self, pn: PageNum) -> Result<Option<Vec<u8>>, Error> {
+        let (offset, mut f) = match (self.frames.get(&pn), &self.f) {
+            (Some(offset), Some(f)) => (*offset, f),
+            _ => return Ok(None),
+        };
+        let mut v = vec![0_u8; self.page_size as usize];
+        f.seek(SeekFrom::Start(offset))?;
+        f.read_exact(&mut v)?;
+        Ok(Some(v))
+    }
+
+    /// appends a frame for each of `pages`, and commits them, as a database of `numpages` pages.
+    ///
+    /// The frames are not synced, which the caller does with `sync()`.
+    pub fn append_commit(&mut self, pages: &[(PageNum, &[u8])], numpages: PageNum) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut b = vec![];
+        let start = match self.end {
+            0 => {
+                b = self.new_header();
+                0
+            }
+            end => end,
+        };
+        let mut offset = start + b.len() as u64;
+        let mut new_frames = vec![];
+        for (i, (pn, page)) in pages.iter().enumerate() {
+            let mut h = [0_u8; FRAME_HEADER_BYTES as usize];
+            BigEndian::write_u32(&mut h[0..], *pn as u32);
+            if i == pages.len() - 1 {
+                BigEndian::write_u32(&mut h[4..], numpages as u32);
+            }
+            BigEndian::write_u32(&mut h[8..], self.salts[0]);
+            BigEndian::write_u32(&mut h[12..], self.salts[1]);
+            self.checksum = checksum(self.big_endian_checksums, self.checksum, &h[0..8]);
+            self.checksum = checksum(self.big_endian_checksums, self.checksum, page);
+            BigEndian::write_u32(&mut h[16..], self.checksum.0);
+            BigEndian::write_u32(&mut h[20..], self.checksum.1);
+            b.extend_from_slice(&h);
+            b.extend_from_slice(page);
+            new_frames.push((*pn, offset + FRAME_HEADER_BYTES));
+            offset += FRAME_HEADER_BYTES + self.page_size as u64;
+        }
+        let f = match &mut self.f {
+            Some(f) => f,
+            None => self.f.insert(
+                std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&self.path)?,
+            ),
+        };
+        f.seek(SeekFrom::Start(start))?;
+        f.write_all(&b)?;
+        self.frames.extend(new_frames);
+        self.numpages = Some(numpages);
+        self.end = offset;
+        Ok(())
+    }
+
+    // returns a new WAL header, and starts the running checksum from it.
+    fn new_header(&mut self) -> Vec<u8> {
+        let mut h = vec![0_u8; WAL_HEADER_BYTES as usize];
+        BigEndian::write_u32(&mut h[0..], WAL_MAGIC | 1);
+        BigEndian::write_u32(&mut h[4..], WAL_FORMAT_VERSION);
+        BigEndian::write_u32(&mut h[8..], self.page_size);
+        BigEndian::write_u32(&mut h[12..], self.checkpoint_seq);
+        BigEndian::write_u32(&mut h[16..], self.salts[0]);
+        BigEndian::write_u32(&mut h[20..], self.salts[1]);
+        self.big_endian_checksums = true;
+        self.checksum = checksum(true, (0, 0), &h[0..24]);
+        BigEndian::write_u32(&mut h[24..], self.checksum.0);
+        BigEndian::write_u32(&mut h[28..], self.checksum.1);
+        h
+    }
+
+    /// syncs the WAL file to disk.
+    pub fn sync(&self) -> Result<(), Error> {
+        if let Some(f) = &self.f {
+            f.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// copies the latest committed frame of every page in the WAL into the database file `db`, and then empties the WAL.
+    pub fn checkpoint(&mut self, db: &mut std::fs::File) -> Result<(), Error> {
+        let numpages = match self.numpages {
+            None => return Ok(()),
+            Some(n) => n,
+        };
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        for pn in self.frames.keys().filter(|pn| **pn <= numpages) {
+            // Every page in `frames` has a frame in the file.
+            let page = self.read_page(*pn)?.ok_or(Error::Internal)?;
+            db.seek(SeekFrom::Start((*pn - 1) as u64 * self.page_size as u64))?;
+            db.write_all(&page)?;
+        }
+        db.set_len(numpages as u64 * self.page_size as u64)?;
+        db.sync_all()?;
+        // The next header has new salts, so that frames left from before the checkpoint are not mistaken for new ones.
+        if let Some(f) = &self.f {
+            f.set_len(0)?;
+            f.sync_all()?;
+        }
+        self.frames.clear();
+        self.numpages = None;
+        self.end = 0;
+        self.checkpoint_seq = self.checkpoint_seq.wrapping_add(1);
+        self.salts = [self.salts[0].wrapping_add(1), random_salt()];
+        Ok(())
+    }
+}
+
+#[test]
+fn test_checksum() {
+    // Each pair of words is added to the running sums.
+    let b = [0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4];
+    assert_eq!(checksum(true, (0, 0), &b[0..8]), (1, 3));
+    assert_eq!(checksum(true, (0, 0), &b), (1 + 3 + 3, 3 + 4 + 7));
+    assert_eq!(checksum(false, (0, 0), &b[0..8]), (1 << 24, (2 << 24) + (1 << 24)));
+}
@@ -4,6 +4,42 @@ fn path_to_testdata(filename: &str) -> String {
         + filename
 }
 
+/// A `TestFile` is the path of a database file in the temp directory that a test can change.  The file and its journal
+/// are removed when it is dropped, so that a test that fails does not leave them behind.
+struct TestFile(String);
+
+impl TestFile {
+    /// returns a path in the temp directory, named for `test`, with no file at it yet.
+    fn new(test: &str) -> TestFile {
+        let path = std::env::temp_dir().join(format!("diydb-{}-{}.db", std::process::id(), test));
+        let f = TestFile(path.to_str().unwrap().to_string());
+        f.remove();
+        f
+    }
+
+    fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn remove(&self) {
+        let _ = std::fs::remove_file(&self.0);
+        let _ = std::fs::remove_file(format!("{}-journal", self.0));
+    }
+}
+
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// copies a test database to a temporary file, named for `test`, so that the test can change it.
+fn copy_of_testdata(filename: &str, test: &str) -> TestFile {
+    let to = TestFile::new(&format!("{}-{}", test, filename.trim_end_matches(".db")));
+    std::fs::copy(path_to_testdata(filename), to.as_str()).expect("Should have copied test db.");
+    to
+}
+
 fn server_state_with_open_db_for_run_query_tests(path: &str) -> diydb::DbServerState {
     let mut ss = diydb::DbServerState::new();
     diydb::open_db(&mut ss, path)
@@ -107,6 +143,32 @@ fn test_run_query_on_three_level_db() {
     }
 }
 
+#[test]
+fn test_run_query_on_rows_from_before_added_columns() {
+    // Rows 1 and 2 were written before columns b, c and d were added, and row 3 before c and d were.
+    let path = path_to_testdata("added_columns.db");
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    use diydb::sql_value::SqlValue::*;
+    let tt = diydb::run_query_no_print(&ss, "select a, b, c, d from t").unwrap();
+    assert_eq!(
+        tt.rows.iter().map(|r| r.items.clone()).collect::<Vec<_>>(),
+        vec![
+            vec![Int(1), Null(), Null(), Null()],
+            vec![Int(2), Null(), Null(), Null()],
+            vec![Int(3), Text(String::from("three")), Null(), Null()],
+            vec![Int(4), Text(String::from("four")), Int(44), Real(4.5)],
+        ]
+    );
+    let tt = diydb::run_query_no_print(&ss, "select * from t where rowid = 3").unwrap();
+    assert_eq!(tt.rows.len(), 1);
+    assert_eq!(tt.rows[0].items, vec![Int(3), Text(String::from("three")), Null(), Null()]);
+    let tt = diydb::run_query_no_print(&ss, "select c, rowid from t where rowid > 2").unwrap();
+    assert_eq!(
+        tt.rows.iter().map(|r| r.items.clone()).collect::<Vec<_>>(),
+        vec![vec![Null(), Int(3)], vec![Int(44), Int(4)]]
+    );
+}
+
 #[test]
 fn test_run_dbless_selects() {
     let ss = diydb::DbServerState::new();
@@ -216,6 +278,86 @@ fn test_run_selects() {
                 Row { items: vec![Int(2), Int(0),] }, 
             ], 
         ),
+        (
+            "select a from t where 1",
+            vec![
+                Row { items: vec![Int(1)] },
+                Row { items: vec![Int(1)] },
+                Row { items: vec![Int(2)] },
+                Row { items: vec![Int(0)] },
+            ],
+        ),
+        (
+            "select * from t where 2 - 2",
+            vec![],
+        ),
+        (
+            "select 1 where 0",
+            vec![],
+        ),
+        (
+            "select 1 < 2, 1 = 2, null is null, 2 between 1 and 3, 1 in (1, 2), 'abc' like 'A%', 'a' || 'b'",
+            vec![
+                Row { items: vec![Int(1), Int(0), Int(1), Int(1), Int(1), Int(1), Text("ab".to_string())] },
+            ],
+        ),
+        (
+            "select 1 = null, null and 1, null or 1, not null",
+            vec![
+                Row { items: vec![Null(), Null(), Int(1), Null()] },
+            ],
+        ),
+        (
+            "select a from t where 1 < 2 and not 3 in (1, 2)",
+            vec![
+                Row { items: vec![Int(1)] },
+                Row { items: vec![Int(1)] },
+                Row { items: vec![Int(2)] },
+                Row { items: vec![Int(0)] },
+            ],
+        ),
+        (
+            "select a from t where null",
+            vec![],
+        ),
+        (
+            "select a + b, c * 2, t.a from t where a > 0 and d > 1.5",
+            vec![
+                Row { items: vec![Int(3), Real(2.2), Int(1)] },
+            ],
+        ),
+        (
+            "select a from t limit 2",
+            vec![
+                Row { items: vec![Int(1)] },
+                Row { items: vec![Int(1)] },
+            ],
+        ),
+        (
+            "select a, b from t order by a desc, b limit 2 offset 1",
+            vec![
+                Row { items: vec![Int(1), Int(1)] },
+                Row { items: vec![Int(1), Int(2)] },
+            ],
+        ),
+        (
+            "select a from t limit 1, 2",
+            vec![
+                Row { items: vec![Int(1)] },
+                Row { items: vec![Int(2)] },
+            ],
+        ),
+        (
+            "select a from t limit 1 + 1 offset 10",
+            vec![],
+        ),
+        (
+            "select e || f from t where e = 'A' and b <> 2",
+            vec![
+                Row { items: vec![Text("AA".to_string())] },
+                Row { items: vec![Text("AA".to_string())] },
+            ],
+        ),
     ];
     for case in cases {
         println!("--------------\n");
@@ -237,7 +379,50 @@ fn test_create_a_temptable() {
     let tt = diydb::run_query_no_print(&ss, "select * from temp.t").unwrap();
     assert_eq!(tt.rows.len(), 0);
 }
-// TODO: be able to create persistent tables.
+
+#[test]
+fn test_create_a_stored_table() {
+    use diydb::sql_value::SqlValue::*;
+    let path = copy_of_testdata("schema_table.db", "create");
+    let mut ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    diydb::run_create(&mut ss, "create table u (a int, b text)").unwrap();
+    diydb::run_create(&mut ss, "create table main.v (a int) strict").unwrap();
+    diydb::run_insert(&mut ss, "insert into u values (1, 'one'), (2, 'two')").unwrap();
+    for stmt in [
+        "create table U (c int)",
+        "create table t1 (a int)",
+        "create table sqlite_stat1 (a int)",
+        "create table w (a string) strict",
+    ] {
+        assert!(diydb::run_create(&mut ss, stmt).is_err(), "{} should fail", stmt);
+    }
+
+    // The tables are in the file after reopening it.
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let schema = ss.stored_db.as_ref().unwrap().main_schema().unwrap();
+    assert!(schema.ends_with("CREATE TABLE u (a int, b text);CREATE TABLE v (a int) STRICT;"), "{}", schema);
+    let tt = diydb::run_query_no_print(&ss, "select * from u").unwrap();
+    assert_eq!(tt.rows.len(), 2);
+    assert_eq!(tt.rows[1].items, vec![Int(2), Text(String::from("two"))]);
+    assert_eq!(diydb::run_query_no_print(&ss, "select * from v").unwrap().rows.len(), 0);
+}
+
+#[test]
+fn test_create_a_database_file() {
+    use diydb::sql_value::SqlValue::*;
+    let path = TestFile::new("createdb");
+    let path = path.as_str();
+    let mut ss = diydb::DbServerState::new();
+    diydb::create_db(&mut ss, path, 512).unwrap();
+    assert!(diydb::run_query_no_print(&ss, "select * from nosuchtable").is_err());
+    diydb::run_create(&mut ss, "create table t (a int, b text)").unwrap();
+    diydb::run_insert(&mut ss, "insert into t values (1, 'x'), (2, 'y')").unwrap();
+
+    let ss = server_state_with_open_db_for_run_query_tests(path);
+    let tt = diydb::run_query_no_print(&ss, "select b from t where a = 2").unwrap();
+    assert_eq!(tt.rows[0].items, vec![Text(String::from("y"))]);
+    assert!(diydb::create_db(&mut diydb::DbServerState::new(), path, 512).is_err());
+}
 
 #[test]
 fn test_insert_into_temptable_adds_a_row() {
@@ -278,3 +463,495 @@ fn test_insert_select_on_temptable_strict_works() {
     diydb::run_insert(&mut ss, "insert into temp.t values (42)").expect_err("Should have gotten error inserting short row");
     diydb::run_insert(&mut ss, "insert into temp.t values (42, 43, 44)").expect_err("Should have gotten error inserting long row");
 }
+
+#[test]
+fn test_where_on_temptable() {
+    let mut ss = diydb::DbServerState::new();
+    diydb::run_create(&mut ss, "create temp table t (i int)").unwrap();
+    diydb::run_insert(&mut ss, "insert into temp.t values (1), (2)").unwrap();
+    let tt = diydb::run_query_no_print(&ss, "select * from temp.t where 1").unwrap();
+    assert_eq!(tt.rows.len(), 2);
+    let tt = diydb::run_query_no_print(&ss, "select i from temp.t where null").unwrap();
+    assert_eq!(tt.rows.len(), 0);
+}
+
+#[test]
+fn test_column_expressions_on_temptable() {
+    use diydb::typed_row::Row;
+    use diydb::sql_value::SqlValue::*;
+    let mut ss = diydb::DbServerState::new();
+    diydb::run_create(&mut ss, "create temp table orders (price real, qty int)").unwrap();
+    diydb::run_insert(&mut ss, "insert into temp.orders values (1.5, 2), (10.0, 3)").unwrap();
+    let tt = diydb::run_query_no_print(&ss, "select price * qty from temp.orders where qty > 2").unwrap();
+    assert_eq!(tt.rows, vec![Row { items: vec![Real(30.0)] }]);
+    assert!(diydb::run_query_no_print(&ss, "select nosuchcol + 1 from temp.orders").is_err());
+}
+
+#[test]
+fn test_column_aliases() {
+    let path = path_to_testdata("for_exprs.db");
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let cases = vec![
+        ("select 1 as one, 'two' two", vec!["one", "two"]),
+        ("select 1, 2 as two", vec!["_f0", "two"]),
+        ("select a as x, b y, a + b AS total, t.c as c2, d from t", vec!["x", "y", "total", "c2", "d"]),
+    ];
+    for (query, expected) in cases {
+        println!("running: {}", query);
+        let tt = diydb::run_query_no_print(&ss, query).unwrap();
+        assert_eq!(tt.column_names, expected);
+    }
+}
+
+#[test]
+fn test_order_by() {
+    use diydb::typed_row::Row;
+    use diydb::sql_value::SqlValue::*;
+    let mut ss = diydb::DbServerState::new();
+    diydb::run_create(&mut ss, "create temp table t (a int, b text)").unwrap();
+    diydb::run_insert(&mut ss, "insert into temp.t values (2, 'x'), (NULL, 'y'), (1, 'z'), (2, 'w'), (1.5, 'v'), ('one', 'u')").unwrap();
+    let col_b = |q: &str| -> Vec<String> {
+        diydb::run_query_no_print(&ss, q)
+            .unwrap()
+            .rows
+            .iter()
+            .map(|r| match &r.items[0] {
+                Text(s) => s.clone(),
+                x => panic!("unexpected {:?}", x),
+            })
+            .collect()
+    };
+    assert_eq!(col_b("select b from temp.t order by a"), vec!["y", "z", "v", "x", "w", "u"]);
+    assert_eq!(col_b("select b from temp.t order by a desc, b"), vec!["u", "w", "x", "v", "z", "y"]);
+    assert_eq!(col_b("select b from temp.t order by a nulls last, b desc"), vec!["z", "v", "x", "w", "u", "y"]);
+    assert_eq!(col_b("select b, a as k from temp.t where a > 1 order by k desc, 1"), vec!["u", "w", "x", "v"]);
+    let tt = diydb::run_query_no_print(&ss, "select a * 2 from temp.t where b > 'v' order by 1 desc").unwrap();
+    assert_eq!(tt.rows[0], Row { items: vec![Int(4)] });
+}
+
+#[test]
+fn test_group_by_and_aggregates() {
+    use diydb::typed_row::Row;
+    use diydb::sql_value::SqlValue::*;
+    let path = path_to_testdata("for_exprs.db");
+    let mut ss = diydb::DbServerState::new();
+    diydb::open_db(&mut ss, path.as_str()).expect("Should have opened db.");
+    let rows = |q: &str| diydb::run_query_no_print(&ss, q).unwrap().rows;
+    assert_eq!(rows("select count(*) from t"), vec![Row { items: vec![Int(4)] }]);
+    assert_eq!(
+        rows("select a, count(*), sum(b), max(e) from t group by a"),
+        vec![
+            Row { items: vec![Int(0), Int(1), Int(3), Text(String::from("A"))] },
+            Row { items: vec![Int(1), Int(2), Int(3), Text(String::from("A"))] },
+            Row { items: vec![Int(2), Int(1), Int(1), Text(String::from("B"))] },
+        ]
+    );
+    assert_eq!(
+        rows("select e, group_concat(f, '') as fs from t group by 1 having count(*) > 1"),
+        vec![Row { items: vec![Text(String::from("A")), Text(String::from("ABA"))] }]
+    );
+    assert_eq!(
+        rows("select a, avg(c) from t where b < 3 group by a order by count(*) desc, a limit 1"),
+        vec![Row { items: vec![Int(1), Real(1.1)] }]
+    );
+    assert_eq!(rows("select min(c), max(c) from t where a > 5"), vec![Row { items: vec![Null(), Null()] }]);
+    let tt = diydb::run_query_no_print(&ss, "select a, count(*) from t group by a").unwrap();
+    assert_eq!(tt.column_names, vec!["a", "count(*)"]);
+    for q in ["select b, count(*) from t group by a", "select * from t group by a", "select a from t where sum(b) > 1"] {
+        assert!(diydb::run_query_no_print(&ss, q).is_err(), "{} should fail", q);
+    }
+}
+
+#[test]
+fn test_joins_between_temp_and_main() {
+    use diydb::typed_row::Row;
+    use diydb::sql_value::SqlValue::*;
+    let path = path_to_testdata("for_exprs.db");
+    let mut ss = diydb::DbServerState::new();
+    diydb::open_db(&mut ss, path.as_str()).expect("Should have opened db.");
+    diydb::run_create(&mut ss, "create temp table u (a int, g text)").unwrap();
+    diydb::run_insert(&mut ss, "insert into temp.u values (1, 'one'), (2, 'two'), (2, 'deux'), (NULL, 'none')").unwrap();
+    let rows = |q: &str| diydb::run_query_no_print(&ss, q).unwrap().rows;
+    let text = |s: &str| Text(String::from(s));
+
+    // An equi-join, done by hashing.
+    assert_eq!(
+        rows("select t.b, g from t join temp.u on t.a = u.a order by t.b, g"),
+        vec![
+            Row { items: vec![Int(1), text("deux")] },
+            Row { items: vec![Int(1), text("one")] },
+            Row { items: vec![Int(1), text("two")] },
+            Row { items: vec![Int(2), text("one")] },
+        ]
+    );
+    // A left join, with a condition that needs a nested-loop join.
+    assert_eq!(
+        rows("select x.b, y.g from t as x left join temp.u y on y.a > x.a + 1 order by x.b, y.g"),
+        vec![
+            Row { items: vec![Int(1), Null()] },
+            Row { items: vec![Int(1), Null()] },
+            Row { items: vec![Int(2), Null()] },
+            Row { items: vec![Int(3), text("deux")] },
+            Row { items: vec![Int(3), text("two")] },
+        ]
+    );
+    // A comma join, with the join condition in the where clause.
+    assert_eq!(
+        rows("select u.g, count(*) from temp.u, t where u.a = t.a and t.e = 'A' group by u.g"),
+        vec![Row { items: vec![text("one"), Int(2)] }]
+    );
+    let tt = diydb::run_query_no_print(&ss, "select * from temp.u v join t on v.a = t.a where t.b = 2").unwrap();
+    assert_eq!(tt.column_names, vec!["a", "g", "a", "b", "c", "d", "e", "f"]);
+    assert_eq!(tt.rows.len(), 1);
+    for q in ["select a from t join temp.u on t.a = u.a", "select * from t x join temp.u on t.a = u.a"] {
+        assert!(diydb::run_query_no_print(&ss, q).is_err(), "{} should fail", q);
+    }
+}
+
+#[test]
+fn test_rowid_seeks() {
+    use diydb::sql_value::SqlValue::*;
+    for db in ["multipage-512B-page.db", "multipage.db"] {
+        let path = path_to_testdata(db);
+        let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+        let q = |q: &str| diydb::run_query_no_print(&ss, q).unwrap();
+
+        let tt = q("select rowid, * from thousandrows where rowid between 285 and 287");
+        assert_eq!(tt.column_names, vec!["rowid", "x", "y", "z"]);
+        assert_eq!(tt.rows.len(), 3);
+        assert_eq!(
+            tt.rows[0].items,
+            vec![Int(285), Text(String::from("C")), Text(String::from("I")), Text(String::from("E"))]
+        );
+        assert_eq!(tt.rows[2].items[0], Int(287));
+
+        // The rowid is not selected by `*`.
+        let tt = q("select * from thousandrows where rowid = 285");
+        assert_eq!(tt.rows.len(), 1);
+        assert_eq!(tt.rows[0].items.len(), 3);
+
+        // Terms that aren't on the rowid still filter the rows.
+        assert_eq!(q("select rowid from thousandrows where 285 = rowid and x = 'B'").rows.len(), 0);
+        assert_eq!(q("select rowid from thousandrows where rowid > 990 and rowid <= 995").rows.len(), 5);
+        assert_eq!(q("select rowid from thousandrows where rowid > 2000").rows.len(), 0);
+        assert_eq!(q("select rowid from thousandrows where rowid < 5 and rowid > 10").rows.len(), 0);
+    }
+}
+
+#[test]
+fn test_index_seeks() {
+    let path = path_to_testdata("indexes.db");
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    // Each query can use an index, and gets the same rows as the query after it, which can't.
+    let cases = vec![
+        ("age = 37", "age + 0 = 37", 12),
+        ("city = 'Denver' and age >= 80", "city || '' = 'Denver' and age + 0 >= 80", 22),
+        ("age between 10 and 12 and name like 'p1%'", "age + 0 between 10 and 12 and name like 'p1%'", 4),
+        ("age < 3", "age + 0 < 3", 31),
+        ("city = 'Austin' and age >= 80", "city || '' = 'Austin' and age + 0 >= 80", 20),
+        ("city = 'Nowhere'", "city || '' = 'Nowhere'", 0),
+    ];
+    for (indexed, scanned, expected_len) in cases {
+        let q = |w: &str| {
+            diydb::run_query_no_print(&ss, &format!("select rowid, name, age, city from people where {} order by rowid", w))
+                .unwrap()
+        };
+        let (tt, expected) = (q(indexed), q(scanned));
+        assert_eq!(tt.rows, expected.rows, "for where clause {}", indexed);
+        assert_eq!(tt.rows.len(), expected_len, "for where clause {}", indexed);
+    }
+}
+
+#[test]
+fn test_where_on_a_declared_rowid_column() {
+    use diydb::sql_value::SqlValue::*;
+    let path = TestFile::new("declared-rowid");
+    let path = path.as_str();
+    let mut ss = diydb::DbServerState::new();
+    diydb::create_db(&mut ss, path, 512).unwrap();
+    // A declared column named rowid hides the rowid, so its values need not match the rowids 1, 2 and 3.
+    diydb::run_create(&mut ss, "create table t (rowid int, x text)").unwrap();
+    diydb::run_insert(&mut ss, "insert into t values (30, 'a'), (10, 'b'), (20, 'c')").unwrap();
+    // The terms are compared with the column's values as written, which need not be integers.
+    diydb::run_create(&mut ss, "create table r (rowid real, x text)").unwrap();
+    diydb::run_insert(&mut ss, "insert into r values (5.5, 'a'), (7, 'b'), (4.9, 'c')").unwrap();
+    let x = |q: &str| -> Vec<diydb::sql_value::SqlValue> {
+        let tt = diydb::run_query_no_print(&ss, &format!("{} order by x", q)).unwrap();
+        tt.rows.iter().map(|r| r.items[0].clone()).collect()
+    };
+    let text = |s: &str| Text(String::from(s));
+    assert_eq!(x("select x from t where rowid = 10"), vec![text("b")]);
+    assert_eq!(x("select x from t where t.rowid = 1"), vec![]);
+    assert_eq!(x("select x from t where rowid > 15"), vec![text("a"), text("c")]);
+    assert_eq!(x("select x from t where rowid between 2 and 20 and x != 'b'"), vec![text("c")]);
+    assert_eq!(x("select x from t where rowid <= 30 and x = 'a'"), vec![text("a")]);
+    assert_eq!(x("select x from r where rowid > 5"), vec![text("a"), text("b")]);
+    assert_eq!(x("select x from r where rowid < 5"), vec![text("c")]);
+    assert_eq!(x("select x from r where rowid between 5 and 6"), vec![text("a")]);
+}
+
+#[test]
+fn test_insert_into_stored_table() {
+    use diydb::sql_value::SqlValue::*;
+    let path = copy_of_testdata("multipage-512B-page.db", "insert");
+    let mut ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    // Enough rows to split the leaves and interior pages of the table, on 512 byte pages.
+    for i in 0..200 {
+        let values: Vec<String> = (0..10).map(|j| format!("('r{}', 'i{}', 'j{}')", i, i * 10 + j, j)).collect();
+        diydb::run_insert(&mut ss, &format!("insert into thousandrows values {}", values.join(", "))).unwrap();
+    }
+    // A statement with one bad row inserts none of its rows.
+    diydb::run_insert(&mut ss, "insert into main.thousandrows values ('a', 'b', 'c'), ('too short')").expect_err("Should have failed.");
+    diydb::run_insert(&mut ss, "insert into nosuchtable values (1)").expect_err("Should have failed.");
+
+    // The rows are in the file after reopening it.
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let q = |q: &str| diydb::run_query_no_print(&ss, q).unwrap();
+    assert_eq!(q("select count(*) from thousandrows").rows[0].items, vec![Int(3000)]);
+    assert_eq!(
+        q("select rowid, * from thousandrows where rowid = 1001").rows[0].items,
+        vec![Int(1001), Text(String::from("r0")), Text(String::from("i0")), Text(String::from("j0"))]
+    );
+    assert_eq!(q("select y from thousandrows where rowid = 3000").rows[0].items, vec![Text(String::from("i1999"))]);
+    assert_eq!(q("select count(*) from thousandrows where z = 'j3'").rows[0].items, vec![Int(200)]);
+}
+
+#[test]
+fn test_delete_from_tables() {
+    use diydb::sql_value::SqlValue::*;
+    let path = copy_of_testdata("multipage-512B-page.db", "delete");
+    let mut ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let count = |ss: &diydb::DbServerState, q: &str| diydb::run_query_no_print(ss, q).unwrap().rows[0].items[0].clone();
+
+    diydb::run_create(&mut ss, "create temp table t (a int, b text)").unwrap();
+    diydb::run_insert(&mut ss, "insert into temp.t values (1, 'x'), (2, 'y'), (3, null), (4, 'x')").unwrap();
+    diydb::run_delete(&mut ss, "delete from temp.t where b = 'x' or a > 3").unwrap();
+    assert_eq!(count(&ss, "select group_concat(a) from temp.t"), Text(String::from("2,3")));
+    diydb::run_delete(&mut ss, "delete from temp.t where nosuchcolumn = 1").expect_err("Should have failed.");
+    diydb::run_delete(&mut ss, "delete from temp.t").unwrap();
+    assert_eq!(count(&ss, "select count(*) from temp.t"), Int(0));
+
+    // Deleting most of the rows of a table merges its pages.
+    diydb::run_delete(&mut ss, "delete from thousandrows where rowid / 10 * 10 != rowid and x != 'q'").unwrap();
+    diydb::run_delete(&mut ss, "delete from main.thousandrows where rowid between 500 and 700").unwrap();
+    diydb::run_delete(&mut ss, "delete from nosuchtable").expect_err("Should have failed.");
+    diydb::run_delete(&mut ss, "delete from sqlite_schema").expect_err("Should have failed.");
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    assert_eq!(count(&ss, "select count(*) from thousandrows"), Int(79));
+    assert_eq!(count(&ss, "select min(rowid) from thousandrows where rowid > 490"), Int(710));
+    assert_eq!(count(&ss, "select count(*) from letters"), Int(10));
+}
+
+#[test]
+fn test_update_tables() {
+    use diydb::sql_value::SqlValue::*;
+    let path = copy_of_testdata("multipage-512B-page.db", "update");
+    let mut ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let value = |ss: &diydb::DbServerState, q: &str| diydb::run_query_no_print(ss, q).unwrap().rows[0].items[0].clone();
+
+    diydb::run_create(&mut ss, "create temp table t (a int, b text) strict").unwrap();
+    diydb::run_insert(&mut ss, "insert into temp.t values (1, 'x'), (2, 'y'), (3, null)").unwrap();
+    // Every expression sees the values from before the update.
+    diydb::run_update(&mut ss, "update temp.t set a = a * 10, b = b || a where a >= 2").unwrap();
+    assert_eq!(value(&ss, "select group_concat(a) from temp.t"), Text(String::from("1,20,30")));
+    assert_eq!(value(&ss, "select group_concat(b) from temp.t"), Text(String::from("x,y2")));
+    // A value of the wrong type for a STRICT table fails, and changes no rows.
+    diydb::run_update(&mut ss, "update temp.t set a = 'five' where a = 30").expect_err("Should have failed.");
+    diydb::run_update(&mut ss, "update temp.t set nosuchcolumn = 1").expect_err("Should have failed.");
+    diydb::run_update(&mut ss, "update temp.t set a = a + 1").unwrap();
+    assert_eq!(value(&ss, "select sum(a) from temp.t"), Int(54));
+
+    // Rows that grow move to new pages.
+    diydb::run_update(&mut ss, "update thousandrows set x = x || x || x || x, z = 'new' where rowid > 100").unwrap();
+    diydb::run_update(&mut ss, "update main.thousandrows set y = null where rowid = 7").unwrap();
+    diydb::run_update(&mut ss, "update thousandrows set x = 'a' || 'b'").unwrap();
+    diydb::run_update(&mut ss, "update nosuchtable set x = 1").expect_err("Should have failed.");
+    diydb::run_update(&mut ss, "update sqlite_schema set name = 'x'").expect_err("Should have failed.");
+    diydb::run_create(&mut ss, "create table s (i int) strict").unwrap();
+    diydb::run_insert(&mut ss, "insert into s values (1), (2)").unwrap();
+    diydb::run_update(&mut ss, "update s set i = 'two' where i = 2").expect_err("Should have failed.");
+    diydb::run_update(&mut ss, "update s set i = i + 10").unwrap();
+
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    assert_eq!(value(&ss, "select count(*) from thousandrows where x = 'ab'"), Int(1000));
+    assert_eq!(value(&ss, "select count(*) from thousandrows where z = 'new'"), Int(900));
+    assert_eq!(value(&ss, "select count(*) from thousandrows where y is null"), Int(1));
+    assert_eq!(value(&ss, "select group_concat(i) from s"), Text(String::from("11,12")));
+}
+
+#[test]
+fn test_transactions() {
+    use diydb::sql_value::SqlValue::*;
+    let path = copy_of_testdata("minimal.db", "transactions");
+    let mut ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let value = |ss: &diydb::DbServerState, q: &str| diydb::run_query_no_print(ss, q).unwrap().rows[0].items[0].clone();
+    diydb::run_create(&mut ss, "create temp table t (a int) strict").unwrap();
+
+    // A statement that fails part way changes nothing.
+    diydb::run_insert(&mut ss, "insert into temp.t values (1), (2), ('three')").expect_err("Should have failed.");
+    assert_eq!(value(&ss, "select count(*) from temp.t"), Int(0));
+
+    // Rolled back changes are undone in both databases, and are not written to the file.
+    diydb::run_transaction_statement(&mut ss, "begin").unwrap();
+    assert!(ss.in_transaction());
+    diydb::run_insert(&mut ss, "insert into temp.t values (1), (2)").unwrap();
+    diydb::run_insert(&mut ss, "insert into a values (10), (20)").unwrap();
+    diydb::run_transaction_statement(&mut ss, "begin").expect_err("Should have failed.");
+    assert_eq!(value(&ss, "select count(*) from a"), Int(3));
+    diydb::run_transaction_statement(&mut ss, "rollback").unwrap();
+    assert!(!ss.in_transaction());
+    assert_eq!(value(&ss, "select count(*) from temp.t"), Int(0));
+    assert_eq!(value(&ss, "select count(*) from a"), Int(1));
+    diydb::run_transaction_statement(&mut ss, "rollback").expect_err("Should have failed.");
+    diydb::run_transaction_statement(&mut ss, "commit").expect_err("Should have failed.");
+
+    // Savepoints undo only the changes made after them.
+    diydb::run_transaction_statement(&mut ss, "begin transaction").unwrap();
+    diydb::run_insert(&mut ss, "insert into a values (2)").unwrap();
+    diydb::run_insert(&mut ss, "insert into temp.t values (2)").unwrap();
+    diydb::run_transaction_statement(&mut ss, "savepoint sp1").unwrap();
+    diydb::run_insert(&mut ss, "insert into a values (3)").unwrap();
+    diydb::run_create(&mut ss, "create table b (x int)").unwrap();
+    diydb::run_update(&mut ss, "update temp.t set a = 20").unwrap();
+    diydb::run_transaction_statement(&mut ss, "rollback to sp1").unwrap();
+    assert_eq!(value(&ss, "select sum(b) from a"), Int(3));
+    assert_eq!(value(&ss, "select sum(a) from temp.t"), Int(2));
+    assert!(diydb::run_query_no_print(&ss, "select * from b").is_err());
+    // The savepoint can be rolled back to again, until it is released.
+    diydb::run_delete(&mut ss, "delete from a").unwrap();
+    diydb::run_transaction_statement(&mut ss, "rollback transaction to savepoint sp1").unwrap();
+    diydb::run_transaction_statement(&mut ss, "release sp1").unwrap();
+    diydb::run_transaction_statement(&mut ss, "rollback to sp1").expect_err("Should have failed.");
+    // A failed statement in a transaction is undone, without ending the transaction.
+    diydb::run_insert(&mut ss, "insert into temp.t values (3), ('four')").expect_err("Should have failed.");
+    assert!(ss.in_transaction());
+    diydb::run_transaction_statement(&mut ss, "commit").unwrap();
+    assert_eq!(value(&ss, "select sum(a) from temp.t"), Int(2));
+
+    // Releasing the outermost savepoint commits the transaction it started.
+    diydb::run_transaction_statement(&mut ss, "savepoint outer_sp").unwrap();
+    diydb::run_insert(&mut ss, "insert into a values (4)").unwrap();
+    diydb::run_transaction_statement(&mut ss, "release savepoint outer_sp").unwrap();
+    assert!(!ss.in_transaction());
+
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    assert_eq!(value(&ss, "select group_concat(b) from a"), Text(String::from("1,2,4")));
+}
+
+#[test]
+fn test_nested_savepoints() {
+    use diydb::sql_value::SqlValue::*;
+    let path = copy_of_testdata("minimal.db", "nested-savepoints");
+    let mut ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let value = |ss: &diydb::DbServerState, q: &str| diydb::run_query_no_print(ss, q).unwrap().rows[0].items[0].clone();
+    diydb::run_create(&mut ss, "create temp table t (a int)").unwrap();
+
+    diydb::run_transaction_statement(&mut ss, "begin").unwrap();
+    diydb::run_insert(&mut ss, "insert into a values (5)").unwrap();
+    diydb::run_transaction_statement(&mut ss, "savepoint s1").unwrap();
+    diydb::run_insert(&mut ss, "insert into a values (6)").unwrap();
+    diydb::run_insert(&mut ss, "insert into temp.t values (6)").unwrap();
+    diydb::run_transaction_statement(&mut ss, "savepoint s2").unwrap();
+    diydb::run_insert(&mut ss, "insert into a values (7)").unwrap();
+    diydb::run_insert(&mut ss, "insert into temp.t values (7)").unwrap();
+    // Releasing s2 keeps its changes, which s1 can still roll back.
+    diydb::run_transaction_statement(&mut ss, "release s2").unwrap();
+    assert_eq!(value(&ss, "select group_concat(b) from a"), Text(String::from("1,5,6,7")));
+    diydb::run_transaction_statement(&mut ss, "rollback to s1").unwrap();
+    assert_eq!(value(&ss, "select group_concat(b) from a"), Text(String::from("1,5")));
+    assert_eq!(value(&ss, "select count(*) from temp.t"), Int(0));
+    diydb::run_insert(&mut ss, "insert into a values (8)").unwrap();
+    diydb::run_transaction_statement(&mut ss, "commit").unwrap();
+
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    assert_eq!(value(&ss, "select group_concat(b) from a"), Text(String::from("1,5,8")));
+}
+
+#[test]
+fn test_integrity_check() {
+    let mut ss = diydb::DbServerState::new();
+    assert!(diydb::integrity_check(&ss).is_err());
+    let path = copy_of_testdata("multipage-512B-page.db", "check");
+    ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    assert_eq!(diydb::integrity_check(&ss).unwrap(), Vec::<String>::new());
+    diydb::run_delete(&mut ss, "delete from thousandrows where rowid > 100").unwrap();
+    diydb::run_insert(&mut ss, "insert into letters values ('a'), ('b')").unwrap();
+    assert_eq!(diydb::integrity_check(&ss).unwrap(), Vec::<String>::new());
+
+    // Claim there are freelist pages which are not in the freelist.
+    let mut bytes = std::fs::read(path.as_str()).unwrap();
+    let count = u32::from_be_bytes(bytes[36..40].try_into().unwrap());
+    bytes[36..40].copy_from_slice(&(count + 1).to_be_bytes());
+    std::fs::write(path.as_str(), bytes).unwrap();
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let problems = diydb::integrity_check(&ss).unwrap();
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert!(problems[0].starts_with("Freelist has"), "{:?}", problems);
+}
+
+#[test]
+fn test_query_on_corrupt_db() {
+    let path = copy_of_testdata("multipage.db", "corrupt");
+    // Give the second leaf page of "thousandrows" an invalid page type.
+    let mut bytes = std::fs::read(path.as_str()).unwrap();
+    bytes[4 * 4096] = 0x07;
+    std::fs::write(path.as_str(), bytes).unwrap();
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let err = diydb::run_query_no_print(&ss, "select * from thousandrows").unwrap_err();
+    assert!(err.to_string().contains("page 5"), "{}", err);
+    // Rows on other pages can still be read.
+    let tt = diydb::run_query_no_print(&ss, "select * from thousandrows where rowid = 1").unwrap();
+    assert_eq!(tt.rows.len(), 1);
+}
+
+#[test]
+fn test_statements_on_a_btree_that_loops() {
+    let path = copy_of_testdata("multipage.db", "loop");
+    // Make the rightmost child of the root of "thousandrows", on page 3, the root itself.
+    let mut bytes = std::fs::read(path.as_str()).unwrap();
+    assert_eq!(bytes[2 * 4096], 0x05);
+    bytes[2 * 4096 + 8..2 * 4096 + 12].copy_from_slice(&3_u32.to_be_bytes());
+    std::fs::write(path.as_str(), bytes).unwrap();
+    let mut ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let err = diydb::run_query_no_print(&ss, "select * from thousandrows").unwrap_err();
+    assert!(err.to_string().contains("more than 20 levels"), "{}", err);
+    let err = diydb::run_insert(&mut ss, "insert into thousandrows values ('a', 'b', 'c')").unwrap_err();
+    assert!(err.to_string().contains("page 3, offset 0: btree has more than 20 levels"), "{}", err);
+    let problems = diydb::integrity_check(&ss).unwrap();
+    assert!(problems.contains(&String::from("Page 3: page 3 is already used elsewhere")), "{:?}", problems);
+}
+
+/// returns a copy of multipage.db, named for `test`, in which `f` has changed the record of row 1.
+///
+/// The first cell of the first leaf of "thousandrows", on page 4, is row 1, whose record is header length 4, three
+/// serial types for 1 byte texts, and "AAA".  `f` is given the bytes from the header length to the first "A".
+fn copy_with_row_1_changed(test: &str, f: &dyn Fn(&mut [u8])) -> TestFile {
+    let path = copy_of_testdata("multipage.db", test);
+    let mut bytes = std::fs::read(path.as_str()).unwrap();
+    let page = &mut bytes[3 * 4096..4 * 4096];
+    let cell = u16::from_be_bytes([page[8], page[9]]) as usize;
+    assert_eq!(page[cell..cell + 9], [0x07, 0x01, 0x04, 0x0f, 0x0f, 0x0f, 0x41, 0x41, 0x41]);
+    f(&mut page[cell + 2..cell + 7]);
+    std::fs::write(path.as_str(), bytes).unwrap();
+    path
+}
+
+#[test]
+fn test_query_on_records_with_too_few_values() {
+    use diydb::sql_value::SqlValue::*;
+    // A header of two values leaves the third column NULL.
+    let path = copy_with_row_1_changed("short-record", &|r| r[0] = 0x03);
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let tt = diydb::run_query_no_print(&ss, "select x, y, z from thousandrows where rowid = 1").unwrap();
+    assert_eq!(tt.rows[0].items, vec![Text(String::from("\x0f")), Text(String::from("A")), Null()]);
+    assert_eq!(diydb::run_query_no_print(&ss, "select * from thousandrows").unwrap().rows.len(), 1000);
+}
+
+#[test]
+fn test_query_on_records_with_too_many_values() {
+    // A header of four values, "A", "A", 0 and 0, has more values than the table has columns.
+    let path = copy_with_row_1_changed("long-record", &|r| r.copy_from_slice(&[0x05, 0x0f, 0x0f, 0x08, 0x08]));
+    let ss = server_state_with_open_db_for_run_query_tests(path.as_str());
+    let err = diydb::run_query_no_print(&ss, "select * from thousandrows").unwrap_err();
+    assert!(err.to_string().contains("row 1"), "{}", err);
+}